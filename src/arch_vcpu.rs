@@ -1,7 +1,15 @@
-use axaddrspace::{GuestPhysAddr, HostPhysAddr};
-use axerrno::AxResult;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use axaddrspace::{GuestPhysAddr, HostPhysAddr, MappingFlags};
+use axerrno::{AxResult, ax_err};
 
 use crate::exit::AxVCpuExitReason;
+use crate::{
+    AxVCpuHal, ExecCounters, ExecProfilingConfig, GuestEndian, GuestMode, HwWatchpoint,
+    InjectionOrder, InterceptConfig, IntrospectionVerdict, MemoryAttributePolicy, SysRegTrapMode,
+    VCpuCapabilities, VCpuTopology,
+};
 
 /// A trait for architecture-specific vcpu.
 ///
@@ -11,15 +19,100 @@ pub trait AxArchVCpu: Sized {
     type CreateConfig;
     /// The configuration for setting up a created [`AxArchVCpu`]. Used by [`AxArchVCpu::setup`].
     type SetupConfig;
+    /// The interfaces of the underlying software, used by [`AxVCpu`](crate::AxVCpu) for architecture-independent
+    /// operations, e.g., reading the host clock.
+    type Hal: AxVCpuHal;
 
     /// Create a new `AxArchVCpu`.
+    ///
+    /// It may be called on any physical CPU, and concurrently with the creation of other vcpus of the same VM (see
+    /// [`AxVCpuGroup::create_vcpus`](crate::AxVCpuGroup::create_vcpus)), so it must not depend on the current
+    /// physical CPU, e.g., by loading per-CPU virtualization state. Such initialization belongs to
+    /// [`AxArchVCpu::bind`].
     fn new(config: Self::CreateConfig) -> AxResult<Self>;
 
+    /// Create a new `AxArchVCpu` with the architecture-independent attributes of the vcpu, e.g., its position in
+    /// the CPU topology of the VM. [`AxVCpu`](crate::AxVCpu) always creates the architecture-specific vcpu with this
+    /// method.
+    ///
+    /// The same restrictions as [`AxArchVCpu::new`] apply. The default implementation ignores `ctx` and calls
+    /// [`AxArchVCpu::new`].
+    fn new_with_context(config: Self::CreateConfig, ctx: &VCpuCreateContext) -> AxResult<Self> {
+        let _ = ctx;
+        Self::new(config)
+    }
+
     /// Set the entry point of the vcpu.
     ///
-    /// It's guaranteed that this function is called only once, before [`AxArchVCpu::setup`] being called.
+    /// It's guaranteed that this function is called only once before [`AxArchVCpu::setup`] being called, and
+    /// afterwards only when the VMM moves the entry (see [`AxVCpu::set_entry`](crate::AxVCpu::set_entry)) or, by
+    /// the default implementation of [`AxArchVCpu::set_boot_args`], when a vcpu powered off by the guest is
+    /// brought up again.
     fn set_entry(&mut self, entry: GuestPhysAddr) -> AxResult;
 
+    /// Set the entry point and the boot arguments of a vcpu powered off by the guest and brought up again (see
+    /// [`AxVCpu::unpark`](crate::AxVCpu::unpark)), as defined by the CPU bring-up call of the architecture, e.g.,
+    /// `x0 = arg` for the PSCI `CPU_ON` of Aarch64, or `a0 = arch_cpu_id` and `a1 = arg` for the SBI HSM
+    /// `hart_start` of RISC-V. The arguments are truncated to the register width of the guest mode.
+    ///
+    /// The default implementation calls [`AxArchVCpu::set_entry`] and ignores the arguments, which suits
+    /// architectures without boot arguments, e.g., the INIT-SIPI sequence of x86.
+    fn set_boot_args(&mut self, entry: GuestPhysAddr, arch_cpu_id: u64, arg: u64) -> AxResult {
+        let _ = (arch_cpu_id, arg);
+        self.set_entry(entry)
+    }
+
+    /// Set the execution mode of the guest, e.g., AArch32 or x86 compatibility mode for a 32-bit guest on a
+    /// 64-bit host.
+    ///
+    /// It's guaranteed that this function is called before [`AxArchVCpu::setup`] being called, and afterwards only
+    /// right before [`AxArchVCpu::set_entry`]. The default implementation only accepts [`GuestMode::Native`], and
+    /// returns `Unsupported` for other modes.
+    fn set_guest_mode(&mut self, mode: GuestMode) -> AxResult {
+        match mode {
+            GuestMode::Native => Ok(()),
+            _ => ax_err!(Unsupported, "32-bit guests are not supported"),
+        }
+    }
+
+    /// Set the byte order of the data accesses of the guest, e.g., by setting `SCTLR_EL1.EE` in Aarch64.
+    ///
+    /// It's guaranteed that this function is called only once, before [`AxArchVCpu::setup`] being called. The
+    /// default implementation only accepts [`GuestEndian::Little`], and returns `Unsupported` otherwise.
+    fn set_guest_endian(&mut self, endian: GuestEndian) -> AxResult {
+        match endian {
+            GuestEndian::Little => Ok(()),
+            GuestEndian::Big => ax_err!(Unsupported, "big-endian guests are not supported"),
+        }
+    }
+
+    /// Set the size of the guest physical address space in bits, e.g., `VTCR_EL2.T0SZ` in Aarch64 or the G-stage
+    /// translation mode in RISC-V. The nested page table set by [`AxArchVCpu::set_ept_root`] must cover it.
+    ///
+    /// It's guaranteed that this function is called at most once, before [`AxArchVCpu::setup`] being called, and
+    /// only if the size is configured. The default implementation returns `Unsupported`.
+    fn set_guest_phys_bits(&mut self, bits: u8) -> AxResult {
+        let _ = bits;
+        ax_err!(
+            Unsupported,
+            "configurable guest physical address size is not supported"
+        )
+    }
+
+    /// Set the default attributes of the stage-2 mappings of the vcpu, and how mismatches with the guest attributes
+    /// are handled, e.g., `MAIR`-derived `MemAttr` and `HCR_EL2.FWB` in Aarch64, or the EPT memory type and `IPAT`
+    /// in x86.
+    ///
+    /// It's guaranteed that this function is called only once, before [`AxArchVCpu::setup`] being called. The
+    /// default implementation only accepts the default policy, and returns `Unsupported` otherwise.
+    fn set_memory_attribute_policy(&mut self, policy: &MemoryAttributePolicy) -> AxResult {
+        if policy.is_default() {
+            Ok(())
+        } else {
+            ax_err!(Unsupported, "memory attribute policies are not supported")
+        }
+    }
+
     /// Set the EPT root of the vcpu.
     ///
     /// It's guaranteed that this function is called only once, before [`AxArchVCpu::setup`] being called.
@@ -41,4 +134,365 @@ pub trait AxArchVCpu: Sized {
 
     /// Set the value of a general-purpose register according to the given index.
     fn set_gpr(&mut self, reg: usize, val: usize);
+
+    /// Inject an interrupt into the vcpu.
+    ///
+    /// It's guaranteed that this function is called only when the vcpu is bound to the current physical CPU,
+    /// right before [`AxArchVCpu::run`] being called.
+    fn inject_interrupt(&mut self, vector: usize) -> AxResult;
+
+    /// Synchronize the pending state of the hardware guest-interrupt file of the vcpu (e.g., the IMSIC guest
+    /// interrupt file in RISC-V AIA, or the list registers of the vGIC in Aarch64) back to the common injection
+    /// queue, calling `requeue` with each interrupt which is pending but no longer tracked by the hardware (e.g.,
+    /// evicted from a list register), so that it's injected again by [`AxArchVCpu::inject_interrupt`] later.
+    ///
+    /// It's guaranteed that this function is called only when the vcpu is bound to the current physical CPU, after
+    /// each [`AxArchVCpu::run`], before [`AxArchVCpu::unbind`], and before the guest-interrupt file is changed by
+    /// [`AxArchVCpu::set_guest_irq_file`]. The default implementation does nothing, as for architectures injecting
+    /// interrupts purely in software.
+    fn sync_hw_irq_state(&mut self, requeue: &mut dyn FnMut(usize)) -> AxResult {
+        let _ = requeue;
+        Ok(())
+    }
+
+    /// Attach the hardware guest-interrupt file allocated by
+    /// [`AxArchPerCpu::alloc_guest_irq_file`](crate::AxArchPerCpu::alloc_guest_irq_file) on the current physical
+    /// CPU to the vcpu, or detach it if `file` is `None`.
+    ///
+    /// It's guaranteed that this function is called only when the vcpu is bound to the current physical CPU. The
+    /// default implementation returns `Unsupported`.
+    fn set_guest_irq_file(&mut self, file: Option<usize>) -> AxResult {
+        let _ = file;
+        ax_err!(
+            Unsupported,
+            "hardware guest-interrupt files are not supported"
+        )
+    }
+
+    /// Get the optional capabilities of the vcpu.
+    ///
+    /// The default implementation reports no capability.
+    fn capabilities(&self) -> VCpuCapabilities {
+        VCpuCapabilities::empty()
+    }
+
+    /// Get the priority of an interrupt vector, higher first, used to inject pending interrupts by
+    /// [`InjectionOrder::Priority`], e.g., the priority class of the vector in x86, or the priority programmed by
+    /// the guest into the virtual GIC in Aarch64.
+    ///
+    /// Only called on vcpus reporting [`VCpuCapabilities::INTERRUPT_PRIORITIES`]. The default implementation
+    /// returns 0 for all vectors.
+    fn interrupt_priority(&self, vector: usize) -> u32 {
+        let _ = vector;
+        0
+    }
+
+    /// Get the injection order preferred by the vcpu, used for [`InjectionOrder::ArchDefined`]. Returning
+    /// [`InjectionOrder::ArchDefined`] itself is the same as returning [`InjectionOrder::Fifo`].
+    ///
+    /// The default implementation returns [`InjectionOrder::Fifo`].
+    fn injection_order(&self) -> InjectionOrder {
+        InjectionOrder::Fifo
+    }
+
+    /// Save the control-flow-integrity state (x86 CET, Aarch64 PAC/BTI keys) of the guest and restore the one of the host.
+    ///
+    /// Called only if [`AxArchVCpu::capabilities`] reports [`VCpuCapabilities::SECURITY_STATE`], right before
+    /// [`AxArchVCpu::unbind`] being called.
+    fn save_security_state(&mut self) -> AxResult {
+        Ok(())
+    }
+
+    /// Save the control-flow-integrity state (x86 CET, Aarch64 PAC/BTI keys) of the host and restore the one of the guest.
+    ///
+    /// Called only if [`AxArchVCpu::capabilities`] reports [`VCpuCapabilities::SECURITY_STATE`], right after
+    /// [`AxArchVCpu::bind`] being called.
+    fn restore_security_state(&mut self) -> AxResult {
+        Ok(())
+    }
+
+    /// Whether the register state of the guest is protected (e.g., encrypted) and not accessible to the host.
+    ///
+    /// Only vcpus reporting [`VCpuCapabilities::CONFIDENTIAL`] may return `true`. The default implementation returns `false`.
+    fn is_protected(&self) -> bool {
+        false
+    }
+
+    /// Configure which guest operations cause vm-exits.
+    ///
+    /// It's guaranteed that this function is called only after [`AxArchVCpu::setup`] being called. The default
+    /// implementation returns `Unsupported`.
+    fn set_intercepts(&mut self, config: &InterceptConfig) -> AxResult {
+        let _ = config;
+        ax_err!(Unsupported, "intercept configuration is not supported")
+    }
+
+    /// Configure which accesses to the system registers in `range` cause vm-exits, overriding the set trapped by
+    /// default. See [`AxVCpuExitReason::SysRegRead`](crate::AxVCpuExitReason::SysRegRead) for the encoding of the
+    /// addresses.
+    ///
+    /// It's guaranteed that this function is called only after [`AxArchVCpu::setup`] being called. The default
+    /// implementation returns `Unsupported`.
+    fn configure_sysreg_traps(&mut self, range: Range<usize>, mode: SysRegTrapMode) -> AxResult {
+        let _ = (range, mode);
+        ax_err!(
+            Unsupported,
+            "system register trap configuration is not supported"
+        )
+    }
+
+    /// Complete the access held by an [`AxVCpuExitReason::Introspection`](crate::AxVCpuExitReason::Introspection)
+    /// exit to `addr` with `access`, as decided by the handler.
+    ///
+    /// It's guaranteed that this function is called only after the exit is returned, before the vcpu runs again.
+    /// The default implementation returns `Unsupported`.
+    fn complete_introspection(
+        &mut self,
+        addr: GuestPhysAddr,
+        access: MappingFlags,
+        verdict: IntrospectionVerdict,
+    ) -> AxResult {
+        let _ = (addr, access, verdict);
+        ax_err!(Unsupported, "introspection is not supported")
+    }
+
+    /// Inject a synchronous data abort (a bus error, e.g., an external abort in Aarch64, a `#MC`/`#GP` in x86, or
+    /// an access fault in RISC-V) for the access of `addr`, so that it's taken by the guest when the vcpu runs
+    /// again. Used for [`UnhandledMmioPolicy::InjectAbort`](crate::UnhandledMmioPolicy::InjectAbort).
+    ///
+    /// It's guaranteed that this function is called only after the access exits, before the vcpu runs again. The
+    /// default implementation returns `Unsupported`.
+    fn inject_data_abort(&mut self, addr: GuestPhysAddr, is_write: bool) -> AxResult {
+        let _ = (addr, is_write);
+        ax_err!(Unsupported, "data abort injection is not supported")
+    }
+
+    /// Program the hardware performance counters to count guest execution as configured (only while the guest
+    /// runs), or stop counting if nothing is enabled.
+    ///
+    /// It's guaranteed that this function is called only after [`AxArchVCpu::setup`] being called. The default
+    /// implementation returns `Unsupported`.
+    fn enable_exec_profiling(&mut self, cfg: &ExecProfilingConfig) -> AxResult {
+        let _ = cfg;
+        ax_err!(Unsupported, "execution profiling is not supported")
+    }
+
+    /// Read and reset the guest execution counters enabled by [`AxArchVCpu::enable_exec_profiling`], i.e., get
+    /// the counts since the last read.
+    ///
+    /// Called right after each [`AxArchVCpu::run`] while profiling is enabled. The default implementation returns
+    /// zeros.
+    fn read_exec_counters(&mut self) -> ExecCounters {
+        ExecCounters::default()
+    }
+
+    /// Get the encoding of the software breakpoint instruction of the architecture (e.g., `INT3` in x86, `BRK` in
+    /// Aarch64, `ebreak` in RISC-V), used by [`AxVCpu::add_sw_breakpoint`](crate::AxVCpu::add_sw_breakpoint).
+    ///
+    /// The default implementation returns `None`, i.e., software breakpoints are not supported.
+    fn sw_breakpoint_insn() -> Option<&'static [u8]> {
+        None
+    }
+
+    /// Get the number of debug register slots available for hardware watchpoints. The default implementation
+    /// returns 0.
+    fn hw_watchpoint_slots(&self) -> usize {
+        0
+    }
+
+    /// Set (`Some`) or clear (`None`) the hardware watchpoint of the debug register `slot`, which is less than
+    /// [`AxArchVCpu::hw_watchpoint_slots`].
+    ///
+    /// It's guaranteed that this function is called only after [`AxArchVCpu::setup`] being called. The default
+    /// implementation returns `Unsupported`.
+    fn set_hw_watchpoint(&mut self, slot: usize, watchpoint: Option<HwWatchpoint>) -> AxResult {
+        let _ = (slot, watchpoint);
+        ax_err!(Unsupported, "hardware watchpoints are not supported")
+    }
+
+    /// Set the offset (in nanoseconds) subtracted from the host counter to get the guest virtual counter
+    /// (TSC in x86, `CNTVOFF_EL2` in Aarch64, `htimedelta` in RISC-V).
+    ///
+    /// It's guaranteed that this function is called only when the vcpu is bound to the current physical CPU,
+    /// right before [`AxArchVCpu::run`] being called. The default implementation does nothing, i.e., the guest
+    /// counter keeps following the host counter, and [`AxVCpu::pause_time`](crate::AxVCpu::pause_time) and
+    /// [`AxVCpu::set_time_offset`](crate::AxVCpu::set_time_offset) have no effect on the guest.
+    fn set_virtual_counter_offset(&mut self, offset_ns: u64) -> AxResult {
+        let _ = offset_ns;
+        Ok(())
+    }
+
+    /// Get the deadline of the guest timer (the LAPIC timer / TSC deadline in x86, the EL1 virtual timer in Aarch64,
+    /// `vstimecmp` in RISC-V) in nanoseconds on the guest virtual counter, or `None` if it's not armed. Saved in
+    /// snapshots, see [`VCpuTimeState`](crate::VCpuTimeState).
+    ///
+    /// It's guaranteed that this function is called only when the vcpu is not running. The default implementation
+    /// returns `None`, i.e., the timer state is part of [`AxArchVCpu::save_state`] if any.
+    fn timer_deadline(&mut self) -> AxResult<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Re-arm the guest timer with a deadline saved by [`AxArchVCpu::timer_deadline`], on the guest virtual counter
+    /// whose offset is set by [`AxArchVCpu::set_virtual_counter_offset`] before the vcpu runs again.
+    ///
+    /// It's guaranteed that this function is called only when the vcpu is not running, after
+    /// [`AxArchVCpu::restore_state`]. The default implementation only accepts `None`, and returns `Unsupported`
+    /// otherwise.
+    fn set_timer_deadline(&mut self, deadline_ns: Option<u64>) -> AxResult {
+        match deadline_ns {
+            None => Ok(()),
+            Some(_) => ax_err!(Unsupported, "timer deadline restoring is not supported"),
+        }
+    }
+
+    /// Grant (`true`) or revoke (`false`) direct guest access to the architectural timer of the current physical
+    /// CPU (the LAPIC timer / TSC deadline in x86, the EL1 virtual timer in Aarch64, `vstimecmp` in RISC-V).
+    ///
+    /// It's guaranteed that this function is called only when the vcpu is bound to the current physical CPU.
+    /// The default implementation returns `Unsupported`.
+    fn set_timer_passthrough(&mut self, enable: bool) -> AxResult {
+        let _ = enable;
+        ax_err!(Unsupported, "timer passthrough is not supported")
+    }
+
+    /// Enable (`true`) or disable (`false`) trapping FP/SIMD accesses of the guest, used by [`FpuPolicy::Lazy`](crate::FpuPolicy::Lazy).
+    ///
+    /// While enabled, the first FP/SIMD access of the guest exits with [`AxVCpuExitReason::FirstFpuUse`]. It's
+    /// guaranteed that this function is called only when the vcpu is bound to the current physical CPU. The default
+    /// implementation returns `Unsupported`.
+    fn set_fpu_trap(&mut self, enable: bool) -> AxResult {
+        let _ = enable;
+        ax_err!(Unsupported, "FP/SIMD trapping is not supported")
+    }
+
+    /// Load the saved FP/SIMD state of the guest into the current physical CPU, used by [`FpuPolicy::Lazy`](crate::FpuPolicy::Lazy).
+    ///
+    /// It's guaranteed that this function is called only when the vcpu is bound to the current physical CPU. The
+    /// default implementation returns `Unsupported`.
+    fn restore_fpu_state(&mut self) -> AxResult {
+        ax_err!(Unsupported, "FP/SIMD state switching is not supported")
+    }
+
+    /// Save the FP/SIMD state of the guest from the current physical CPU, used by [`FpuPolicy::Lazy`](crate::FpuPolicy::Lazy).
+    ///
+    /// It's guaranteed that this function is called only when the vcpu is bound to the current physical CPU, and
+    /// only after [`AxArchVCpu::restore_fpu_state`] being called since the last bind. The default implementation
+    /// returns `Unsupported`.
+    fn save_fpu_state(&mut self) -> AxResult {
+        ax_err!(Unsupported, "FP/SIMD state switching is not supported")
+    }
+
+    /// Get the size in bytes of the extended register state of the vcpu, i.e., the variable-length vector state
+    /// which doesn't fit in a fixed-size structure (the SVE/SME state in Aarch64, the AVX-512/AMX state in x86,
+    /// the V extension state in RISC-V), or 0 if there is none.
+    ///
+    /// The size may depend on the host (e.g., the SVE vector length) and the configuration of the vcpu, but must
+    /// not change after [`AxArchVCpu::setup`] being called. The default implementation returns 0.
+    fn ext_state_size(&self) -> usize {
+        0
+    }
+
+    /// Save the extended register state of the vcpu into `buf`, whose length is [`AxArchVCpu::ext_state_size`].
+    ///
+    /// The default implementation returns `Unsupported`.
+    fn save_ext_state(&mut self, buf: &mut [u8]) -> AxResult {
+        let _ = buf;
+        ax_err!(Unsupported, "extended state saving is not supported")
+    }
+
+    /// Restore the extended register state of the vcpu from `buf`, whose length is
+    /// [`AxArchVCpu::ext_state_size`].
+    ///
+    /// The default implementation returns `Unsupported`.
+    fn restore_ext_state(&mut self, buf: &[u8]) -> AxResult {
+        let _ = buf;
+        ax_err!(Unsupported, "extended state restoring is not supported")
+    }
+
+    /// Save the architecture-specific state of the vcpu (registers, virtualization control structures) for
+    /// [`AxVCpu::snapshot`](crate::AxVCpu::snapshot), appending it to `out` in an architecture-specific encoding.
+    ///
+    /// The default implementation returns `Unsupported`.
+    fn save_state(&mut self, out: &mut Vec<u8>) -> AxResult {
+        let _ = out;
+        ax_err!(Unsupported, "state saving is not supported")
+    }
+
+    /// Get the size in bytes of the architecture-specific state saved by [`AxArchVCpu::save_state`], used to size
+    /// the buffers of [`AxVCpu::save_state_into`](crate::AxVCpu::save_state_into).
+    ///
+    /// The default implementation saves the state into a temporary buffer and returns its length. Implementations
+    /// should override it (together with [`AxArchVCpu::save_state_into`]) to avoid allocating.
+    fn saved_state_size(&mut self) -> AxResult<usize> {
+        let mut out = Vec::new();
+        self.save_state(&mut out)?;
+        Ok(out.len())
+    }
+
+    /// Save the architecture-specific state of the vcpu like [`AxArchVCpu::save_state`], into `buf`, whose length
+    /// is [`AxArchVCpu::saved_state_size`].
+    ///
+    /// The default implementation saves the state into a temporary buffer and copies it, returning `InvalidData`
+    /// if its size differs from the length of `buf`.
+    fn save_state_into(&mut self, buf: &mut [u8]) -> AxResult {
+        let mut out = Vec::new();
+        self.save_state(&mut out)?;
+        if out.len() != buf.len() {
+            return ax_err!(InvalidData, "saved state size changed");
+        }
+        buf.copy_from_slice(&out);
+        Ok(())
+    }
+
+    /// Restore the architecture-specific state saved by [`AxArchVCpu::save_state`], possibly on another host with
+    /// a compatible [`HostInfo`](crate::HostInfo).
+    ///
+    /// The default implementation returns `Unsupported`.
+    fn restore_state(&mut self, data: &[u8]) -> AxResult {
+        let _ = data;
+        ax_err!(Unsupported, "state restoring is not supported")
+    }
+
+    /// Get the architectural name (e.g., `x0`, `rax`, `a7`) of the general-purpose register with the given index,
+    /// as used by [`AxArchVCpu::set_gpr`] and the `reg` fields of [`AxVCpuExitReason`], or `None` if the index is
+    /// invalid. See [`RegName`](crate::RegName).
+    ///
+    /// The default implementation looks the index up in [`DEFAULT_GPR_NAMES`](crate::DEFAULT_GPR_NAMES).
+    fn reg_name(index: usize) -> Option<&'static str> {
+        crate::DEFAULT_GPR_NAMES.get(index).copied()
+    }
+
+    /// Get the index of the general-purpose register with the given architectural name, the reverse of
+    /// [`AxArchVCpu::reg_name`].
+    fn reg_index(name: &str) -> Option<usize> {
+        (0..)
+            .map_while(Self::reg_name)
+            .position(|reg_name| reg_name == name)
+    }
+
+    /// Get the number of levels of the nested page table (whose root is set by [`AxArchVCpu::set_ept_root`]) the
+    /// vcpu is set up with, or `None` if unknown.
+    ///
+    /// Used to cross-check the negotiated number of levels after [`AxArchVCpu::setup`] being called. The default
+    /// implementation returns `None`.
+    fn guest_page_table_levels(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// The architecture-independent attributes of a vcpu, passed to [`AxArchVCpu::new_with_context`].
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct VCpuCreateContext {
+    /// The id of the vcpu.
+    pub vcpu_id: usize,
+    /// The architectural id of the vcpu, see [`AxVCpu::arch_cpu_id`](crate::AxVCpu::arch_cpu_id).
+    pub arch_cpu_id: u64,
+    /// The position of the vcpu in the CPU topology of the VM.
+    pub topology: VCpuTopology,
+    /// The NUMA node the memory of the vcpu should preferably be allocated from, see
+    /// [`AxVCpuHal::alloc_frame_on_node`].
+    pub numa_node: Option<usize>,
+    /// The execution mode the guest starts in.
+    pub guest_mode: GuestMode,
 }
@@ -0,0 +1,162 @@
+use axerrno::{AxResult, ax_err};
+
+use crate::vcpu::AxVCpuInnerConst;
+use crate::{
+    AxArchVCpu, AxVCpu, AxVCpuHal, CpuClass, GuestEndian, GuestMode, MemoryAttributePolicy,
+    VCpuTopology,
+};
+
+/// A builder of [`AxVCpu`], for configuring the optional attributes of a vcpu.
+///
+/// ```ignore
+/// let vcpu = AxVCpuBuilder::<MyArchVCpu>::new(0, create_config)
+///     .favor_phys_cpu(1)
+///     .phys_cpu_set(Some(0b11))
+///     .build()?;
+/// ```
+pub struct AxVCpuBuilder<A: AxArchVCpu> {
+    /// The constant attributes of the vcpu.
+    inner_const: AxVCpuInnerConst,
+    /// The configuration for creating the architecture-specific vcpu.
+    arch_config: A::CreateConfig,
+}
+
+impl<A: AxArchVCpu> AxVCpuBuilder<A> {
+    /// Create a new builder of the vcpu with the given id.
+    ///
+    /// By default, the vcpu favors physical CPU 0, can run on any physical CPU, has no preferred CPU class,
+    /// its architectural id equals `id`, and it's placed in a flat topology (see [`VCpuTopology::flat`]).
+    pub fn new(id: usize, arch_config: A::CreateConfig) -> Self {
+        Self {
+            inner_const: AxVCpuInnerConst {
+                id,
+                vm_id: 0,
+                favor_phys_cpu: 0,
+                phys_cpu_set: None,
+                prefer_class: None,
+                guest_page_table_levels: None,
+                arch_cpu_id: id as u64,
+                topology: VCpuTopology::flat(id),
+                numa_node: None,
+                guest_mode: GuestMode::Native,
+                guest_endian: GuestEndian::Little,
+                guest_phys_bits: None,
+                memory_attribute_policy: MemoryAttributePolicy::new(),
+            },
+            arch_config,
+        }
+    }
+
+    /// Set the id of the VM the vcpu belongs to. Defaults to 0.
+    pub fn vm_id(mut self, vm_id: usize) -> Self {
+        self.inner_const.vm_id = vm_id;
+        self
+    }
+
+    /// Set the id of the physical CPU who has the priority to run the vcpu.
+    pub fn favor_phys_cpu(mut self, favor_phys_cpu: usize) -> Self {
+        self.inner_const.favor_phys_cpu = favor_phys_cpu;
+        self
+    }
+
+    /// Set the set of physical CPUs who can run the vcpu, `None` for no limitation.
+    pub fn phys_cpu_set(mut self, phys_cpu_set: Option<usize>) -> Self {
+        self.inner_const.phys_cpu_set = phys_cpu_set;
+        self
+    }
+
+    /// Set the class of physical CPUs the vcpu should preferably run on, `None` for no preference.
+    pub fn prefer_class(mut self, prefer_class: Option<CpuClass>) -> Self {
+        self.inner_const.prefer_class = prefer_class;
+        self
+    }
+
+    /// Set the negotiated number of guest (nested) page table levels, generally obtained from
+    /// [`AxPerCpu::negotiate_guest_page_table_levels`](crate::AxPerCpu::negotiate_guest_page_table_levels).
+    ///
+    /// If set, [`AxVCpu::setup`] fails if the architecture-specific vcpu is set up with a different number of levels.
+    pub fn guest_page_table_levels(mut self, levels: Option<usize>) -> Self {
+        self.inner_const.guest_page_table_levels = levels;
+        self
+    }
+
+    /// Set the size of the guest physical (intermediate physical in Arm) address space in bits, generally obtained
+    /// from [`AxPerCpu::negotiate_guest_phys_bits`](crate::AxPerCpu::negotiate_guest_phys_bits) so that it's
+    /// within the hardware limits, and consistent with the address space of the VM. `None` leaves the default of
+    /// the architecture-specific vcpu.
+    ///
+    /// It's passed to the architecture-specific vcpu on [`AxVCpu::setup`]. [`AxVCpuBuilder::build`] fails with
+    /// `InvalidInput` if it's not within `12..=64`.
+    pub fn guest_phys_bits(mut self, bits: Option<u8>) -> Self {
+        self.inner_const.guest_phys_bits = bits;
+        self
+    }
+
+    /// Set the architectural id (APIC ID in x86, MPIDR affinity in Aarch64, hartid in RISC-V) of the vcpu,
+    /// generally obtained from a [`CpuIdMap`](crate::CpuIdMap). Defaults to the vcpu id.
+    pub fn arch_cpu_id(mut self, arch_cpu_id: u64) -> Self {
+        self.inner_const.arch_cpu_id = arch_cpu_id;
+        self
+    }
+
+    /// Set the position of the vcpu in the CPU topology of the VM, generally obtained from a
+    /// [`CpuIdMap`](crate::CpuIdMap). It's passed to the architecture-specific vcpu on creation.
+    pub fn topology(mut self, topology: VCpuTopology) -> Self {
+        self.inner_const.topology = topology;
+        self
+    }
+
+    /// Set the NUMA node the memory of the vcpu should preferably be allocated from. If `None`, the node of the
+    /// favored physical CPU (see [`AxVCpuHal::node_of_cpu`](crate::AxVCpuHal::node_of_cpu)) is used.
+    pub fn numa_node(mut self, numa_node: Option<usize>) -> Self {
+        self.inner_const.numa_node = numa_node;
+        self
+    }
+
+    /// Set the execution mode the guest starts in, e.g., [`GuestMode::Aarch32`] for a 32-bit guest on an Aarch64
+    /// host. It's passed to the architecture-specific vcpu on creation and on [`AxVCpu::setup`]. Defaults to
+    /// [`GuestMode::Native`].
+    pub fn guest_mode(mut self, mode: GuestMode) -> Self {
+        self.inner_const.guest_mode = mode;
+        self
+    }
+
+    /// Set the byte order of the data accesses of the guest, e.g., [`GuestEndian::Big`] for big-endian network
+    /// appliances. It's passed to the architecture-specific vcpu on [`AxVCpu::setup`]. Defaults to
+    /// [`GuestEndian::Little`].
+    pub fn guest_endian(mut self, endian: GuestEndian) -> Self {
+        self.inner_const.guest_endian = endian;
+        self
+    }
+
+    /// Set the default attributes of the stage-2 mappings and the handling of mismatched guest attributes, e.g.,
+    /// [`MemoryAttributePolicy::guest_override`] for VMs with passed-through framebuffers or non-coherent DMA. It
+    /// should be the same for all vcpus of a VM. It's passed to the architecture-specific vcpu on
+    /// [`AxVCpu::setup`]. Defaults to [`MemoryAttributePolicy::new`].
+    pub fn memory_attribute_policy(mut self, policy: MemoryAttributePolicy) -> Self {
+        self.inner_const.memory_attribute_policy = policy;
+        self
+    }
+
+    /// Create the vcpu.
+    pub fn build(self) -> AxResult<AxVCpu<A>> {
+        let (inner_const, arch_config) = self.into_parts()?;
+        AxVCpu::new_with(inner_const, arch_config)
+    }
+
+    /// Validate the attributes and split the builder into the constant attributes of the vcpu and the
+    /// configuration of the architecture-specific vcpu.
+    pub(crate) fn into_parts(self) -> AxResult<(AxVCpuInnerConst, A::CreateConfig)> {
+        let mut inner_const = self.inner_const;
+        if inner_const
+            .guest_phys_bits
+            .is_some_and(|bits| !(12..=64).contains(&bits))
+        {
+            return ax_err!(InvalidInput, "guest physical address size out of range");
+        }
+        if inner_const.numa_node.is_none() {
+            inner_const.numa_node = A::Hal::node_of_cpu(inner_const.favor_phys_cpu);
+        }
+        Ok((inner_const, self.arch_config))
+    }
+}
@@ -0,0 +1,36 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// Optional capabilities of an architecture-specific vcpu, reported by [`AxArchVCpu::capabilities`](crate::AxArchVCpu::capabilities).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct VCpuCapabilities: u64 {
+        /// The vcpu context-switches the x86 CET shadow stack state.
+        const CET_SHADOW_STACK = 1 << 0;
+        /// The vcpu context-switches the x86 CET indirect branch tracking state.
+        const CET_IBT = 1 << 1;
+        /// The vcpu context-switches the Aarch64 pointer authentication keys.
+        const POINTER_AUTH = 1 << 2;
+        /// The vcpu context-switches the Aarch64 branch target identification state.
+        const BTI = 1 << 3;
+        /// The vcpu is able to run confidential guests (AMD SEV-SNP, Intel TDX, Arm CCA), whose register state
+        /// may be encrypted and inaccessible to the host. See [`AxArchVCpu::is_protected`](crate::AxArchVCpu::is_protected).
+        const CONFIDENTIAL = 1 << 4;
+        /// The vcpu defines the priorities of interrupt vectors (see [`AxArchVCpu::interrupt_priority`](crate::AxArchVCpu::interrupt_priority)),
+        /// so that pending interrupts can be injected by priority, see [`InjectionOrder`](crate::InjectionOrder).
+        const INTERRUPT_PRIORITIES = 1 << 5;
+    }
+}
+
+impl VCpuCapabilities {
+    /// The capabilities which require [`AxArchVCpu::save_security_state`](crate::AxArchVCpu::save_security_state)
+    /// and [`AxArchVCpu::restore_security_state`](crate::AxArchVCpu::restore_security_state) to be called.
+    pub const SECURITY_STATE: Self = Self::CET_SHADOW_STACK
+        .union(Self::CET_IBT)
+        .union(Self::POINTER_AUTH)
+        .union(Self::BTI);
+
+    /// Whether the vcpu has any control-flow-integrity state to be context-switched.
+    pub const fn has_security_state(&self) -> bool {
+        self.intersects(Self::SECURITY_STATE)
+    }
+}
@@ -0,0 +1,124 @@
+use alloc::vec::Vec;
+
+use axerrno::{AxResult, ax_err};
+
+/// A compression algorithm for snapshot and migration streams, see
+/// [`AxVCpuSnapshot::encode_with`](crate::AxVCpuSnapshot::encode_with).
+///
+/// The crate doesn't bake in any specific algorithm: VMMs transferring state over slow links plug in their own
+/// (e.g., LZ4), or use the trivial [`RleCompressor`].
+pub trait Compressor {
+    /// Get the id of the algorithm, recorded in compressed streams so that they're only decompressed with the same
+    /// algorithm. Ids below 128 are reserved for this crate, VMMs should use the others.
+    fn id(&self) -> u8;
+
+    /// Compress `data`, appending the result to `out`.
+    fn compress(&self, data: &[u8], out: &mut Vec<u8>);
+
+    /// Decompress `data` compressed by [`Compressor::compress`], appending the result to `out`. Returns
+    /// `InvalidData` if `data` is malformed, or if it decompresses to more than `limit` bytes, in which case
+    /// implementations should stop before appending them.
+    fn decompress(&self, data: &[u8], limit: usize, out: &mut Vec<u8>) -> AxResult;
+}
+
+/// A trivial run-length [`Compressor`], encoding each run of up to 255 equal bytes as `(length, byte)`.
+///
+/// Effective on sparse state (e.g., mostly zeroed control structures and vector registers), and a reference for
+/// implementing other compressors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RleCompressor;
+
+impl RleCompressor {
+    /// The id of the algorithm.
+    pub const ID: u8 = 1;
+}
+
+impl Compressor for RleCompressor {
+    fn id(&self) -> u8 {
+        Self::ID
+    }
+
+    fn compress(&self, data: &[u8], out: &mut Vec<u8>) {
+        let mut rest = data;
+        while let Some(&byte) = rest.first() {
+            let len = rest
+                .iter()
+                .take(u8::MAX as usize)
+                .take_while(|&&b| b == byte)
+                .count();
+            out.extend_from_slice(&[len as u8, byte]);
+            rest = &rest[len..];
+        }
+    }
+
+    fn decompress(&self, data: &[u8], limit: usize, out: &mut Vec<u8>) -> AxResult {
+        let runs = data.chunks_exact(2);
+        if !runs.remainder().is_empty() {
+            return ax_err!(InvalidData, "truncated run-length data");
+        }
+        let mut remaining = limit;
+        for run in runs {
+            let len = run[0] as usize;
+            if len == 0 {
+                return ax_err!(InvalidData, "empty run in run-length data");
+            }
+            remaining = match remaining.checked_sub(len) {
+                Some(remaining) => remaining,
+                None => return ax_err!(InvalidData, "run-length data exceeds the output limit"),
+            };
+            out.extend(core::iter::repeat_n(run[1], len));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use axerrno::AxError;
+
+    use super::*;
+
+    #[test]
+    fn rle_round_trip() {
+        let data: Vec<u8> = [vec![0; 600], vec![1, 2, 2, 3], vec![0xff; 255]].concat();
+        let mut compressed = Vec::new();
+        RleCompressor.compress(&data, &mut compressed);
+        // 600 zeros take three runs, 255 bytes one.
+        assert_eq!(compressed.len(), 2 * (3 + 3 + 1));
+        let mut out = Vec::new();
+        RleCompressor
+            .decompress(&compressed, data.len(), &mut out)
+            .unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn rle_rejects_malformed_data() {
+        let mut out = Vec::new();
+        assert_eq!(
+            RleCompressor.decompress(&[3, 1, 2], 16, &mut out),
+            Err(AxError::InvalidData)
+        );
+        assert_eq!(
+            RleCompressor.decompress(&[3, 1, 0, 2], 16, &mut out),
+            Err(AxError::InvalidData)
+        );
+    }
+
+    #[test]
+    fn rle_stops_at_the_output_limit() {
+        let mut out = Vec::new();
+        RleCompressor
+            .decompress(&[255, 0, 1, 1], 256, &mut out)
+            .unwrap();
+        assert_eq!(out.len(), 256);
+        out.clear();
+        assert_eq!(
+            RleCompressor.decompress(&[255, 0, 255, 0, 255, 0], 300, &mut out),
+            Err(AxError::InvalidData)
+        );
+        assert_eq!(out.len(), 255);
+    }
+}
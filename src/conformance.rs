@@ -0,0 +1,296 @@
+//! A conformance suite for [`AxArchVCpu`] implementations.
+//!
+//! Authors of architecture-specific vcpus can run [`run`] (e.g., from a test on the target) to verify that their
+//! implementation satisfies the contract of the trait as relied upon by [`AxVCpu`]:
+//!
+//! ```ignore
+//! let config = ConformanceConfig::new(|| MyCreateConfig::default(), || MySetupConfig::default(), entry, ept_root);
+//! let report = axvcpu::conformance::run::<MyArchVCpu>(&config);
+//! assert!(report.is_success(), "{:?}", report.failures().collect::<Vec<_>>());
+//! ```
+//!
+//! The checks create, set up, bind and unbind vcpus on the current physical CPU, but never enter the guest.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use axaddrspace::{GuestPhysAddr, HostPhysAddr};
+
+use crate::{AxArchVCpu, AxVCpu, InterceptConfig, VCpuCapabilities, VCpuState};
+
+/// The configuration of the vcpus created by [`run`].
+pub struct ConformanceConfig<A: AxArchVCpu> {
+    /// The factory of the configuration passed to [`AxArchVCpu::new`].
+    create: Box<dyn Fn() -> A::CreateConfig>,
+    /// The factory of the configuration passed to [`AxArchVCpu::setup`].
+    setup: Box<dyn Fn() -> A::SetupConfig>,
+    /// The entry of the vcpus.
+    entry: GuestPhysAddr,
+    /// The root of the nested page table of the vcpus.
+    ept_root: HostPhysAddr,
+}
+
+impl<A: AxArchVCpu> ConformanceConfig<A> {
+    /// Create the configuration. Each check creates fresh vcpus, so the configurations are produced by factories.
+    pub fn new(
+        create: impl Fn() -> A::CreateConfig + 'static,
+        setup: impl Fn() -> A::SetupConfig + 'static,
+        entry: GuestPhysAddr,
+        ept_root: HostPhysAddr,
+    ) -> Self {
+        Self {
+            create: Box::new(create),
+            setup: Box::new(setup),
+            entry,
+            ept_root,
+        }
+    }
+
+    /// Create a vcpu.
+    fn create(&self) -> Result<AxVCpu<A>, String> {
+        AxVCpu::new(0, 0, None, (self.create)()).map_err(|err| format!("create failed: {:?}", err))
+    }
+
+    /// Create and set up a vcpu.
+    fn create_setup(&self) -> Result<AxVCpu<A>, String> {
+        let vcpu = self.create()?;
+        vcpu.setup(self.entry, self.ept_root, (self.setup)())
+            .map_err(|err| format!("setup failed: {:?}", err))?;
+        Ok(vcpu)
+    }
+}
+
+/// The outcome of a check of [`run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceResult {
+    /// The name of the check.
+    pub name: &'static str,
+    /// `Ok` if the check passed, or the description of the failure.
+    pub outcome: Result<(), String>,
+}
+
+/// The outcomes of all checks of [`run`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    /// The outcomes, in the order the checks ran.
+    pub results: Vec<ConformanceResult>,
+}
+
+impl ConformanceReport {
+    /// Whether all checks passed.
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(|result| result.outcome.is_ok())
+    }
+
+    /// Get the failed checks with the descriptions of the failures.
+    pub fn failures(&self) -> impl Iterator<Item = (&'static str, &str)> + '_ {
+        self.results
+            .iter()
+            .filter_map(|result| match &result.outcome {
+                Ok(()) => None,
+                Err(msg) => Some((result.name, msg.as_str())),
+            })
+    }
+}
+
+/// A check of the suite.
+type Check<A> = fn(&ConformanceConfig<A>) -> Result<(), String>;
+
+/// Fail with `msg` unless `cond` holds.
+fn ensure(cond: bool, msg: &str) -> Result<(), String> {
+    if cond { Ok(()) } else { Err(String::from(msg)) }
+}
+
+/// Run the conformance suite against the architecture-specific vcpu `A`.
+pub fn run<A: AxArchVCpu>(config: &ConformanceConfig<A>) -> ConformanceReport {
+    let checks: [(&'static str, Check<A>); 8] = [
+        ("lifecycle_ordering", lifecycle_ordering),
+        ("out_of_order_calls_rejected", out_of_order_calls_rejected),
+        ("set_entry_after_setup", set_entry_after_setup),
+        ("injection_while_unbound", injection_while_unbound),
+        ("error_propagation", error_propagation),
+        ("reg_names_roundtrip", reg_names_roundtrip),
+        (
+            "protection_requires_confidential",
+            protection_requires_confidential,
+        ),
+        ("ext_state_size_stable", ext_state_size_stable),
+    ];
+    ConformanceReport {
+        results: checks
+            .into_iter()
+            .map(|(name, check)| ConformanceResult {
+                name,
+                outcome: check(config),
+            })
+            .collect(),
+    }
+}
+
+/// Create, set up, bind and unbind a vcpu, checking the state after each step.
+fn lifecycle_ordering<A: AxArchVCpu>(config: &ConformanceConfig<A>) -> Result<(), String> {
+    let vcpu = config.create()?;
+    ensure(vcpu.state() == VCpuState::Created, "not Created after new")?;
+    vcpu.setup(config.entry, config.ept_root, (config.setup)())
+        .map_err(|err| format!("setup failed: {:?}", err))?;
+    ensure(vcpu.state() == VCpuState::Free, "not Free after setup")?;
+    let token = vcpu
+        .bind()
+        .map_err(|err| format!("bind failed: {:?}", err))?;
+    ensure(vcpu.state() == VCpuState::Ready, "not Ready after bind")?;
+    vcpu.unbind(token)
+        .map_err(|err| format!("unbind failed: {:?}", err))?;
+    ensure(vcpu.state() == VCpuState::Free, "not Free after unbind")?;
+    let token = vcpu
+        .bind()
+        .map_err(|err| format!("rebind failed: {:?}", err))?;
+    vcpu.unbind(token)
+        .map_err(|err| format!("second unbind failed: {:?}", err))
+}
+
+/// Binding before setup, and setting up twice, must be rejected.
+fn out_of_order_calls_rejected<A: AxArchVCpu>(config: &ConformanceConfig<A>) -> Result<(), String> {
+    let vcpu = config.create()?;
+    ensure(vcpu.bind().is_err(), "bind before setup accepted")?;
+    let vcpu = config.create_setup()?;
+    let second = vcpu.setup(config.entry, config.ept_root, (config.setup)());
+    ensure(second.is_err(), "second setup accepted")
+}
+
+/// The entry may be changed after setup, both unbound and bound.
+fn set_entry_after_setup<A: AxArchVCpu>(config: &ConformanceConfig<A>) -> Result<(), String> {
+    let vcpu = config.create_setup()?;
+    if vcpu.is_protected() {
+        return Ok(());
+    }
+    vcpu.set_entry(config.entry)
+        .map_err(|err| format!("set_entry after setup failed: {:?}", err))?;
+    let token = vcpu
+        .bind()
+        .map_err(|err| format!("bind failed: {:?}", err))?;
+    vcpu.set_entry(config.entry)
+        .map_err(|err| format!("set_entry while bound failed: {:?}", err))?;
+    vcpu.unbind(token)
+        .map_err(|err| format!("unbind failed: {:?}", err))
+}
+
+/// Interrupts injected while the vcpu is unbound are queued, and survive binding and unbinding.
+fn injection_while_unbound<A: AxArchVCpu>(config: &ConformanceConfig<A>) -> Result<(), String> {
+    let vcpu = config.create_setup()?;
+    vcpu.inject_interrupt(0)
+        .map_err(|err| format!("injection while unbound failed: {:?}", err))?;
+    ensure(vcpu.pending_interrupts() == 1, "interrupt not queued")?;
+    let token = vcpu
+        .bind()
+        .map_err(|err| format!("bind failed: {:?}", err))?;
+    ensure(vcpu.pending_interrupts() == 1, "interrupt lost by bind")?;
+    vcpu.unbind(token)
+        .map_err(|err| format!("unbind failed: {:?}", err))?;
+    ensure(vcpu.pending_interrupts() == 1, "interrupt lost by unbind")
+}
+
+/// Optional operations fail with errors instead of panicking, without breaking the vcpu, and invalid state data is
+/// rejected.
+fn error_propagation<A: AxArchVCpu>(config: &ConformanceConfig<A>) -> Result<(), String> {
+    let vcpu = config.create_setup()?;
+    let _ = vcpu.configure_intercepts(InterceptConfig::new());
+    ensure(
+        vcpu.state() == VCpuState::Free,
+        "intercept configuration broke the vcpu",
+    )?;
+    let restored = vcpu.with_arch(|arch_vcpu| arch_vcpu.restore_state(&[]));
+    ensure(
+        restored.is_err(),
+        "empty state data accepted by restore_state",
+    )?;
+    let token = vcpu
+        .bind()
+        .map_err(|err| format!("bind after errors failed: {:?}", err))?;
+    vcpu.unbind(token)
+        .map_err(|err| format!("unbind failed: {:?}", err))
+}
+
+/// Register names and indices map back and forth.
+fn reg_names_roundtrip<A: AxArchVCpu>(_config: &ConformanceConfig<A>) -> Result<(), String> {
+    for (index, name) in (0..).map_while(|index| Some((index, A::reg_name(index)?))) {
+        if A::reg_index(name) != Some(index) {
+            return Err(format!("register {} doesn't map back to {}", name, index));
+        }
+    }
+    Ok(())
+}
+
+/// Only confidential vcpus may protect the register state of the guest.
+fn protection_requires_confidential<A: AxArchVCpu>(
+    config: &ConformanceConfig<A>,
+) -> Result<(), String> {
+    let vcpu = config.create_setup()?;
+    ensure(
+        !vcpu.is_protected() || vcpu.capabilities().contains(VCpuCapabilities::CONFIDENTIAL),
+        "protected without the CONFIDENTIAL capability",
+    )
+}
+
+/// The size of the extended state doesn't change after setup.
+fn ext_state_size_stable<A: AxArchVCpu>(config: &ConformanceConfig<A>) -> Result<(), String> {
+    let vcpu = config.create_setup()?;
+    let size = vcpu
+        .read_arch_vcpu(A::ext_state_size)
+        .map_err(|err| format!("read failed: {:?}", err))?;
+    let token = vcpu
+        .bind()
+        .map_err(|err| format!("bind failed: {:?}", err))?;
+    let bound_size = vcpu
+        .read_arch_vcpu(A::ext_state_size)
+        .map_err(|err| format!("read failed: {:?}", err))?;
+    vcpu.unbind(token)
+        .map_err(|err| format!("unbind failed: {:?}", err))?;
+    ensure(size == bound_size, "extended state size changed after bind")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MockArchVCpu, MockConfig, serial};
+
+    /// The configuration of the suite for the mock vcpu created with `mock`.
+    fn mock_config(mock: MockConfig) -> ConformanceConfig<MockArchVCpu> {
+        ConformanceConfig::new(
+            move || mock,
+            || (),
+            GuestPhysAddr::from(0x8000),
+            HostPhysAddr::from(0),
+        )
+    }
+
+    #[test]
+    fn mock_passes() {
+        let _serial = serial();
+        let report = run(&mock_config(MockConfig::default()));
+        assert_eq!(report.results.len(), 8);
+        assert!(
+            report.is_success(),
+            "{:?}",
+            report.failures().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn broken_mocks_fail() {
+        let _serial = serial();
+        let report = run(&mock_config(MockConfig {
+            unstable_ext_state: true,
+            ..MockConfig::default()
+        }));
+        let failures: Vec<_> = report.failures().map(|(name, _)| name).collect();
+        assert_eq!(failures, ["ext_state_size_stable"]);
+
+        let report = run(&mock_config(MockConfig {
+            protected: true,
+            ..MockConfig::default()
+        }));
+        let failures: Vec<_> = report.failures().map(|(name, _)| name).collect();
+        assert_eq!(failures, ["protection_requires_confidential"]);
+    }
+}
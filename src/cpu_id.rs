@@ -0,0 +1,279 @@
+use alloc::vec::Vec;
+
+/// The convention of architectural CPU ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchIdScheme {
+    /// x86 APIC IDs, with the thread, core, cluster (module) and socket indices packed in bit fields just wide
+    /// enough for each level.
+    X86ApicId,
+    /// Aarch64 MPIDR affinity fields. Without SMT, `Aff0` is the core, `Aff1` the cluster and `Aff2` the socket.
+    /// With SMT, `Aff0` is the thread, `Aff1` the core, `Aff2` the cluster, `Aff3` the socket, and the `MT` bit is set.
+    Aarch64Mpidr,
+    /// RISC-V hartids, equal to the vcpu ids.
+    RiscvHartId,
+}
+
+impl ArchIdScheme {
+    /// The scheme of the current architecture.
+    pub const fn current() -> Self {
+        if cfg!(target_arch = "x86_64") {
+            Self::X86ApicId
+        } else if cfg!(target_arch = "aarch64") {
+            Self::Aarch64Mpidr
+        } else {
+            Self::RiscvHartId
+        }
+    }
+}
+
+/// The position of a vcpu in the CPU topology of a VM.
+///
+/// Architecture-specific vcpus receive it through [`VCpuCreateContext`](crate::VCpuCreateContext), so that they can
+/// synthesize topology registers and CPUID leaves consistent with the architectural id of the vcpu.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VCpuTopology {
+    /// The index of the socket.
+    pub socket: usize,
+    /// The index of the cluster (module in x86) in the socket.
+    pub cluster: usize,
+    /// The index of the core in the cluster.
+    pub core: usize,
+    /// The index of the thread in the core.
+    pub thread: usize,
+}
+
+impl VCpuTopology {
+    /// The position of a vcpu in a flat topology, where every vcpu is a single-threaded core in one cluster of one
+    /// socket.
+    pub const fn flat(vcpu_id: usize) -> Self {
+        Self {
+            socket: 0,
+            cluster: 0,
+            core: vcpu_id,
+            thread: 0,
+        }
+    }
+}
+
+/// The shape of the CPU topology of a VM. Every level has at least one element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuTopologyShape {
+    /// The number of sockets.
+    pub sockets: usize,
+    /// The number of clusters (modules in x86) per socket.
+    pub clusters_per_socket: usize,
+    /// The number of cores per cluster.
+    pub cores_per_cluster: usize,
+    /// The number of threads per core.
+    pub threads_per_core: usize,
+}
+
+impl CpuTopologyShape {
+    /// A flat topology of `cpus` single-threaded cores in one cluster of one socket.
+    pub const fn flat(cpus: usize) -> Self {
+        Self {
+            sockets: 1,
+            clusters_per_socket: 1,
+            cores_per_cluster: cpus,
+            threads_per_core: 1,
+        }
+    }
+
+    /// The total number of CPUs.
+    pub const fn cpus(&self) -> usize {
+        self.sockets * self.clusters_per_socket * self.cores_per_cluster * self.threads_per_core
+    }
+
+    /// Split a linear vcpu id into its position in the topology.
+    pub const fn split(&self, vcpu_id: usize) -> VCpuTopology {
+        let thread = vcpu_id % self.threads_per_core;
+        let core = vcpu_id / self.threads_per_core % self.cores_per_cluster;
+        let cluster =
+            vcpu_id / (self.threads_per_core * self.cores_per_cluster) % self.clusters_per_socket;
+        let socket =
+            vcpu_id / (self.threads_per_core * self.cores_per_cluster * self.clusters_per_socket);
+        VCpuTopology {
+            socket,
+            cluster,
+            core,
+            thread,
+        }
+    }
+}
+
+/// The number of bits needed to represent `count` different values.
+const fn field_bits(count: usize) -> u32 {
+    if count <= 1 {
+        0
+    } else {
+        usize::BITS - (count - 1).leading_zeros()
+    }
+}
+
+/// Assigns architectural CPU ids (APIC IDs, MPIDR affinities, hartids) to vcpus according to a topology.
+///
+/// The ids are meant to be passed to [`AxVCpuBuilder::arch_cpu_id`](crate::AxVCpuBuilder::arch_cpu_id), so that
+/// they can be used to route [`AxVCpuExitReason::CpuUp`](crate::AxVCpuExitReason::CpuUp) and interrupts (see
+/// [`AxVCpuGroup::vcpu_by_arch_id`](crate::AxVCpuGroup::vcpu_by_arch_id)), and obtained by architecture-specific
+/// vcpus through [`get_current_vcpu`](crate::get_current_vcpu).
+#[derive(Debug, Clone)]
+pub struct CpuIdMap {
+    /// The convention of the ids.
+    scheme: ArchIdScheme,
+    /// The shape of the topology.
+    shape: CpuTopologyShape,
+    /// The id of each vcpu, indexed by vcpu id.
+    ids: Vec<u64>,
+}
+
+impl CpuIdMap {
+    /// Assign ids to all vcpus of the given topology.
+    pub fn new(scheme: ArchIdScheme, shape: CpuTopologyShape) -> Self {
+        let ids = (0..shape.cpus())
+            .map(|vcpu_id| Self::compute(scheme, &shape, vcpu_id))
+            .collect();
+        Self { scheme, shape, ids }
+    }
+
+    /// Compute the id of a vcpu.
+    fn compute(scheme: ArchIdScheme, shape: &CpuTopologyShape, vcpu_id: usize) -> u64 {
+        let topo = shape.split(vcpu_id);
+        let (socket, cluster, core, thread) = (
+            topo.socket as u64,
+            topo.cluster as u64,
+            topo.core as u64,
+            topo.thread as u64,
+        );
+        match scheme {
+            ArchIdScheme::X86ApicId => {
+                let thread_bits = field_bits(shape.threads_per_core);
+                let core_bits = field_bits(shape.cores_per_cluster);
+                let cluster_bits = field_bits(shape.clusters_per_socket);
+                (socket << (thread_bits + core_bits + cluster_bits))
+                    | (cluster << (thread_bits + core_bits))
+                    | (core << thread_bits)
+                    | thread
+            }
+            ArchIdScheme::Aarch64Mpidr if shape.threads_per_core > 1 => {
+                const MPIDR_MT: u64 = 1 << 24;
+                (socket << 32) | (cluster << 16) | (core << 8) | thread | MPIDR_MT
+            }
+            ArchIdScheme::Aarch64Mpidr => (socket << 16) | (cluster << 8) | core,
+            ArchIdScheme::RiscvHartId => vcpu_id as u64,
+        }
+    }
+
+    /// Get the convention of the ids.
+    pub const fn scheme(&self) -> ArchIdScheme {
+        self.scheme
+    }
+
+    /// Get the shape of the topology.
+    pub const fn shape(&self) -> &CpuTopologyShape {
+        &self.shape
+    }
+
+    /// Get the architectural id of a vcpu.
+    pub fn arch_id(&self, vcpu_id: usize) -> Option<u64> {
+        self.ids.get(vcpu_id).copied()
+    }
+
+    /// Get the position of a vcpu in the topology.
+    pub fn topology(&self, vcpu_id: usize) -> Option<VCpuTopology> {
+        (vcpu_id < self.ids.len()).then(|| self.shape.split(vcpu_id))
+    }
+
+    /// Get the vcpu id of an architectural id.
+    pub fn vcpu_id(&self, arch_id: u64) -> Option<usize> {
+        self.ids.iter().position(|&id| id == arch_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_bits_fit_the_count() {
+        assert_eq!(field_bits(0), 0);
+        assert_eq!(field_bits(1), 0);
+        assert_eq!(field_bits(2), 1);
+        assert_eq!(field_bits(3), 2);
+        assert_eq!(field_bits(4), 2);
+        assert_eq!(field_bits(5), 3);
+    }
+
+    #[test]
+    fn split_is_thread_major() {
+        let shape = CpuTopologyShape {
+            sockets: 2,
+            clusters_per_socket: 2,
+            cores_per_cluster: 3,
+            threads_per_core: 2,
+        };
+        assert_eq!(shape.cpus(), 24);
+        assert_eq!(shape.split(0), VCpuTopology::default());
+        assert_eq!(
+            shape.split(19),
+            VCpuTopology {
+                socket: 1,
+                cluster: 1,
+                core: 0,
+                thread: 1,
+            }
+        );
+        assert_eq!(CpuTopologyShape::flat(4).split(3), VCpuTopology::flat(3));
+    }
+
+    #[test]
+    fn x86_apic_ids_pack_the_fields() {
+        let map = CpuIdMap::new(
+            ArchIdScheme::X86ApicId,
+            CpuTopologyShape {
+                sockets: 2,
+                clusters_per_socket: 1,
+                cores_per_cluster: 3,
+                threads_per_core: 2,
+            },
+        );
+        // One thread bit and two core bits, so the socket starts at bit 3.
+        assert_eq!(map.arch_id(5), Some(0b101));
+        assert_eq!(map.arch_id(6), Some(0b1000));
+        assert_eq!(map.arch_id(11), Some(0b1101));
+        assert_eq!(map.arch_id(12), None);
+        assert_eq!(map.vcpu_id(0b1000), Some(6));
+        // The fourth core of a socket doesn't exist.
+        assert_eq!(map.vcpu_id(0b110), None);
+    }
+
+    #[test]
+    fn aarch64_mpidrs_shift_with_smt() {
+        let shape = CpuTopologyShape {
+            sockets: 2,
+            clusters_per_socket: 2,
+            cores_per_cluster: 4,
+            threads_per_core: 1,
+        };
+        let map = CpuIdMap::new(ArchIdScheme::Aarch64Mpidr, shape);
+        assert_eq!(map.arch_id(13), Some(0x1_0101));
+
+        let shape = CpuTopologyShape {
+            sockets: 1,
+            clusters_per_socket: 2,
+            cores_per_cluster: 2,
+            threads_per_core: 2,
+        };
+        let map = CpuIdMap::new(ArchIdScheme::Aarch64Mpidr, shape);
+        assert_eq!(map.arch_id(7), Some(0x101_0101));
+        assert_eq!(map.vcpu_id(0x100_0000), Some(0));
+    }
+
+    #[test]
+    fn riscv_hartids_are_vcpu_ids() {
+        let map = CpuIdMap::new(ArchIdScheme::RiscvHartId, CpuTopologyShape::flat(4));
+        assert_eq!(map.arch_id(3), Some(3));
+        assert_eq!(map.vcpu_id(2), Some(2));
+        assert_eq!(map.topology(3), Some(VCpuTopology::flat(3)));
+        assert_eq!(map.topology(4), None);
+    }
+}
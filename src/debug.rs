@@ -0,0 +1,416 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+use axaddrspace::{GuestPhysAddr, GuestVirtAddr};
+use axerrno::{AxResult, ax_err};
+
+use crate::{AxVCpuExitReason, ExitKindSet};
+
+/// The handler invoked when an exit matches the filter set by
+/// [`AxVCpu::set_exit_filter`](crate::AxVCpu::set_exit_filter).
+pub trait ExitBreakpointHandler: Send + Sync {
+    /// Called inside [`AxVCpu::run`](crate::AxVCpu::run) with the matching exit, before it's handled by anything
+    /// else. The vcpu stays paused (out of the guest) until this method returns, so a debugger can inspect it.
+    fn on_exit_break(&self, vcpu_id: usize, exit: &AxVCpuExitReason);
+}
+
+/// A filter selecting exits to break on, e.g., "MMIO writes to this range".
+///
+/// An exit matches if its kind is in the set, and it satisfies the predicates applying to it: the guest physical
+/// address range applies to exits carrying a guest physical address (MMIO accesses, nested page faults,
+/// introspection exits, IOMMU faults), and the port range applies to port I/O exits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitFilter {
+    /// The kinds of exits to break on.
+    kinds: ExitKindSet,
+    /// The guest physical address range to break on, if any.
+    gpa_range: Option<Range<GuestPhysAddr>>,
+    /// The port range to break on, if any.
+    port_range: Option<Range<u16>>,
+}
+
+impl ExitFilter {
+    /// Create a filter matching all exits of the given kinds.
+    pub const fn new(kinds: ExitKindSet) -> Self {
+        Self {
+            kinds,
+            gpa_range: None,
+            port_range: None,
+        }
+    }
+
+    /// Only match exits accessing guest physical addresses within `range`.
+    pub fn gpa_range(mut self, range: Range<GuestPhysAddr>) -> Self {
+        self.gpa_range = Some(range);
+        self
+    }
+
+    /// Only match port I/O exits accessing ports within `range`.
+    pub fn port_range(mut self, range: Range<u16>) -> Self {
+        self.port_range = Some(range);
+        self
+    }
+
+    /// Whether `exit` matches the filter.
+    pub fn matches(&self, exit: &AxVCpuExitReason) -> bool {
+        if !self.kinds.contains(exit.kind()) {
+            return false;
+        }
+        match exit {
+            AxVCpuExitReason::MmioRead { addr, .. }
+            | AxVCpuExitReason::MmioWrite { addr, .. }
+            | AxVCpuExitReason::UnhandledMmio { addr, .. }
+            | AxVCpuExitReason::CacheMaintenance { addr, .. }
+            | AxVCpuExitReason::NestedPageFault { addr, .. }
+            | AxVCpuExitReason::Introspection { addr, .. }
+            | AxVCpuExitReason::IommuFault { addr, .. } => self
+                .gpa_range
+                .as_ref()
+                .is_none_or(|range| range.contains(addr)),
+            AxVCpuExitReason::IoRead { port, .. } | AxVCpuExitReason::IoWrite { port, .. } => self
+                .port_range
+                .as_ref()
+                .is_none_or(|range| range.contains(port)),
+            _ => true,
+        }
+    }
+}
+
+impl From<ExitKindSet> for ExitFilter {
+    fn from(kinds: ExitKindSet) -> Self {
+        Self::new(kinds)
+    }
+}
+
+/// A resolver of guest symbols, registered by the VMM (e.g., from the symbol table of the guest kernel) with
+/// [`AxVCpu::set_symbol_resolver`](crate::AxVCpu::set_symbol_resolver), so that reports about a guest (crash
+/// reports, core dumps, stall warnings) can annotate guest PCs with function names.
+pub trait GuestSymbolResolver: Send + Sync {
+    /// Resolve `pc` to the name of the symbol containing it and the offset of `pc` from the start of the symbol.
+    fn resolve(&self, pc: GuestVirtAddr) -> Option<(&str, usize)>;
+}
+
+/// A guest PC annotated with its symbol, displayed as `0x1234 <func+0x10>`, or just `0x1234` if it can't be
+/// resolved. Obtained by [`AxVCpu::symbolize`](crate::AxVCpu::symbolize).
+pub struct SymbolizedPc {
+    /// The guest PC.
+    pub(crate) pc: GuestVirtAddr,
+    /// The resolver, if any.
+    pub(crate) resolver: Option<Arc<dyn GuestSymbolResolver>>,
+}
+
+impl fmt::Display for SymbolizedPc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.pc.as_usize())?;
+        match self
+            .resolver
+            .as_ref()
+            .and_then(|resolver| resolver.resolve(self.pc))
+        {
+            Some((name, 0)) => write!(f, " <{}>", name),
+            Some((name, offset)) => write!(f, " <{}+{:#x}>", name, offset),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Access to the memory of the guest, provided by the VMM (which owns the address space of the VM) to
+/// [`BreakpointManager`] for patching breakpoint instructions.
+pub trait GuestMemoryAccess: Send + Sync {
+    /// Read `buf.len()` bytes of guest memory at `addr`.
+    fn read(&self, addr: GuestPhysAddr, buf: &mut [u8]) -> AxResult;
+    /// Write `buf` to guest memory at `addr`.
+    fn write(&self, addr: GuestPhysAddr, buf: &[u8]) -> AxResult;
+}
+
+/// The accesses a [`HwWatchpoint`] triggers on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchpointKind {
+    /// Instruction fetches, i.e., a hardware breakpoint.
+    Execute,
+    /// Data reads.
+    Read,
+    /// Data writes.
+    Write,
+    /// Data reads and writes.
+    Access,
+}
+
+/// A hardware watchpoint (or breakpoint), backed by the debug registers of the architecture-specific vcpu, see
+/// [`AxVCpu::add_watchpoint`](crate::AxVCpu::add_watchpoint).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HwWatchpoint {
+    /// The guest virtual address watched.
+    pub addr: GuestVirtAddr,
+    /// The number of bytes watched, subject to the alignment and size constraints of the architecture.
+    pub len: usize,
+    /// The accesses watched.
+    pub kind: WatchpointKind,
+}
+
+/// The breakpoints and watchpoints of a vcpu, used by the gdbstub integration. Obtained with
+/// [`AxVCpu::breakpoint_manager`](crate::AxVCpu::breakpoint_manager).
+///
+/// Software breakpoints are set by replacing the instruction at a guest physical address with the breakpoint
+/// instruction of the architecture, saving the original bytes to restore them on removal. Hardware watchpoints
+/// occupy the debug register slots of the architecture-specific vcpu, and are set through
+/// [`AxVCpu::add_watchpoint`](crate::AxVCpu::add_watchpoint).
+#[derive(Debug, Clone, Default)]
+pub struct BreakpointManager {
+    /// The software breakpoints, with the original bytes of the patched instructions.
+    sw_breakpoints: BTreeMap<GuestPhysAddr, Vec<u8>>,
+    /// The hardware watchpoints, indexed by debug register slot.
+    watchpoints: Vec<Option<HwWatchpoint>>,
+}
+
+impl BreakpointManager {
+    /// Create an empty manager.
+    pub const fn new() -> Self {
+        Self {
+            sw_breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    /// Set a software breakpoint at `addr` by writing `insn`, the breakpoint instruction, over the original
+    /// instruction. Returns `AlreadyExists` if there is already one at `addr`.
+    pub fn add_sw_breakpoint(
+        &mut self,
+        mem: &dyn GuestMemoryAccess,
+        addr: GuestPhysAddr,
+        insn: &[u8],
+    ) -> AxResult {
+        if self.sw_breakpoints.contains_key(&addr) {
+            return ax_err!(AlreadyExists, "software breakpoint already set");
+        }
+        let mut original = vec![0; insn.len()];
+        mem.read(addr, &mut original)?;
+        mem.write(addr, insn)?;
+        self.sw_breakpoints.insert(addr, original);
+        Ok(())
+    }
+
+    /// Remove the software breakpoint at `addr`, restoring the original instruction. Returns `NotFound` if there
+    /// is none.
+    pub fn remove_sw_breakpoint(
+        &mut self,
+        mem: &dyn GuestMemoryAccess,
+        addr: GuestPhysAddr,
+    ) -> AxResult {
+        let Some(original) = self.sw_breakpoints.get(&addr) else {
+            return ax_err!(NotFound, "software breakpoint not set");
+        };
+        mem.write(addr, original)?;
+        self.sw_breakpoints.remove(&addr);
+        Ok(())
+    }
+
+    /// Remove all software breakpoints, e.g., when the debugger detaches.
+    pub fn clear_sw_breakpoints(&mut self, mem: &dyn GuestMemoryAccess) -> AxResult {
+        while let Some((&addr, _)) = self.sw_breakpoints.first_key_value() {
+            self.remove_sw_breakpoint(mem, addr)?;
+        }
+        Ok(())
+    }
+
+    /// Get the addresses of the software breakpoints, in ascending order.
+    pub fn sw_breakpoints(&self) -> impl Iterator<Item = GuestPhysAddr> + '_ {
+        self.sw_breakpoints.keys().copied()
+    }
+
+    /// Get the original bytes of the instruction patched by the software breakpoint at `addr`, so that memory
+    /// reads of the debugger can hide the breakpoint.
+    pub fn original_bytes(&self, addr: GuestPhysAddr) -> Option<&[u8]> {
+        self.sw_breakpoints.get(&addr).map(Vec::as_slice)
+    }
+
+    /// Get the hardware watchpoints with their debug register slots.
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &HwWatchpoint)> + '_ {
+        self.watchpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, watchpoint)| Some((slot, watchpoint.as_ref()?)))
+    }
+
+    /// Get the slot of a hardware watchpoint, if it's set.
+    pub(crate) fn watchpoint_slot(&self, watchpoint: &HwWatchpoint) -> Option<usize> {
+        self.watchpoints
+            .iter()
+            .position(|slot| slot.as_ref() == Some(watchpoint))
+    }
+
+    /// Get the first free slot among `slots` debug register slots.
+    pub(crate) fn free_watchpoint_slot(&self, slots: usize) -> Option<usize> {
+        (0..slots).find(|&slot| self.watchpoints.get(slot).is_none_or(Option::is_none))
+    }
+
+    /// Record the hardware watchpoint of a slot.
+    pub(crate) fn set_watchpoint(&mut self, slot: usize, watchpoint: Option<HwWatchpoint>) {
+        if self.watchpoints.len() <= slot {
+            self.watchpoints.resize(slot + 1, None);
+        }
+        self.watchpoints[slot] = watchpoint;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use std::sync::Mutex;
+
+    use axerrno::AxError;
+
+    use super::*;
+    use crate::{AccessWidth, ExitKind};
+
+    /// Guest memory of 64 bytes at address 0, failing writes to the last 16 bytes.
+    struct Memory(Mutex<[u8; 64]>);
+
+    impl Memory {
+        fn new() -> Self {
+            Self(Mutex::new(core::array::from_fn(|i| i as u8)))
+        }
+    }
+
+    impl GuestMemoryAccess for Memory {
+        fn read(&self, addr: GuestPhysAddr, buf: &mut [u8]) -> AxResult {
+            let addr = addr.as_usize();
+            buf.copy_from_slice(&self.0.lock().unwrap()[addr..addr + buf.len()]);
+            Ok(())
+        }
+
+        fn write(&self, addr: GuestPhysAddr, buf: &[u8]) -> AxResult {
+            let addr = addr.as_usize();
+            if addr + buf.len() > 48 {
+                return ax_err!(PermissionDenied);
+            }
+            self.0.lock().unwrap()[addr..addr + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    const BRK: [u8; 4] = [0x00, 0x00, 0x20, 0xd4];
+
+    #[test]
+    fn sw_breakpoints_patch_and_restore() {
+        let mem = Memory::new();
+        let mut manager = BreakpointManager::new();
+        let addr = GuestPhysAddr::from(8);
+        manager.add_sw_breakpoint(&mem, addr, &BRK).unwrap();
+        assert_eq!(
+            manager.add_sw_breakpoint(&mem, addr, &BRK),
+            Err(AxError::AlreadyExists)
+        );
+        assert_eq!(mem.0.lock().unwrap()[8..12], BRK);
+        assert_eq!(manager.original_bytes(addr), Some(&[8, 9, 10, 11][..]));
+
+        manager
+            .add_sw_breakpoint(&mem, GuestPhysAddr::from(0), &BRK)
+            .unwrap();
+        assert!(manager.sw_breakpoints().eq([GuestPhysAddr::from(0), addr]));
+        manager.remove_sw_breakpoint(&mem, addr).unwrap();
+        assert_eq!(
+            manager.remove_sw_breakpoint(&mem, addr),
+            Err(AxError::NotFound)
+        );
+        manager.clear_sw_breakpoints(&mem).unwrap();
+        assert_eq!(manager.sw_breakpoints().count(), 0);
+        assert_eq!(
+            *mem.0.lock().unwrap(),
+            Memory::new().0.into_inner().unwrap()
+        );
+    }
+
+    #[test]
+    fn failed_patch_sets_no_breakpoint() {
+        let mem = Memory::new();
+        let mut manager = BreakpointManager::new();
+        let addr = GuestPhysAddr::from(48);
+        assert_eq!(
+            manager.add_sw_breakpoint(&mem, addr, &BRK),
+            Err(AxError::PermissionDenied)
+        );
+        assert_eq!(manager.original_bytes(addr), None);
+    }
+
+    #[test]
+    fn watchpoints_take_free_slots() {
+        let mut manager = BreakpointManager::new();
+        let watchpoint = |addr: usize| HwWatchpoint {
+            addr: GuestVirtAddr::from(addr),
+            len: 8,
+            kind: WatchpointKind::Write,
+        };
+        assert_eq!(manager.free_watchpoint_slot(2), Some(0));
+        manager.set_watchpoint(1, Some(watchpoint(0x1000)));
+        assert_eq!(manager.free_watchpoint_slot(2), Some(0));
+        manager.set_watchpoint(0, Some(watchpoint(0x2000)));
+        assert_eq!(manager.free_watchpoint_slot(2), None);
+        assert_eq!(manager.watchpoint_slot(&watchpoint(0x1000)), Some(1));
+        manager.set_watchpoint(1, None);
+        assert_eq!(manager.watchpoint_slot(&watchpoint(0x1000)), None);
+        assert_eq!(manager.free_watchpoint_slot(2), Some(1));
+        assert!(manager.watchpoints().eq([(0, &watchpoint(0x2000))]));
+    }
+
+    #[test]
+    fn filters_match_addresses_and_ports() {
+        let filter = ExitFilter::new(
+            ExitKindSet::empty()
+                .with(ExitKind::MmioWrite)
+                .with(ExitKind::IoRead),
+        )
+        .gpa_range(GuestPhysAddr::from(0x1000)..GuestPhysAddr::from(0x2000))
+        .port_range(0x3f8..0x400);
+        let mmio_write = |addr: usize| AxVCpuExitReason::MmioWrite {
+            addr: GuestPhysAddr::from(addr),
+            width: AccessWidth::Dword,
+            data: 0,
+        };
+        let io_read = |port| AxVCpuExitReason::IoRead {
+            port,
+            width: AccessWidth::Byte,
+        };
+        assert!(filter.matches(&mmio_write(0x1ffc)));
+        assert!(!filter.matches(&mmio_write(0x2000)));
+        assert!(filter.matches(&io_read(0x3f8)));
+        assert!(!filter.matches(&io_read(0x60)));
+        assert!(!filter.matches(&AxVCpuExitReason::Halt));
+        assert!(ExitFilter::from(ExitKindSet::all()).matches(&mmio_write(0x2000)));
+    }
+
+    struct Symbols;
+
+    impl GuestSymbolResolver for Symbols {
+        fn resolve(&self, pc: GuestVirtAddr) -> Option<(&str, usize)> {
+            let pc = pc.as_usize();
+            (0x1000..0x1100)
+                .contains(&pc)
+                .then_some(("start_kernel", pc - 0x1000))
+        }
+    }
+
+    #[test]
+    fn pcs_are_symbolized() {
+        let symbolize = |pc: usize, resolver: Option<Arc<dyn GuestSymbolResolver>>| {
+            SymbolizedPc {
+                pc: GuestVirtAddr::from(pc),
+                resolver,
+            }
+            .to_string()
+        };
+        assert_eq!(
+            symbolize(0x1000, Some(Arc::new(Symbols))),
+            "0x1000 <start_kernel>"
+        );
+        assert_eq!(
+            symbolize(0x1010, Some(Arc::new(Symbols))),
+            "0x1010 <start_kernel+0x10>"
+        );
+        assert_eq!(symbolize(0x2000, Some(Arc::new(Symbols))), "0x2000");
+        assert_eq!(symbolize(0x1010, None), "0x1010");
+    }
+}
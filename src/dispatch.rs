@@ -0,0 +1,79 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use axerrno::{AxResult, ax_err};
+
+use crate::{AxArchVCpu, AxVCpu, AxVCpuExitReason, ExitAction};
+
+/// A link of the exit handler chain of an [`ExitDispatcher`], e.g., a tracer, a fast-path device, or the fallback
+/// handler of the VMM.
+pub trait ExitMiddleware<A: AxArchVCpu> {
+    /// Handle an exit of `vcpu`: return `Some(action)` to consume the exit, or `None` to pass it along to the next
+    /// middleware of the chain.
+    fn handle(&mut self, vcpu: &AxVCpu<A>, exit: &AxVCpuExitReason)
+    -> AxResult<Option<ExitAction>>;
+}
+
+impl<A, F> ExitMiddleware<A> for F
+where
+    A: AxArchVCpu,
+    F: FnMut(&AxVCpu<A>, &AxVCpuExitReason) -> AxResult<Option<ExitAction>>,
+{
+    fn handle(
+        &mut self,
+        vcpu: &AxVCpu<A>,
+        exit: &AxVCpuExitReason,
+    ) -> AxResult<Option<ExitAction>> {
+        self(vcpu, exit)
+    }
+}
+
+/// A chain of [`ExitMiddleware`]s ordered by priority, used by
+/// [`AxVCpu::run_dispatch`](crate::AxVCpu::run_dispatch).
+///
+/// Each exit is passed to the middlewares from the highest priority to the lowest, until one of them consumes it.
+/// Middlewares of the same priority are called in the order they're registered.
+pub struct ExitDispatcher<A: AxArchVCpu> {
+    /// The middlewares with their priorities, sorted by descending priority.
+    chain: Vec<(i32, Box<dyn ExitMiddleware<A>>)>,
+}
+
+impl<A: AxArchVCpu> Default for ExitDispatcher<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: AxArchVCpu> ExitDispatcher<A> {
+    /// Create an empty dispatcher.
+    pub const fn new() -> Self {
+        Self { chain: Vec::new() }
+    }
+
+    /// Register a middleware with the given priority, higher runs first.
+    pub fn register(&mut self, priority: i32, middleware: impl ExitMiddleware<A> + 'static) {
+        let index = self.chain.partition_point(|&(p, _)| p >= priority);
+        self.chain.insert(index, (priority, Box::new(middleware)));
+    }
+
+    /// Get the number of registered middlewares.
+    pub fn len(&self) -> usize {
+        self.chain.len()
+    }
+
+    /// Whether no middleware is registered.
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty()
+    }
+
+    /// Pass an exit of `vcpu` along the chain until a middleware consumes it, returning the action of that
+    /// middleware. Returns `Unsupported` if no middleware consumes the exit.
+    pub fn dispatch(&mut self, vcpu: &AxVCpu<A>, exit: &AxVCpuExitReason) -> AxResult<ExitAction> {
+        for (_, middleware) in &mut self.chain {
+            if let Some(action) = middleware.handle(vcpu, exit)? {
+                return Ok(action);
+            }
+        }
+        ax_err!(Unsupported, format!("unhandled exit {}", exit.kind()))
+    }
+}
@@ -0,0 +1,86 @@
+use axaddrspace::GuestVirtAddr;
+
+/// The number of entries of a [`DecodeCache`] if not specified.
+pub const DECODE_CACHE_DEFAULT_ENTRIES: usize = 16;
+
+/// A small direct-mapped cache of decoded guest instructions, keyed by the guest PC.
+///
+/// Guests often poll device registers in tight loops, exiting repeatedly on the same instruction. Architecture-
+/// specific vcpus can keep one cache per vcpu (e.g., in [`AxVCpu::scratch`](crate::AxVCpu::scratch)) to skip
+/// fetching and decoding the faulting instruction again.
+///
+/// A cached decoding is only valid as long as the guest memory it was fetched from is unchanged. Every lookup
+/// takes the current [`AxVCpu::decode_generation`](crate::AxVCpu::decode_generation), and the whole cache is
+/// flushed when it changes, i.e., after stage-2 mappings or permissions are changed (see
+/// [`AxVCpu::invalidate_decode_cache`](crate::AxVCpu::invalidate_decode_cache)).
+#[derive(Debug, Clone)]
+pub struct DecodeCache<I: Copy, const N: usize = DECODE_CACHE_DEFAULT_ENTRIES> {
+    /// The cached decodings, indexed by a hash of the guest PC.
+    entries: [Option<(usize, I)>; N],
+    /// The generation the cached decodings belong to.
+    generation: u64,
+    /// The number of lookups that hit.
+    hits: u64,
+    /// The number of lookups that missed.
+    misses: u64,
+}
+
+impl<I: Copy, const N: usize> Default for DecodeCache<I, N> {
+    fn default() -> Self {
+        Self {
+            entries: [None; N],
+            generation: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+}
+
+impl<I: Copy, const N: usize> DecodeCache<I, N> {
+    /// Get the index of the entry for `pc`.
+    const fn index(pc: usize) -> usize {
+        // Instructions are at least 2-byte aligned on all supported architectures except x86.
+        (pc ^ (pc >> 1)) % N
+    }
+
+    /// Flush the cache if `generation` differs from the one of the cached decodings.
+    fn sync_generation(&mut self, generation: u64) {
+        if self.generation != generation {
+            self.invalidate();
+            self.generation = generation;
+        }
+    }
+
+    /// Look up the decoding of the instruction at `pc`.
+    pub fn lookup(&mut self, generation: u64, pc: GuestVirtAddr) -> Option<I> {
+        self.sync_generation(generation);
+        let pc = pc.as_usize();
+        match self.entries[Self::index(pc)] {
+            Some((cached_pc, insn)) if cached_pc == pc => {
+                self.hits += 1;
+                Some(insn)
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Cache the decoding of the instruction at `pc`, replacing the one sharing the same entry.
+    pub fn insert(&mut self, generation: u64, pc: GuestVirtAddr, insn: I) {
+        self.sync_generation(generation);
+        let pc = pc.as_usize();
+        self.entries[Self::index(pc)] = Some((pc, insn));
+    }
+
+    /// Drop all cached decodings.
+    pub fn invalidate(&mut self) {
+        self.entries = [None; N];
+    }
+
+    /// Get the number of lookups that hit and missed, respectively.
+    pub fn hit_stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+}
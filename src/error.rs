@@ -0,0 +1,214 @@
+use core::fmt;
+use core::panic::Location;
+
+use axerrno::AxError;
+
+use crate::{ExitKind, SandboxViolation, SnapshotIncompatibility, SnapshotSection, VirtHwFeatures};
+
+/// Errors specific to this crate, carrying more information than [`AxError`].
+///
+/// Every [`AxVCpuError`] can be converted into the closest [`AxError`], so that it can be propagated
+/// with `?` in functions returning [`AxResult`](axerrno::AxResult). Conversely, an [`AxError`] can be
+/// propagated in functions returning [`AxVCpuResult`] as [`AxVCpuError::Other`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AxVCpuError {
+    /// The requested number of guest page table levels is not supported by the hardware.
+    PageTableLevelsUnsupported {
+        /// The requested number of levels.
+        requested: usize,
+        /// The minimum number of levels supported by the hardware.
+        min: usize,
+        /// The maximum number of levels supported by the hardware.
+        max: usize,
+    },
+    /// The number of levels of the nested page table the vcpu is set up with differs from the negotiated one.
+    PageTableLevelsMismatch {
+        /// The negotiated number of levels.
+        expected: usize,
+        /// The number of levels the architecture-specific vcpu is set up with.
+        actual: usize,
+    },
+    /// The requested guest physical address size exceeds the hardware limit.
+    IpaSizeUnsupported {
+        /// The requested size in bits.
+        requested: u8,
+        /// The maximum size in bits supported by the hardware.
+        max: u8,
+    },
+    /// Some required hardware virtualization features are missing.
+    HardwareFeaturesMissing {
+        /// The missing features.
+        missing: VirtHwFeatures,
+    },
+    /// The vcpu is already being run by another host context.
+    AlreadyRunning,
+    /// The guest caused an exit denied by the [`ExitPolicy`](crate::ExitPolicy) of the vcpu.
+    ExitDenied {
+        /// The kind of the denied exit.
+        kind: ExitKind,
+    },
+    /// The sandboxed guest attempted a device access outside its allowlist and is terminated, see
+    /// [`AxVCpu::enable_sandbox`](crate::AxVCpu::enable_sandbox).
+    SandboxViolation(SandboxViolation),
+    /// The vcpu is run without being bound to a physical CPU, see [`AxVCpu::bind`](crate::AxVCpu::bind).
+    NotBound,
+    /// The vcpu is being bound to a physical CPU outside its `phys_cpu_set`.
+    AffinityViolation {
+        /// The id of the current physical CPU.
+        cpu_id: usize,
+        /// The set of physical CPUs allowed to run the vcpu.
+        allowed: usize,
+    },
+    /// A snapshot can't be restored on this host or by this version of the crate.
+    SnapshotIncompatible(SnapshotIncompatibility),
+    /// A section of an encoded snapshot doesn't match its checksum, e.g., because it's partially transferred.
+    SnapshotCorrupted {
+        /// The corrupted section.
+        section: SnapshotSection,
+    },
+    /// A generic error without more specific information.
+    Other(AxError),
+    /// The vcpu has used up its CPU quota of the current period, see
+    /// [`AxVCpu::set_cpu_quota`](crate::AxVCpu::set_cpu_quota).
+    Throttled {
+        /// The time until the next period starts, in nanoseconds.
+        resume_in_ns: u64,
+    },
+    /// The vcpu is being stopped, see [`AxVCpu::request_stop`](crate::AxVCpu::request_stop).
+    Stopped,
+    /// The vcpus of the VM are being kept outside guest mode, see
+    /// [`AxVCpuGroup::quiesce`](crate::AxVCpuGroup::quiesce).
+    Quiesced,
+    /// The guest attempted to make an immutable code region writable, and the vcpu is blocked until the violation
+    /// is cleared, see [`AxVCpu::protect_code_region`](crate::AxVCpu::protect_code_region).
+    IntegrityViolation,
+    /// The vcpu is accessed from a physical CPU other than the one it's bound to, see
+    /// [`AxVCpu::with_arch`](crate::AxVCpu::with_arch).
+    ForeignCpu {
+        /// The id of the current physical CPU.
+        cpu_id: usize,
+        /// The id of the physical CPU the vcpu is bound to.
+        bound_cpu: usize,
+    },
+    /// A vcpu operation is nested in another one on the same physical CPU, see
+    /// [`AxVCpu::with_current_cpu_set`](crate::AxVCpu::with_current_cpu_set).
+    NestedOperation {
+        /// The id of the VM of the vcpu already current.
+        vm_id: usize,
+        /// The id of the vcpu already current.
+        vcpu_id: usize,
+        /// The caller of the operation owning the current vcpu, if known.
+        owner: Option<&'static Location<'static>>,
+    },
+}
+
+impl fmt::Display for AxVCpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PageTableLevelsUnsupported {
+                requested,
+                min,
+                max,
+            } => write!(
+                f,
+                "{} guest page table levels requested, but only {}..={} are supported",
+                requested, min, max
+            ),
+            Self::PageTableLevelsMismatch { expected, actual } => write!(
+                f,
+                "vcpu is set up with {} nested page table levels, but {} are negotiated",
+                actual, expected
+            ),
+            Self::IpaSizeUnsupported { requested, max } => write!(
+                f,
+                "{}-bit guest physical address space requested, but only up to {} bits are supported",
+                requested, max
+            ),
+            Self::HardwareFeaturesMissing { missing } => {
+                write!(f, "missing hardware virtualization features: {:?}", missing)
+            }
+            Self::AlreadyRunning => write!(f, "vcpu is already running"),
+            Self::NotBound => write!(f, "vcpu is not bound to a physical CPU"),
+            Self::SandboxViolation(violation) => write!(
+                f,
+                "sandboxed guest terminated by a {} exit targeting {:#x}",
+                violation.kind, violation.target
+            ),
+            Self::ExitDenied { kind } => write!(f, "{} exit denied by the exit policy", kind),
+            Self::AffinityViolation { cpu_id, allowed } => write!(
+                f,
+                "vcpu cannot be bound to physical CPU {}, allowed set is {:#x}",
+                cpu_id, allowed
+            ),
+            Self::SnapshotIncompatible(reason) => write!(f, "{}", reason),
+            Self::SnapshotCorrupted { section } => {
+                write!(f, "snapshot section {} is corrupted", section.as_str())
+            }
+            Self::Other(err) => write!(f, "{}", err),
+            Self::Throttled { resume_in_ns } => {
+                write!(f, "vcpu quota exhausted, resuming in {} ns", resume_in_ns)
+            }
+            Self::Stopped => write!(f, "vcpu is being stopped"),
+            Self::Quiesced => write!(f, "vcpu is quiesced"),
+            Self::IntegrityViolation => write!(f, "vcpu is blocked by a code integrity violation"),
+            Self::ForeignCpu { cpu_id, bound_cpu } => write!(
+                f,
+                "vcpu is bound to physical CPU {}, but accessed from physical CPU {}",
+                bound_cpu, cpu_id
+            ),
+            Self::NestedOperation {
+                vm_id,
+                vcpu_id,
+                owner: Some(owner),
+            } => write!(
+                f,
+                "nested vcpu operation, vcpu {} of VM {} is already current (owned by {})",
+                vcpu_id, vm_id, owner
+            ),
+            Self::NestedOperation {
+                vm_id,
+                vcpu_id,
+                owner: None,
+            } => write!(
+                f,
+                "nested vcpu operation, vcpu {} of VM {} is already current",
+                vcpu_id, vm_id
+            ),
+        }
+    }
+}
+
+impl From<AxVCpuError> for AxError {
+    fn from(err: AxVCpuError) -> Self {
+        match err {
+            AxVCpuError::PageTableLevelsUnsupported { .. } => AxError::Unsupported,
+            AxVCpuError::PageTableLevelsMismatch { .. } => AxError::InvalidInput,
+            AxVCpuError::IpaSizeUnsupported { .. } => AxError::Unsupported,
+            AxVCpuError::HardwareFeaturesMissing { .. } => AxError::Unsupported,
+            AxVCpuError::AlreadyRunning => AxError::ResourceBusy,
+            AxVCpuError::NotBound => AxError::BadState,
+            AxVCpuError::SandboxViolation(_) => AxError::PermissionDenied,
+            AxVCpuError::ExitDenied { .. } => AxError::PermissionDenied,
+            AxVCpuError::AffinityViolation { .. } => AxError::BadState,
+            AxVCpuError::Throttled { .. } => AxError::WouldBlock,
+            AxVCpuError::Stopped => AxError::BadState,
+            AxVCpuError::Quiesced => AxError::WouldBlock,
+            AxVCpuError::IntegrityViolation => AxError::PermissionDenied,
+            AxVCpuError::ForeignCpu { .. } => AxError::BadState,
+            AxVCpuError::NestedOperation { .. } => AxError::BadState,
+            AxVCpuError::SnapshotIncompatible(_) => AxError::InvalidData,
+            AxVCpuError::SnapshotCorrupted { .. } => AxError::InvalidData,
+            AxVCpuError::Other(err) => err,
+        }
+    }
+}
+
+impl From<AxError> for AxVCpuError {
+    fn from(err: AxError) -> Self {
+        Self::Other(err)
+    }
+}
+
+/// A [`Result`] type with [`AxVCpuError`] as the error type.
+pub type AxVCpuResult<T = ()> = Result<T, AxVCpuError>;
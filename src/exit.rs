@@ -60,6 +60,65 @@ impl AccessWidth {
     }
 }
 
+/// The kind of guest memory region a nested page fault happened in, see [`AxVCpuExitReason::NestedPageFault`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Not classified.
+    Unknown,
+    /// No memory or device is mapped at the address.
+    Unmapped,
+    /// The address is in a read-only memory region, e.g., a copy-on-write page.
+    ReadOnly,
+    /// The address is in a region registered for MMIO emulation.
+    MmioRegistered,
+}
+
+/// A data cache maintenance operation, see [`AxVCpuExitReason::CacheMaintenance`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheOp {
+    /// Write dirty lines back to memory (`DC CVAC` in Aarch64, `CBO.CLEAN` in RISC-V).
+    Clean,
+    /// Discard the lines without writing them back (`DC IVAC` in Aarch64, `CBO.INVAL` in RISC-V).
+    Invalidate,
+    /// Write dirty lines back to memory and discard them (`DC CIVAC` in Aarch64, `CBO.FLUSH` in RISC-V, `CLFLUSH`
+    /// in x86).
+    CleanInvalidate,
+}
+
+/// What [`AxVCpu::run`](crate::AxVCpu::run) does with MMIO accesses to addresses where no device is
+/// implemented, see [`AxVCpuExitReason::UnhandledMmio`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnhandledMmioPolicy {
+    /// Return the access to the VMM as an [`AxVCpuExitReason::UnhandledMmio`] exit.
+    #[default]
+    Surface,
+    /// Read as zero, ignore writes ("RAZ/WI"), and re-enter the guest without returning, which suits chatty
+    /// guests probing for devices.
+    RazWi,
+    /// Inject a data abort (a bus error) into the guest with [`AxArchVCpu::inject_data_abort`](crate::AxArchVCpu::inject_data_abort),
+    /// and re-enter the guest without returning.
+    InjectAbort,
+}
+
+/// Classifies guest physical addresses into [`RegionKind`]s by consulting the address space of the VM.
+///
+/// Set with [`AxVCpu::set_region_classifier`](crate::AxVCpu::set_region_classifier).
+pub trait GuestRegionClassifier: Send + Sync {
+    /// Classify the region containing `addr`, which was accessed with `access_flags`.
+    fn classify(&self, addr: GuestPhysAddr, access_flags: MappingFlags) -> RegionKind;
+}
+
+/// The instruction used by the guest to make a [`AxVCpuExitReason::FirmwareCall`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareConduit {
+    /// `SMC` in Aarch64, i.e., a call to the secure monitor.
+    Smc,
+    /// `HVC` in Aarch64, i.e., a call to the hypervisor following the SMCCC.
+    Hvc,
+    /// `ecall` from VS-mode in RISC-V, i.e., an SBI call.
+    Sbi,
+}
+
 /// The port number of an I/O operation.
 type Port = u16;
 
@@ -75,6 +134,23 @@ pub enum AxVCpuExitReason {
         /// The arguments for the hypercall.
         args: [u64; 6],
     },
+    /// The instruction executed by the vcpu performs a call to the firmware, e.g., an SMCCC call (PSCI, OP-TEE,
+    /// ...) through `SMC` or `HVC` in Aarch64, or an SBI call through `ecall` in RISC-V.
+    ///
+    /// Unlike [`AxVCpuExitReason::Hypercall`], which is the hypervisor-defined interface, this carries calls to
+    /// interfaces defined by the platform firmware, so that the VMM can branch on the conduit, e.g., to proxy secure
+    /// calls to the real secure monitor.
+    FirmwareCall {
+        /// The instruction used to make the call.
+        conduit: FirmwareConduit,
+        /// The function id.
+        /// * for SMCCC, it's the function identifier in `w0`,
+        /// * for SBI, it's the extension id (`a7`) in the upper 32 bits and the function id (`a6`) in the lower
+        ///   32 bits.
+        func_id: u64,
+        /// The arguments of the call, i.e., `x1`-`x6` for SMCCC and `a0`-`a5` for SBI.
+        args: [u64; 6],
+    },
     /// The instruction executed by the vcpu performs a MMIO read operation.
     MmioRead {
         /// The physical address of the MMIO read.
@@ -92,9 +168,24 @@ pub enum AxVCpuExitReason {
         addr: GuestPhysAddr,
         /// The width of the MMIO write.
         width: AccessWidth,
-        /// The data to be written.
+        /// The data to be written, in bus (little-endian) order, see [`GuestEndian`](crate::GuestEndian).
         data: u64,
     },
+    /// The guest accessed an MMIO address where no device is implemented, i.e., the lookup on the MMIO bus of
+    /// the VM missed.
+    ///
+    /// Produced by [`AxVCpu::run`](crate::AxVCpu::run) from the [`AxVCpuExitReason::MmioRead`] and
+    /// [`AxVCpuExitReason::MmioWrite`] exits whose address the [`GuestRegionClassifier`] of the vcpu classifies as
+    /// [`RegionKind::Unmapped`], under [`UnhandledMmioPolicy::Surface`]. The access is not performed: a read leaves
+    /// the destination register unchanged.
+    UnhandledMmio {
+        /// The guest physical address accessed.
+        addr: GuestPhysAddr,
+        /// The width of the access.
+        width: AccessWidth,
+        /// Whether the access is a write.
+        is_write: bool,
+    },
     /// The instruction executed by the vcpu performs a system register read operation.
     ///
     /// System register here refers `MSR`s in x86, `CSR`s in RISC-V, and `System registers` in Aarch64.
@@ -158,6 +249,36 @@ pub enum AxVCpuExitReason {
         addr: GuestPhysAddr,
         /// The access flags of the fault.
         access_flags: MappingFlags,
+        /// What kind of region the faulting address belongs to.
+        ///
+        /// Architecture-specific vcpus should set it to [`RegionKind::Unknown`], it's filled by
+        /// [`AxVCpu::run`](crate::AxVCpu::run) with the [`GuestRegionClassifier`] of the vcpu, if any.
+        region_kind: RegionKind,
+    },
+    /// The guest accessed a guest physical address range watched for introspection, see
+    /// [`AxVCpu::watch_guest_memory`](crate::AxVCpu::watch_guest_memory).
+    ///
+    /// Produced by [`AxVCpu::run`](crate::AxVCpu::run) from the matching
+    /// [`AxVCpuExitReason::NestedPageFault`]s. The access is held until the handler completes it with
+    /// [`AxVCpu::complete_introspection`](crate::AxVCpu::complete_introspection).
+    Introspection {
+        /// The guest physical address accessed.
+        addr: GuestPhysAddr,
+        /// The kind of the access (read, write, or execute).
+        access: MappingFlags,
+    },
+    /// The guest attempted to make an immutable code region writable, by writing to it (a stage-2 fault) or by
+    /// changing its permissions (reported by the VMM with
+    /// [`AxVCpu::check_permission_change`](crate::AxVCpu::check_permission_change)). See
+    /// [`AxVCpu::protect_code_region`](crate::AxVCpu::protect_code_region).
+    ///
+    /// The attempt is not performed, and the vcpu refuses to run until the violation is cleared with
+    /// [`AxVCpu::clear_integrity_violation`](crate::AxVCpu::clear_integrity_violation).
+    IntegrityViolation {
+        /// The first guest physical address of the immutable region involved.
+        addr: GuestPhysAddr,
+        /// The access attempted, or the permissions requested.
+        access: MappingFlags,
     },
     /// The vcpu is halted.
     Halt,
@@ -196,10 +317,69 @@ pub enum AxVCpuExitReason {
     ///
     /// This is used to notify the hypervisor that the whole system should be powered off.
     SystemDown,
+    /// The system should be reset.
+    ///
+    /// This is used to notify the hypervisor that the whole system should be rebooted, e.g., by a PSCI
+    /// `SYSTEM_RESET` call, an SBI system reset, or a write to the x86 reset control register. Rapid cycles of
+    /// resets can be detected with [`AxVCpuGroup::check_reboot_storm`](crate::AxVCpuGroup::check_reboot_storm).
+    SystemReset,
+    /// The guest issued a trapped data cache maintenance operation by address, e.g., because the cache is shared
+    /// with non-coherent DMA devices, or the stage-2 attributes of the memory differ from the guest's.
+    ///
+    /// The VMM should perform it on the host mappings of the range, generally with
+    /// [`AxVCpu::handle_cache_maintenance`](crate::AxVCpu::handle_cache_maintenance).
+    CacheMaintenance {
+        /// The guest physical address of the start of the range, translated by the architecture-specific vcpu.
+        addr: GuestPhysAddr,
+        /// The size of the range in bytes, generally a cache line.
+        size: usize,
+        /// The operation.
+        op: CacheOp,
+    },
     /// Nothing special happened, the vcpu has handled the exit itself.
     ///
     /// This exists to allow the caller to have a chance to check virtual devices/physical devices/virtual interrupts.
     Nothing,
+    /// A DMA request issued by a passthrough device of the VM was blocked by the IOMMU.
+    ///
+    /// Unlike other exit reasons, this one is not produced by [`AxArchVCpu::run`], but reported by the host
+    /// (generally the IOMMU fault handler of the HAL) through [`AxVCpuGroup::report_iommu_fault`](crate::AxVCpuGroup::report_iommu_fault),
+    /// and surfaced on the BSP of the faulting VM.
+    IommuFault {
+        /// The id of the faulting device, i.e., the PCI requester id in x86 and RISC-V, and the stream id in Aarch64.
+        device: u32,
+        /// The guest physical address the device tried to access.
+        addr: GuestPhysAddr,
+        /// The access flags of the DMA request.
+        flags: MappingFlags,
+    },
+    /// A confidential guest issued a request to the host, e.g., an SEV-SNP guest request, a TDX `TDG.VP.VMCALL`,
+    /// or an Arm CCA RSI host call.
+    ///
+    /// Only produced by vcpus reporting [`VCpuCapabilities::CONFIDENTIAL`](crate::VCpuCapabilities::CONFIDENTIAL).
+    GuestRequest {
+        /// The architecture-specific request code.
+        request: u64,
+        /// The guest physical address of the (shared) request buffer.
+        request_addr: GuestPhysAddr,
+        /// The guest physical address of the (shared) response buffer.
+        response_addr: GuestPhysAddr,
+    },
+    /// The guest requested a CPU frequency (performance) level for this vcpu, e.g., by writing the CPPC desired
+    /// performance register or through a para-virtualized frequency call.
+    ///
+    /// The VMM may propagate it to the physical CPU with [`AxVCpuHal::apply_cpu_freq_request`](crate::AxVCpuHal::apply_cpu_freq_request)
+    /// (see [`AxVCpu::apply_cpu_freq_request`](crate::AxVCpu::apply_cpu_freq_request)), or ignore it.
+    CpuFreqRequest {
+        /// The requested performance level, higher is faster. The scale is architecture-specific, e.g., the
+        /// abstract performance scale of CPPC.
+        level: u32,
+    },
+    /// The guest used the FP/SIMD unit for the first time since the vcpu was bound, trapped because of
+    /// [`FpuPolicy::Lazy`](crate::FpuPolicy::Lazy).
+    ///
+    /// Handled inside [`AxVCpu::run`](crate::AxVCpu::run), never returned to the VMM.
+    FirstFpuUse,
     /// Something bad happened during VM entry, the vcpu could not be run due to unknown reasons.
     /// Further architecture-specific information is available in hardware_entry_failure_reason.
     /// Corresponds to `KVM_EXIT_FAIL_ENTRY`.
@@ -208,3 +388,507 @@ pub enum AxVCpuExitReason {
         hardware_entry_failure_reason: u64,
     },
 }
+
+impl AxVCpuExitReason {
+    /// Get the kind of this exit reason, for labeling counters and traces.
+    pub const fn kind(&self) -> ExitKind {
+        match self {
+            Self::Hypercall { .. } => ExitKind::Hypercall,
+            Self::FirmwareCall { .. } => ExitKind::FirmwareCall,
+            Self::MmioRead { .. } => ExitKind::MmioRead,
+            Self::MmioWrite { .. } => ExitKind::MmioWrite,
+            Self::UnhandledMmio { .. } => ExitKind::UnhandledMmio,
+            Self::CacheMaintenance { .. } => ExitKind::CacheMaintenance,
+            Self::SysRegRead { .. } => ExitKind::SysRegRead,
+            Self::SysRegWrite { .. } => ExitKind::SysRegWrite,
+            Self::IoRead { .. } => ExitKind::IoRead,
+            Self::IoWrite { .. } => ExitKind::IoWrite,
+            Self::ExternalInterrupt { .. } => ExitKind::ExternalInterrupt,
+            Self::NestedPageFault { .. } => ExitKind::NestedPageFault,
+            Self::Introspection { .. } => ExitKind::Introspection,
+            Self::IntegrityViolation { .. } => ExitKind::IntegrityViolation,
+            Self::Halt => ExitKind::Halt,
+            Self::CpuUp { .. } => ExitKind::CpuUp,
+            Self::CpuDown { .. } => ExitKind::CpuDown,
+            Self::SystemDown => ExitKind::SystemDown,
+            Self::SystemReset => ExitKind::SystemReset,
+            Self::Nothing => ExitKind::Nothing,
+            Self::IommuFault { .. } => ExitKind::IommuFault,
+            Self::GuestRequest { .. } => ExitKind::GuestRequest,
+            Self::FailEntry { .. } => ExitKind::FailEntry,
+            Self::CpuFreqRequest { .. } => ExitKind::CpuFreqRequest,
+            Self::FirstFpuUse => ExitKind::FirstFpuUse,
+        }
+    }
+
+    /// Get the payload of a [`AxVCpuExitReason::Hypercall`], as `(nr, args)`.
+    pub const fn as_hypercall(&self) -> Option<(u64, &[u64; 6])> {
+        match self {
+            Self::Hypercall { nr, args } => Some((*nr, args)),
+            _ => None,
+        }
+    }
+
+    /// Get the payload of a [`AxVCpuExitReason::FirmwareCall`], as `(conduit, func_id, args)`.
+    pub const fn as_firmware_call(&self) -> Option<(FirmwareConduit, u64, &[u64; 6])> {
+        match self {
+            Self::FirmwareCall {
+                conduit,
+                func_id,
+                args,
+            } => Some((*conduit, *func_id, args)),
+            _ => None,
+        }
+    }
+
+    /// Get the payload of a [`AxVCpuExitReason::MmioRead`], as `(addr, width, reg, reg_width)`.
+    pub const fn as_mmio_read(&self) -> Option<(GuestPhysAddr, AccessWidth, usize, AccessWidth)> {
+        match *self {
+            Self::MmioRead {
+                addr,
+                width,
+                reg,
+                reg_width,
+            } => Some((addr, width, reg, reg_width)),
+            _ => None,
+        }
+    }
+
+    /// Get the payload of a [`AxVCpuExitReason::MmioWrite`], as `(addr, width, data)`.
+    pub const fn as_mmio_write(&self) -> Option<(GuestPhysAddr, AccessWidth, u64)> {
+        match *self {
+            Self::MmioWrite { addr, width, data } => Some((addr, width, data)),
+            _ => None,
+        }
+    }
+
+    /// Get the payload of a [`AxVCpuExitReason::SysRegRead`], as `(addr, reg)`.
+    pub const fn as_sysreg_read(&self) -> Option<(usize, usize)> {
+        match *self {
+            Self::SysRegRead { addr, reg } => Some((addr, reg)),
+            _ => None,
+        }
+    }
+
+    /// Get the payload of a [`AxVCpuExitReason::SysRegWrite`], as `(addr, value)`.
+    pub const fn as_sysreg_write(&self) -> Option<(usize, u64)> {
+        match *self {
+            Self::SysRegWrite { addr, value } => Some((addr, value)),
+            _ => None,
+        }
+    }
+
+    /// Get the payload of a [`AxVCpuExitReason::IoRead`], as `(port, width)`.
+    pub const fn as_io_read(&self) -> Option<(u16, AccessWidth)> {
+        match *self {
+            Self::IoRead { port, width } => Some((port, width)),
+            _ => None,
+        }
+    }
+
+    /// Get the payload of a [`AxVCpuExitReason::IoWrite`], as `(port, width, data)`.
+    pub const fn as_io_write(&self) -> Option<(u16, AccessWidth, u64)> {
+        match *self {
+            Self::IoWrite { port, width, data } => Some((port, width, data)),
+            _ => None,
+        }
+    }
+
+    /// Get the guest physical address accessed by the guest (or a passthrough device of the VM), if the exit is
+    /// caused by such an access, i.e., an MMIO access, a nested page fault, an introspection exit, an IOMMU fault,
+    /// or a cache maintenance operation.
+    pub const fn guest_addr(&self) -> Option<GuestPhysAddr> {
+        match *self {
+            Self::MmioRead { addr, .. }
+            | Self::MmioWrite { addr, .. }
+            | Self::UnhandledMmio { addr, .. }
+            | Self::NestedPageFault { addr, .. }
+            | Self::Introspection { addr, .. }
+            | Self::IommuFault { addr, .. }
+            | Self::CacheMaintenance { addr, .. } => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Whether the exit is an access to an emulated device, i.e., an MMIO, port I/O, or system register access.
+    pub const fn is_device_access(&self) -> bool {
+        matches!(
+            self,
+            Self::MmioRead { .. }
+                | Self::MmioWrite { .. }
+                | Self::IoRead { .. }
+                | Self::IoWrite { .. }
+                | Self::SysRegRead { .. }
+                | Self::SysRegWrite { .. }
+        )
+    }
+
+    /// Whether the exit means the vcpu can't continue running, i.e., the VM entry failed, or the guest violated
+    /// the code integrity.
+    pub const fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Self::FailEntry { .. } | Self::IntegrityViolation { .. }
+        )
+    }
+}
+
+/// The kind of an [`AxVCpuExitReason`], without the payload.
+///
+/// Unlike [`AxVCpuExitReason`], this enum can be matched exhaustively, and both its numeric id and its name are
+/// stable, so it's suitable for labeling counters in statistics, traces, and external metrics exporters. New kinds
+/// are only appended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum ExitKind {
+    /// [`AxVCpuExitReason::Hypercall`].
+    Hypercall = 0,
+    /// [`AxVCpuExitReason::MmioRead`].
+    MmioRead = 1,
+    /// [`AxVCpuExitReason::MmioWrite`].
+    MmioWrite = 2,
+    /// [`AxVCpuExitReason::SysRegRead`].
+    SysRegRead = 3,
+    /// [`AxVCpuExitReason::SysRegWrite`].
+    SysRegWrite = 4,
+    /// [`AxVCpuExitReason::IoRead`].
+    IoRead = 5,
+    /// [`AxVCpuExitReason::IoWrite`].
+    IoWrite = 6,
+    /// [`AxVCpuExitReason::ExternalInterrupt`].
+    ExternalInterrupt = 7,
+    /// [`AxVCpuExitReason::NestedPageFault`].
+    NestedPageFault = 8,
+    /// [`AxVCpuExitReason::Halt`].
+    Halt = 9,
+    /// [`AxVCpuExitReason::CpuUp`].
+    CpuUp = 10,
+    /// [`AxVCpuExitReason::CpuDown`].
+    CpuDown = 11,
+    /// [`AxVCpuExitReason::SystemDown`].
+    SystemDown = 12,
+    /// [`AxVCpuExitReason::Nothing`].
+    Nothing = 13,
+    /// [`AxVCpuExitReason::IommuFault`].
+    IommuFault = 14,
+    /// [`AxVCpuExitReason::GuestRequest`].
+    GuestRequest = 15,
+    /// [`AxVCpuExitReason::FailEntry`].
+    FailEntry = 16,
+    /// [`AxVCpuExitReason::CpuFreqRequest`].
+    CpuFreqRequest = 17,
+    /// [`AxVCpuExitReason::FirstFpuUse`].
+    FirstFpuUse = 18,
+    /// [`AxVCpuExitReason::FirmwareCall`].
+    FirmwareCall = 19,
+    /// [`AxVCpuExitReason::Introspection`].
+    Introspection = 20,
+    /// [`AxVCpuExitReason::IntegrityViolation`].
+    IntegrityViolation = 21,
+    /// [`AxVCpuExitReason::SystemReset`].
+    SystemReset = 22,
+    /// [`AxVCpuExitReason::UnhandledMmio`].
+    UnhandledMmio = 23,
+    /// [`AxVCpuExitReason::CacheMaintenance`].
+    CacheMaintenance = 24,
+}
+
+impl ExitKind {
+    /// All exit kinds, in the order of their ids.
+    pub const ALL: &[ExitKind] = &[
+        Self::Hypercall,
+        Self::MmioRead,
+        Self::MmioWrite,
+        Self::SysRegRead,
+        Self::SysRegWrite,
+        Self::IoRead,
+        Self::IoWrite,
+        Self::ExternalInterrupt,
+        Self::NestedPageFault,
+        Self::Halt,
+        Self::CpuUp,
+        Self::CpuDown,
+        Self::SystemDown,
+        Self::Nothing,
+        Self::IommuFault,
+        Self::GuestRequest,
+        Self::FailEntry,
+        Self::CpuFreqRequest,
+        Self::FirstFpuUse,
+        Self::FirmwareCall,
+        Self::Introspection,
+        Self::IntegrityViolation,
+        Self::SystemReset,
+        Self::UnhandledMmio,
+        Self::CacheMaintenance,
+    ];
+
+    /// The number of exit kinds.
+    pub const COUNT: usize = Self::ALL.len();
+
+    /// Get the stable numeric id of this kind, which is also its index in [`ExitKind::ALL`].
+    pub const fn id(self) -> u8 {
+        self as u8
+    }
+
+    /// Get the kind with the given numeric id.
+    pub const fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::Hypercall),
+            1 => Some(Self::MmioRead),
+            2 => Some(Self::MmioWrite),
+            3 => Some(Self::SysRegRead),
+            4 => Some(Self::SysRegWrite),
+            5 => Some(Self::IoRead),
+            6 => Some(Self::IoWrite),
+            7 => Some(Self::ExternalInterrupt),
+            8 => Some(Self::NestedPageFault),
+            9 => Some(Self::Halt),
+            10 => Some(Self::CpuUp),
+            11 => Some(Self::CpuDown),
+            12 => Some(Self::SystemDown),
+            13 => Some(Self::Nothing),
+            14 => Some(Self::IommuFault),
+            15 => Some(Self::GuestRequest),
+            16 => Some(Self::FailEntry),
+            17 => Some(Self::CpuFreqRequest),
+            18 => Some(Self::FirstFpuUse),
+            19 => Some(Self::FirmwareCall),
+            20 => Some(Self::Introspection),
+            21 => Some(Self::IntegrityViolation),
+            22 => Some(Self::SystemReset),
+            23 => Some(Self::UnhandledMmio),
+            24 => Some(Self::CacheMaintenance),
+            _ => None,
+        }
+    }
+
+    /// Get the stable name of this kind, in `snake_case`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Hypercall => "hypercall",
+            Self::MmioRead => "mmio_read",
+            Self::MmioWrite => "mmio_write",
+            Self::SysRegRead => "sysreg_read",
+            Self::SysRegWrite => "sysreg_write",
+            Self::IoRead => "io_read",
+            Self::IoWrite => "io_write",
+            Self::ExternalInterrupt => "external_interrupt",
+            Self::NestedPageFault => "nested_page_fault",
+            Self::Halt => "halt",
+            Self::CpuUp => "cpu_up",
+            Self::CpuDown => "cpu_down",
+            Self::SystemDown => "system_down",
+            Self::Nothing => "nothing",
+            Self::IommuFault => "iommu_fault",
+            Self::GuestRequest => "guest_request",
+            Self::FailEntry => "fail_entry",
+            Self::CpuFreqRequest => "cpu_freq_request",
+            Self::FirstFpuUse => "first_fpu_use",
+            Self::FirmwareCall => "firmware_call",
+            Self::Introspection => "introspection",
+            Self::IntegrityViolation => "integrity_violation",
+            Self::SystemReset => "system_reset",
+            Self::UnhandledMmio => "unhandled_mmio",
+            Self::CacheMaintenance => "cache_maintenance",
+        }
+    }
+}
+
+impl core::fmt::Display for ExitKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The reason why a vcpu (and generally the whole VM) is shut down, carried by [`ExitAction::Shutdown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// The guest powered the system off, e.g., by an [`AxVCpuExitReason::SystemDown`] exit.
+    PowerOff,
+    /// The host requested the shutdown.
+    Requested,
+    /// An unrecoverable error happened while handling an exit.
+    Fatal,
+    /// The guest caused an exit denied by the [`ExitPolicy`] of the vcpu.
+    ExitDenied {
+        /// The kind of the denied exit.
+        kind: ExitKind,
+    },
+}
+
+/// The action to take after an exit is handled, returned by the exit handler passed to
+/// [`AxVCpu::run_loop`](crate::AxVCpu::run_loop).
+///
+/// [`AxVCpu::run_loop`](crate::AxVCpu::run_loop) keeps running the vcpu as long as the handler returns
+/// [`ExitAction::Continue`], and returns any other action to the caller with the vcpu left in
+/// [`VCpuState::Ready`](crate::VCpuState::Ready). It's the caller's responsibility to carry out the action:
+///
+/// - [`ExitAction::Pause`]: stop running the vcpu, it may be run again later without further operations.
+/// - [`ExitAction::Shutdown`]: unbind the vcpu and tear it down.
+/// - [`ExitAction::Reset`]: reset the vcpu to its initial state and run it again.
+/// - [`ExitAction::Migrate`]: unbind the vcpu, so that it can be bound to another physical CPU.
+/// - [`ExitAction::Forward`]: handle the carried exit in the VMM, then run the vcpu again.
+#[derive(Debug)]
+pub enum ExitAction {
+    /// Re-enter the guest.
+    Continue,
+    /// Stop running the vcpu for now.
+    Pause,
+    /// Shut the vcpu down.
+    Shutdown {
+        /// The reason of the shutdown.
+        reason: ShutdownReason,
+    },
+    /// Reset the vcpu.
+    Reset,
+    /// Move the vcpu to another physical CPU.
+    Migrate,
+    /// Hand the exit over to the VMM, see [`ExitDisposition::ForwardToVmm`].
+    Forward {
+        /// The forwarded exit.
+        exit: AxVCpuExitReason,
+    },
+}
+
+/// How [`AxVCpu::run_loop`](crate::AxVCpu::run_loop) treats exits of a kind, see [`ExitPolicy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExitDisposition {
+    /// Pass the exit to the in-kernel handler of the run loop.
+    #[default]
+    HandleInKernel,
+    /// Bypass the in-kernel handler and return the exit in [`ExitAction::Forward`], for the VMM to handle.
+    ForwardToVmm,
+    /// Treat the exit as a policy violation: report it to
+    /// [`AxVCpuHal::on_fatal_vcpu_error`](crate::AxVCpuHal::on_fatal_vcpu_error), and return
+    /// [`ExitAction::Shutdown`] with [`ShutdownReason::ExitDenied`], so that the VM is killed.
+    Deny,
+}
+
+/// The table mapping each [`ExitKind`] to its [`ExitDisposition`], set per vcpu with
+/// [`AxVCpu::set_exit_policy`](crate::AxVCpu::set_exit_policy) and consulted by
+/// [`AxVCpu::run_loop`](crate::AxVCpu::run_loop) before the handler.
+///
+/// Security-focused deployments deny the exits some guests have no business causing, e.g.:
+///
+/// ```ignore
+/// let policy = ExitPolicy::new()
+///     .with(ExitKind::Hypercall, ExitDisposition::Deny)
+///     .with(ExitKind::IoRead, ExitDisposition::Deny)
+///     .with(ExitKind::IoWrite, ExitDisposition::Deny);
+/// vcpu.set_exit_policy(policy);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitPolicy {
+    /// The dispositions, indexed by [`ExitKind::id`].
+    dispositions: [ExitDisposition; ExitKind::COUNT],
+}
+
+impl Default for ExitPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExitPolicy {
+    /// Create the default policy, handling all exits in kernel.
+    pub const fn new() -> Self {
+        Self {
+            dispositions: [ExitDisposition::HandleInKernel; ExitKind::COUNT],
+        }
+    }
+
+    /// Get the policy with the exits of `kind` treated as `disposition`.
+    pub const fn with(mut self, kind: ExitKind, disposition: ExitDisposition) -> Self {
+        self.dispositions[kind.id() as usize] = disposition;
+        self
+    }
+
+    /// Get the policy with the exits of all kinds in `kinds` treated as `disposition`.
+    pub fn with_kinds(mut self, kinds: ExitKindSet, disposition: ExitDisposition) -> Self {
+        for kind in kinds.iter() {
+            self.set(kind, disposition);
+        }
+        self
+    }
+
+    /// Treat the exits of `kind` as `disposition`.
+    pub fn set(&mut self, kind: ExitKind, disposition: ExitDisposition) {
+        self.dispositions[kind.id() as usize] = disposition;
+    }
+
+    /// Get how the exits of `kind` are treated.
+    pub const fn disposition(&self, kind: ExitKind) -> ExitDisposition {
+        self.dispositions[kind.id() as usize]
+    }
+
+    /// Get the set of exit kinds treated as `disposition`.
+    pub fn kinds(&self, disposition: ExitDisposition) -> ExitKindSet {
+        ExitKind::ALL
+            .iter()
+            .copied()
+            .filter(|&kind| self.disposition(kind) == disposition)
+            .collect()
+    }
+}
+
+/// A set of [`ExitKind`]s.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ExitKindSet(u64);
+
+const _: () = assert!(ExitKind::COUNT <= 64);
+
+impl ExitKindSet {
+    /// The empty set.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// The set of all exit kinds.
+    pub const fn all() -> Self {
+        Self(u64::MAX >> (64 - ExitKind::COUNT))
+    }
+
+    /// Get the set with `kind` added.
+    pub const fn with(self, kind: ExitKind) -> Self {
+        Self(self.0 | 1 << kind.id())
+    }
+
+    /// Add `kind` to the set.
+    pub fn insert(&mut self, kind: ExitKind) {
+        self.0 |= 1 << kind.id();
+    }
+
+    /// Remove `kind` from the set.
+    pub fn remove(&mut self, kind: ExitKind) {
+        self.0 &= !(1 << kind.id());
+    }
+
+    /// Whether `kind` is in the set.
+    pub const fn contains(&self, kind: ExitKind) -> bool {
+        self.0 & (1 << kind.id()) != 0
+    }
+
+    /// Whether the set is empty.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Iterate over the exit kinds in the set, in the order of their ids.
+    pub fn iter(&self) -> impl Iterator<Item = ExitKind> + '_ {
+        ExitKind::ALL
+            .iter()
+            .copied()
+            .filter(|&kind| self.contains(kind))
+    }
+}
+
+impl FromIterator<ExitKind> for ExitKindSet {
+    fn from_iter<T: IntoIterator<Item = ExitKind>>(iter: T) -> Self {
+        let mut set = Self::empty();
+        for kind in iter {
+            set.insert(kind);
+        }
+        set
+    }
+}
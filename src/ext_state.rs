@@ -0,0 +1,70 @@
+use core::marker::PhantomData;
+
+use axaddrspace::HostPhysAddr;
+
+use crate::AxVCpuHal;
+
+/// The size of a frame allocated by [`AxVCpuHal`].
+const FRAME_SIZE: usize = 0x1000;
+
+/// A buffer holding the extended (variable-length vector) register state of a vcpu, e.g., the SVE state in Aarch64
+/// or the AVX-512 state in x86, obtained by [`AxVCpu::save_ext_state`](crate::AxVCpu::save_ext_state).
+///
+/// The size of the state depends on the host (e.g., the SVE vector length), so the buffer is sized by
+/// [`AxArchVCpu::ext_state_size`](crate::AxArchVCpu::ext_state_size) and allocated with
+/// [`AxVCpuHal::alloc_contiguous_frames`]. The frames are freed when the buffer is dropped.
+pub struct ExtStateBuffer<H: AxVCpuHal> {
+    /// The physical address of the first frame.
+    paddr: HostPhysAddr,
+    /// The number of frames.
+    frames: usize,
+    /// The size of the state in bytes.
+    len: usize,
+    _hal: PhantomData<H>,
+}
+
+impl<H: AxVCpuHal> ExtStateBuffer<H> {
+    /// Allocate a zeroed buffer of `len` bytes, preferably from the given NUMA node.
+    pub(crate) fn new(len: usize, node: Option<usize>) -> Option<Self> {
+        let frames = len.div_ceil(FRAME_SIZE).max(1);
+        let paddr = H::alloc_contiguous_frames(frames, node)?;
+        let mut buf = Self {
+            paddr,
+            frames,
+            len,
+            _hal: PhantomData,
+        };
+        buf.as_mut_slice().fill(0);
+        Some(buf)
+    }
+
+    /// Get the size of the state in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the state is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the state as bytes.
+    pub fn as_slice(&self) -> &[u8] {
+        let ptr = H::phys_to_virt(self.paddr).as_usize() as *const u8;
+        // SAFETY: the buffer owns `frames` contiguous frames, which hold at least `len` bytes.
+        unsafe { core::slice::from_raw_parts(ptr, self.len) }
+    }
+
+    /// Get the state as mutable bytes.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        let ptr = H::phys_to_virt(self.paddr).as_usize() as *mut u8;
+        // SAFETY: the buffer owns `frames` contiguous frames, which hold at least `len` bytes.
+        unsafe { core::slice::from_raw_parts_mut(ptr, self.len) }
+    }
+}
+
+impl<H: AxVCpuHal> Drop for ExtStateBuffer<H> {
+    fn drop(&mut self) {
+        H::dealloc_contiguous_frames(self.paddr, self.frames);
+    }
+}
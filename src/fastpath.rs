@@ -0,0 +1,99 @@
+//! Built-in fast-path handlers for the most common interrupt controller register accesses.
+//!
+//! With the `x86-apic-fast` or `arm-gic-fast` feature enabled and an [`IrqChipGlue`] installed via
+//! [`AxVCpu::set_irqchip_glue`](crate::AxVCpu::set_irqchip_glue), the following exits are handled inside
+//! [`AxVCpu::run`](crate::AxVCpu::run), which then re-enters the guest without returning to the VMM:
+//!
+//! - `x86-apic-fast`: x2APIC `EOI` and `ICR` writes, xAPIC `EOI` writes.
+//! - `arm-gic-fast`: `ICC_EOIR1_EL1` and `ICC_SGI1R_EL1` writes, `ICC_IAR1_EL1` reads.
+
+use axerrno::AxResult;
+
+use crate::AxVCpuExitReason;
+
+/// The glue between the vcpus and the emulated interrupt controller, used by the fast-path handlers.
+pub trait IrqChipGlue: Send + Sync {
+    /// Signal the end of the interrupt being serviced by the vcpu (`EOI` in x86, `ICC_EOIR1_EL1` in Aarch64).
+    fn eoi(&self, vcpu_id: usize, value: u64) -> AxResult;
+    /// Send an inter-processor interrupt (`ICR` in x86, `ICC_SGI1R_EL1` in Aarch64) from the vcpu.
+    fn send_ipi(&self, vcpu_id: usize, value: u64) -> AxResult;
+    /// Acknowledge the highest priority pending interrupt of the vcpu (`ICC_IAR1_EL1` in Aarch64), returning the
+    /// value read by the guest.
+    fn acknowledge(&self, vcpu_id: usize) -> AxResult<u64>;
+}
+
+/// An operation handled by the fast path.
+pub(crate) enum FastPathOp {
+    /// An end-of-interrupt write.
+    Eoi(u64),
+    /// An inter-processor interrupt write.
+    SendIpi(u64),
+    /// An interrupt acknowledge read, whose result goes to the given GPR.
+    #[cfg_attr(not(feature = "arm-gic-fast"), allow(dead_code))]
+    Acknowledge {
+        /// The index of the destination GPR.
+        reg: usize,
+    },
+}
+
+/// The x2APIC `EOI` MSR.
+#[cfg(feature = "x86-apic-fast")]
+const X2APIC_EOI: usize = 0x80b;
+/// The x2APIC `ICR` MSR.
+#[cfg(feature = "x86-apic-fast")]
+const X2APIC_ICR: usize = 0x830;
+/// The guest physical address of the xAPIC `EOI` register.
+#[cfg(feature = "x86-apic-fast")]
+const XAPIC_EOI: usize = 0xfee0_00b0;
+
+/// Encode an Aarch64 system register in the format of [`AxVCpuExitReason::SysRegRead::addr`].
+#[cfg(feature = "arm-gic-fast")]
+const fn aarch64_sysreg(op0: usize, op1: usize, crn: usize, crm: usize, op2: usize) -> usize {
+    (op0 << 20) | (op2 << 17) | (op1 << 14) | (crn << 10) | (crm << 1)
+}
+
+/// `ICC_IAR1_EL1`.
+#[cfg(feature = "arm-gic-fast")]
+const ICC_IAR1_EL1: usize = aarch64_sysreg(3, 0, 12, 12, 0);
+/// `ICC_EOIR1_EL1`.
+#[cfg(feature = "arm-gic-fast")]
+const ICC_EOIR1_EL1: usize = aarch64_sysreg(3, 0, 12, 12, 1);
+/// `ICC_SGI1R_EL1`.
+#[cfg(feature = "arm-gic-fast")]
+const ICC_SGI1R_EL1: usize = aarch64_sysreg(3, 0, 12, 11, 5);
+
+/// Check whether an exit can be handled by the fast path.
+pub(crate) fn classify(exit: &AxVCpuExitReason) -> Option<FastPathOp> {
+    match *exit {
+        #[cfg(feature = "x86-apic-fast")]
+        AxVCpuExitReason::SysRegWrite {
+            addr: X2APIC_EOI,
+            value,
+        } => Some(FastPathOp::Eoi(value)),
+        #[cfg(feature = "x86-apic-fast")]
+        AxVCpuExitReason::SysRegWrite {
+            addr: X2APIC_ICR,
+            value,
+        } => Some(FastPathOp::SendIpi(value)),
+        #[cfg(feature = "x86-apic-fast")]
+        AxVCpuExitReason::MmioWrite { addr, data, .. } if addr.as_usize() == XAPIC_EOI => {
+            Some(FastPathOp::Eoi(data))
+        }
+        #[cfg(feature = "arm-gic-fast")]
+        AxVCpuExitReason::SysRegWrite {
+            addr: ICC_EOIR1_EL1,
+            value,
+        } => Some(FastPathOp::Eoi(value)),
+        #[cfg(feature = "arm-gic-fast")]
+        AxVCpuExitReason::SysRegWrite {
+            addr: ICC_SGI1R_EL1,
+            value,
+        } => Some(FastPathOp::SendIpi(value)),
+        #[cfg(feature = "arm-gic-fast")]
+        AxVCpuExitReason::SysRegRead {
+            addr: ICC_IAR1_EL1,
+            reg,
+        } => Some(FastPathOp::Acknowledge { reg }),
+        _ => None,
+    }
+}
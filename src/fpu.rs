@@ -0,0 +1,15 @@
+/// The policy of switching the FP/SIMD state of a vcpu, see [`AxVCpu::set_fpu_policy`](crate::AxVCpu::set_fpu_policy).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FpuPolicy {
+    /// The FP/SIMD state is switched by the architecture-specific vcpu itself, generally on every bind and unbind.
+    #[default]
+    Eager,
+    /// The FP/SIMD state of the guest is restored on the first use after each bind.
+    ///
+    /// FP/SIMD accesses of the guest are trapped (see [`AxArchVCpu::set_fpu_trap`](crate::AxArchVCpu::set_fpu_trap))
+    /// after bind. The first one exits with [`AxVCpuExitReason::FirstFpuUse`](crate::AxVCpuExitReason::FirstFpuUse),
+    /// which is handled inside [`AxVCpu::run`](crate::AxVCpu::run) by restoring the state and disabling the trap.
+    /// The state is saved on unbind only if it was restored, so guests rarely using FP/SIMD don't pay for the
+    /// switch at all.
+    Lazy,
+}
@@ -0,0 +1,664 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+
+use axaddrspace::{GuestPhysAddr, HostVirtAddr, MappingFlags};
+use axerrno::{AxResult, ax_err, ax_err_type};
+
+use crate::lockstep::{LockstepBarrier, LockstepHandle};
+use crate::msi::{DefaultMsiDecoder, MsiDecoder, MsiDestination, MsiMessage};
+use crate::parallel::parallel_map;
+use crate::pvclock::PvTimePages;
+use crate::reboot::{RebootStorm, RebootStormDetector};
+use crate::{
+    AxArchVCpu, AxVCpu, AxVCpuBuilder, AxVCpuExitReason, AxVCpuHal, AxVCpuResult, ExitKind,
+    ExitPolicy, FinalStatsReport, GroupStats, RunToken, SandboxConfig, UnhandledMmioPolicy,
+    VCpuState,
+};
+
+/// A reference to a vcpu shared between the vcpu group and the scheduler.
+pub type AxVCpuRef<A> = Arc<AxVCpu<A>>;
+
+/// A group of virtual CPUs belonging to the same virtual machine.
+///
+/// This struct provides operations which involve more than one vcpu, e.g., routing a message
+/// signaled interrupt to its destination vcpu.
+pub struct AxVCpuGroup<A: AxArchVCpu> {
+    /// The vcpus in this group.
+    vcpus: Vec<AxVCpuRef<A>>,
+    /// The decoder used to translate MSI messages.
+    msi_decoder: Box<dyn MsiDecoder>,
+    /// The para-virtualized time pages registered by the guest.
+    pv_time: RefCell<PvTimePages>,
+    /// The handle to the barrier used by [`AxVCpuGroup::run_lockstep`], cloned by
+    /// [`AxVCpuGroup::lockstep_handle`].
+    lockstep: LockstepHandle<A::Hal>,
+    /// What to do with the pending interrupts of vcpus powered off by the guest.
+    cpu_down_irq_policy: Cell<CpuDownIrqPolicy>,
+    /// What to do with interrupts targeting unavailable vcpus, see [`AxVCpuGroup::inject_interrupt`].
+    irq_fallback: Cell<IrqFallbackPolicy>,
+    /// For how long a vcpu must have been blocked to be considered unavailable, in nanoseconds.
+    irq_fallback_block_ns: Cell<u64>,
+    /// The recent resets of the guest, see [`AxVCpuGroup::check_reboot_storm`].
+    reboot_storm: RefCell<RebootStormDetector>,
+    /// The last sample of [`AxVCpuGroup::aggregate_stats`], as `(time, total_exits, exit_rate)`.
+    stats_sample: Cell<Option<(u64, u64, u64)>>,
+}
+
+/// The default for how long a vcpu must have been blocked before [`IrqFallbackPolicy`] applies to it, in
+/// nanoseconds.
+pub const IRQ_FALLBACK_DEFAULT_BLOCK_NS: u64 = 100_000_000;
+
+/// What [`AxVCpuGroup::handle_cpu_down`] does with the interrupts still pending on a vcpu powered off by the guest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CpuDownIrqPolicy {
+    /// Drop the interrupts.
+    Drop,
+    /// Re-route the interrupts to the BSP, so that no device interrupt is lost.
+    #[default]
+    RerouteToBsp,
+}
+
+/// What [`AxVCpuGroup::inject_interrupt`] does with an interrupt whose target vcpu is unavailable, i.e., parked
+/// (see [`AxVCpu::park`]) or blocked for too long (see [`AxVCpuGroup::set_irq_fallback_policy`]).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IrqFallbackPolicy {
+    /// Queue the interrupt on the target vcpu anyway, to be injected when it runs again.
+    #[default]
+    Queue,
+    /// Drop the interrupt.
+    Drop,
+    /// Route the interrupt to the vcpu with the given id instead, so that device interrupts are still serviced
+    /// during guest CPU hotplug or suspend.
+    ///
+    /// The interrupt is queued on the target vcpu if the given vcpu is the target itself or doesn't exist.
+    RouteTo(usize),
+}
+
+/// The state of a vcpu of a group at a point in time, collected by [`AxVCpuGroup::collect_states`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VCpuStatus {
+    /// The id of the vcpu.
+    pub vcpu_id: usize,
+    /// The state of the vcpu.
+    pub state: VCpuState,
+    /// Whether the vcpu is in guest mode, see [`AxVCpu::is_running`].
+    pub in_guest: bool,
+    /// The physical CPU the vcpu is bound to, if any.
+    pub bound_cpu: Option<usize>,
+    /// For how long the vcpu has been blocked, in nanoseconds, or `None` if it's not blocked.
+    pub blocked_for_ns: Option<u64>,
+}
+
+/// How many times [`AxVCpuGroup::collect_states`] walks the vcpus looking for two identical passes.
+const COLLECT_STATES_ATTEMPTS: usize = 4;
+
+/// A vcpu which failed to leave guest mode in time, reported by [`AxVCpuGroup::stop_all`] and
+/// [`AxVCpuGroup::quiesce`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StuckVCpu {
+    /// The id of the vcpu.
+    pub vcpu_id: usize,
+    /// The kind of the last exit of the vcpu which is not handled yet, if any. `None` generally means the vcpu is
+    /// stuck in the guest, otherwise in the handler of this exit.
+    pub last_exit: Option<ExitKind>,
+}
+
+impl<A: AxArchVCpu> AxVCpuGroup<A> {
+    /// Create a new [`AxVCpuGroup`] with the default MSI decoder of the current architecture.
+    pub fn new(vcpus: Vec<AxVCpuRef<A>>) -> Self {
+        let lockstep = LockstepHandle::new(
+            Arc::new(LockstepBarrier::new()),
+            vcpus.iter().map(|vcpu| vcpu.id()).collect(),
+        );
+        Self {
+            vcpus,
+            msi_decoder: Box::new(DefaultMsiDecoder::default()),
+            pv_time: RefCell::new(PvTimePages::default()),
+            lockstep,
+            cpu_down_irq_policy: Cell::new(CpuDownIrqPolicy::default()),
+            irq_fallback: Cell::new(IrqFallbackPolicy::default()),
+            irq_fallback_block_ns: Cell::new(IRQ_FALLBACK_DEFAULT_BLOCK_NS),
+            reboot_storm: RefCell::new(RebootStormDetector::new()),
+            stats_sample: Cell::new(None),
+        }
+    }
+
+    /// Create the vcpus `0..n` of a VM and a group of them, with the builders produced by `configs` for each vcpu
+    /// id.
+    ///
+    /// The architecture-specific vcpus are created concurrently with [`AxVCpuHal::parallel_for`], so that VMs with
+    /// hundreds of vcpus are instantiated quickly when the HAL spreads the work over several physical CPUs. Returns
+    /// the error of the vcpu with the lowest id if any creation fails, and `BadState` if the HAL skipped some vcpus.
+    pub fn create_vcpus(
+        n: usize,
+        mut configs: impl FnMut(usize) -> AxVCpuBuilder<A>,
+    ) -> AxResult<Self>
+    where
+        A: Send,
+        A::CreateConfig: Send,
+    {
+        let mut inner_consts = Vec::with_capacity(n);
+        let mut inputs = Vec::with_capacity(n);
+        for id in 0..n {
+            let (inner_const, arch_config) = configs(id).into_parts()?;
+            inputs.push((arch_config, inner_const.create_context()));
+            inner_consts.push(inner_const);
+        }
+        let arch_vcpus = parallel_map::<A::Hal, _, _>(inputs, |(arch_config, ctx)| {
+            A::new_with_context(arch_config, &ctx)
+        })
+        .ok_or_else(|| ax_err_type!(BadState, "vcpu creation skipped by the HAL"))?;
+        let vcpus = inner_consts
+            .into_iter()
+            .zip(arch_vcpus)
+            .map(|(inner_const, arch_vcpu)| {
+                Ok(Arc::new(AxVCpu::from_arch(inner_const, arch_vcpu?)))
+            })
+            .collect::<AxResult<_>>()?;
+        Ok(Self::new(vcpus))
+    }
+
+    /// Replace the MSI decoder used by [`AxVCpuGroup::deliver_msi`].
+    pub fn with_msi_decoder(mut self, decoder: impl MsiDecoder + 'static) -> Self {
+        self.msi_decoder = Box::new(decoder);
+        self
+    }
+
+    /// Get all vcpus in this group.
+    pub fn vcpus(&self) -> &[AxVCpuRef<A>] {
+        &self.vcpus
+    }
+
+    /// Iterate over the vcpus in this group.
+    pub fn iter(&self) -> core::slice::Iter<'_, AxVCpuRef<A>> {
+        self.vcpus.iter()
+    }
+
+    /// Iterate over the vcpus of this group in guest mode, see [`AxVCpu::is_running`].
+    pub fn running(&self) -> impl Iterator<Item = &AxVCpuRef<A>> {
+        self.vcpus.iter().filter(|vcpu| vcpu.is_running())
+    }
+
+    /// Iterate over the vcpus of this group in [`VCpuState::Blocked`].
+    pub fn blocked(&self) -> impl Iterator<Item = &AxVCpuRef<A>> {
+        self.vcpus
+            .iter()
+            .filter(|vcpu| vcpu.state() == VCpuState::Blocked)
+    }
+
+    /// Iterate over the vcpus of this group bound to the physical CPU `cpu_id`, see [`AxVCpu::bound_cpu`].
+    pub fn on_cpu(&self, cpu_id: usize) -> impl Iterator<Item = &AxVCpuRef<A>> {
+        self.vcpus
+            .iter()
+            .filter(move |vcpu| vcpu.bound_cpu() == Some(cpu_id))
+    }
+
+    /// Collect the states of all vcpus in this group, ordered as [`AxVCpuGroup::vcpus`].
+    ///
+    /// The vcpus keep running while their states are read, so a single walk may mix states from before and after
+    /// a transition of another vcpu. The vcpus are walked until two consecutive passes agree (the blocked times
+    /// being taken at the same host time), so that callers get a consistent view of the group; if the group keeps
+    /// changing, the last pass is returned after a few attempts.
+    pub fn collect_states(&self) -> Vec<VCpuStatus> {
+        let now = A::Hal::current_time_nanos();
+        let collect = || -> Vec<_> {
+            self.vcpus
+                .iter()
+                .map(|vcpu| VCpuStatus {
+                    vcpu_id: vcpu.id(),
+                    state: vcpu.state(),
+                    in_guest: vcpu.is_running(),
+                    bound_cpu: vcpu.bound_cpu(),
+                    blocked_for_ns: vcpu.blocked_for_ns_at(now),
+                })
+                .collect()
+        };
+        let mut states = collect();
+        for _ in 1..COLLECT_STATES_ATTEMPTS {
+            let next = collect();
+            if next == states {
+                break;
+            }
+            states = next;
+        }
+        states
+    }
+
+    /// Get the number of vcpus in this group.
+    pub fn len(&self) -> usize {
+        self.vcpus.len()
+    }
+
+    /// Whether this group contains no vcpu.
+    pub fn is_empty(&self) -> bool {
+        self.vcpus.is_empty()
+    }
+
+    /// Get the vcpu with the given id.
+    pub fn vcpu(&self, vcpu_id: usize) -> Option<&AxVCpuRef<A>> {
+        self.vcpus.iter().find(|vcpu| vcpu.id() == vcpu_id)
+    }
+
+    /// Get the vcpu with the given architectural id, e.g., the `target_cpu` of [`AxVCpuExitReason::CpuUp`].
+    pub fn vcpu_by_arch_id(&self, arch_id: u64) -> Option<&AxVCpuRef<A>> {
+        self.vcpus.iter().find(|vcpu| vcpu.arch_cpu_id() == arch_id)
+    }
+
+    /// Get the BSP of this group.
+    pub fn bsp(&self) -> Option<&AxVCpuRef<A>> {
+        self.vcpus.iter().find(|vcpu| vcpu.is_bsp())
+    }
+
+    /// Set what [`AxVCpuGroup::inject_interrupt`] does with interrupts targeting unavailable vcpus, and for how
+    /// long a vcpu must have been blocked to be considered unavailable, in nanoseconds. Parked vcpus are always
+    /// considered unavailable.
+    pub fn set_irq_fallback_policy(&self, policy: IrqFallbackPolicy, block_threshold_ns: u64) {
+        self.irq_fallback.set(policy);
+        self.irq_fallback_block_ns.set(block_threshold_ns);
+    }
+
+    /// Get the policy set by [`AxVCpuGroup::set_irq_fallback_policy`].
+    pub fn irq_fallback_policy(&self) -> IrqFallbackPolicy {
+        self.irq_fallback.get()
+    }
+
+    /// Queue an interrupt on a vcpu of this group (see [`AxVCpu::inject_interrupt`]), consulting the
+    /// [`IrqFallbackPolicy`] if the vcpu is unavailable, i.e., parked or blocked for too long.
+    ///
+    /// Interrupts for a vcpu running or bound to another physical CPU are injected with
+    /// [`AxVCpu::inject_interrupt_from_irq`], so `vector` must be less than
+    /// [`IRQ_BITMAP_VECTORS`](crate::IRQ_BITMAP_VECTORS) then.
+    pub fn inject_interrupt(&self, vcpu_id: usize, vector: usize) -> AxResult {
+        let Some(vcpu) = self.vcpu(vcpu_id) else {
+            return ax_err!(NotFound, format!("VCpu {} not found", vcpu_id));
+        };
+        let unavailable = vcpu.state() == VCpuState::Parked
+            || vcpu
+                .blocked_for_ns()
+                .is_some_and(|ns| ns >= self.irq_fallback_block_ns.get());
+        if !unavailable {
+            return Self::inject_into(vcpu, vector);
+        }
+        match self.irq_fallback.get() {
+            IrqFallbackPolicy::Queue => Self::inject_into(vcpu, vector),
+            IrqFallbackPolicy::Drop => {
+                vcpu.record_dropped_irq(vector);
+                vcpu_log!(Injection, Debug, vcpu = vcpu_id, vector = vector; "interrupt dropped, vcpu unavailable");
+                Ok(())
+            }
+            IrqFallbackPolicy::RouteTo(other) => match self.vcpu(other) {
+                Some(other) if other.id() != vcpu_id => {
+                    vcpu_log!(Injection, Debug, vcpu = vcpu_id, vector = vector, to = other.id(); "interrupt re-routed, vcpu unavailable");
+                    Self::inject_into(other, vector)
+                }
+                _ => Self::inject_into(vcpu, vector),
+            },
+        }
+    }
+
+    /// Queue `vector` on `vcpu`. A vcpu which may be operated on by another host context, i.e., one running or
+    /// bound to another physical CPU, is only touched through the lock-free [`AxVCpu::inject_interrupt_from_irq`].
+    fn inject_into(vcpu: &AxVCpu<A>, vector: usize) -> AxResult {
+        let local = !vcpu.is_running()
+            && vcpu
+                .bound_cpu()
+                .is_none_or(|cpu_id| cpu_id == A::Hal::current_cpu_id());
+        if local {
+            vcpu.inject_interrupt(vector)
+        } else {
+            vcpu.inject_interrupt_from_irq(vector)
+        }
+    }
+
+    /// Decode a message signaled interrupt and queue it on the destination vcpu(s).
+    ///
+    /// Interrupts with a single destination are subject to the [`IrqFallbackPolicy`], see
+    /// [`AxVCpuGroup::inject_interrupt`].
+    ///
+    /// The interrupt is injected into the guest the next time the destination vcpu runs.
+    pub fn deliver_msi(&self, msg: MsiMessage) -> AxResult {
+        let Some(target) = self.msi_decoder.decode(&msg) else {
+            return ax_err!(InvalidInput, format!("Malformed MSI message {:x?}", msg));
+        };
+        match target.dest {
+            MsiDestination::Single(dest) => match self.vcpu_by_arch_id(dest) {
+                Some(vcpu) => self.inject_interrupt(vcpu.id(), target.vector),
+                None => ax_err!(NotFound, format!("MSI destination {} not found", dest)),
+            },
+            MsiDestination::Broadcast => self
+                .vcpus
+                .iter()
+                .try_for_each(|vcpu| Self::inject_into(vcpu, target.vector)),
+        }
+    }
+
+    /// Report a DMA fault caused by a passthrough device of this VM.
+    ///
+    /// This method is intended to be called by the host IOMMU fault handler, and may be called from interrupt
+    /// context. The fault is surfaced as an [`AxVCpuExitReason::IommuFault`] returned by the next [`AxVCpu::run`]
+    /// of the BSP, so that the VMM handles all DMA faults of a VM in one place.
+    ///
+    /// Only one fault is held until the BSP picks it up: `ResourceBusy` is returned, and the fault discarded, if
+    /// an earlier one is still pending.
+    pub fn report_iommu_fault(
+        &self,
+        device: u32,
+        addr: GuestPhysAddr,
+        flags: MappingFlags,
+    ) -> AxResult {
+        let Some(bsp) = self.bsp() else {
+            return ax_err!(NotFound, "BSP not found");
+        };
+        bsp.queue_iommu_fault_from_irq(device, addr, flags)
+    }
+
+    /// Combine the counters of all vcpus in this group, e.g., to attribute the CPU cost of the VM on multi-tenant
+    /// hosts.
+    ///
+    /// Each call also samples the total number of exits, so that [`GroupStats::exit_rate`] is the average exit
+    /// rate since the previous call, and [`GroupStats::exit_rate_change`] its trend. Calling this method
+    /// periodically (e.g., from a monitoring timer) yields per-VM exit rate trends.
+    pub fn aggregate_stats(&self) -> GroupStats {
+        let mut stats = GroupStats::new(self.vcpus.len());
+        for vcpu in self {
+            stats.add(&vcpu.stats());
+            stats.steal_ns += vcpu.steal_time();
+            stats.exits_per_sec += vcpu.load_hint().exits_per_sec;
+        }
+        let now = A::Hal::current_time_nanos();
+        let total_exits = stats.total_exits();
+        if let Some((time, exits, rate)) = self.stats_sample.get() {
+            let elapsed = now.saturating_sub(time);
+            if elapsed > 0 {
+                stats.exit_rate = (total_exits.saturating_sub(exits) as u128 * 1_000_000_000
+                    / elapsed as u128) as u64;
+            }
+            stats.previous_exit_rate = rate;
+        }
+        self.stats_sample
+            .set(Some((now, total_exits, stats.exit_rate)));
+        stats
+    }
+
+    /// Set how the final counters of all vcpus in this group are reported when they're dropped, see
+    /// [`AxVCpu::set_final_stats_report`].
+    pub fn set_final_stats_report(&self, report: FinalStatsReport) {
+        for vcpu in &self.vcpus {
+            vcpu.set_final_stats_report(report.clone());
+        }
+    }
+
+    /// Set what all vcpus in this group do with MMIO accesses to addresses without a device, see
+    /// [`AxVCpu::set_unhandled_mmio_policy`].
+    pub fn set_unhandled_mmio_policy(&self, policy: UnhandledMmioPolicy) {
+        for vcpu in &self.vcpus {
+            vcpu.set_unhandled_mmio_policy(policy);
+        }
+    }
+
+    /// Set how all vcpus in this group treat each kind of exit, see [`AxVCpu::set_exit_policy`].
+    pub fn set_exit_policy(&self, policy: ExitPolicy) {
+        for vcpu in &self.vcpus {
+            vcpu.set_exit_policy(policy);
+        }
+    }
+
+    /// Put all vcpus in this group in sandbox mode with the same allowlist, see [`AxVCpu::enable_sandbox`].
+    pub fn enable_sandbox(&self, config: &SandboxConfig) {
+        for vcpu in &self.vcpus {
+            vcpu.enable_sandbox(config.clone());
+        }
+    }
+
+    /// Invalidate the decoded instruction caches of all vcpus in this group. Must be called after the stage-2
+    /// mappings or permissions of the VM are changed.
+    pub fn invalidate_decode_caches(&self) {
+        for vcpu in &self.vcpus {
+            vcpu.invalidate_decode_cache();
+        }
+    }
+
+    /// Synchronize the guest clocks of all vcpus in this group.
+    ///
+    /// The current host time is captured as the reference point, and the time offset of every vcpu is set to the
+    /// reference point minus the current guest clock of the BSP (see [`AxVCpu::guest_time_ns`]), so that all vcpus
+    /// (including secondary ones started later by [`AxVCpuExitReason::CpuUp`]) observe the guest clock of the BSP.
+    /// The guest clocks start from zero if the group has no BSP. Paused vcpus (see [`AxVCpu::pause_time`]) stay
+    /// paused at the synchronized guest clock, and resume from it.
+    ///
+    /// Returns the reference point in nanoseconds.
+    pub fn sync_time(&self) -> u64 {
+        let reference = A::Hal::current_time_nanos();
+        let bsp_guest_time = self.bsp().map_or(0, |bsp| bsp.guest_time_ns());
+        for vcpu in &self.vcpus {
+            vcpu.set_guest_time_at(bsp_guest_time, reference);
+        }
+        reference
+    }
+
+    /// Set the vector injected into a vcpu after its para-virtualized time page is updated by
+    /// [`AxVCpuGroup::notify_time_jump`]. If `None`, no interrupt is injected.
+    pub fn set_pv_time_vector(&self, vector: Option<usize>) {
+        self.pv_time.borrow_mut().vector = vector;
+    }
+
+    /// Register the para-virtualized time page of a vcpu, generally on behalf of a hypercall of the guest.
+    ///
+    /// # Safety
+    ///
+    /// `page` must point to a valid, writable [`PvTimeJumpInfo`](crate::PvTimeJumpInfo) mapped to the guest, and
+    /// stay valid until it's unregistered or this group is dropped.
+    pub unsafe fn register_pv_time_page(&self, vcpu_id: usize, page: HostVirtAddr) -> AxResult {
+        if self.vcpu(vcpu_id).is_none() {
+            return ax_err!(NotFound, format!("VCpu {} not found", vcpu_id));
+        }
+        if page.as_usize() & (core::mem::align_of::<crate::PvTimeJumpInfo>() - 1) != 0 {
+            return ax_err!(InvalidInput, "PV time page is not aligned");
+        }
+        self.pv_time.borrow_mut().pages.insert(vcpu_id, page);
+        Ok(())
+    }
+
+    /// Unregister the para-virtualized time page of a vcpu.
+    pub fn unregister_pv_time_page(&self, vcpu_id: usize) {
+        self.pv_time.borrow_mut().pages.remove(&vcpu_id);
+    }
+
+    /// Notify the guest that the host time jumped by `delta_ns` nanoseconds, e.g., after host suspend or migration.
+    ///
+    /// The jump is recorded in every registered para-virtualized time page, and the vector set by
+    /// [`AxVCpuGroup::set_pv_time_vector`] is injected into the corresponding vcpus, so that the guest can
+    /// resynchronize its clock.
+    pub fn notify_time_jump(&self, delta_ns: i64) -> AxResult {
+        let pv_time = self.pv_time.borrow();
+        for (&vcpu_id, &page) in &pv_time.pages {
+            // SAFETY: `page` is guaranteed to be valid by the caller of `register_pv_time_page`.
+            unsafe { PvTimePages::write_jump(page, delta_ns) };
+            if let (Some(vector), Some(vcpu)) = (pv_time.vector, self.vcpu(vcpu_id)) {
+                Self::inject_into(vcpu, vector)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set what [`AxVCpuGroup::handle_cpu_down`] does with the pending interrupts of the vcpu powered off.
+    pub fn set_cpu_down_irq_policy(&self, policy: CpuDownIrqPolicy) {
+        self.cpu_down_irq_policy.set(policy);
+    }
+
+    /// Handle an [`AxVCpuExitReason::CpuDown`] exit of a vcpu: park the vcpu (see [`AxVCpu::park`]) with the
+    /// power state requested by the guest, and drop or re-route its pending interrupts according to the policy
+    /// set by [`AxVCpuGroup::set_cpu_down_irq_policy`].
+    ///
+    /// The vcpu is resumed by [`AxVCpuGroup::handle_cpu_up`].
+    pub fn handle_cpu_down(&self, vcpu_id: usize, state: u64) -> AxResult {
+        let Some(vcpu) = self.vcpu(vcpu_id) else {
+            return ax_err!(NotFound, format!("VCpu {} not found", vcpu_id));
+        };
+        vcpu.park(state)?;
+        let irqs = vcpu.take_pending_irqs();
+        match (self.cpu_down_irq_policy.get(), self.bsp()) {
+            (CpuDownIrqPolicy::RerouteToBsp, Some(bsp)) if bsp.id() != vcpu_id => irqs
+                .into_iter()
+                .try_for_each(|vector| Self::inject_into(bsp, vector)),
+            _ => {
+                irqs.into_iter()
+                    .for_each(|vector| vcpu.record_dropped_irq(vector));
+                Ok(())
+            }
+        }
+    }
+
+    /// Handle an [`AxVCpuExitReason::CpuUp`] exit targeting a vcpu parked by [`AxVCpuGroup::handle_cpu_down`]:
+    /// resume it at `entry_point` with `arg` (see [`AxVCpu::unpark`]).
+    ///
+    /// `target_cpu` is the architectural id of the vcpu. Returns the resumed vcpu, to be scheduled by the caller.
+    pub fn handle_cpu_up(
+        &self,
+        target_cpu: u64,
+        entry_point: GuestPhysAddr,
+        arg: u64,
+    ) -> AxResult<&AxVCpuRef<A>> {
+        let Some(vcpu) = self.vcpu_by_arch_id(target_cpu) else {
+            return ax_err!(NotFound, format!("VCpu {:#x} not found", target_cpu));
+        };
+        vcpu.unpark(entry_point, arg)?;
+        Ok(vcpu)
+    }
+
+    /// Stop all vcpus of this group within `timeout_ns` nanoseconds, e.g., before the VM is destroyed.
+    ///
+    /// Every vcpu is requested to stop with [`AxVCpu::request_stop`], which kicks the running ones out of the guest,
+    /// then this method waits until no vcpu is running. On timeout, the vcpus still running are returned with
+    /// their last exits, so that the caller can report them before tearing the VM down anyway.
+    pub fn stop_all(&self, timeout_ns: u64) -> Result<(), Vec<StuckVCpu>> {
+        for vcpu in &self.vcpus {
+            vcpu.request_stop();
+        }
+        self.wait_out_of_guest(timeout_ns, "vcpu failed to stop")
+    }
+
+    /// Request all vcpus of this group to be outside guest mode within `timeout_ns` nanoseconds, e.g., before the
+    /// stage-2 page table is modified, a device is hot-plugged, or a consistent snapshot is taken.
+    ///
+    /// Running vcpus are kicked out of the guest (see [`AxVCpuHal::kick_vcpu`]), and [`AxVCpu::run`] returns
+    /// [`AxVCpuError::Quiesced`](crate::AxVCpuError::Quiesced) instead of entering the guest until
+    /// [`AxVCpuGroup::unquiesce`] is called. On timeout, the vcpus still running are returned, and the group stays
+    /// quiesced.
+    pub fn quiesce(&self, timeout_ns: u64) -> Result<(), Vec<StuckVCpu>> {
+        for vcpu in &self.vcpus {
+            vcpu.set_quiesced(true);
+        }
+        self.wait_out_of_guest(timeout_ns, "vcpu failed to quiesce")
+    }
+
+    /// Release the vcpus quiesced by [`AxVCpuGroup::quiesce`], notifying them with [`AxVCpuHal::notify_vcpu`].
+    pub fn unquiesce(&self) {
+        for vcpu in &self.vcpus {
+            vcpu.set_quiesced(false);
+        }
+    }
+
+    /// Wait up to `timeout_ns` nanoseconds until no vcpu of this group is running, yielding with
+    /// [`AxVCpuHal::yield_now`] between polls, and reporting the ones still running on timeout.
+    #[cfg_attr(not(feature = "log"), allow(unused_variables))]
+    fn wait_out_of_guest(&self, timeout_ns: u64, msg: &str) -> Result<(), Vec<StuckVCpu>> {
+        let start = A::Hal::current_time_nanos();
+        while self.running().next().is_some() {
+            if A::Hal::current_time_nanos().saturating_sub(start) >= timeout_ns {
+                let stuck: Vec<_> = self
+                    .running()
+                    .map(|vcpu| StuckVCpu {
+                        vcpu_id: vcpu.id(),
+                        last_exit: vcpu.last_exit_kind(),
+                    })
+                    .collect();
+                #[cfg_attr(not(feature = "log"), allow(unused_variables))]
+                for stuck in &stuck {
+                    vcpu_log!(State, Error, vcpu = stuck.vcpu_id, last_exit:? = stuck.last_exit; "{}", msg);
+                }
+                return Err(stuck);
+            }
+            A::Hal::yield_now();
+        }
+        Ok(())
+    }
+
+    /// Set how many resets within `window_ns` nanoseconds [`AxVCpuGroup::check_reboot_storm`] considers a reboot
+    /// storm. A `threshold` of 0 disables the detection. Defaults to
+    /// [`REBOOT_STORM_DEFAULT_THRESHOLD`](crate::REBOOT_STORM_DEFAULT_THRESHOLD) resets within
+    /// [`REBOOT_STORM_DEFAULT_WINDOW_NS`](crate::REBOOT_STORM_DEFAULT_WINDOW_NS).
+    pub fn set_reboot_storm_threshold(&self, threshold: usize, window_ns: u64) {
+        let mut reboot_storm = self.reboot_storm.borrow_mut();
+        reboot_storm.threshold = threshold;
+        reboot_storm.window_ns = window_ns;
+        reboot_storm.clear();
+    }
+
+    /// Record an exit returned by [`AxVCpu::run`] on any vcpu of this group, and check whether the guest is
+    /// crash-looping.
+    ///
+    /// [`AxVCpuExitReason::SystemReset`] and [`AxVCpuExitReason::SystemDown`] exits are recorded as resets (the
+    /// latter being how many guests reboot when the VMM restarts them on power-off), other exits are ignored. If
+    /// the threshold set by [`AxVCpuGroup::set_reboot_storm_threshold`] is reached, the [`RebootStorm`] is returned,
+    /// and the caller should stop the VM rather than restarting it again.
+    pub fn check_reboot_storm(&self, exit: &AxVCpuExitReason) -> Option<RebootStorm> {
+        if !matches!(
+            exit,
+            AxVCpuExitReason::SystemReset | AxVCpuExitReason::SystemDown
+        ) {
+            return None;
+        }
+        let storm = self
+            .reboot_storm
+            .borrow_mut()
+            .record(exit.kind(), A::Hal::current_time_nanos())?;
+        vcpu_log!(State, Warn, count = storm.count, window_ns = storm.window_ns, last:? = storm.last; "reboot storm detected");
+        Some(storm)
+    }
+
+    /// Forget the resets recorded by [`AxVCpuGroup::check_reboot_storm`], e.g., after the guest is restarted
+    /// with a fixed configuration.
+    pub fn clear_reboot_storm(&self) {
+        self.reboot_storm.borrow_mut().clear();
+    }
+
+    /// Set the window within which all vcpus of this group must arrive at [`AxVCpuGroup::run_lockstep`], in
+    /// nanoseconds. Defaults to [`LOCKSTEP_DEFAULT_WINDOW_NS`](crate::LOCKSTEP_DEFAULT_WINDOW_NS).
+    pub fn set_lockstep_window(&self, window_ns: u64) {
+        self.lockstep.set_window(window_ns);
+    }
+
+    /// Get a handle to the lockstep barrier of this group, see [`AxVCpuGroup::run_lockstep`].
+    pub fn lockstep_handle(&self) -> LockstepHandle<A::Hal> {
+        self.lockstep.clone()
+    }
+
+    /// Run a vcpu of this group in lockstep with the others, for TSC-sensitive or gang-scheduled workloads.
+    ///
+    /// Waits (with [`AxVCpuHal::wait_for_notification`]) until all vcpus have arrived, then runs the vcpu with
+    /// [`AxVCpu::run`], so that all vcpus enter the guest together. If the others don't arrive within the window
+    /// set by [`AxVCpuGroup::set_lockstep_window`], `WouldBlock` is returned without running the vcpu.
+    ///
+    /// Every vcpu of the group is expected to arrive from its own host context. The group is not `Sync`, so host
+    /// contexts not sharing it call [`LockstepHandle::wait`] on a handle from [`AxVCpuGroup::lockstep_handle`]
+    /// instead, then run their vcpu: all arrivals count towards the same barrier.
+    pub fn run_lockstep(&self, vcpu_id: usize, token: &RunToken) -> AxVCpuResult<AxVCpuExitReason> {
+        let Some(vcpu) = self.vcpu(vcpu_id) else {
+            return Err(ax_err_type!(NotFound, format!("VCpu {} not found", vcpu_id)).into());
+        };
+        self.lockstep.wait(vcpu_id)?;
+        vcpu.run(token)
+    }
+}
+
+impl<'a, A: AxArchVCpu> IntoIterator for &'a AxVCpuGroup<A> {
+    type Item = &'a AxVCpuRef<A>;
+    type IntoIter = core::slice::Iter<'a, AxVCpuRef<A>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
@@ -0,0 +1,74 @@
+use crate::AccessWidth;
+
+/// The execution mode of the guest, for running 32-bit guests on 64-bit hosts.
+///
+/// Set with [`AxVCpuBuilder::guest_mode`](crate::AxVCpuBuilder::guest_mode) or
+/// [`AxVCpu::set_entry_with_mode`](crate::AxVCpu::set_entry_with_mode), and passed to the architecture-specific vcpu
+/// with [`AxArchVCpu::set_guest_mode`](crate::AxArchVCpu::set_guest_mode).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GuestMode {
+    /// The native mode of the host architecture, i.e., Aarch64, x86-64 long mode, or RV64.
+    #[default]
+    Native,
+    /// AArch32 (EL1 in AArch32 state) on an Aarch64 host.
+    Aarch32,
+    /// 32-bit protected mode (including compatibility mode) on an x86-64 host.
+    Compat32,
+    /// RV32 (`VSXL`/`SXL` of 32 bits) on an RV64 host.
+    Rv32,
+}
+
+impl GuestMode {
+    /// Whether the guest runs with 32-bit general-purpose registers.
+    pub const fn is_32bit(self) -> bool {
+        !matches!(self, Self::Native) || usize::BITS == 32
+    }
+
+    /// Get the width of the general-purpose registers of the guest.
+    pub const fn gpr_width(self) -> AccessWidth {
+        if self.is_32bit() {
+            AccessWidth::Dword
+        } else {
+            AccessWidth::Qword
+        }
+    }
+
+    /// Get the mask of the bits of a general-purpose register visible to the guest.
+    pub const fn gpr_mask(self) -> usize {
+        if self.is_32bit() {
+            u32::MAX as usize
+        } else {
+            usize::MAX
+        }
+    }
+}
+
+/// The byte order of the data accesses of the guest.
+///
+/// Set with [`AxVCpuBuilder::guest_endian`](crate::AxVCpuBuilder::guest_endian), and passed to the
+/// architecture-specific vcpu with [`AxArchVCpu::set_guest_endian`](crate::AxArchVCpu::set_guest_endian).
+///
+/// Device models always see MMIO data in bus (little-endian) order: for big-endian guests,
+/// [`AxVCpu::run`](crate::AxVCpu::run) byte-swaps the data of [`AxVCpuExitReason::MmioWrite`](crate::AxVCpuExitReason::MmioWrite)
+/// exits, and the completions of [`AxVCpuExitReason::MmioRead`](crate::AxVCpuExitReason::MmioRead) exits (see
+/// [`AxVCpu::complete_mmio_read`](crate::AxVCpu::complete_mmio_read)) are byte-swapped before being stored into
+/// the target register.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GuestEndian {
+    /// Little-endian, the default of all supported architectures.
+    #[default]
+    Little,
+    /// Big-endian, e.g., an Aarch64 guest with `SCTLR_EL1.EE` set.
+    Big,
+}
+
+impl GuestEndian {
+    /// Convert the `width` low bytes of `data` between the byte order of the guest and the bus (little-endian)
+    /// order. The conversion is its own inverse.
+    pub fn swap_mmio(self, data: u64, width: AccessWidth) -> u64 {
+        match self {
+            Self::Little => data,
+            Self::Big => data.swap_bytes() >> (64 - width.bits_range().end),
+        }
+    }
+}
@@ -1,4 +1,9 @@
+use core::ops::Range;
+
 use axaddrspace::{HostPhysAddr, HostVirtAddr};
+use axerrno::{AxResult, ax_err};
+
+use crate::{AxVCpuError, CacheOp};
 
 /// The interfaces which the underlying software (kernel or hypervisor) must implement.
 pub trait AxVCpuHal {
@@ -16,6 +21,60 @@ pub trait AxVCpuHal {
     /// * `paddr` - The physical address of the frame to deallocate.
     fn dealloc_frame(paddr: HostPhysAddr);
 
+    /// Allocates a frame, preferably from the memory of the given NUMA node, and returns its host physical address.
+    ///
+    /// Architecture-specific vcpus should use it for per-vcpu memory, passing the
+    /// [`numa_node`](crate::VCpuCreateContext::numa_node) they are created with. The default implementation
+    /// ignores `node` and calls [`AxVCpuHal::alloc_frame`].
+    ///
+    /// # Parameters
+    ///
+    /// * `node` - The preferred NUMA node, `None` for no preference.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<HostPhysAddr>` - Some containing the physical address of the allocated frame, or None if allocation fails.
+    fn alloc_frame_on_node(node: Option<usize>) -> Option<HostPhysAddr> {
+        let _ = node;
+        Self::alloc_frame()
+    }
+
+    /// Allocates `num` physically contiguous frames, preferably from the memory of the given NUMA node, and returns
+    /// the host physical address of the first one.
+    ///
+    /// Used for buffers larger than a frame, e.g., [`ExtStateBuffer`](crate::ExtStateBuffer). The default
+    /// implementation only supports `num == 1`, and calls [`AxVCpuHal::alloc_frame_on_node`].
+    ///
+    /// # Parameters
+    ///
+    /// * `num` - The number of frames.
+    /// * `node` - The preferred NUMA node, `None` for no preference.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<HostPhysAddr>` - Some containing the physical address of the first frame, or None if allocation fails.
+    fn alloc_contiguous_frames(num: usize, node: Option<usize>) -> Option<HostPhysAddr> {
+        if num == 1 {
+            Self::alloc_frame_on_node(node)
+        } else {
+            None
+        }
+    }
+
+    /// Deallocates frames allocated by [`AxVCpuHal::alloc_contiguous_frames`].
+    ///
+    /// The default implementation only supports `num == 1`, and calls [`AxVCpuHal::dealloc_frame`].
+    ///
+    /// # Parameters
+    ///
+    /// * `paddr` - The physical address of the first frame.
+    /// * `num` - The number of frames.
+    fn dealloc_contiguous_frames(paddr: HostPhysAddr, num: usize) {
+        if num == 1 {
+            Self::dealloc_frame(paddr);
+        }
+    }
+
     /// Converts a host physical address to a host virtual address.
     ///
     /// # Parameters
@@ -40,15 +99,243 @@ pub trait AxVCpuHal {
 
     /// Fetches current interrupt (IRQ) number.
     ///
+    /// Used by [`AxVCpu::run`](crate::AxVCpu::run) when host interrupts are handled automatically, see
+    /// [`AxVCpu::set_auto_handle_host_irqs`](crate::AxVCpu::set_auto_handle_host_irqs).
+    ///
     /// # Returns
     ///
     /// * `usize` - The current IRQ number.
-    fn irq_fetch() -> usize {
+    fn irq_fetch() -> usize;
+
+    /// Dispatch an interrupt request (IRQ) to the underlying host OS.
+    ///
+    /// Used by [`AxVCpu::run`](crate::AxVCpu::run) when host interrupts are handled automatically, see
+    /// [`AxVCpu::set_auto_handle_host_irqs`](crate::AxVCpu::set_auto_handle_host_irqs).
+    fn irq_hanlder();
+
+    /// Returns the current time of the host in nanoseconds, from a monotonic clock.
+    ///
+    /// Used to compensate guest time across pauses. The default implementation returns 0, which
+    /// disables the compensation.
+    fn current_time_nanos() -> u64 {
         0
     }
 
-    /// Dispatch an interrupt request (IRQ) to the underlying host OS.
-    fn irq_hanlder() {
-        unimplemented!("irq_handler is not implemented");
+    /// Writes the output of a guest to the host console, used by the built-in para-virtualized console handler
+    /// (see [`PvConsole`](crate::PvConsole)). The default implementation discards the output.
+    fn console_write(bytes: &[u8]) {
+        let _ = bytes;
+    }
+
+    /// Returns the raw cycle counter of the current physical CPU (`RDTSC` in x86, `CNTVCT_EL0` or `PMCCNTR_EL0` in
+    /// Aarch64, `rdcycle` in RISC-V), for micro-architectural analysis of exits.
+    ///
+    /// Read right after every exit, so it should be as cheap as a single instruction. The default implementation
+    /// returns 0.
+    fn read_cycle_counter() -> u64 {
+        0
+    }
+
+    /// Notifies a vcpu: wakes it up if it's waiting in [`AxVCpuHal::wait_for_notification`].
+    ///
+    /// # Parameters
+    ///
+    /// * `vcpu_id` - The id of the vcpu to notify.
+    fn notify_vcpu(vcpu_id: usize) {
+        let _ = vcpu_id;
+    }
+
+    /// Forces a vcpu out of the guest as soon as possible, e.g., by sending an IPI to the physical CPU it runs on.
+    /// Used by [`AxVCpu::request_stop`](crate::AxVCpu::request_stop).
+    ///
+    /// The default implementation does nothing, in which case the vcpu only stops at its next vm-exit.
+    ///
+    /// # Parameters
+    ///
+    /// * `vm_id` - The id of the VM the vcpu belongs to.
+    /// * `vcpu_id` - The id of the vcpu to kick.
+    fn kick_vcpu(vm_id: usize, vcpu_id: usize) {
+        let _ = (vm_id, vcpu_id);
+    }
+
+    /// Blocks the current host context until the vcpu is notified by [`AxVCpuHal::notify_vcpu`].
+    ///
+    /// Spurious wakeups are allowed. The default implementation returns immediately.
+    ///
+    /// # Parameters
+    ///
+    /// * `vcpu_id` - The id of the vcpu waiting for notification.
+    fn wait_for_notification(vcpu_id: usize) {
+        let _ = vcpu_id;
+        core::hint::spin_loop();
+    }
+
+    /// Yields the current host context between two polls of a condition which may take a while, e.g., while
+    /// [`AxVCpuGroup::quiesce`](crate::AxVCpuGroup::quiesce) waits for the vcpus to leave the guest.
+    ///
+    /// The default implementation is a spin-loop hint.
+    fn yield_now() {
+        core::hint::spin_loop();
+    }
+
+    /// Returns the id of the current physical CPU.
+    ///
+    /// It is checked against the physical CPU set of the vcpu in [`AxVCpu::bind`](crate::AxVCpu::bind), and used
+    /// to tell whether a vcpu is operated on from the CPU it's bound to.
+    ///
+    /// # Returns
+    ///
+    /// * `usize` - The id of the current physical CPU, which must be unique among the CPUs of the host.
+    fn current_cpu_id() -> usize;
+
+    /// Returns the NUMA node of a physical CPU, or `None` if unknown.
+    ///
+    /// The default implementation returns `None`.
+    ///
+    /// # Parameters
+    ///
+    /// * `cpu_id` - The id of the physical CPU.
+    fn node_of_cpu(cpu_id: usize) -> Option<usize> {
+        let _ = cpu_id;
+        None
+    }
+
+    /// Notifies the host that a vcpu has been bound to the current physical CPU, so that host-side subsystems
+    /// (e.g., lazy FPU switching, per-CPU caches, performance counters) can react.
+    ///
+    /// Called by [`AxVCpu::bind`](crate::AxVCpu::bind) after the vcpu is bound. The default implementation does
+    /// nothing.
+    ///
+    /// # Parameters
+    ///
+    /// * `cpu_id` - The id of the physical CPU, as returned by [`AxVCpuHal::current_cpu_id`].
+    /// * `vm_id` - The id of the VM the vcpu belongs to.
+    /// * `vcpu_id` - The id of the vcpu.
+    fn on_vcpu_bind(cpu_id: usize, vm_id: usize, vcpu_id: usize) {
+        let _ = (cpu_id, vm_id, vcpu_id);
+    }
+
+    /// Notifies the host that a vcpu has been unbound from the current physical CPU.
+    ///
+    /// Called by [`AxVCpu::unbind`](crate::AxVCpu::unbind) after the vcpu is unbound. The default implementation
+    /// does nothing.
+    ///
+    /// # Parameters
+    ///
+    /// * `cpu_id` - The id of the physical CPU, as returned by [`AxVCpuHal::current_cpu_id`].
+    /// * `vm_id` - The id of the VM the vcpu belongs to.
+    /// * `vcpu_id` - The id of the vcpu.
+    fn on_vcpu_unbind(cpu_id: usize, vm_id: usize, vcpu_id: usize) {
+        let _ = (cpu_id, vm_id, vcpu_id);
+    }
+
+    /// Applies a CPU frequency (performance) level requested by a guest, see
+    /// [`AxVCpuExitReason::CpuFreqRequest`](crate::AxVCpuExitReason::CpuFreqRequest).
+    ///
+    /// Energy-aware hosts can propagate the request to the physical CPU, e.g., by aggregating the requests of all
+    /// vcpus running on it. The default implementation ignores the request.
+    ///
+    /// # Parameters
+    ///
+    /// * `cpu_id` - The id of the physical CPU the requesting vcpu is bound to.
+    /// * `vcpu_id` - The id of the requesting vcpu.
+    /// * `level` - The requested performance level.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether the request is applied.
+    fn apply_cpu_freq_request(cpu_id: usize, vcpu_id: usize, level: u32) -> bool {
+        let _ = (cpu_id, vcpu_id, level);
+        false
+    }
+
+    /// Pins a vcpu to a physical CPU, or unpins it if `cpu_id` is `None`, in the host scheduler.
+    ///
+    /// The default implementation returns `Unsupported`.
+    ///
+    /// # Parameters
+    ///
+    /// * `vcpu_id` - The id of the vcpu.
+    /// * `cpu_id` - The id of the physical CPU to pin the vcpu to.
+    fn pin_vcpu(vcpu_id: usize, cpu_id: Option<usize>) -> AxResult {
+        let _ = (vcpu_id, cpu_id);
+        ax_err!(Unsupported, "vcpu pinning is not supported")
+    }
+
+    /// Masks (`isolate` is `true`) or unmasks non-essential host interrupts on a physical CPU, so that a real-time
+    /// vcpu running on it is not disturbed by host devices.
+    ///
+    /// The default implementation returns `Unsupported`.
+    ///
+    /// # Parameters
+    ///
+    /// * `cpu_id` - The id of the physical CPU.
+    /// * `isolate` - Whether to mask non-essential host interrupts.
+    fn isolate_host_irqs(cpu_id: usize, isolate: bool) -> AxResult {
+        let _ = (cpu_id, isolate);
+        ax_err!(Unsupported, "host interrupt isolation is not supported")
+    }
+
+    /// Issues an SMC call to the secure monitor of the host, used by [`SecureCallProxy`](crate::SecureCallProxy)
+    /// to forward the secure calls of guests.
+    ///
+    /// The default implementation returns `Unsupported`.
+    ///
+    /// # Parameters
+    ///
+    /// * `func_id` - The SMCCC function id, in `w0`.
+    /// * `args` - The arguments, in `x1`-`x6`.
+    ///
+    /// # Returns
+    ///
+    /// * `AxResult<[u64; 4]>` - The results in `x0`-`x3`.
+    fn smc_call(func_id: u32, args: &[u64; 6]) -> AxResult<[u64; 4]> {
+        let _ = (func_id, args);
+        ax_err!(Unsupported, "SMC calls are not supported")
+    }
+
+    /// Runs `work` for each index in `0..count`, possibly concurrently on several physical CPUs, and returns once
+    /// all of them are done. Used to create the vcpus of large VMs in parallel, see
+    /// [`AxVCpuGroup::create_vcpus`](crate::AxVCpuGroup::create_vcpus).
+    ///
+    /// Each index must be run exactly once. The default implementation runs them serially on the current CPU.
+    ///
+    /// # Parameters
+    ///
+    /// * `count` - The number of work items.
+    /// * `work` - The work, called with the index of the item.
+    fn parallel_for(count: usize, work: &(dyn Fn(usize) + Sync)) {
+        (0..count).for_each(work);
+    }
+
+    /// Performs a data cache maintenance operation on a range of host physical memory, on behalf of a guest
+    /// whose cache maintenance operations are trapped, see [`AxVCpu::handle_cache_maintenance`](crate::AxVCpu::handle_cache_maintenance).
+    ///
+    /// The operation must reach the point of coherency, so that it's visible to non-coherent DMA devices. The
+    /// default implementation returns `Unsupported`.
+    ///
+    /// # Parameters
+    ///
+    /// * `range` - The range of host physical memory, not necessarily aligned to cache lines.
+    /// * `op` - The operation.
+    fn cache_maintain(range: Range<HostPhysAddr>, op: CacheOp) -> AxResult {
+        let _ = (range, op);
+        ax_err!(Unsupported, "cache maintenance is not supported")
+    }
+
+    /// Called when a vcpu hits an unrecoverable error, e.g., the architecture-specific vcpu failed to run and the
+    /// vcpu became [`VCpuState::Invalid`](crate::VCpuState::Invalid), or it's run without being bound.
+    ///
+    /// The host decides what to do: kill the VM, halt the host (e.g., by panicking), or log and continue. When
+    /// this method returns, the error is returned to the caller of the failed operation, and the vcpu is left
+    /// unusable. The default implementation does nothing, the error being logged by the crate.
+    ///
+    /// # Parameters
+    ///
+    /// * `vm_id` - The id of the VM the vcpu belongs to.
+    /// * `vcpu_id` - The id of the vcpu.
+    /// * `err` - The error.
+    fn on_fatal_vcpu_error(vm_id: usize, vcpu_id: usize, err: &AxVCpuError) {
+        let _ = (vm_id, vcpu_id, err);
     }
 }
@@ -0,0 +1,144 @@
+/// The tunables of the adaptive halt-polling, following the `halt_poll_ns*` parameters of KVM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HaltPollConfig {
+    /// The maximum polling window in nanoseconds. 0 disables halt-polling.
+    pub max_ns: u64,
+    /// The factor by which the polling window grows.
+    pub grow: u64,
+    /// The polling window set when growing from 0.
+    pub grow_start_ns: u64,
+    /// The divisor by which the polling window shrinks. 0 resets the window to 0.
+    pub shrink: u64,
+}
+
+impl Default for HaltPollConfig {
+    fn default() -> Self {
+        Self {
+            max_ns: 200_000,
+            grow: 2,
+            grow_start_ns: 10_000,
+            shrink: 0,
+        }
+    }
+}
+
+/// The statistics of the adaptive halt-polling of a vcpu.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HaltPollStats {
+    /// The number of halts ended by an interrupt arriving during polling.
+    pub successful_polls: u64,
+    /// The number of halts where no interrupt arrived during polling.
+    pub failed_polls: u64,
+    /// The number of times the vcpu actually blocked.
+    pub blocks: u64,
+    /// The total time spent polling successfully, in nanoseconds.
+    pub successful_poll_ns: u64,
+    /// The total time spent polling unsuccessfully, in nanoseconds.
+    pub failed_poll_ns: u64,
+    /// The number of times the polling window grew.
+    pub grows: u64,
+    /// The number of times the polling window shrank.
+    pub shrinks: u64,
+    /// The current polling window in nanoseconds.
+    pub window_ns: u64,
+}
+
+/// The per-vcpu state of the adaptive halt-polling.
+#[derive(Debug, Default)]
+pub(crate) struct HaltPoll {
+    /// The tunables.
+    pub config: HaltPollConfig,
+    /// The statistics, including the current polling window.
+    pub stats: HaltPollStats,
+}
+
+impl HaltPoll {
+    /// Record a successful poll which took `poll_ns` nanoseconds.
+    pub fn record_poll_success(&mut self, poll_ns: u64) {
+        self.stats.successful_polls += 1;
+        self.stats.successful_poll_ns += poll_ns;
+    }
+
+    /// Record a block which took `block_ns` nanoseconds in total (including the failed poll of
+    /// `poll_ns` nanoseconds), and adjust the polling window the way KVM does.
+    pub fn record_block(&mut self, poll_ns: u64, block_ns: u64) {
+        if poll_ns > 0 {
+            self.stats.failed_polls += 1;
+            self.stats.failed_poll_ns += poll_ns;
+        }
+        self.stats.blocks += 1;
+
+        let window = self.stats.window_ns;
+        let max = self.config.max_ns;
+        if block_ns <= window {
+            // The window was long enough, but the interrupt arrived right after polling stopped.
+        } else if window > 0 && block_ns > max {
+            // Polling could not have helped, stop wasting CPU time.
+            self.stats.window_ns = match self.config.shrink {
+                0 => 0,
+                shrink => window / shrink,
+            };
+            self.stats.shrinks += 1;
+        } else if window < max && block_ns < max {
+            // A longer window would have caught the interrupt.
+            let grown = window
+                .saturating_mul(self.config.grow)
+                .max(self.config.grow_start_ns);
+            self.stats.window_ns = grown.min(max);
+            self.stats.grows += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_grows_up_to_the_maximum() {
+        let mut poll = HaltPoll::default();
+        poll.record_block(0, 5_000);
+        assert_eq!(poll.stats.window_ns, 10_000);
+        poll.record_block(10_000, 15_000);
+        assert_eq!(poll.stats.window_ns, 20_000);
+        for _ in 0..4 {
+            poll.record_block(poll.stats.window_ns, 190_000);
+        }
+        assert_eq!(poll.stats.window_ns, 200_000);
+        assert_eq!(poll.stats.grows, 6);
+        assert_eq!(poll.stats.failed_polls, 5);
+        assert_eq!(poll.stats.blocks, 6);
+    }
+
+    #[test]
+    fn window_shrinks_on_long_blocks() {
+        let mut poll = HaltPoll::default();
+        // Polling could not have helped, but there is no window to shrink.
+        poll.record_block(0, 1_000_000);
+        assert_eq!(poll.stats.window_ns, 0);
+        assert_eq!(poll.stats.shrinks, 0);
+
+        poll.record_block(0, 5_000);
+        poll.record_block(10_000, 1_000_000);
+        assert_eq!(poll.stats.window_ns, 0);
+        assert_eq!(poll.stats.shrinks, 1);
+
+        poll.config.shrink = 2;
+        poll.stats.window_ns = 40_000;
+        poll.record_block(40_000, 1_000_000);
+        assert_eq!(poll.stats.window_ns, 20_000);
+        assert_eq!(poll.stats.shrinks, 2);
+    }
+
+    #[test]
+    fn window_is_kept_when_long_enough() {
+        let mut poll = HaltPoll::default();
+        poll.stats.window_ns = 40_000;
+        poll.record_block(40_000, 40_000);
+        assert_eq!(poll.stats.window_ns, 40_000);
+        poll.record_poll_success(3_000);
+        assert_eq!(poll.stats.successful_polls, 1);
+        assert_eq!(poll.stats.successful_poll_ns, 3_000);
+        assert_eq!((poll.stats.grows, poll.stats.shrinks), (0, 0));
+    }
+}
@@ -0,0 +1,48 @@
+use alloc::vec::Vec;
+
+use crate::ExitKind;
+
+/// The number of exits kept in the exit history of a vcpu, see
+/// [`AxVCpu::exit_history`](crate::AxVCpu::exit_history).
+pub const EXIT_HISTORY_LEN: usize = 32;
+
+/// An exit in the exit history of a vcpu, timestamped with both the host clock and the raw cycle counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitStamp {
+    /// The kind of the exit.
+    pub kind: ExitKind,
+    /// The host time of the exit, in nanoseconds of [`AxVCpuHal::current_time_nanos`](crate::AxVCpuHal::current_time_nanos).
+    pub time_ns: u64,
+    /// The raw cycle counter at the exit, see [`AxVCpuHal::read_cycle_counter`](crate::AxVCpuHal::read_cycle_counter).
+    pub cycles: u64,
+}
+
+/// The last [`EXIT_HISTORY_LEN`] exits of a vcpu, kept in a fixed-size ring so that recording never allocates.
+pub(crate) struct ExitHistory {
+    /// The ring of stamps.
+    stamps: [Option<ExitStamp>; EXIT_HISTORY_LEN],
+    /// The index of the slot the next stamp is recorded into.
+    next: usize,
+}
+
+impl ExitHistory {
+    /// Create an empty history.
+    pub(crate) const fn new() -> Self {
+        Self {
+            stamps: [None; EXIT_HISTORY_LEN],
+            next: 0,
+        }
+    }
+
+    /// Record an exit, replacing the oldest one if the history is full.
+    pub(crate) fn record(&mut self, stamp: ExitStamp) {
+        self.stamps[self.next] = Some(stamp);
+        self.next = (self.next + 1) % EXIT_HISTORY_LEN;
+    }
+
+    /// Get the recorded exits, from the oldest to the newest.
+    pub(crate) fn to_vec(&self) -> Vec<ExitStamp> {
+        let (newer, older) = self.stamps.split_at(self.next);
+        older.iter().chain(newer).flatten().copied().collect()
+    }
+}
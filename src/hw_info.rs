@@ -0,0 +1,87 @@
+use bitflags::bitflags;
+
+use crate::AxVCpuError;
+
+/// The hardware virtualization extension of a physical CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtExtension {
+    /// Unknown or not reported.
+    Unknown,
+    /// Intel VT-x (VMX).
+    Vmx,
+    /// AMD-V (SVM).
+    Svm,
+    /// Arm virtualization extensions (EL2).
+    ArmEl2,
+    /// RISC-V hypervisor extension (H-extension).
+    RiscvH,
+}
+
+bitflags! {
+    /// Optional hardware virtualization features of a physical CPU.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct VirtHwFeatures: u64 {
+        /// Nested paging (EPT in Intel, NPT in AMD, stage-2 translation in Arm, G-stage translation in RISC-V).
+        const NESTED_PAGING = 1 << 0;
+        /// Tagged guest TLB entries (VPID in Intel, ASID in AMD, VMID in Arm and RISC-V).
+        const TAGGED_TLB = 1 << 1;
+        /// Hardware-assisted interrupt virtualization (APICv/AVIC in x86, GICv4 in Arm, AIA in RISC-V).
+        const INTERRUPT_VIRT = 1 << 2;
+        /// Arm Virtualization Host Extensions.
+        const VHE = 1 << 3;
+        /// Nested virtualization support.
+        const NESTED_VIRT = 1 << 4;
+        /// A timer the guest can program directly (TSC deadline in x86, `Sstc` in RISC-V, always present in Arm).
+        const GUEST_TIMER = 1 << 5;
+    }
+}
+
+/// A report of the hardware virtualization support of a physical CPU, returned by
+/// [`AxArchPerCpu::hardware_info`](crate::AxArchPerCpu::hardware_info).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtHwInfo {
+    /// The virtualization extension.
+    pub extension: VirtExtension,
+    /// The architecture-specific version of the extension (e.g., the VMCS revision id in Intel, the
+    /// `ID_AA64MMFR1_EL1.VH`/`VMIDBits` encoding in Arm), 0 if unknown.
+    pub version: u32,
+    /// The maximum guest physical (intermediate physical in Arm) address size in bits, 0 if unknown.
+    pub max_ipa_bits: u8,
+    /// The optional features.
+    pub features: VirtHwFeatures,
+}
+
+impl VirtHwInfo {
+    /// A report with nothing known.
+    pub const UNKNOWN: Self = Self {
+        extension: VirtExtension::Unknown,
+        version: 0,
+        max_ipa_bits: 0,
+        features: VirtHwFeatures::empty(),
+    };
+
+    /// Check that a VM with `ipa_bits` bits of guest physical address space fits in the hardware limit.
+    ///
+    /// Always succeeds if the limit is unknown.
+    pub fn check_ipa_bits(&self, ipa_bits: u8) -> Result<(), AxVCpuError> {
+        if self.max_ipa_bits != 0 && ipa_bits > self.max_ipa_bits {
+            Err(AxVCpuError::IpaSizeUnsupported {
+                requested: ipa_bits,
+                max: self.max_ipa_bits,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check that the hardware provides all the given features.
+    pub fn check_features(&self, required: VirtHwFeatures) -> Result<(), AxVCpuError> {
+        if self.features.contains(required) {
+            Ok(())
+        } else {
+            Err(AxVCpuError::HardwareFeaturesMissing {
+                missing: required.difference(self.features),
+            })
+        }
+    }
+}
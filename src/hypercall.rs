@@ -0,0 +1,116 @@
+use core::mem::{MaybeUninit, align_of, size_of};
+
+use axaddrspace::GuestPhysAddr;
+use axerrno::{AxResult, ax_err};
+
+use crate::{AxVCpuExitReason, GuestMemoryAccess};
+
+/// Types which can be copied from and to guest memory as raw bytes by [`HypercallContext`].
+///
+/// # Safety
+///
+/// Implementors must be valid for any bit pattern and contain no padding bytes, e.g., integers, arrays of them,
+/// and `#[repr(C)]` structs of them without padding.
+pub unsafe trait GuestPod: Copy {}
+
+macro_rules! impl_guest_pod {
+    ($($t:ty),*) => {
+        // SAFETY: integers are valid for any bit pattern and have no padding.
+        $(unsafe impl GuestPod for $t {})*
+    };
+}
+
+impl_guest_pod!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+// SAFETY: arrays have no padding between elements.
+unsafe impl<T: GuestPod, const N: usize> GuestPod for [T; N] {}
+
+/// The arguments of an [`AxVCpuExitReason::Hypercall`] with typed extractors, reading and writing guest buffers
+/// passed by guest physical address through a [`GuestMemoryAccess`].
+///
+/// Buffers are checked to be aligned to their type, so that handlers don't juggle pointers themselves.
+pub struct HypercallContext<'a> {
+    /// The hypercall number.
+    nr: u64,
+    /// The arguments.
+    args: [u64; 6],
+    /// The memory of the guest.
+    mem: &'a dyn GuestMemoryAccess,
+}
+
+impl<'a> HypercallContext<'a> {
+    /// Create the context of a hypercall.
+    pub fn new(nr: u64, args: [u64; 6], mem: &'a dyn GuestMemoryAccess) -> Self {
+        Self { nr, args, mem }
+    }
+
+    /// Create the context of an exit, if it's a [`AxVCpuExitReason::Hypercall`].
+    pub fn from_exit(exit: &AxVCpuExitReason, mem: &'a dyn GuestMemoryAccess) -> Option<Self> {
+        exit.as_hypercall()
+            .map(|(nr, args)| Self::new(nr, *args, mem))
+    }
+
+    /// Get the hypercall number.
+    pub const fn nr(&self) -> u64 {
+        self.nr
+    }
+
+    /// Get the `i`-th argument. Returns `InvalidInput` if `i` is not less than 6.
+    pub fn arg(&self, i: usize) -> AxResult<u64> {
+        match self.args.get(i) {
+            Some(&arg) => Ok(arg),
+            None => ax_err!(InvalidInput, "hypercall argument index out of range"),
+        }
+    }
+
+    /// Get the `i`-th argument as a guest physical address.
+    pub fn arg_gpa(&self, i: usize) -> AxResult<GuestPhysAddr> {
+        let arg = self.arg(i)?;
+        match usize::try_from(arg) {
+            Ok(addr) => Ok(GuestPhysAddr::from(addr)),
+            Err(_) => ax_err!(InvalidInput, "guest physical address out of range"),
+        }
+    }
+
+    /// Get the `i`-th argument as the guest physical address of a `T`, checking its alignment.
+    fn struct_gpa<T>(&self, i: usize) -> AxResult<GuestPhysAddr> {
+        let addr = self.arg_gpa(i)?;
+        if addr.as_usize() & (align_of::<T>() - 1) != 0 {
+            return ax_err!(InvalidInput, "misaligned guest buffer");
+        }
+        Ok(addr)
+    }
+
+    /// Read a `T` from the guest buffer whose guest physical address is the `i`-th argument.
+    pub fn read_struct<T: GuestPod>(&self, i: usize) -> AxResult<T> {
+        let addr = self.struct_gpa::<T>(i)?;
+        let mut value = MaybeUninit::<T>::uninit();
+        // SAFETY: `T` has no padding, so all its bytes may be written, and any bit pattern is a valid `T`.
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        bytes.fill(0);
+        self.mem.read(addr, bytes)?;
+        // SAFETY: all bytes of `value` are initialized above.
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Write `value` as the result into the guest buffer whose guest physical address is the `i`-th argument.
+    pub fn write_result_struct<T: GuestPod>(&self, i: usize, value: &T) -> AxResult {
+        let addr = self.struct_gpa::<T>(i)?;
+        // SAFETY: `T` has no padding, so all its bytes are initialized.
+        let bytes =
+            unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        self.mem.write(addr, bytes)
+    }
+
+    /// Read `buf.len()` bytes from the guest buffer whose guest physical address is the `i`-th argument.
+    pub fn read_bytes(&self, i: usize, buf: &mut [u8]) -> AxResult {
+        self.mem.read(self.arg_gpa(i)?, buf)
+    }
+
+    /// Write `buf` into the guest buffer whose guest physical address is the `i`-th argument.
+    pub fn write_bytes(&self, i: usize, buf: &[u8]) -> AxResult {
+        self.mem.write(self.arg_gpa(i)?, buf)
+    }
+}
@@ -0,0 +1,37 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use axaddrspace::{GuestPhysAddr, MappingFlags};
+
+/// The guest executable regions made immutable by
+/// [`AxVCpu::protect_code_region`](crate::AxVCpu::protect_code_region), and the violation blocking the vcpu, if
+/// any.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CodeIntegrity {
+    /// The immutable regions.
+    regions: Vec<Range<GuestPhysAddr>>,
+    /// The violation returned by the last [`AxVCpuExitReason::IntegrityViolation`](crate::AxVCpuExitReason::IntegrityViolation)
+    /// exit, until it's cleared.
+    pub(crate) violation: Option<(GuestPhysAddr, MappingFlags)>,
+}
+
+impl CodeIntegrity {
+    /// Make `range` immutable.
+    pub(crate) fn protect(&mut self, range: Range<GuestPhysAddr>) {
+        self.regions.push(range);
+    }
+
+    /// Whether `addr` is in an immutable region.
+    pub(crate) fn is_protected(&self, addr: GuestPhysAddr) -> bool {
+        self.regions.iter().any(|region| region.contains(&addr))
+    }
+
+    /// Get the first address of `range` in an immutable region, if any.
+    pub(crate) fn first_overlap(&self, range: &Range<GuestPhysAddr>) -> Option<GuestPhysAddr> {
+        self.regions
+            .iter()
+            .filter(|region| region.start < range.end && range.start < region.end)
+            .map(|region| region.start.max(range.start))
+            .min()
+    }
+}
@@ -0,0 +1,82 @@
+use alloc::vec::Vec;
+
+/// The classes of guest operations which should (or should not) cause a vm-exit, used by
+/// [`AxVCpu::configure_intercepts`](crate::AxVCpu::configure_intercepts).
+///
+/// By default everything the architecture-specific vcpu traps keeps being trapped. Latency-sensitive
+/// workloads may opt out of some exit classes to reduce the exit rate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterceptConfig {
+    /// Whether a halt instruction (`HLT` in x86, `WFI` in Aarch64 and RISC-V) causes a vm-exit.
+    pub trap_halt: bool,
+    /// Whether accesses to control registers (`CR`s in x86, `SCTLR_EL1` and friends in Aarch64, `satp` in RISC-V)
+    /// cause vm-exits.
+    pub trap_control_regs: bool,
+    /// The system registers (`MSR`s in x86, `CSR`s in RISC-V, and `System registers` in Aarch64) which are passed
+    /// through to the guest. See [`AxVCpuExitReason::SysRegRead`](crate::AxVCpuExitReason::SysRegRead) for the
+    /// encoding of the addresses.
+    pub passthrough_sysregs: Vec<usize>,
+}
+
+/// Which accesses to a range of system registers cause vm-exits, used by
+/// [`AxVCpu::trap_sysreg_range`](crate::AxVCpu::trap_sysreg_range).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysRegTrapMode {
+    /// Both reads and writes cause vm-exits.
+    Trap,
+    /// Only reads cause vm-exits, writes are passed through.
+    TrapRead,
+    /// Only writes cause vm-exits, reads are passed through.
+    TrapWrite,
+    /// Neither reads nor writes cause vm-exits.
+    Passthrough,
+}
+
+impl SysRegTrapMode {
+    /// Whether reads cause vm-exits.
+    pub const fn traps_read(self) -> bool {
+        matches!(self, Self::Trap | Self::TrapRead)
+    }
+
+    /// Whether writes cause vm-exits.
+    pub const fn traps_write(self) -> bool {
+        matches!(self, Self::Trap | Self::TrapWrite)
+    }
+}
+
+impl Default for InterceptConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterceptConfig {
+    /// Create a new [`InterceptConfig`] which traps everything.
+    pub const fn new() -> Self {
+        Self {
+            trap_halt: true,
+            trap_control_regs: true,
+            passthrough_sysregs: Vec::new(),
+        }
+    }
+
+    /// Do not trap halt instructions.
+    pub fn no_halt_exit(mut self) -> Self {
+        self.trap_halt = false;
+        self
+    }
+
+    /// Pass through accesses to control registers.
+    pub fn passthrough_control_regs(mut self) -> Self {
+        self.trap_control_regs = false;
+        self
+    }
+
+    /// Pass through accesses to the given system register.
+    pub fn passthrough_sysreg(mut self, addr: usize) -> Self {
+        if !self.passthrough_sysregs.contains(&addr) {
+            self.passthrough_sysregs.push(addr);
+        }
+        self
+    }
+}
@@ -0,0 +1,49 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use axaddrspace::{GuestPhysAddr, MappingFlags};
+
+/// What the handler of an [`AxVCpuExitReason::Introspection`](crate::AxVCpuExitReason::Introspection) exit
+/// decides to do with the access, passed to [`AxVCpu::complete_introspection`](crate::AxVCpu::complete_introspection).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntrospectionVerdict {
+    /// Let the access go through, e.g., by single-stepping the instruction with the access granted, so that the
+    /// next access to the range exits again.
+    Permit,
+    /// Block the access: the instruction is skipped without effect.
+    Deny,
+    /// The access is emulated by the handler: the instruction is skipped, and reads return `value`.
+    Emulate {
+        /// The value read by the guest, ignored for writes and executions.
+        value: u64,
+    },
+}
+
+/// The guest physical address ranges watched for introspection, see
+/// [`AxVCpu::watch_guest_memory`](crate::AxVCpu::watch_guest_memory).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IntrospectionWatch {
+    /// The watched ranges, with the accesses watched.
+    ranges: Vec<(Range<GuestPhysAddr>, MappingFlags)>,
+}
+
+impl IntrospectionWatch {
+    /// Watch the accesses in `access` to `range`.
+    pub(crate) fn watch(&mut self, range: Range<GuestPhysAddr>, access: MappingFlags) {
+        self.ranges.push((range, access));
+    }
+
+    /// Stop watching `range`, returns whether it was watched.
+    pub(crate) fn unwatch(&mut self, range: &Range<GuestPhysAddr>) -> bool {
+        let len = self.ranges.len();
+        self.ranges.retain(|(watched, _)| watched != range);
+        self.ranges.len() != len
+    }
+
+    /// Whether an access to `addr` with `access` is watched.
+    pub(crate) fn matches(&self, addr: GuestPhysAddr, access: MappingFlags) -> bool {
+        self.ranges
+            .iter()
+            .any(|(range, watched)| range.contains(&addr) && watched.intersects(access))
+    }
+}
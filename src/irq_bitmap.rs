@@ -0,0 +1,131 @@
+use core::sync::atomic::{AtomicU8, AtomicU16, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use axaddrspace::{GuestPhysAddr, MappingFlags};
+
+use crate::AxVCpuExitReason;
+
+/// The number of vectors [`AxVCpu::inject_interrupt_from_irq`](crate::AxVCpu::inject_interrupt_from_irq) accepts,
+/// i.e., the size of the lock-free pending bitmap of a vcpu.
+pub const IRQ_BITMAP_VECTORS: usize = 1024;
+
+/// A fixed-size bitmap of pending interrupt vectors, which can be set from any host context without locks.
+pub(crate) struct AtomicIrqBitmap {
+    /// The bits, vector `v` is bit `v % 64` of word `v / 64`.
+    words: [AtomicU64; IRQ_BITMAP_VECTORS / 64],
+    /// The number of times each vector was set while already set, since the last drain.
+    coalesced: [AtomicU16; IRQ_BITMAP_VECTORS],
+}
+
+impl AtomicIrqBitmap {
+    /// Create an empty bitmap.
+    pub(crate) fn new() -> Self {
+        Self {
+            words: core::array::from_fn(|_| AtomicU64::new(0)),
+            coalesced: core::array::from_fn(|_| AtomicU16::new(0)),
+        }
+    }
+
+    /// Set the bit of `vector`, which must be less than [`IRQ_BITMAP_VECTORS`].
+    pub(crate) fn set(&self, vector: usize) {
+        let bit = 1 << (vector % 64);
+        if self.words[vector / 64].fetch_or(bit, Ordering::Release) & bit != 0 {
+            self.coalesced[vector].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Set the bit of `vector` again after a drain, without counting it as coalesced.
+    pub(crate) fn restore(&self, vector: usize) {
+        self.words[vector / 64].fetch_or(1 << (vector % 64), Ordering::Release);
+    }
+
+    /// Get the number of set bits.
+    pub(crate) fn count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.load(Ordering::Relaxed).count_ones() as usize)
+            .sum()
+    }
+
+    /// Clear all bits, calling `f` with the vector of each bit that was set and the number of times it was
+    /// coalesced, in ascending order.
+    pub(crate) fn drain(&self, mut f: impl FnMut(usize, u64)) {
+        for (index, word) in self.words.iter().enumerate() {
+            let mut bits = word.swap(0, Ordering::Acquire);
+            while bits != 0 {
+                let vector = index * 64 + bits.trailing_zeros() as usize;
+                f(
+                    vector,
+                    self.coalesced[vector].swap(0, Ordering::Relaxed) as u64,
+                );
+                bits &= bits - 1;
+            }
+        }
+    }
+}
+
+/// The [`AtomicFaultSlot`] holds no fault.
+const SLOT_EMPTY: u8 = 0;
+/// The [`AtomicFaultSlot`] is being written.
+const SLOT_WRITING: u8 = 1;
+/// The [`AtomicFaultSlot`] holds a fault.
+const SLOT_FULL: u8 = 2;
+
+/// A slot holding one [`AxVCpuExitReason::IommuFault`], which can be filled from any host context without locks,
+/// see [`AxVCpuGroup::report_iommu_fault`](crate::AxVCpuGroup::report_iommu_fault).
+pub(crate) struct AtomicFaultSlot {
+    /// [`SLOT_EMPTY`], [`SLOT_WRITING`] or [`SLOT_FULL`].
+    state: AtomicU8,
+    /// The device causing the fault.
+    device: AtomicU32,
+    /// The guest physical address accessed.
+    addr: AtomicUsize,
+    /// The bits of the access flags.
+    flags: AtomicUsize,
+}
+
+impl AtomicFaultSlot {
+    /// Create an empty slot.
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(SLOT_EMPTY),
+            device: AtomicU32::new(0),
+            addr: AtomicUsize::new(0),
+            flags: AtomicUsize::new(0),
+        }
+    }
+
+    /// Store a fault, returns whether the slot was empty. The fault is discarded otherwise.
+    pub(crate) fn put(&self, device: u32, addr: GuestPhysAddr, flags: MappingFlags) -> bool {
+        if self
+            .state
+            .compare_exchange(
+                SLOT_EMPTY,
+                SLOT_WRITING,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            return false;
+        }
+        self.device.store(device, Ordering::Relaxed);
+        self.addr.store(addr.as_usize(), Ordering::Relaxed);
+        self.flags.store(flags.bits(), Ordering::Relaxed);
+        self.state.store(SLOT_FULL, Ordering::Release);
+        true
+    }
+
+    /// Take the fault stored, as an [`AxVCpuExitReason::IommuFault`], emptying the slot.
+    pub(crate) fn take(&self) -> Option<AxVCpuExitReason> {
+        if self.state.load(Ordering::Acquire) != SLOT_FULL {
+            return None;
+        }
+        let exit = AxVCpuExitReason::IommuFault {
+            device: self.device.load(Ordering::Relaxed),
+            addr: GuestPhysAddr::from(self.addr.load(Ordering::Relaxed)),
+            flags: MappingFlags::from_bits_truncate(self.flags.load(Ordering::Relaxed)),
+        };
+        self.state.store(SLOT_EMPTY, Ordering::Release);
+        Some(exit)
+    }
+}
@@ -0,0 +1,183 @@
+use alloc::vec::Vec;
+
+use axerrno::{AxResult, ax_err};
+
+use crate::{AxVCpuExitReason, ExitKind, VCpuRunPage};
+
+/// An entry of a [`DeviceJournal`]: a device access performed on behalf of an exit, and the state mutation it
+/// resulted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// The sequence number of the exit among the exits recorded by the journal, starting from 0.
+    pub exit_seq: u64,
+    /// The kind of the exit.
+    pub exit_kind: ExitKind,
+    /// The payload of the exit, encoded by [`VCpuRunPage::encode_payload`].
+    pub exit_payload: [u64; 8],
+    /// The id of the device accessed, assigned by the VMM.
+    pub device: u32,
+    /// The id of the resulting state mutation, assigned by the device model, e.g., a generation number or a hash
+    /// of the device state after the access.
+    pub mutation: u64,
+}
+
+/// A journal of the device accesses performed while handling the exits of a vcpu, enabled with
+/// [`AxVCpu::enable_journal`](crate::AxVCpu::enable_journal).
+///
+/// Device models record each access with [`AxVCpu::journal_device_access`](crate::AxVCpu::journal_device_access),
+/// which ties it to the last exit returned by [`AxVCpu::run`](crate::AxVCpu::run). Comparing the journals of a
+/// run and its replay (or their digests, e.g., in snapshot consistency checks) verifies that device emulation is
+/// deterministic with respect to the exit stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceJournal {
+    /// The recorded entries.
+    entries: Vec<JournalEntry>,
+    /// The sequence number of the next exit.
+    next_exit_seq: u64,
+    /// The last exit, as `(seq, kind, payload)`.
+    current_exit: Option<(u64, ExitKind, [u64; 8])>,
+}
+
+impl DeviceJournal {
+    /// Create an empty journal.
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            next_exit_seq: 0,
+            current_exit: None,
+        }
+    }
+
+    /// Record an exit, to which the following device accesses are tied.
+    pub(crate) fn begin_exit(&mut self, exit: &AxVCpuExitReason) {
+        self.current_exit = Some((
+            self.next_exit_seq,
+            exit.kind(),
+            VCpuRunPage::encode_payload(exit),
+        ));
+        self.next_exit_seq += 1;
+    }
+
+    /// Take the recorded entries into a new journal, keeping the exit sequence, so that recording continues
+    /// seamlessly.
+    pub(crate) fn take_entries(&mut self) -> Self {
+        Self {
+            entries: core::mem::take(&mut self.entries),
+            next_exit_seq: self.next_exit_seq,
+            current_exit: self.current_exit,
+        }
+    }
+
+    /// Record an access to `device` resulting in the state mutation `mutation`, tied to the last exit. Returns
+    /// `BadState` if no exit is recorded yet.
+    pub fn record(&mut self, device: u32, mutation: u64) -> AxResult {
+        let Some((exit_seq, exit_kind, exit_payload)) = self.current_exit else {
+            return ax_err!(BadState, "device access recorded before any exit");
+        };
+        self.entries.push(JournalEntry {
+            exit_seq,
+            exit_kind,
+            exit_payload,
+            device,
+            mutation,
+        });
+        Ok(())
+    }
+
+    /// Get the recorded entries, oldest first.
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Get a 64-bit FNV-1a digest of the recorded entries, cheap to store along a snapshot and compare later.
+    pub fn digest(&self) -> u64 {
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        let mut feed = |word: u64| {
+            for byte in word.to_le_bytes() {
+                hash = (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        };
+        for entry in &self.entries {
+            feed(entry.exit_seq);
+            feed(entry.exit_kind.id() as u64);
+            entry.exit_payload.iter().for_each(|&word| feed(word));
+            feed(entry.device as u64);
+            feed(entry.mutation);
+        }
+        hash
+    }
+
+    /// Compare the recorded entries with `expected`, e.g., the journal of the original run when replaying it.
+    /// Returns the index of the first differing entry (which is missing from one of them if the lengths differ),
+    /// or `None` if they're identical.
+    pub fn first_divergence(&self, expected: &[JournalEntry]) -> Option<usize> {
+        let len = self.entries.len().max(expected.len());
+        (0..len).find(|&index| self.entries.get(index) != expected.get(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axerrno::AxError;
+
+    use super::*;
+
+    fn journal(mutations: &[u64]) -> DeviceJournal {
+        let mut journal = DeviceJournal::new();
+        journal.begin_exit(&AxVCpuExitReason::Halt);
+        for &mutation in mutations {
+            journal.record(7, mutation).unwrap();
+        }
+        journal
+    }
+
+    #[test]
+    fn accesses_are_tied_to_the_last_exit() {
+        let mut journal = DeviceJournal::new();
+        assert_eq!(journal.record(7, 1), Err(AxError::BadState));
+        journal.begin_exit(&AxVCpuExitReason::Halt);
+        journal.record(7, 1).unwrap();
+        journal.begin_exit(&AxVCpuExitReason::Nothing);
+        journal.begin_exit(&AxVCpuExitReason::SystemDown);
+        journal.record(8, 2).unwrap();
+        let entries = journal.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            (entries[0].exit_seq, entries[0].exit_kind),
+            (0, ExitKind::Halt)
+        );
+        assert_eq!(
+            (entries[1].exit_seq, entries[1].exit_kind, entries[1].device),
+            (2, ExitKind::SystemDown, 8)
+        );
+    }
+
+    #[test]
+    fn taken_entries_keep_the_exit_sequence() {
+        let mut journal = journal(&[1, 2]);
+        let taken = journal.take_entries();
+        assert_eq!(taken.entries().len(), 2);
+        assert!(journal.entries().is_empty());
+        journal.record(7, 3).unwrap();
+        journal.begin_exit(&AxVCpuExitReason::Halt);
+        journal.record(7, 4).unwrap();
+        assert_eq!(journal.entries()[0].exit_seq, 0);
+        assert_eq!(journal.entries()[1].exit_seq, 1);
+    }
+
+    #[test]
+    fn digests_and_divergences() {
+        assert_eq!(DeviceJournal::new().digest(), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(journal(&[1, 2]).digest(), journal(&[1, 2]).digest());
+        assert_ne!(journal(&[1, 2]).digest(), journal(&[2, 1]).digest());
+
+        let run = journal(&[1, 2, 3]);
+        assert_eq!(run.first_divergence(journal(&[1, 2, 3]).entries()), None);
+        assert_eq!(run.first_divergence(journal(&[1, 5, 3]).entries()), Some(1));
+        assert_eq!(run.first_divergence(journal(&[1, 2]).entries()), Some(2));
+        assert_eq!(
+            run.first_divergence(journal(&[1, 2, 3, 4]).entries()),
+            Some(3)
+        );
+    }
+}
@@ -5,17 +5,136 @@
 
 #[macro_use]
 extern crate alloc;
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
+#[macro_use]
+mod logging;
 
 mod arch_vcpu;
+mod builder;
+mod caps;
+mod compress;
+pub mod conformance;
+mod cpu_id;
+mod debug;
+mod dispatch;
+mod emulate;
+mod error;
 mod exit;
+mod ext_state;
+#[cfg(any(feature = "x86-apic-fast", feature = "arm-gic-fast"))]
+mod fastpath;
+mod fpu;
+mod group;
+mod guest_mode;
 mod hal;
+mod halt_poll;
+mod history;
+mod hw_info;
+mod hypercall;
+mod integrity;
+mod intercept;
+mod introspect;
+mod irq_bitmap;
+mod journal;
+mod load;
+mod lockstep;
+mod mem_attr;
+#[cfg(any(test, feature = "mock-percpu"))]
+mod mock_percpu;
+mod msi;
+mod parallel;
 mod percpu;
+pub mod prelude;
+mod profiling;
+mod pv_console;
+mod pvclock;
+mod quota;
+mod reboot;
+mod regs;
+mod replay;
+mod run_page;
+mod sandbox;
+mod secure;
+mod snapshot;
+mod stats;
+mod sysreg;
+#[cfg(test)]
+mod test_utils;
+mod trace;
+mod transport;
 mod vcpu;
 
-pub use arch_vcpu::AxArchVCpu;
+pub use arch_vcpu::{AxArchVCpu, VCpuCreateContext};
+pub use builder::AxVCpuBuilder;
+pub use caps::VCpuCapabilities;
+pub use compress::{Compressor, RleCompressor};
+pub use cpu_id::{ArchIdScheme, CpuIdMap, CpuTopologyShape, VCpuTopology};
+pub use debug::{
+    BreakpointManager, ExitBreakpointHandler, ExitFilter, GuestMemoryAccess, GuestSymbolResolver,
+    HwWatchpoint, SymbolizedPc, WatchpointKind,
+};
+pub use dispatch::{ExitDispatcher, ExitMiddleware};
+pub use emulate::{DECODE_CACHE_DEFAULT_ENTRIES, DecodeCache};
+pub use error::{AxVCpuError, AxVCpuResult};
+pub use ext_state::ExtStateBuffer;
+#[cfg(any(feature = "x86-apic-fast", feature = "arm-gic-fast"))]
+pub use fastpath::IrqChipGlue;
+pub use fpu::FpuPolicy;
+pub use group::{
+    AxVCpuGroup, AxVCpuRef, CpuDownIrqPolicy, IRQ_FALLBACK_DEFAULT_BLOCK_NS, IrqFallbackPolicy,
+    StuckVCpu, VCpuStatus,
+};
+pub use guest_mode::{GuestEndian, GuestMode};
 pub use hal::AxVCpuHal;
+pub use halt_poll::{HaltPollConfig, HaltPollStats};
+pub use history::{EXIT_HISTORY_LEN, ExitStamp};
+pub use hw_info::{VirtExtension, VirtHwFeatures, VirtHwInfo};
+pub use hypercall::{GuestPod, HypercallContext};
+pub use intercept::{InterceptConfig, SysRegTrapMode};
+pub use introspect::IntrospectionVerdict;
+pub use irq_bitmap::IRQ_BITMAP_VECTORS;
+pub use journal::{DeviceJournal, JournalEntry};
+pub use load::{LOAD_WINDOW_NS, LoadHint};
+pub use lockstep::{LOCKSTEP_DEFAULT_WINDOW_NS, LockstepHandle};
+#[cfg(feature = "log")]
+pub use logging::{LogSubsystem, log_filter, set_log_filter};
+pub use mem_attr::{AttributeMismatchPolicy, MemoryAttributePolicy, MemoryType, Shareability};
+#[cfg(any(test, feature = "mock-percpu"))]
+pub use mock_percpu::{MOCK_PERCPU_MAX_CPUS, MockArchPerCpu};
+pub use msi::{
+    DefaultMsiDecoder, FlatMsiDecoder, ImsicMsiDecoder, MsiDecoder, MsiDestination, MsiMessage,
+    MsiTarget, X86MsiDecoder,
+};
 pub use percpu::*;
+pub use profiling::{ExecCounters, ExecProfilingConfig};
+pub use pv_console::{PV_CONSOLE_MAX_WRITE, PV_CONSOLE_PUTCHAR, PV_CONSOLE_WRITE, PvConsole};
+pub use pvclock::{PvStealTime, PvTimeJumpInfo};
+pub use reboot::{REBOOT_STORM_DEFAULT_THRESHOLD, REBOOT_STORM_DEFAULT_WINDOW_NS, RebootStorm};
+pub use regs::{AARCH64_GPR_NAMES, DEFAULT_GPR_NAMES, RISCV_GPR_NAMES, RegName, X86_64_GPR_NAMES};
+pub use replay::{ReplayEntry, ReplaySession};
+pub use run_page::{RUN_PAGE_NO_EXIT, VCpuRunPage};
+pub use sandbox::{SandboxConfig, SandboxViolation};
+pub use secure::{SMCCC_RET_NOT_SUPPORTED, SecureCallProxy, SecureCallSanitizer};
+pub use snapshot::{
+    AxVCpuSnapshot, HostInfo, SNAPSHOT_FORMAT_VERSION, SnapshotArch, SnapshotHeader,
+    SnapshotIncompatibility, SnapshotSection, VCpuTimeState,
+};
+pub use stats::{
+    AxVCpuStats, ExitTiming, FinalStatsReport, GroupStats, HandlerStage, StageTimer, StatsReporter,
+    VectorStats,
+};
+pub use sysreg::SysRegFile;
+pub use trace::{TRACE_RECORD_SIZE, TraceEvent, TraceRecord, TraceRing, TraceSink};
+#[cfg(feature = "std")]
+pub use trace::{decode_trace, write_chrome_trace};
+pub use transport::{ExitCompletion, ExitMessage, ExitTransport, RingExitTransport};
 pub use vcpu::*;
 
 // TODO: consider, should [`AccessWidth`] be moved to a new crate?
-pub use exit::{AccessWidth, AxVCpuExitReason};
+pub use exit::{
+    AccessWidth, AxVCpuExitReason, CacheOp, ExitAction, ExitDisposition, ExitKind, ExitKindSet,
+    ExitPolicy, FirmwareConduit, GuestRegionClassifier, RegionKind, ShutdownReason,
+    UnhandledMmioPolicy,
+};
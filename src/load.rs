@@ -0,0 +1,101 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// The length of the window over which [`LoadHint::runnable_permille`] and [`LoadHint::exits_per_sec`] are
+/// measured, in nanoseconds.
+pub const LOAD_WINDOW_NS: u64 = 100_000_000;
+
+/// Load-balancing hints of a vcpu for external schedulers, obtained by
+/// [`AxVCpu::load_hint`](crate::AxVCpu::load_hint).
+///
+/// All values are measured with [`AxVCpuHal::current_time_nanos`](crate::AxVCpuHal::current_time_nanos), and are
+/// zero if the HAL doesn't provide a clock.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadHint {
+    /// The fraction of the last complete window the vcpu was runnable (i.e., not blocked), in permille.
+    pub runnable_permille: u32,
+    /// The number of exits per second in the last complete window.
+    pub exits_per_sec: u64,
+    /// The time since the vcpu was last unbound from each physical CPU, as `(cpu_id, ns)` sorted by `cpu_id`, an
+    /// estimate of how warm the caches of each CPU still are for this vcpu.
+    pub cache_warmth: Vec<(usize, u64)>,
+}
+
+/// The tracker of the load of a vcpu.
+#[derive(Debug, Default)]
+pub(crate) struct LoadTracker {
+    /// When the current window started.
+    window_start: u64,
+    /// The number of exits in the current window.
+    window_exits: u64,
+    /// The time blocked in the current window, excluding the ongoing block.
+    window_blocked_ns: u64,
+    /// When the ongoing block started, if the vcpu is blocked.
+    blocked_since: Option<u64>,
+    /// The runnable ratio of the last complete window, in permille.
+    runnable_permille: u32,
+    /// The exit rate of the last complete window.
+    exits_per_sec: u64,
+    /// When the vcpu was last unbound from each physical CPU.
+    last_unbind: BTreeMap<usize, u64>,
+}
+
+impl LoadTracker {
+    /// Close the current window if it's complete.
+    fn roll(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.window_start);
+        if elapsed < LOAD_WINDOW_NS {
+            return;
+        }
+        let mut blocked = self.window_blocked_ns;
+        if let Some(since) = self.blocked_since.as_mut() {
+            blocked += now.saturating_sub(*since);
+            *since = now;
+        }
+        let runnable = elapsed.saturating_sub(blocked);
+        self.runnable_permille = (runnable * 1000 / elapsed) as u32;
+        self.exits_per_sec = self.window_exits * 1_000_000_000 / elapsed;
+        self.window_start = now;
+        self.window_exits = 0;
+        self.window_blocked_ns = 0;
+    }
+
+    /// Record an exit.
+    pub fn record_exit(&mut self, now: u64) {
+        self.roll(now);
+        self.window_exits += 1;
+    }
+
+    /// Record that the vcpu is blocked.
+    pub fn record_block(&mut self, now: u64) {
+        self.roll(now);
+        self.blocked_since = Some(now);
+    }
+
+    /// Record that the vcpu is woken up.
+    pub fn record_wake(&mut self, now: u64) {
+        self.roll(now);
+        if let Some(since) = self.blocked_since.take() {
+            self.window_blocked_ns += now.saturating_sub(since);
+        }
+    }
+
+    /// Record that the vcpu is unbound from the given physical CPU.
+    pub fn record_unbind(&mut self, cpu_id: usize, now: u64) {
+        self.last_unbind.insert(cpu_id, now);
+    }
+
+    /// Get the load-balancing hints.
+    pub fn hint(&mut self, now: u64) -> LoadHint {
+        self.roll(now);
+        LoadHint {
+            runnable_permille: self.runnable_permille,
+            exits_per_sec: self.exits_per_sec,
+            cache_warmth: self
+                .last_unbind
+                .iter()
+                .map(|(&cpu_id, &at)| (cpu_id, now.saturating_sub(at)))
+                .collect(),
+        }
+    }
+}
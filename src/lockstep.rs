@@ -0,0 +1,181 @@
+use alloc::sync::Arc;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axerrno::{AxResult, ax_err};
+
+use crate::AxVCpuHal;
+
+/// The default window within which all vcpus of a lockstep group must arrive, in nanoseconds.
+pub const LOCKSTEP_DEFAULT_WINDOW_NS: u64 = 1_000_000;
+
+/// A reusable barrier over the HAL notification API, used by
+/// [`AxVCpuGroup::run_lockstep`](crate::AxVCpuGroup::run_lockstep).
+///
+/// The generation of the barrier and the number of arrived vcpus are packed into one atomic word (generation in
+/// the upper 32 bits), so that a vcpu giving up after the window expires never races with the last arrival.
+pub(crate) struct LockstepBarrier {
+    /// The generation and the number of arrived vcpus.
+    state: AtomicU64,
+    /// The window within which all vcpus must arrive, in nanoseconds.
+    window_ns: AtomicU64,
+}
+
+impl LockstepBarrier {
+    /// Create a new barrier.
+    pub(crate) const fn new() -> Self {
+        Self {
+            state: AtomicU64::new(0),
+            window_ns: AtomicU64::new(LOCKSTEP_DEFAULT_WINDOW_NS),
+        }
+    }
+
+    /// Set the window within which all vcpus must arrive.
+    pub(crate) fn set_window(&self, window_ns: u64) {
+        self.window_ns.store(window_ns, Ordering::Relaxed);
+    }
+
+    /// Wait until all `count` vcpus (listed by `vcpu_ids`) arrive.
+    ///
+    /// Returns `WouldBlock` and withdraws the arrival if the others don't arrive within the window.
+    pub(crate) fn wait<H: AxVCpuHal>(
+        &self,
+        vcpu_id: usize,
+        vcpu_ids: impl Iterator<Item = usize>,
+        count: usize,
+    ) -> AxResult {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let (generation, arrived) = (state >> 32, state & 0xffff_ffff);
+            let next = if arrived as usize + 1 >= count {
+                (generation.wrapping_add(1) & 0xffff_ffff) << 32
+            } else {
+                state + 1
+            };
+            match self
+                .state
+                .compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) if arrived as usize + 1 >= count => {
+                    for id in vcpu_ids.filter(|&id| id != vcpu_id) {
+                        H::notify_vcpu(id);
+                    }
+                    return Ok(());
+                }
+                Ok(_) => break,
+                Err(current) => state = current,
+            }
+        }
+
+        let generation = state >> 32;
+        let start = H::current_time_nanos();
+        let window = self.window_ns.load(Ordering::Relaxed);
+        loop {
+            let state = self.state.load(Ordering::Acquire);
+            if state >> 32 != generation {
+                return Ok(());
+            }
+            if H::current_time_nanos().saturating_sub(start) >= window
+                && self
+                    .state
+                    .compare_exchange(state, state - 1, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+            {
+                return ax_err!(WouldBlock, "lockstep window expired");
+            }
+            H::wait_for_notification(vcpu_id);
+        }
+    }
+}
+
+/// A handle to the lockstep barrier of an [`AxVCpuGroup`](crate::AxVCpuGroup), obtained by
+/// [`AxVCpuGroup::lockstep_handle`](crate::AxVCpuGroup::lockstep_handle).
+///
+/// Unlike the group, the handle is `Send` and `Sync`, so that each host context running a vcpu of the group can
+/// hold one.
+pub struct LockstepHandle<H: AxVCpuHal> {
+    /// The barrier shared with the group.
+    barrier: Arc<LockstepBarrier>,
+    /// The ids of the vcpus of the group.
+    vcpu_ids: Arc<[usize]>,
+    _hal: PhantomData<fn() -> H>,
+}
+
+impl<H: AxVCpuHal> LockstepHandle<H> {
+    /// Create a handle to `barrier`, shared by the vcpus listed in `vcpu_ids`.
+    pub(crate) fn new(barrier: Arc<LockstepBarrier>, vcpu_ids: Arc<[usize]>) -> Self {
+        Self {
+            barrier,
+            vcpu_ids,
+            _hal: PhantomData,
+        }
+    }
+
+    /// Set the window within which all vcpus must arrive, see
+    /// [`AxVCpuGroup::set_lockstep_window`](crate::AxVCpuGroup::set_lockstep_window).
+    pub(crate) fn set_window(&self, window_ns: u64) {
+        self.barrier.set_window(window_ns);
+    }
+
+    /// Wait (with [`AxVCpuHal::wait_for_notification`]) until all vcpus of the group have arrived, after which the
+    /// caller runs the vcpu `vcpu_id` with [`AxVCpu::run`](crate::AxVCpu::run).
+    ///
+    /// Returns `WouldBlock` if the others don't arrive within the window set by
+    /// [`AxVCpuGroup::set_lockstep_window`](crate::AxVCpuGroup::set_lockstep_window).
+    pub fn wait(&self, vcpu_id: usize) -> AxResult {
+        self.barrier
+            .wait::<H>(vcpu_id, self.vcpu_ids.iter().copied(), self.vcpu_ids.len())
+    }
+}
+
+impl<H: AxVCpuHal> Clone for LockstepHandle<H> {
+    fn clone(&self) -> Self {
+        Self::new(self.barrier.clone(), self.vcpu_ids.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axerrno::AxError;
+
+    use super::*;
+    use crate::test_utils::{MockHal, serial};
+
+    fn handle(count: usize) -> LockstepHandle<MockHal> {
+        LockstepHandle::new(Arc::new(LockstepBarrier::new()), (0..count).collect())
+    }
+
+    #[test]
+    fn handles_are_shareable() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<LockstepHandle<MockHal>>();
+    }
+
+    #[test]
+    fn all_vcpus_pass_together() {
+        let _serial = serial();
+        let handle = handle(3);
+        std::thread::scope(|scope| {
+            for vcpu_id in 0..3 {
+                let handle = handle.clone();
+                scope.spawn(move || handle.wait(vcpu_id).unwrap());
+            }
+        });
+        // The barrier is reusable.
+        std::thread::scope(|scope| {
+            for vcpu_id in 0..3 {
+                let handle = handle.clone();
+                scope.spawn(move || handle.wait(vcpu_id).unwrap());
+            }
+        });
+    }
+
+    #[test]
+    fn late_arrivals_withdraw() {
+        let _serial = serial();
+        let handle = handle(2);
+        handle.barrier.set_window(0);
+        assert_eq!(handle.wait(0), Err(AxError::WouldBlock));
+        assert_eq!(handle.barrier.state.load(Ordering::Acquire), 0);
+    }
+}
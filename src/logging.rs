@@ -0,0 +1,79 @@
+//! Optional logging of the vcpu state machine, interrupt injections and vm-exits.
+//!
+//! With the `log` feature enabled, records are emitted through the [`log`] crate with structured
+//! key-value pairs, under the targets `axvcpu::state`, `axvcpu::injection` and `axvcpu::exit`. The
+//! level of each subsystem can be further restricted with [`set_log_filter`].
+
+/// Emit a log record of the given subsystem, if the `log` feature is enabled and the subsystem
+/// filter allows it.
+///
+/// Accepts the same key-value and message arguments as [`log::log`].
+macro_rules! vcpu_log {
+    ($subsystem:ident, $level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "log")]
+        if $crate::logging::enabled($crate::logging::LogSubsystem::$subsystem, log::Level::$level) {
+            log::log!(
+                target: $crate::logging::LogSubsystem::$subsystem.target(),
+                log::Level::$level,
+                $($arg)+
+            );
+        }
+    };
+}
+
+#[cfg(feature = "log")]
+pub use filter::*;
+
+#[cfg(feature = "log")]
+mod filter {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use log::{Level, LevelFilter};
+
+    /// The subsystems of this crate which emit log records.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum LogSubsystem {
+        /// State transitions of vcpus.
+        State = 0,
+        /// Interrupt injections.
+        Injection = 1,
+        /// Vm-exits.
+        Exit = 2,
+    }
+
+    impl LogSubsystem {
+        /// Get the log target of this subsystem.
+        pub const fn target(self) -> &'static str {
+            match self {
+                Self::State => "axvcpu::state",
+                Self::Injection => "axvcpu::injection",
+                Self::Exit => "axvcpu::exit",
+            }
+        }
+    }
+
+    /// The maximum level of each subsystem, stored as `LevelFilter as usize`.
+    static LOG_FILTERS: [AtomicUsize; 3] = [
+        AtomicUsize::new(LevelFilter::Trace as usize),
+        AtomicUsize::new(LevelFilter::Trace as usize),
+        AtomicUsize::new(LevelFilter::Trace as usize),
+    ];
+
+    /// Set the maximum level of the records emitted by the given subsystem.
+    ///
+    /// The global filter of the [`log`] crate still applies. All subsystems default to [`LevelFilter::Trace`].
+    pub fn set_log_filter(subsystem: LogSubsystem, level: LevelFilter) {
+        LOG_FILTERS[subsystem as usize].store(level as usize, Ordering::Relaxed);
+    }
+
+    /// Get the maximum level of the records emitted by the given subsystem.
+    pub fn log_filter(subsystem: LogSubsystem) -> LevelFilter {
+        let level = LOG_FILTERS[subsystem as usize].load(Ordering::Relaxed);
+        LevelFilter::iter().nth(level).unwrap_or(LevelFilter::Trace)
+    }
+
+    /// Whether a record of the given subsystem and level should be emitted.
+    pub(crate) fn enabled(subsystem: LogSubsystem, level: Level) -> bool {
+        level <= log_filter(subsystem)
+    }
+}
@@ -0,0 +1,96 @@
+/// The memory type of a stage-2 (nested) mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryType {
+    /// Device memory, non-gathering, non-reordering, with early write acknowledgement (`Device-nGnRE` in Aarch64,
+    /// `UC` in x86, `IO` PMA in RISC-V).
+    Device,
+    /// Normal memory, non-cacheable, e.g., for framebuffers passed through to the guest (`WC` in x86).
+    NormalNonCacheable,
+    /// Normal memory, write-through cacheable.
+    NormalWriteThrough,
+    /// Normal memory, write-back cacheable, the usual type of guest RAM.
+    NormalWriteBack,
+}
+
+/// The shareability domain of a stage-2 mapping of normal memory. Only meaningful in Aarch64, ignored elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shareability {
+    /// Non-shareable.
+    NonShareable,
+    /// Inner shareable, the usual domain of SMP guests.
+    Inner,
+    /// Outer shareable.
+    Outer,
+}
+
+/// How the attributes of the guest (stage-1 in Aarch64, `PAT` in x86) are combined with the stage-2 defaults when
+/// they disagree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AttributeMismatchPolicy {
+    /// Combine them by the rules of the architecture, i.e., the most restrictive type wins.
+    #[default]
+    Combine,
+    /// Force the stage-2 attributes, ignoring the guest's, e.g., `HCR_EL2.FWB` in Aarch64 or `IPAT` in x86. Keeps
+    /// guest RAM coherent with the host regardless of what the guest maps it as.
+    ForceStage2,
+    /// Let the guest attributes override the stage-2 ones, e.g., so that a guest driver can map a passed-through
+    /// framebuffer write-combining, or a non-coherent DMA buffer non-cacheable.
+    GuestOverride,
+}
+
+/// The default attributes of the stage-2 mappings of a vcpu, and how mismatches with the guest attributes are
+/// handled.
+///
+/// Set with [`AxVCpuBuilder::memory_attribute_policy`](crate::AxVCpuBuilder::memory_attribute_policy) (with the
+/// same policy for all vcpus of a VM), and passed to the architecture-specific vcpu with
+/// [`AxArchVCpu::set_memory_attribute_policy`](crate::AxArchVCpu::set_memory_attribute_policy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAttributePolicy {
+    /// The type of the mappings of device regions, i.e., mappings with `MappingFlags::DEVICE`.
+    pub device: MemoryType,
+    /// The type of the mappings of normal regions.
+    pub normal: MemoryType,
+    /// The shareability of the mappings of normal regions.
+    pub shareability: Shareability,
+    /// How mismatches with the guest attributes are handled.
+    pub mismatch: AttributeMismatchPolicy,
+}
+
+impl Default for MemoryAttributePolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryAttributePolicy {
+    /// Create the default policy: device regions mapped as [`MemoryType::Device`], normal regions as inner
+    /// shareable [`MemoryType::NormalWriteBack`], and mismatches combined by the rules of the architecture.
+    pub const fn new() -> Self {
+        Self {
+            device: MemoryType::Device,
+            normal: MemoryType::NormalWriteBack,
+            shareability: Shareability::Inner,
+            mismatch: AttributeMismatchPolicy::Combine,
+        }
+    }
+
+    /// Whether this is the default policy.
+    pub const fn is_default(&self) -> bool {
+        matches!(
+            self,
+            Self {
+                device: MemoryType::Device,
+                normal: MemoryType::NormalWriteBack,
+                shareability: Shareability::Inner,
+                mismatch: AttributeMismatchPolicy::Combine,
+            }
+        )
+    }
+
+    /// Set the mismatch handling to [`AttributeMismatchPolicy::GuestOverride`], as needed by passed-through
+    /// framebuffers and non-coherent DMA.
+    pub const fn guest_override(mut self) -> Self {
+        self.mismatch = AttributeMismatchPolicy::GuestOverride;
+        self
+    }
+}
@@ -0,0 +1,76 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axerrno::{AxResult, ax_err};
+
+use crate::AxArchPerCpu;
+
+/// The number of physical CPUs supported by [`MockArchPerCpu`].
+pub const MOCK_PERCPU_MAX_CPUS: usize = 64;
+
+/// The number of [`MockArchPerCpu`] dropped so far, indexed by CPU.
+static DROPS: [AtomicUsize; MOCK_PERCPU_MAX_CPUS] =
+    [const { AtomicUsize::new(0) }; MOCK_PERCPU_MAX_CPUS];
+
+/// A mock [`AxArchPerCpu`], for testing the per-CPU setup and teardown of hypervisors without virtualization
+/// hardware. Enabled by the `mock-percpu` feature.
+///
+/// Enabling and disabling hardware virtualization only flips a flag. Failures of disabling can be injected with
+/// [`MockArchPerCpu::set_fail_disable`], and the states dropped so far are counted per CPU by
+/// [`MockArchPerCpu::drops`], so that leaks and double drops can be detected.
+#[derive(Debug)]
+pub struct MockArchPerCpu {
+    /// The id of the CPU.
+    cpu_id: usize,
+    /// Whether hardware virtualization is enabled.
+    enabled: bool,
+    /// Whether disabling hardware virtualization fails.
+    fail_disable: bool,
+}
+
+impl MockArchPerCpu {
+    /// Make [`AxArchPerCpu::hardware_disable`] fail with `ResourceBusy` (`true`) or succeed (`false`, the default).
+    pub fn set_fail_disable(&mut self, fail: bool) {
+        self.fail_disable = fail;
+    }
+
+    /// Get the number of states of the CPU `cpu_id` dropped so far.
+    pub fn drops(cpu_id: usize) -> usize {
+        DROPS[cpu_id].load(Ordering::Acquire)
+    }
+}
+
+impl AxArchPerCpu for MockArchPerCpu {
+    fn new(cpu_id: usize) -> AxResult<Self> {
+        if cpu_id >= MOCK_PERCPU_MAX_CPUS {
+            return ax_err!(InvalidInput, "CPU id out of the range of the mock");
+        }
+        Ok(Self {
+            cpu_id,
+            enabled: false,
+            fail_disable: false,
+        })
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn hardware_enable(&mut self) -> AxResult {
+        self.enabled = true;
+        Ok(())
+    }
+
+    fn hardware_disable(&mut self) -> AxResult {
+        if self.fail_disable {
+            return ax_err!(ResourceBusy, "injected failure of disabling virtualization");
+        }
+        self.enabled = false;
+        Ok(())
+    }
+}
+
+impl Drop for MockArchPerCpu {
+    fn drop(&mut self) {
+        DROPS[self.cpu_id].fetch_add(1, Ordering::AcqRel);
+    }
+}
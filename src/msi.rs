@@ -0,0 +1,209 @@
+/// A message signaled interrupt (MSI) as written by a device.
+///
+/// Both MSI and MSI-X deliver an interrupt by having the device write `data` to `addr`. How the
+/// address and the data are interpreted depends on the interrupt controller being emulated, see
+/// [`MsiDecoder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiMessage {
+    /// The address the device writes to.
+    pub addr: u64,
+    /// The data the device writes.
+    pub data: u32,
+}
+
+impl MsiMessage {
+    /// Create a new MSI message.
+    pub const fn new(addr: u64, data: u32) -> Self {
+        Self { addr, data }
+    }
+}
+
+/// The destination of a decoded MSI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsiDestination {
+    /// The interrupt targets the vcpu with the given architectural id.
+    ///
+    /// The architectural id is the APIC ID in x86, the hartid in RISC-V, and the affinity fields
+    /// of the MPIDR register in Aarch64.
+    Single(u64),
+    /// The interrupt targets all vcpus.
+    Broadcast,
+}
+
+/// The result of decoding an [`MsiMessage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiTarget {
+    /// The destination vcpu(s) of the interrupt.
+    pub dest: MsiDestination,
+    /// The interrupt vector to be injected.
+    pub vector: usize,
+}
+
+/// A decoder translating an [`MsiMessage`] into the destination and vector of the interrupt.
+///
+/// Each architecture (or more precisely, each interrupt controller) has its own convention of
+/// encoding the destination and the vector into an MSI message. Implement this trait to support
+/// a convention not provided by this crate.
+pub trait MsiDecoder: Send + Sync {
+    /// Decode the given message, returns `None` if the message is malformed.
+    fn decode(&self, msg: &MsiMessage) -> Option<MsiTarget>;
+}
+
+/// The MSI decoder following the x86 LAPIC convention.
+///
+/// The address is in the `0xFEEx_xxxx` range with the destination APIC ID in bits 19:12, and the
+/// vector is in bits 7:0 of the data. Destination `0xff` is treated as a broadcast.
+///
+/// Only physical destinations without redirection hint, delivered in the fixed or lowest-priority
+/// mode, are modeled: messages with a logical destination mode (address bit 2), the redirection
+/// hint (address bit 3), or another delivery mode (data bits 10:8) are not decoded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct X86MsiDecoder;
+
+impl X86MsiDecoder {
+    /// The base of the MSI address range.
+    const ADDR_BASE: u64 = 0xfee0_0000;
+    /// The mask of the fixed part of the MSI address.
+    const ADDR_MASK: u64 = 0xfff0_0000;
+    /// The broadcast destination id.
+    const DEST_BROADCAST: u64 = 0xff;
+    /// The redirection hint (bit 3) and the logical destination mode (bit 2) of the address.
+    const ADDR_RH_DM: u64 = 0b1100;
+    /// The shift of the delivery mode in the data.
+    const DELIVERY_MODE_SHIFT: u32 = 8;
+    /// The fixed delivery mode.
+    const DELIVERY_FIXED: u32 = 0b000;
+    /// The lowest-priority delivery mode, equivalent to the fixed one for a physical destination.
+    const DELIVERY_LOWEST_PRIORITY: u32 = 0b001;
+}
+
+impl MsiDecoder for X86MsiDecoder {
+    fn decode(&self, msg: &MsiMessage) -> Option<MsiTarget> {
+        if msg.addr & Self::ADDR_MASK != Self::ADDR_BASE || msg.addr & Self::ADDR_RH_DM != 0 {
+            return None;
+        }
+        match (msg.data >> Self::DELIVERY_MODE_SHIFT) & 0b111 {
+            Self::DELIVERY_FIXED | Self::DELIVERY_LOWEST_PRIORITY => {}
+            _ => return None,
+        }
+        let dest = match (msg.addr >> 12) & 0xff {
+            Self::DEST_BROADCAST => MsiDestination::Broadcast,
+            id => MsiDestination::Single(id),
+        };
+        Some(MsiTarget {
+            dest,
+            vector: (msg.data & 0xff) as usize,
+        })
+    }
+}
+
+/// The MSI decoder following the RISC-V AIA IMSIC convention.
+///
+/// Each hart owns an interrupt file of `1 << stride_shift` bytes starting from `base`, the
+/// destination hartid is the index of the interrupt file written to, and the data is the
+/// interrupt identity. Only writes to the start of an interrupt file (its little-endian
+/// `seteipnum` register) are decoded.
+#[derive(Debug, Clone, Copy)]
+pub struct ImsicMsiDecoder {
+    /// The address of the interrupt file of hart 0.
+    pub base: u64,
+    /// The log2 of the distance between the interrupt files of two adjacent harts.
+    pub stride_shift: u32,
+}
+
+impl MsiDecoder for ImsicMsiDecoder {
+    fn decode(&self, msg: &MsiMessage) -> Option<MsiTarget> {
+        let offset = msg.addr.checked_sub(self.base)?;
+        let hart = offset.checked_shr(self.stride_shift)?;
+        if offset != hart << self.stride_shift {
+            return None;
+        }
+        Some(MsiTarget {
+            dest: MsiDestination::Single(hart),
+            vector: msg.data as usize,
+        })
+    }
+}
+
+/// The MSI decoder for doorbell-style controllers (such as ARM GICv2m), where the data is the
+/// interrupt number and the interrupt is always delivered to the vcpu with architectural id 0.
+///
+/// The address is not checked.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FlatMsiDecoder;
+
+impl MsiDecoder for FlatMsiDecoder {
+    fn decode(&self, msg: &MsiMessage) -> Option<MsiTarget> {
+        Some(MsiTarget {
+            dest: MsiDestination::Single(0),
+            vector: msg.data as usize,
+        })
+    }
+}
+
+/// The default MSI decoder of the current architecture.
+#[cfg(target_arch = "x86_64")]
+pub type DefaultMsiDecoder = X86MsiDecoder;
+
+/// The default MSI decoder of the current architecture.
+#[cfg(not(target_arch = "x86_64"))]
+pub type DefaultMsiDecoder = FlatMsiDecoder;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(dest: MsiDestination, vector: usize) -> Option<MsiTarget> {
+        Some(MsiTarget { dest, vector })
+    }
+
+    #[test]
+    fn x86_fixed_physical_messages() {
+        let decoder = X86MsiDecoder;
+        assert_eq!(
+            decoder.decode(&MsiMessage::new(0xfee0_3000, 0x41)),
+            target(MsiDestination::Single(3), 0x41)
+        );
+        assert_eq!(
+            decoder.decode(&MsiMessage::new(0xfeef_f000, 0x141)),
+            target(MsiDestination::Broadcast, 0x41)
+        );
+        assert_eq!(decoder.decode(&MsiMessage::new(0xfed0_3000, 0x41)), None);
+    }
+
+    #[test]
+    fn x86_unmodeled_modes_are_not_decoded() {
+        let decoder = X86MsiDecoder;
+        // Logical destination mode, redirection hint.
+        assert_eq!(decoder.decode(&MsiMessage::new(0xfee0_3004, 0x41)), None);
+        assert_eq!(decoder.decode(&MsiMessage::new(0xfee0_3008, 0x41)), None);
+        // SMI, NMI, INIT and ExtINT delivery modes.
+        for mode in [0b010, 0b100, 0b101, 0b111] {
+            let data = (mode << 8) | 0x41;
+            assert_eq!(decoder.decode(&MsiMessage::new(0xfee0_3000, data)), None);
+        }
+    }
+
+    #[test]
+    fn imsic_messages_target_the_start_of_a_file() {
+        let decoder = ImsicMsiDecoder {
+            base: 0x2800_0000,
+            stride_shift: 12,
+        };
+        assert_eq!(
+            decoder.decode(&MsiMessage::new(0x2800_2000, 9)),
+            target(MsiDestination::Single(2), 9)
+        );
+        assert_eq!(decoder.decode(&MsiMessage::new(0x2800_2004, 9)), None);
+        assert_eq!(decoder.decode(&MsiMessage::new(0x27ff_f000, 9)), None);
+    }
+
+    #[test]
+    fn imsic_stride_is_checked() {
+        let decoder = ImsicMsiDecoder {
+            base: 0x2800_0000,
+            stride_shift: 64,
+        };
+        assert_eq!(decoder.decode(&MsiMessage::new(0x2800_0000, 9)), None);
+    }
+}
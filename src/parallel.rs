@@ -0,0 +1,58 @@
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::AxVCpuHal;
+
+/// A work item of [`parallel_map`], with its input and its result.
+struct WorkSlot<T, R> {
+    /// Whether a worker has claimed the item.
+    claimed: AtomicBool,
+    /// The input, then the result once the item is done.
+    cell: UnsafeCell<(Option<T>, Option<R>)>,
+}
+
+/// The work items of [`parallel_map`].
+struct WorkSlots<T, R>(Vec<WorkSlot<T, R>>);
+
+// SAFETY: each slot is only accessed by the single worker which claimed it, and the inputs and results are `Send`.
+unsafe impl<T: Send, R: Send> Sync for WorkSlots<T, R> {}
+
+impl<T, R> WorkSlots<T, R> {
+    /// Run the item `index` with `f`, unless it's already claimed by another worker.
+    fn run(&self, index: usize, f: impl FnOnce(T) -> R) {
+        let Some(slot) = self.0.get(index) else {
+            return;
+        };
+        if slot.claimed.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // SAFETY: the slot is claimed by this worker.
+        let (input, result) = unsafe { &mut *slot.cell.get() };
+        *result = input.take().map(f);
+    }
+}
+
+/// Map `inputs` with `f`, spreading the work over the physical CPUs with [`AxVCpuHal::parallel_for`].
+///
+/// Returns `None` if the HAL skipped some items.
+pub(crate) fn parallel_map<H: AxVCpuHal, T: Send, R: Send>(
+    inputs: Vec<T>,
+    f: impl Fn(T) -> R + Sync,
+) -> Option<Vec<R>> {
+    let slots = WorkSlots(
+        inputs
+            .into_iter()
+            .map(|input| WorkSlot {
+                claimed: AtomicBool::new(false),
+                cell: UnsafeCell::new((Some(input), None)),
+            })
+            .collect(),
+    );
+    H::parallel_for(slots.0.len(), &|index| slots.run(index, &f));
+    slots
+        .0
+        .into_iter()
+        .map(|slot| slot.cell.into_inner().1)
+        .collect()
+}
@@ -1,6 +1,57 @@
+use core::fmt;
 use core::mem::MaybeUninit;
 
-use axerrno::{AxResult, ax_err};
+use axerrno::{AxError, AxResult, ax_err};
+
+use crate::{AxVCpuError, VirtHwInfo};
+
+/// The class of a physical CPU on heterogeneous (e.g., big.LITTLE) systems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CpuClass {
+    /// A performance ("big") core.
+    Performance,
+    /// An efficiency ("LITTLE") core.
+    Efficiency,
+}
+
+/// The reason why hardware virtualization is unavailable on a physical CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtUnavailableReason {
+    /// The CPU does not support hardware virtualization at all.
+    UnsupportedCpu,
+    /// Hardware virtualization is supported but disabled by the firmware (e.g., locked off by `IA32_FEATURE_CONTROL`
+    /// in x86, or the kernel is not entered at EL2 in Aarch64).
+    BiosDisabled,
+    /// The host itself runs as a guest of another hypervisor, which does not expose nested virtualization.
+    NestedUnderOtherHypervisor,
+    /// The per-CPU state is not initialized.
+    NotInitialized,
+    /// Enabling hardware virtualization failed for another reason.
+    Failed(AxError),
+}
+
+impl fmt::Display for VirtUnavailableReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedCpu => write!(f, "the CPU does not support hardware virtualization"),
+            Self::BiosDisabled => write!(f, "hardware virtualization is disabled by the firmware"),
+            Self::NestedUnderOtherHypervisor => write!(
+                f,
+                "running under another hypervisor without nested virtualization"
+            ),
+            Self::NotInitialized => write!(f, "the per-CPU state is not initialized"),
+            Self::Failed(err) => write!(f, "failed to enable hardware virtualization: {:?}", err),
+        }
+    }
+}
+
+/// Probe whether hardware virtualization is usable on the current CPU, without enabling it.
+///
+/// Hypervisor frontends can call this before initializing any per-CPU state, to guide users instead of failing
+/// later. See [`AxArchPerCpu::probe`].
+pub fn is_virtualization_supported<A: AxArchPerCpu>() -> Result<(), VirtUnavailableReason> {
+    A::probe()
+}
 
 /// Trait representing the per-CPU architecture-specific virtualization state in a virtual machine.
 ///
@@ -10,12 +61,48 @@ use axerrno::{AxResult, ax_err};
 pub trait AxArchPerCpu: Sized {
     /// Create a new per-CPU state.
     fn new(cpu_id: usize) -> AxResult<Self>;
+    /// Probe whether hardware virtualization is usable on the current CPU, without enabling it. The default
+    /// implementation reports it's usable.
+    fn probe() -> Result<(), VirtUnavailableReason> {
+        Ok(())
+    }
     /// Whether hardware virtualization is enabled on the current CPU.
     fn is_enabled(&self) -> bool;
     /// Enable hardware virtualization on the current CPU.
     fn hardware_enable(&mut self) -> AxResult;
     /// Disable hardware virtualization on the current CPU.
     fn hardware_disable(&mut self) -> AxResult;
+    /// The class of the current CPU. The default implementation returns [`CpuClass::Performance`], as on
+    /// homogeneous systems.
+    fn cpu_class(&self) -> CpuClass {
+        CpuClass::Performance
+    }
+    /// The maximum number of guest (nested) page table levels supported by the current CPU. The default
+    /// implementation returns 4.
+    fn max_guest_page_table_levels(&self) -> usize {
+        4
+    }
+    /// The minimum number of guest (nested) page table levels supported by the current CPU. The default
+    /// implementation returns [`AxArchPerCpu::max_guest_page_table_levels`].
+    fn min_guest_page_table_levels(&self) -> usize {
+        self.max_guest_page_table_levels()
+    }
+    /// Report the hardware virtualization support of the current CPU. The default implementation returns
+    /// [`VirtHwInfo::UNKNOWN`].
+    fn hardware_info(&self) -> VirtHwInfo {
+        VirtHwInfo::UNKNOWN
+    }
+    /// Allocate a hardware guest-interrupt file (e.g., an IMSIC guest interrupt file in RISC-V AIA) on the current
+    /// CPU, returning its index, or `None` if none is available. The default implementation returns `None`, as for
+    /// CPUs without such files.
+    fn alloc_guest_irq_file(&mut self) -> Option<usize> {
+        None
+    }
+    /// Free a hardware guest-interrupt file allocated by [`AxArchPerCpu::alloc_guest_irq_file`]. The default
+    /// implementation does nothing.
+    fn free_guest_irq_file(&mut self, file: usize) {
+        let _ = file;
+    }
 }
 
 /// Host per-CPU states to run the guest.
@@ -88,16 +175,176 @@ impl<A: AxArchPerCpu> AxPerCpu<A> {
         self.arch_checked_mut().hardware_enable()
     }
 
+    /// Try to enable hardware virtualization on the current CPU, reporting why it's unavailable on failure.
+    ///
+    /// Unlike [`AxPerCpu::hardware_enable`], this method never panics, and probes the hardware with
+    /// [`AxArchPerCpu::probe`] first.
+    pub fn try_hardware_enable(&mut self) -> Result<(), VirtUnavailableReason> {
+        if self.cpu_id.is_none() {
+            return Err(VirtUnavailableReason::NotInitialized);
+        }
+        A::probe()?;
+        self.hardware_enable()
+            .map_err(VirtUnavailableReason::Failed)
+    }
+
     /// Disable hardware virtualization on the current CPU.
     pub fn hardware_disable(&mut self) -> AxResult {
         self.arch_checked_mut().hardware_disable()
     }
+
+    /// Tear down the per-CPU state: disable hardware virtualization if it's enabled, and drop the
+    /// architecture-specific state, leaving the per-CPU state uninitialized.
+    ///
+    /// Does nothing if the per-CPU state is not initialized. If disabling hardware virtualization fails, the error
+    /// is returned and the per-CPU state is kept intact.
+    pub fn shutdown(&mut self) -> AxResult {
+        if self.cpu_id.is_none() {
+            return Ok(());
+        }
+        if self.is_enabled() {
+            self.hardware_disable()?;
+        }
+        self.cpu_id = None;
+        // SAFETY: `cpu_id` was `Some`, so `arch` is initialized, and it's marked uninitialized above.
+        unsafe { self.arch.assume_init_drop() };
+        Ok(())
+    }
+
+    /// The class of the current CPU.
+    pub fn cpu_class(&self) -> CpuClass {
+        self.arch_checked().cpu_class()
+    }
+
+    /// Report the hardware virtualization support of the current CPU.
+    pub fn hardware_info(&self) -> VirtHwInfo {
+        self.arch_checked().hardware_info()
+    }
+
+    /// Allocate a hardware guest-interrupt file on the current CPU, to be attached to a vcpu bound to it by
+    /// [`AxVCpu::set_guest_irq_file`](crate::AxVCpu::set_guest_irq_file). Returns `None` if none is available, in
+    /// which case interrupts are injected in software.
+    pub fn alloc_guest_irq_file(&mut self) -> Option<usize> {
+        self.arch_checked_mut().alloc_guest_irq_file()
+    }
+
+    /// Free a hardware guest-interrupt file allocated by [`AxPerCpu::alloc_guest_irq_file`], after it's detached
+    /// from the vcpu.
+    pub fn free_guest_irq_file(&mut self, file: usize) {
+        self.arch_checked_mut().free_guest_irq_file(file)
+    }
+
+    /// The maximum number of guest (nested) page table levels supported by the current CPU.
+    pub fn max_guest_page_table_levels(&self) -> usize {
+        self.arch_checked().max_guest_page_table_levels()
+    }
+
+    /// Negotiate the number of guest (nested) page table levels of a VM.
+    ///
+    /// Returns `requested` if it's supported by the current CPU, or the maximum supported number if `requested` is
+    /// `None`. The result is meant to be passed to [`AxVCpuBuilder::guest_page_table_levels`](crate::AxVCpuBuilder::guest_page_table_levels),
+    /// so that it's cross-checked when the vcpus are set up.
+    pub fn negotiate_guest_page_table_levels(
+        &self,
+        requested: Option<usize>,
+    ) -> Result<usize, AxVCpuError> {
+        let arch = self.arch_checked();
+        let (min, max) = (
+            arch.min_guest_page_table_levels(),
+            arch.max_guest_page_table_levels(),
+        );
+        match requested {
+            None => Ok(max),
+            Some(requested) if (min..=max).contains(&requested) => Ok(requested),
+            Some(requested) => Err(AxVCpuError::PageTableLevelsUnsupported {
+                requested,
+                min,
+                max,
+            }),
+        }
+    }
+
+    /// Negotiate the size of the guest physical address space of a VM in bits.
+    ///
+    /// Returns `requested` if it's within the limit reported by [`AxPerCpu::hardware_info`] (or if the limit is
+    /// unknown), or the limit if `requested` is `None` (`None` if the limit is unknown too). The result is meant
+    /// to be passed to [`AxVCpuBuilder::guest_phys_bits`](crate::AxVCpuBuilder::guest_phys_bits).
+    pub fn negotiate_guest_phys_bits(
+        &self,
+        requested: Option<u8>,
+    ) -> Result<Option<u8>, AxVCpuError> {
+        let info = self.hardware_info();
+        match requested {
+            None => Ok((info.max_ipa_bits != 0).then_some(info.max_ipa_bits)),
+            Some(requested) => info.check_ipa_bits(requested).map(|()| Some(requested)),
+        }
+    }
 }
 
 impl<A: AxArchPerCpu> Drop for AxPerCpu<A> {
+    /// Best-effort teardown, errors are ignored. Call [`AxPerCpu::shutdown`] beforehand to handle them.
+    ///
+    /// If disabling hardware virtualization fails, the architecture-specific state is leaked rather than dropped,
+    /// as the hardware may still reference its memory (e.g., the VMXON region in x86).
     fn drop(&mut self) {
-        if self.is_enabled() {
-            self.hardware_disable().unwrap();
-        }
+        let _ = self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockArchPerCpu;
+
+    #[test]
+    fn uninitialized_drop_and_shutdown() {
+        let mut percpu = AxPerCpu::<MockArchPerCpu>::new_uninit();
+        assert_eq!(
+            percpu.try_hardware_enable(),
+            Err(VirtUnavailableReason::NotInitialized)
+        );
+        assert!(percpu.shutdown().is_ok());
+        drop(percpu);
+    }
+
+    #[test]
+    fn failed_disable_keeps_state() {
+        let mut percpu = AxPerCpu::<MockArchPerCpu>::new_uninit();
+        percpu.init(1).unwrap();
+        percpu.hardware_enable().unwrap();
+        percpu.arch_checked_mut().set_fail_disable(true);
+        assert_eq!(percpu.shutdown(), Err(AxError::ResourceBusy));
+        assert!(percpu.is_enabled());
+        assert_eq!(MockArchPerCpu::drops(1), 0);
+
+        percpu.arch_checked_mut().set_fail_disable(false);
+        percpu.shutdown().unwrap();
+        assert_eq!(MockArchPerCpu::drops(1), 1);
+    }
+
+    #[test]
+    fn failed_disable_on_drop_leaks_state() {
+        let mut percpu = AxPerCpu::<MockArchPerCpu>::new_uninit();
+        percpu.init(2).unwrap();
+        percpu.hardware_enable().unwrap();
+        percpu.arch_checked_mut().set_fail_disable(true);
+        drop(percpu);
+        assert_eq!(MockArchPerCpu::drops(2), 0);
+    }
+
+    #[test]
+    fn double_shutdown() {
+        let mut percpu = AxPerCpu::<MockArchPerCpu>::new_uninit();
+        percpu.init(3).unwrap();
+        percpu.try_hardware_enable().unwrap();
+        percpu.shutdown().unwrap();
+        assert_eq!(MockArchPerCpu::drops(3), 1);
+        percpu.shutdown().unwrap();
+        assert_eq!(MockArchPerCpu::drops(3), 1);
+
+        percpu.init(3).unwrap();
+        assert!(!percpu.is_enabled());
+        drop(percpu);
+        assert_eq!(MockArchPerCpu::drops(3), 2);
     }
 }
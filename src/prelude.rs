@@ -0,0 +1,13 @@
+//! The commonly used items of this crate, to be glob-imported by hypervisor modules:
+//!
+//! ```ignore
+//! use axvcpu::prelude::*;
+//! ```
+
+pub use axerrno::{AxError, AxResult};
+
+pub use crate::{
+    AccessWidth, AxArchPerCpu, AxArchVCpu, AxPerCpu, AxVCpu, AxVCpuBuilder, AxVCpuError,
+    AxVCpuExitReason, AxVCpuGroup, AxVCpuHal, AxVCpuRef, AxVCpuResult, ExitAction, ExitKind,
+    ExitKindSet, RunToken, ShutdownReason, VCpuState,
+};
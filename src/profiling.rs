@@ -0,0 +1,29 @@
+/// The hardware counters programmed around guest execution, see
+/// [`AxVCpu::enable_exec_profiling`](crate::AxVCpu::enable_exec_profiling).
+///
+/// The counters only count while the guest runs, so that exits can be correlated with the progress of the guest
+/// without its cooperation. Disabled counters are not programmed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecProfilingConfig {
+    /// Count retired guest instructions.
+    pub instructions: bool,
+    /// Count retired guest branches.
+    pub branches: bool,
+}
+
+impl ExecProfilingConfig {
+    /// Whether no counter is enabled.
+    pub const fn is_disabled(&self) -> bool {
+        !self.instructions && !self.branches
+    }
+}
+
+/// The values of the guest execution counters, read by the architecture-specific vcpu after each run, see
+/// [`AxArchVCpu::read_exec_counters`](crate::AxArchVCpu::read_exec_counters).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecCounters {
+    /// The number of retired guest instructions.
+    pub instructions: u64,
+    /// The number of retired guest branches.
+    pub branches: u64,
+}
@@ -0,0 +1,77 @@
+use alloc::sync::Arc;
+
+use axaddrspace::GuestPhysAddr;
+
+use crate::{AxVCpuExitReason, AxVCpuHal, GuestMemoryAccess};
+
+/// The function of the para-virtualized console hypercall writing one character, passed in the first argument.
+/// The second argument is the character (its low byte).
+pub const PV_CONSOLE_PUTCHAR: u64 = 0;
+/// The function of the para-virtualized console hypercall writing a buffer, passed in the first argument. The
+/// second and third arguments are the guest physical address and the length of the buffer.
+pub const PV_CONSOLE_WRITE: u64 = 1;
+/// The maximum number of bytes written by one [`PV_CONSOLE_WRITE`] call, longer buffers are truncated.
+pub const PV_CONSOLE_MAX_WRITE: usize = 4096;
+
+/// A built-in handler of a "debug putchar/write buffer" hypercall, forwarding the output of the guest to
+/// [`AxVCpuHal::console_write`], so that new guests can print before any virtual UART is emulated. Set with
+/// [`AxVCpu::set_pv_console`](crate::AxVCpu::set_pv_console).
+///
+/// The function is passed in the first argument of the hypercall, see [`PV_CONSOLE_PUTCHAR`] and
+/// [`PV_CONSOLE_WRITE`]. Calls of other functions are returned to the VMM as usual.
+#[derive(Clone)]
+pub struct PvConsole {
+    /// The hypercall number.
+    nr: u64,
+    /// The memory of the guest, required by [`PV_CONSOLE_WRITE`].
+    mem: Option<Arc<dyn GuestMemoryAccess>>,
+}
+
+impl PvConsole {
+    /// Create a handler of the hypercall `nr`, which only supports [`PV_CONSOLE_PUTCHAR`].
+    pub fn new(nr: u64) -> Self {
+        Self { nr, mem: None }
+    }
+
+    /// Support [`PV_CONSOLE_WRITE`], reading the buffers of the guest through `mem`.
+    pub fn with_memory(mut self, mem: Arc<dyn GuestMemoryAccess>) -> Self {
+        self.mem = Some(mem);
+        self
+    }
+
+    /// Get the hypercall number.
+    pub const fn nr(&self) -> u64 {
+        self.nr
+    }
+
+    /// Handle the exit if it's a call to the console, returns whether it's handled. The output is only written if
+    /// `output` is set, it's dropped when replaying an exit.
+    pub(crate) fn handle<H: AxVCpuHal>(&self, exit: &AxVCpuExitReason, output: bool) -> bool {
+        let Some((nr, args)) = exit.as_hypercall() else {
+            return false;
+        };
+        if nr != self.nr {
+            return false;
+        }
+        match (args[0], &self.mem) {
+            (PV_CONSOLE_PUTCHAR, _) | (PV_CONSOLE_WRITE, Some(_)) if !output => {}
+            (PV_CONSOLE_PUTCHAR, _) => H::console_write(&[args[1] as u8]),
+            (PV_CONSOLE_WRITE, Some(mem)) => {
+                let len = (args[2] as usize).min(PV_CONSOLE_MAX_WRITE);
+                let mut chunk = [0; 256];
+                let mut done = 0;
+                while done < len {
+                    let n = (len - done).min(chunk.len());
+                    let addr = GuestPhysAddr::from(args[1] as usize + done);
+                    if mem.read(addr, &mut chunk[..n]).is_err() {
+                        break;
+                    }
+                    H::console_write(&chunk[..n]);
+                    done += n;
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
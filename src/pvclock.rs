@@ -0,0 +1,91 @@
+use alloc::collections::BTreeMap;
+
+use axaddrspace::HostVirtAddr;
+
+/// The layout of the para-virtualized time page, through which the guest is notified of time jumps.
+///
+/// The page is updated in a seqlock fashion: `version` is odd while the page is being updated, and
+/// the guest should retry reading if `version` is odd or changed during the read.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PvTimeJumpInfo {
+    /// The version of the page.
+    pub version: u32,
+    /// Reserved, always 0.
+    pub reserved: u32,
+    /// The sum of all time jumps in nanoseconds.
+    pub total_jump_ns: i64,
+    /// The last time jump in nanoseconds.
+    pub last_jump_ns: i64,
+}
+
+/// The layout of the para-virtualized steal time structure, compatible with the KVM `kvm_steal_time` ABI, through
+/// which the guest learns how long its vcpu was runnable but not running (shown as `steal%` by Linux guests).
+///
+/// The structure is updated in a seqlock fashion like [`PvTimeJumpInfo`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PvStealTime {
+    /// The accumulated steal time in nanoseconds.
+    pub steal: u64,
+    /// The version of the structure.
+    pub version: u32,
+    /// Reserved, always 0.
+    pub flags: u32,
+    /// Whether the vcpu is preempted, i.e., unbound from its physical CPU.
+    pub preempted: u8,
+    /// Padding.
+    pub pad0: [u8; 3],
+    /// Padding to 64 bytes.
+    pub pad1: [u32; 11],
+}
+
+const _: () = assert!(core::mem::size_of::<PvStealTime>() == 64);
+
+/// Update the steal time structure at `page`.
+///
+/// # Safety
+///
+/// `page` must point to a valid, writable [`PvStealTime`].
+pub(crate) unsafe fn write_steal_time(page: HostVirtAddr, steal_ns: u64, preempted: bool) {
+    let info = page.as_usize() as *mut PvStealTime;
+    unsafe {
+        let version = (&raw const (*info).version).read_volatile();
+        (&raw mut (*info).version).write_volatile(version.wrapping_add(1) | 1);
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
+        (&raw mut (*info).steal).write_volatile(steal_ns);
+        (&raw mut (*info).preempted).write_volatile(preempted as u8);
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
+        (&raw mut (*info).version).write_volatile((version | 1).wrapping_add(1));
+    }
+}
+
+/// The para-virtualized time pages registered by the guests, and the vector used to notify them.
+#[derive(Default)]
+pub(crate) struct PvTimePages {
+    /// The vector injected after the page of a vcpu is updated.
+    pub vector: Option<usize>,
+    /// The registered pages, indexed by vcpu id.
+    pub pages: BTreeMap<usize, HostVirtAddr>,
+}
+
+impl PvTimePages {
+    /// Record a time jump in the page at `page`.
+    ///
+    /// # Safety
+    ///
+    /// `page` must point to a valid, writable [`PvTimeJumpInfo`].
+    pub unsafe fn write_jump(page: HostVirtAddr, delta_ns: i64) {
+        let info = page.as_usize() as *mut PvTimeJumpInfo;
+        unsafe {
+            let version = (&raw const (*info).version).read_volatile();
+            (&raw mut (*info).version).write_volatile(version.wrapping_add(1) | 1);
+            core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
+            let total = (&raw const (*info).total_jump_ns).read_volatile();
+            (&raw mut (*info).total_jump_ns).write_volatile(total.wrapping_add(delta_ns));
+            (&raw mut (*info).last_jump_ns).write_volatile(delta_ns);
+            core::sync::atomic::fence(core::sync::atomic::Ordering::Release);
+            (&raw mut (*info).version).write_volatile((version | 1).wrapping_add(1));
+        }
+    }
+}
@@ -0,0 +1,61 @@
+/// The CPU bandwidth cap of a vcpu, see [`AxVCpu::set_cpu_quota`](crate::AxVCpu::set_cpu_quota).
+#[derive(Debug, Default)]
+pub(crate) struct CpuQuota {
+    /// The length of a period in nanoseconds, 0 if the vcpu is not capped.
+    period_ns: u64,
+    /// The guest time allowed in each period in nanoseconds.
+    runtime_ns: u64,
+    /// When the current period started.
+    period_start: u64,
+    /// The guest time consumed in the current period.
+    consumed_ns: u64,
+    /// When the vcpu was first refused to run because of the cap, if it's throttled.
+    throttled_since: Option<u64>,
+}
+
+impl CpuQuota {
+    /// Set the cap, `period_ns == 0` removes it.
+    pub fn set(&mut self, period_ns: u64, runtime_ns: u64, now: u64) {
+        *self = Self {
+            period_ns,
+            runtime_ns,
+            period_start: now,
+            ..Self::default()
+        };
+    }
+
+    /// Check whether the vcpu may enter the guest at `now`.
+    ///
+    /// Returns `Err(ns)` with the time until the next period if the budget is exhausted, or `Ok(throttled_ns)`
+    /// with the time the vcpu has just been throttled for.
+    pub fn check(&mut self, now: u64) -> Result<u64, u64> {
+        if self.period_ns == 0 {
+            return Ok(0);
+        }
+        let elapsed = now.saturating_sub(self.period_start);
+        if elapsed >= self.period_ns {
+            self.period_start = now - elapsed % self.period_ns;
+            self.consumed_ns = 0;
+        }
+        if self.consumed_ns >= self.runtime_ns {
+            self.throttled_since.get_or_insert(now);
+            return Err((self.period_start + self.period_ns).saturating_sub(now));
+        }
+        Ok(self
+            .throttled_since
+            .take()
+            .map_or(0, |since| now.saturating_sub(since)))
+    }
+
+    /// Charge `ns` nanoseconds of guest time.
+    pub fn charge(&mut self, ns: u64) {
+        if self.period_ns != 0 {
+            self.consumed_ns = self.consumed_ns.saturating_add(ns);
+        }
+    }
+
+    /// Get the cap as `(period_ns, runtime_ns)`, or `None` if the vcpu is not capped.
+    pub fn get(&self) -> Option<(u64, u64)> {
+        (self.period_ns != 0).then_some((self.period_ns, self.runtime_ns))
+    }
+}
@@ -0,0 +1,72 @@
+use alloc::collections::VecDeque;
+
+use crate::ExitKind;
+
+/// The default number of resets within [`REBOOT_STORM_DEFAULT_WINDOW_NS`] considered a reboot storm.
+pub const REBOOT_STORM_DEFAULT_THRESHOLD: usize = 5;
+
+/// The default window of [`AxVCpuGroup::set_reboot_storm_threshold`](crate::AxVCpuGroup::set_reboot_storm_threshold),
+/// in nanoseconds.
+pub const REBOOT_STORM_DEFAULT_WINDOW_NS: u64 = 60_000_000_000;
+
+/// A guest crash-looping, i.e., resetting or powering off too often, reported by
+/// [`AxVCpuGroup::check_reboot_storm`](crate::AxVCpuGroup::check_reboot_storm), so that orchestration layers
+/// can stop the VM instead of burning host CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RebootStorm {
+    /// The number of resets within the window which triggered the detection, i.e., the threshold.
+    pub count: usize,
+    /// The window, in nanoseconds.
+    pub window_ns: u64,
+    /// The kind of the last reset, [`ExitKind::SystemReset`] or [`ExitKind::SystemDown`].
+    pub last: ExitKind,
+}
+
+/// The recent resets of a VM, see [`AxVCpuGroup::check_reboot_storm`](crate::AxVCpuGroup::check_reboot_storm).
+pub(crate) struct RebootStormDetector {
+    /// The number of resets within the window considered a storm, 0 if detection is disabled.
+    pub(crate) threshold: usize,
+    /// The window, in nanoseconds.
+    pub(crate) window_ns: u64,
+    /// The times of the resets within the window, in nanoseconds, oldest first.
+    resets: VecDeque<u64>,
+}
+
+impl RebootStormDetector {
+    /// Create a detector with the default threshold and window.
+    pub(crate) const fn new() -> Self {
+        Self {
+            threshold: REBOOT_STORM_DEFAULT_THRESHOLD,
+            window_ns: REBOOT_STORM_DEFAULT_WINDOW_NS,
+            resets: VecDeque::new(),
+        }
+    }
+
+    /// Record a reset of kind `kind` at `now_ns`, returning the storm if the threshold is reached.
+    pub(crate) fn record(&mut self, kind: ExitKind, now_ns: u64) -> Option<RebootStorm> {
+        if self.threshold == 0 {
+            return None;
+        }
+        while self
+            .resets
+            .front()
+            .is_some_and(|&time| now_ns.saturating_sub(time) >= self.window_ns)
+        {
+            self.resets.pop_front();
+        }
+        self.resets.push_back(now_ns);
+        while self.resets.len() > self.threshold {
+            self.resets.pop_front();
+        }
+        (self.resets.len() >= self.threshold).then_some(RebootStorm {
+            count: self.resets.len(),
+            window_ns: self.window_ns,
+            last: kind,
+        })
+    }
+
+    /// Forget the recorded resets.
+    pub(crate) fn clear(&mut self) {
+        self.resets.clear();
+    }
+}
@@ -0,0 +1,103 @@
+use core::fmt;
+use core::marker::PhantomData;
+
+use crate::AxArchVCpu;
+
+/// The names of the general-purpose registers of x86_64, indexed by their encoding.
+pub const X86_64_GPR_NAMES: &[&str] = &[
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+
+/// The names of the general-purpose registers of Aarch64, indexed by their encoding. Index 31 is the zero
+/// register, as in the register fields of data abort syndromes.
+pub const AARCH64_GPR_NAMES: &[&str] = &[
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14",
+    "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27",
+    "x28", "x29", "x30", "xzr",
+];
+
+/// The ABI names of the general-purpose registers of RISC-V, indexed by their encoding.
+pub const RISCV_GPR_NAMES: &[&str] = &[
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// The names of the general-purpose registers of the current architecture, used by the default implementation
+/// of [`AxArchVCpu::reg_name`].
+pub const DEFAULT_GPR_NAMES: &[&str] = if cfg!(target_arch = "x86_64") {
+    X86_64_GPR_NAMES
+} else if cfg!(target_arch = "aarch64") {
+    AARCH64_GPR_NAMES
+} else if cfg!(any(target_arch = "riscv32", target_arch = "riscv64")) {
+    RISCV_GPR_NAMES
+} else {
+    &[]
+};
+
+/// A general-purpose register index of an architecture-specific vcpu, displayed with its architectural name
+/// (e.g., `x0`, `rax`, `a7`) as given by [`AxArchVCpu::reg_name`], or `r<index>` if it has none.
+///
+/// Use it wherever a register index is printed, e.g., in logs, traces and core dumps.
+pub struct RegName<A: AxArchVCpu> {
+    /// The index of the register.
+    index: usize,
+    _arch: PhantomData<A>,
+}
+
+impl<A: AxArchVCpu> RegName<A> {
+    /// Create a register name from the index of the register.
+    pub const fn new(index: usize) -> Self {
+        Self {
+            index,
+            _arch: PhantomData,
+        }
+    }
+
+    /// Look up a register by its architectural name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        A::reg_index(name).map(Self::new)
+    }
+
+    /// Get the index of the register.
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Get the architectural name of the register, if any.
+    pub fn name(&self) -> Option<&'static str> {
+        A::reg_name(self.index)
+    }
+}
+
+impl<A: AxArchVCpu> Clone for RegName<A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A: AxArchVCpu> Copy for RegName<A> {}
+
+impl<A: AxArchVCpu> PartialEq for RegName<A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<A: AxArchVCpu> Eq for RegName<A> {}
+
+impl<A: AxArchVCpu> fmt::Display for RegName<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "r{}", self.index),
+        }
+    }
+}
+
+impl<A: AxArchVCpu> fmt::Debug for RegName<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,370 @@
+use alloc::vec::Vec;
+
+use axerrno::{AxResult, ax_err};
+
+use crate::run_page::{CompletionTarget, completion_target};
+use crate::{
+    AxArchVCpu, AxVCpu, AxVCpuExitReason, AxVCpuResult, AxVCpuSnapshot, ExitKind, HostInfo,
+    RunToken, VCpuRunPage,
+};
+
+/// An exit recorded by a [`ReplaySession`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayEntry {
+    /// The kind of the exit.
+    pub kind: ExitKind,
+    /// The payload of the exit, encoded by [`VCpuRunPage::encode_payload`].
+    pub payload: [u64; 8],
+    /// The value the exit was completed with, for exits reading a value into the guest (see
+    /// [`ReplaySession::complete`]).
+    pub completion: Option<u64>,
+    /// The interrupts injected into the guest before the exit, in injection order, which are injected again on
+    /// replay.
+    pub interrupts: Vec<usize>,
+    /// Whether the exit was queued by [`AxVCpu::queue_exit`] rather than caused by the guest. Such exits are
+    /// skipped on replay.
+    pub queued: bool,
+}
+
+/// The state of a [`ReplaySession`] shared with the vcpu it records.
+///
+/// While replaying, [`AxVCpu::run`] injects the recorded interrupts instead of the pending ones, and leaves out
+/// the side effects already observed when the exits were recorded: the exit filter, the journal and the run page,
+/// the CPU quota, the statistics and the trace, the deferred work and the para-virtualized console output. A
+/// replayed [`AxVCpuExitReason::Halt`] doesn't block the vcpu.
+#[derive(Debug, Default)]
+pub(crate) struct ReplayHook {
+    /// Whether the recorded exits are replayed, rather than new ones recorded.
+    pub(crate) replaying: bool,
+    /// When recording, the interrupts injected since the last exit; when replaying, the interrupts to inject at
+    /// the next entry.
+    pub(crate) interrupts: Vec<usize>,
+    /// Whether the last exit was queued rather than caused by the guest, when recording.
+    pub(crate) queued: bool,
+}
+
+/// A record/replay session of a vcpu, for time-travel debugging: the exits of the vcpu are logged along with
+/// periodic snapshots, from which the register state at any earlier exit boundary is reconstructed with
+/// [`ReplaySession::step_back`].
+///
+/// The VMM runs the vcpu through the session with [`ReplaySession::run`] instead of [`AxVCpu::run`], and completes
+/// the exits reading a value into the guest with [`ReplaySession::complete`], so that the values can be fed again
+/// on replay. Exit boundaries are numbered by the number of exits handled so far, starting from 0 when the session
+/// is created.
+///
+/// The session only covers the state of the vcpu. Guest memory and device models must be rolled back by the VMM
+/// to the same checkpoint (see the `rewind` callback of [`ReplaySession::step_back`]), otherwise the replayed exits
+/// diverge from the log, which is detected and reported. The interrupts injected into the guest are recorded along
+/// with the exits, and injected again on replay.
+pub struct ReplaySession<'a, A: AxArchVCpu> {
+    /// The vcpu recorded.
+    vcpu: &'a AxVCpu<A>,
+    /// The host the snapshots are taken and restored on.
+    host: HostInfo,
+    /// The number of exits between two checkpoints.
+    checkpoint_interval: usize,
+    /// The checkpoints, as `(position, snapshot)`, sorted by position.
+    checkpoints: Vec<(usize, AxVCpuSnapshot)>,
+    /// The recorded exits.
+    log: Vec<ReplayEntry>,
+    /// The current exit boundary, i.e., the number of exits handled.
+    position: usize,
+    /// Where the completion of the last exit is stored, until it's completed.
+    pending_completion: Option<CompletionTarget>,
+}
+
+impl<'a, A: AxArchVCpu> ReplaySession<'a, A> {
+    /// Start recording `vcpu`, which must not be running, taking a snapshot every `checkpoint_interval` exits
+    /// (at least 1). The initial state is the first checkpoint.
+    ///
+    /// Shorter intervals make [`ReplaySession::step_back`] faster (fewer exits to replay) at the cost of memory.
+    pub fn new(vcpu: &'a AxVCpu<A>, host: HostInfo, checkpoint_interval: usize) -> AxResult<Self> {
+        if checkpoint_interval == 0 {
+            return ax_err!(InvalidInput, "checkpoint interval must not be zero");
+        }
+        let initial = vcpu.snapshot(&host)?;
+        *vcpu.replay_hook().borrow_mut() = Some(ReplayHook::default());
+        Ok(Self {
+            vcpu,
+            host,
+            checkpoint_interval,
+            checkpoints: vec![(0, initial)],
+            log: Vec::new(),
+            position: 0,
+            pending_completion: None,
+        })
+    }
+
+    /// Get the current exit boundary, i.e., the number of exits handled since the session started.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Get the recorded exits, oldest first. Entries past [`ReplaySession::position`] are the ones stepped back
+    /// over, which can be replayed with [`ReplaySession::step_forward`].
+    pub fn log(&self) -> &[ReplayEntry] {
+        &self.log
+    }
+
+    /// Get the positions of the checkpoints.
+    pub fn checkpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.checkpoints.iter().map(|&(position, _)| position)
+    }
+
+    /// Run the vcpu to its next exit (see [`AxVCpu::run`]) and record the exit, taking a checkpoint first if one
+    /// is due.
+    ///
+    /// If the session stepped back, the exits past the current position are discarded, and recording continues
+    /// from here on a new timeline.
+    pub fn run(&mut self, token: &RunToken) -> AxVCpuResult<AxVCpuExitReason> {
+        self.log.truncate(self.position);
+        self.checkpoints
+            .retain(|&(position, _)| position <= self.position);
+        if self.position.is_multiple_of(self.checkpoint_interval)
+            && self.checkpoints.last().map(|&(position, _)| position) != Some(self.position)
+        {
+            let snapshot = self.vcpu.snapshot(&self.host)?;
+            self.checkpoints.push((self.position, snapshot));
+        }
+        *self.vcpu.replay_hook().borrow_mut() = Some(ReplayHook::default());
+        let exit = self.vcpu.run(token)?;
+        let hook = self
+            .vcpu
+            .replay_hook()
+            .replace(Some(ReplayHook::default()))
+            .unwrap_or_default();
+        self.log.push(ReplayEntry {
+            kind: exit.kind(),
+            payload: VCpuRunPage::encode_payload(&exit),
+            completion: None,
+            interrupts: hook.interrupts,
+            queued: hook.queued,
+        });
+        self.position += 1;
+        self.pending_completion = completion_target(&exit, self.vcpu.guest_endian());
+        Ok(exit)
+    }
+
+    /// Complete the last exit returned by [`ReplaySession::run`] with `value`, storing it into the target register
+    /// (as for [`VCpuRunPage`]) and recording it for replay. Returns `BadState` if the exit doesn't read a value
+    /// into the guest or is already completed.
+    pub fn complete(&mut self, value: u64) -> AxResult {
+        let Some(target) = self.pending_completion.take() else {
+            return ax_err!(BadState, "no exit to complete");
+        };
+        self.vcpu.set_gpr(target.reg, target.value(value))?;
+        if let Some(entry) = self.log.last_mut() {
+            entry.completion = Some(value);
+        }
+        Ok(())
+    }
+
+    /// Step back `n` exits, reconstructing the state of the vcpu at the exit boundary `position() - n`: the vcpu
+    /// is restored from the closest checkpoint at or before it and woken up if it's blocked, then the recorded
+    /// exits up to it are replayed.
+    ///
+    /// `rewind` is called with the position of the checkpoint before the vcpu is restored, so that the VMM rolls
+    /// guest memory and device models back to the same point. Replayed exits are not returned to the VMM: each one
+    /// is checked against the log and completed with the recorded value, and the recorded interrupts are injected
+    /// again before it (see [`ReplayEntry::interrupts`]).
+    ///
+    /// Returns the new position. Returns `InvalidInput` if `n` exceeds the current position, and `InvalidData` if
+    /// a replayed exit diverges from the log, e.g., because the VMM didn't roll guest memory back, in which case
+    /// the vcpu is left at the exit boundary where the divergence was detected.
+    pub fn step_back(
+        &mut self,
+        n: usize,
+        token: &RunToken,
+        mut rewind: impl FnMut(usize) -> AxResult,
+    ) -> AxResult<usize> {
+        let Some(target) = self.position.checked_sub(n) else {
+            return ax_err!(
+                InvalidInput,
+                "cannot step back before the start of the session"
+            );
+        };
+        let Some((position, snapshot)) = self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|&&(position, _)| position <= target)
+        else {
+            return ax_err!(NotFound, "no checkpoint before the target position");
+        };
+        let checkpoint = *position;
+        rewind(checkpoint)?;
+        self.vcpu.restore(snapshot, &self.host)?;
+        self.vcpu.wake();
+        self.position = checkpoint;
+        self.pending_completion = None;
+        self.replay_to(target, token)?;
+        vcpu_log!(Exit, Debug, vcpu = self.vcpu.id(), position = target, checkpoint = checkpoint; "stepped back");
+        Ok(self.position)
+    }
+
+    /// Step forward `n` exits over the exits stepped back over, replaying them as in [`ReplaySession::step_back`].
+    /// Returns the new position, which stops at the end of the log.
+    pub fn step_forward(&mut self, n: usize, token: &RunToken) -> AxResult<usize> {
+        let target = self.position.saturating_add(n).min(self.log.len());
+        self.replay_to(target, token)?;
+        Ok(self.position)
+    }
+
+    /// Replay the recorded exits from the current position up to `target`.
+    fn replay_to(&mut self, target: usize, token: &RunToken) -> AxResult {
+        let result = self.replay_entries(target, token);
+        *self.vcpu.replay_hook().borrow_mut() = Some(ReplayHook::default());
+        result
+    }
+
+    /// Replay the recorded exits from the current position up to `target`, with the vcpu in replay mode.
+    fn replay_entries(&mut self, target: usize, token: &RunToken) -> AxResult {
+        while self.position < target {
+            let entry = &self.log[self.position];
+            if entry.queued {
+                self.position += 1;
+                continue;
+            }
+            *self.vcpu.replay_hook().borrow_mut() = Some(ReplayHook {
+                replaying: true,
+                interrupts: entry.interrupts.clone(),
+                queued: false,
+            });
+            let exit = self.vcpu.run(token)?;
+            if exit.kind() != entry.kind || VCpuRunPage::encode_payload(&exit) != entry.payload {
+                vcpu_log!(Exit, Warn, vcpu = self.vcpu.id(), position = self.position,
+                    expected = entry.kind.as_str(), actual = exit.kind().as_str(); "replay diverged");
+                return ax_err!(InvalidData, "replayed exit diverges from the log");
+            }
+            if let (Some(target), Some(value)) = (
+                completion_target(&exit, self.vcpu.guest_endian()),
+                entry.completion,
+            ) {
+                self.vcpu.set_gpr(target.reg, target.value(value))?;
+            }
+            self.position += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<A: AxArchVCpu> Drop for ReplaySession<'_, A> {
+    fn drop(&mut self) {
+        *self.vcpu.replay_hook().borrow_mut() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axaddrspace::GuestPhysAddr;
+    use axerrno::AxError;
+
+    use super::*;
+    use crate::test_utils::{MockConfig, bound_vcpu, script_exits, serial};
+    use crate::{AccessWidth, SnapshotArch, VirtHwFeatures};
+
+    fn host() -> HostInfo {
+        HostInfo {
+            arch: SnapshotArch::current(),
+            features: VirtHwFeatures::empty(),
+        }
+    }
+
+    fn mmio_read(addr: usize, reg: usize) -> AxVCpuExitReason {
+        AxVCpuExitReason::MmioRead {
+            addr: GuestPhysAddr::from(addr),
+            width: AccessWidth::Dword,
+            reg,
+            reg_width: AccessWidth::Qword,
+        }
+    }
+
+    #[test]
+    fn steps_replay_completions_from_checkpoints() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        assert_eq!(
+            ReplaySession::new(&vcpu, host(), 0).err(),
+            Some(AxError::InvalidInput)
+        );
+        let mut session = ReplaySession::new(&vcpu, host(), 2).unwrap();
+        script_exits(
+            &vcpu,
+            [
+                mmio_read(0x1000, 3),
+                mmio_read(0x1004, 4),
+                AxVCpuExitReason::Nothing,
+            ],
+        );
+        session.run(&token).unwrap();
+        session.complete(0x11).unwrap();
+        assert_eq!(session.complete(0x11), Err(AxError::BadState));
+        session.run(&token).unwrap();
+        session.complete(0x22).unwrap();
+        session.run(&token).unwrap();
+        assert_eq!(session.position(), 3);
+        assert!(session.checkpoints().eq([0, 2]));
+        assert_eq!(session.log()[1].completion, Some(0x22));
+        assert_eq!(
+            session.step_back(4, &token, |_| Ok(())),
+            Err(AxError::InvalidInput)
+        );
+
+        let mut rewound = None;
+        let position = session.step_back(2, &token, |checkpoint| {
+            rewound = Some(checkpoint);
+            script_exits(&vcpu, [mmio_read(0x1000, 3)]);
+            Ok(())
+        });
+        assert_eq!(position, Ok(1));
+        assert_eq!(rewound, Some(0));
+        let gprs = vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.gprs).unwrap();
+        assert_eq!((gprs[3], gprs[4]), (0x11, 0));
+
+        script_exits(&vcpu, [mmio_read(0x1004, 4), AxVCpuExitReason::Nothing]);
+        assert_eq!(session.step_forward(5, &token), Ok(3));
+        let gprs = vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.gprs).unwrap();
+        assert_eq!(gprs[4], 0x22);
+    }
+
+    #[test]
+    fn divergence_is_detected() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        let mut session = ReplaySession::new(&vcpu, host(), 8).unwrap();
+        script_exits(&vcpu, [mmio_read(0x1000, 3), AxVCpuExitReason::Nothing]);
+        session.run(&token).unwrap();
+        session.run(&token).unwrap();
+        let result = session.step_back(1, &token, |_| {
+            script_exits(&vcpu, [mmio_read(0x2000, 3)]);
+            Ok(())
+        });
+        assert_eq!(result, Err(AxError::InvalidData));
+        assert_eq!(session.position(), 0);
+    }
+
+    #[test]
+    fn recording_after_stepping_back_starts_a_new_timeline() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        let mut session = ReplaySession::new(&vcpu, host(), 1).unwrap();
+        script_exits(
+            &vcpu,
+            [AxVCpuExitReason::Nothing, AxVCpuExitReason::Nothing],
+        );
+        session.run(&token).unwrap();
+        session.run(&token).unwrap();
+        assert!(session.checkpoints().eq([0, 1]));
+        assert_eq!(session.step_back(2, &token, |_| Ok(())), Ok(0));
+        assert_eq!(session.log().len(), 2);
+
+        script_exits(&vcpu, [AxVCpuExitReason::SystemDown]);
+        session.run(&token).unwrap();
+        assert_eq!(session.log().len(), 1);
+        assert_eq!(session.log()[0].kind, ExitKind::SystemDown);
+        assert!(session.checkpoints().eq([0]));
+
+        drop(session);
+        assert!(vcpu.replay_hook().borrow().is_none());
+    }
+}
@@ -0,0 +1,216 @@
+use core::sync::atomic::{Ordering, fence};
+
+use axaddrspace::HostVirtAddr;
+
+use crate::{AccessWidth, AxVCpuExitReason, CacheOp, FirmwareConduit, GuestEndian};
+
+/// The value of [`VCpuRunPage::exit_kind`] before the first exit is published.
+pub const RUN_PAGE_NO_EXIT: u32 = 0;
+
+/// The layout of the shared-memory "run page" of a vcpu (like `kvm_run`), registered by
+/// [`AxVCpu::register_run_page`](crate::AxVCpu::register_run_page), so that a monitor in another process or
+/// protection domain can handle exits without Rust-level access to [`AxVCpu`](crate::AxVCpu).
+///
+/// Each exit returned by [`AxVCpu::run`](crate::AxVCpu::run) is published in the page. For exits reading a value
+/// into the guest ([`AxVCpuExitReason::MmioRead`], [`AxVCpuExitReason::SysRegRead`] and
+/// [`AxVCpuExitReason::IoRead`]), the monitor writes the value to [`VCpuRunPage::completion`] and sets
+/// [`VCpuRunPage::completion_ready`] to 1, and the value is stored into the target register at the next
+/// [`AxVCpu::run`](crate::AxVCpu::run).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VCpuRunPage {
+    /// The [`ExitKind::id`](crate::ExitKind::id) of the last exit plus 1, or [`RUN_PAGE_NO_EXIT`].
+    pub exit_kind: u32,
+    /// Set to 1 by the monitor when [`VCpuRunPage::completion`] is valid, cleared when it's consumed.
+    pub completion_ready: u32,
+    /// Incremented each time an exit is published, so that the monitor can detect new exits.
+    pub sequence: u64,
+    /// The payload of the last exit, see [`VCpuRunPage::encode_payload`].
+    pub payload: [u64; 8],
+    /// The value read by the guest, written by the monitor.
+    pub completion: u64,
+}
+
+impl VCpuRunPage {
+    /// Encode the payload of an exit, in the order of the fields of the variant:
+    ///
+    /// * `Hypercall`: `[nr, args..]`,
+    /// * `FirmwareCall`: `[conduit, func_id, args..]`, where `conduit` is 0 for `SMC`, 1 for `HVC`, 2 for SBI,
+    /// * `MmioRead`: `[addr, width, reg, reg_width]`, `MmioWrite`: `[addr, width, data]`, widths in bytes,
+    /// * `UnhandledMmio`: `[addr, width, is_write]`, `CacheMaintenance`: `[addr, size, op]`, where `op` is 0 for
+    ///   `Clean`, 1 for `Invalidate`, 2 for `CleanInvalidate`,
+    /// * `SysRegRead`: `[addr, reg]`, `SysRegWrite`: `[addr, value]`,
+    /// * `IoRead`: `[port, width]`, `IoWrite`: `[port, width, data]`,
+    /// * `ExternalInterrupt`: `[vector]`, `NestedPageFault`: `[addr, access_flags]`,
+    /// * `Introspection`: `[addr, access]`, `IntegrityViolation`: `[addr, access]`,
+    /// * `CpuUp`: `[target_cpu, entry_point, arg]`, `CpuDown`: `[state]`, `CpuFreqRequest`: `[level]`,
+    /// * `IommuFault`: `[device, addr, flags]`,
+    /// * `GuestRequest`: `[request, request_addr, response_addr]`,
+    /// * `FailEntry`: `[hardware_entry_failure_reason]`,
+    ///
+    /// padded with zeros. Other exits have an all-zero payload.
+    pub fn encode_payload(exit: &AxVCpuExitReason) -> [u64; 8] {
+        let mut payload = [0; 8];
+        let mut put = |fields: &[u64]| payload[..fields.len()].copy_from_slice(fields);
+        match *exit {
+            AxVCpuExitReason::Hypercall { nr, args } => {
+                put(&[nr, args[0], args[1], args[2], args[3], args[4], args[5]])
+            }
+            AxVCpuExitReason::FirmwareCall {
+                conduit,
+                func_id,
+                args,
+            } => {
+                let conduit = match conduit {
+                    FirmwareConduit::Smc => 0,
+                    FirmwareConduit::Hvc => 1,
+                    FirmwareConduit::Sbi => 2,
+                };
+                put(&[
+                    conduit, func_id, args[0], args[1], args[2], args[3], args[4], args[5],
+                ]);
+            }
+            AxVCpuExitReason::MmioRead {
+                addr,
+                width,
+                reg,
+                reg_width,
+            } => put(&[
+                addr.as_usize() as u64,
+                width.size() as u64,
+                reg as u64,
+                reg_width.size() as u64,
+            ]),
+            AxVCpuExitReason::MmioWrite { addr, width, data } => {
+                put(&[addr.as_usize() as u64, width.size() as u64, data])
+            }
+            AxVCpuExitReason::UnhandledMmio {
+                addr,
+                width,
+                is_write,
+            } => put(&[addr.as_usize() as u64, width.size() as u64, is_write as u64]),
+            AxVCpuExitReason::CacheMaintenance { addr, size, op } => {
+                let op = match op {
+                    CacheOp::Clean => 0,
+                    CacheOp::Invalidate => 1,
+                    CacheOp::CleanInvalidate => 2,
+                };
+                put(&[addr.as_usize() as u64, size as u64, op])
+            }
+            AxVCpuExitReason::SysRegRead { addr, reg } => put(&[addr as u64, reg as u64]),
+            AxVCpuExitReason::SysRegWrite { addr, value } => put(&[addr as u64, value]),
+            AxVCpuExitReason::IoRead { port, width } => put(&[port as u64, width.size() as u64]),
+            AxVCpuExitReason::IoWrite { port, width, data } => {
+                put(&[port as u64, width.size() as u64, data])
+            }
+            AxVCpuExitReason::ExternalInterrupt { vector } => put(&[vector]),
+            AxVCpuExitReason::NestedPageFault {
+                addr, access_flags, ..
+            } => put(&[addr.as_usize() as u64, access_flags.bits() as u64]),
+            AxVCpuExitReason::Introspection { addr, access }
+            | AxVCpuExitReason::IntegrityViolation { addr, access } => {
+                put(&[addr.as_usize() as u64, access.bits() as u64])
+            }
+            AxVCpuExitReason::CpuUp {
+                target_cpu,
+                entry_point,
+                arg,
+            } => put(&[target_cpu, entry_point.as_usize() as u64, arg]),
+            AxVCpuExitReason::CpuDown { _state } => put(&[_state]),
+            AxVCpuExitReason::CpuFreqRequest { level } => put(&[level as u64]),
+            AxVCpuExitReason::IommuFault {
+                device,
+                addr,
+                flags,
+            } => put(&[device as u64, addr.as_usize() as u64, flags.bits() as u64]),
+            AxVCpuExitReason::GuestRequest {
+                request,
+                request_addr,
+                response_addr,
+            } => put(&[
+                request,
+                request_addr.as_usize() as u64,
+                response_addr.as_usize() as u64,
+            ]),
+            AxVCpuExitReason::FailEntry {
+                hardware_entry_failure_reason,
+            } => put(&[hardware_entry_failure_reason]),
+            _ => {}
+        }
+        payload
+    }
+}
+
+/// Publish an exit in the run page at `page`.
+///
+/// # Safety
+///
+/// `page` must point to a valid, writable [`VCpuRunPage`].
+pub(crate) unsafe fn publish_exit(page: HostVirtAddr, exit: &AxVCpuExitReason) {
+    let run = page.as_usize() as *mut VCpuRunPage;
+    let payload = VCpuRunPage::encode_payload(exit);
+    unsafe {
+        (&raw mut (*run).completion_ready).write_volatile(0);
+        (&raw mut (*run).exit_kind).write_volatile(exit.kind().id() as u32 + 1);
+        (&raw mut (*run).payload).write_volatile(payload);
+        fence(Ordering::Release);
+        let sequence = (&raw const (*run).sequence).read_volatile();
+        (&raw mut (*run).sequence).write_volatile(sequence.wrapping_add(1));
+    }
+}
+
+/// Take the completion written by the monitor to the run page at `page`, if any.
+///
+/// # Safety
+///
+/// `page` must point to a valid, writable [`VCpuRunPage`].
+pub(crate) unsafe fn take_completion(page: HostVirtAddr) -> Option<u64> {
+    let run = page.as_usize() as *mut VCpuRunPage;
+    unsafe {
+        if (&raw const (*run).completion_ready).read_volatile() == 0 {
+            return None;
+        }
+        fence(Ordering::Acquire);
+        let value = (&raw const (*run).completion).read_volatile();
+        (&raw mut (*run).completion_ready).write_volatile(0);
+        Some(value)
+    }
+}
+
+/// Where the completion of an exit is stored, and how it's converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct CompletionTarget {
+    /// The GPR the completion is stored into.
+    pub(crate) reg: usize,
+    /// The mask applied to the value.
+    mask: u64,
+    /// The byte order conversion of MMIO reads, as `(endian, width)`.
+    swap: Option<(GuestEndian, AccessWidth)>,
+}
+
+impl CompletionTarget {
+    /// Convert a completion into the value stored into the GPR.
+    pub(crate) fn value(&self, completion: u64) -> usize {
+        let value = match self.swap {
+            Some((endian, width)) => endian.swap_mmio(completion, width),
+            None => completion,
+        };
+        (value & self.mask) as usize
+    }
+}
+
+/// Get where the completion of an exit is stored, for a guest with the byte order `endian`.
+pub(crate) fn completion_target(
+    exit: &AxVCpuExitReason,
+    endian: GuestEndian,
+) -> Option<CompletionTarget> {
+    let mask = |width: AccessWidth| u64::MAX >> (64 - width.bits_range().end);
+    let (reg, mask, swap) = match *exit {
+        AxVCpuExitReason::MmioRead { reg, width, .. } => (reg, mask(width), Some((endian, width))),
+        AxVCpuExitReason::SysRegRead { reg, .. } => (reg, u64::MAX, None),
+        // Port I/O exists only in x86, where the destination is always `al`, `ax`, or `eax` (GPR 0).
+        AxVCpuExitReason::IoRead { width, .. } => (0, mask(width), None),
+        _ => return None,
+    };
+    Some(CompletionTarget { reg, mask, swap })
+}
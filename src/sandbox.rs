@@ -0,0 +1,226 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use axaddrspace::GuestPhysAddr;
+
+use crate::{AxVCpuExitReason, ExitKind};
+
+/// The allowlist of device accesses of a vcpu in sandbox mode, see
+/// [`AxVCpu::enable_sandbox`](crate::AxVCpu::enable_sandbox).
+///
+/// Sandboxed guests are compute-only payloads (e.g., serverless functions or enclave-like workloads): any MMIO,
+/// port I/O or system register access outside the allowlist terminates the guest. The allowlist is meant to stay
+/// tiny, e.g., a single para-virtualized console port.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SandboxConfig {
+    /// The allowed MMIO regions.
+    mmio: Vec<Range<GuestPhysAddr>>,
+    /// The allowed I/O port ranges.
+    ports: Vec<Range<u16>>,
+    /// The addresses of the allowed system registers, see [`AxVCpuExitReason::SysRegRead`].
+    sysregs: Vec<usize>,
+}
+
+impl SandboxConfig {
+    /// Create an empty allowlist, denying all device accesses.
+    pub const fn new() -> Self {
+        Self {
+            mmio: Vec::new(),
+            ports: Vec::new(),
+            sysregs: Vec::new(),
+        }
+    }
+
+    /// Allow the accesses to the MMIO region `range`.
+    pub fn allow_mmio(mut self, range: Range<GuestPhysAddr>) -> Self {
+        self.mmio.push(range);
+        self
+    }
+
+    /// Allow the accesses to the I/O ports in `range`.
+    pub fn allow_ports(mut self, range: Range<u16>) -> Self {
+        self.ports.push(range);
+        self
+    }
+
+    /// Allow the accesses to the system register at `addr`, in the format of [`AxVCpuExitReason::SysRegRead`].
+    pub fn allow_sysreg(mut self, addr: usize) -> Self {
+        self.sysregs.push(addr);
+        self
+    }
+
+    /// Check an exit against the allowlist at the host time `now`, returning the violation if it's a device access
+    /// which is not allowed. Exits other than device accesses are always allowed.
+    pub fn check(&self, exit: &AxVCpuExitReason, now: u64) -> Option<SandboxViolation> {
+        let (target, value, allowed) = match *exit {
+            AxVCpuExitReason::MmioRead { addr, .. }
+            | AxVCpuExitReason::UnhandledMmio {
+                addr,
+                is_write: false,
+                ..
+            } => (addr.as_usize() as u64, None, self.allows_mmio(addr)),
+            AxVCpuExitReason::MmioWrite { addr, data, .. } => {
+                (addr.as_usize() as u64, Some(data), self.allows_mmio(addr))
+            }
+            AxVCpuExitReason::UnhandledMmio { addr, .. } => {
+                (addr.as_usize() as u64, Some(0), self.allows_mmio(addr))
+            }
+            AxVCpuExitReason::IoRead { port, .. } => (port as u64, None, self.allows_port(port)),
+            AxVCpuExitReason::IoWrite { port, data, .. } => {
+                (port as u64, Some(data), self.allows_port(port))
+            }
+            AxVCpuExitReason::SysRegRead { addr, .. } => {
+                (addr as u64, None, self.sysregs.contains(&addr))
+            }
+            AxVCpuExitReason::SysRegWrite { addr, value } => {
+                (addr as u64, Some(value), self.sysregs.contains(&addr))
+            }
+            _ => return None,
+        };
+        (!allowed).then_some(SandboxViolation {
+            kind: exit.kind(),
+            target,
+            value,
+            time_ns: now,
+        })
+    }
+
+    /// Whether the MMIO accesses to `addr` are allowed.
+    fn allows_mmio(&self, addr: GuestPhysAddr) -> bool {
+        self.mmio.iter().any(|range| range.contains(&addr))
+    }
+
+    /// Whether the accesses to the I/O port `port` are allowed.
+    fn allows_port(&self, port: u16) -> bool {
+        self.ports.iter().any(|range| range.contains(&port))
+    }
+}
+
+/// The structured report of a device access which terminated a sandboxed guest, see
+/// [`AxVCpu::sandbox_violation`](crate::AxVCpu::sandbox_violation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SandboxViolation {
+    /// The kind of the exit of the access.
+    pub kind: ExitKind,
+    /// The target of the access: the guest physical address for MMIO, the port for port I/O, and the address of
+    /// the register for system registers.
+    pub target: u64,
+    /// The value written, or `None` for reads. The value of the writes reported by
+    /// [`AxVCpuExitReason::UnhandledMmio`] is unknown, and reported as 0.
+    pub value: Option<u64>,
+    /// When the access happened, as returned by
+    /// [`AxVCpuHal::current_time_nanos`](crate::AxVCpuHal::current_time_nanos).
+    pub time_ns: u64,
+}
+
+impl SandboxViolation {
+    /// Whether the access is a write.
+    pub const fn is_write(&self) -> bool {
+        self.value.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AccessWidth;
+
+    fn config() -> SandboxConfig {
+        SandboxConfig::new()
+            .allow_mmio(GuestPhysAddr::from(0x1000)..GuestPhysAddr::from(0x2000))
+            .allow_ports(0x3f8..0x400)
+            .allow_sysreg(0x42)
+    }
+
+    #[test]
+    fn allowed_accesses_pass() {
+        let config = config();
+        let exits = [
+            AxVCpuExitReason::MmioRead {
+                addr: GuestPhysAddr::from(0x1000),
+                width: AccessWidth::Dword,
+                reg: 0,
+                reg_width: AccessWidth::Qword,
+            },
+            AxVCpuExitReason::UnhandledMmio {
+                addr: GuestPhysAddr::from(0x1ffc),
+                width: AccessWidth::Dword,
+                is_write: true,
+            },
+            AxVCpuExitReason::IoWrite {
+                port: 0x3ff,
+                width: AccessWidth::Byte,
+                data: 0x41,
+            },
+            AxVCpuExitReason::SysRegRead { addr: 0x42, reg: 0 },
+            AxVCpuExitReason::Halt,
+        ];
+        for exit in &exits {
+            assert_eq!(config.check(exit, 0), None);
+        }
+        assert_eq!(SandboxConfig::new().check(&AxVCpuExitReason::Halt, 0), None);
+    }
+
+    #[test]
+    fn denied_accesses_are_reported() {
+        let config = config();
+        let violation = config
+            .check(
+                &AxVCpuExitReason::MmioWrite {
+                    addr: GuestPhysAddr::from(0x2000),
+                    width: AccessWidth::Dword,
+                    data: 7,
+                },
+                100,
+            )
+            .unwrap();
+        assert_eq!(
+            violation,
+            SandboxViolation {
+                kind: ExitKind::MmioWrite,
+                target: 0x2000,
+                value: Some(7),
+                time_ns: 100,
+            }
+        );
+        assert!(violation.is_write());
+
+        let violation = config
+            .check(
+                &AxVCpuExitReason::UnhandledMmio {
+                    addr: GuestPhysAddr::from(0x800),
+                    width: AccessWidth::Byte,
+                    is_write: true,
+                },
+                0,
+            )
+            .unwrap();
+        assert_eq!(violation.value, Some(0));
+
+        let violation = config
+            .check(
+                &AxVCpuExitReason::IoRead {
+                    port: 0x400,
+                    width: AccessWidth::Byte,
+                },
+                0,
+            )
+            .unwrap();
+        assert_eq!(
+            (violation.kind, violation.target),
+            (ExitKind::IoRead, 0x400)
+        );
+        assert!(!violation.is_write());
+
+        let violation = config
+            .check(
+                &AxVCpuExitReason::SysRegWrite {
+                    addr: 0x43,
+                    value: 1,
+                },
+                0,
+            )
+            .unwrap();
+        assert_eq!(violation.target, 0x43);
+    }
+}
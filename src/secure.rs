@@ -0,0 +1,119 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+use axerrno::{AxResult, ax_err};
+
+use crate::{AxVCpuHal, FirmwareConduit};
+
+/// The SMCCC return value of calls which are not supported, i.e., `NOT_SUPPORTED` (-1).
+pub const SMCCC_RET_NOT_SUPPORTED: u64 = u64::MAX;
+
+/// The bit of an SMCCC function id set for the SMC64/HVC64 calling convention.
+const SMCCC_SMC64: u32 = 1 << 30;
+
+/// A sanitizer of the arguments of secure calls, run by [`SecureCallProxy`] before forwarding a call, e.g., to
+/// translate guest physical addresses of shared buffers to host physical addresses, or to reject buffers outside
+/// the memory shared with the secure world.
+pub trait SecureCallSanitizer {
+    /// Sanitize the arguments of the call `func_id` in place. Returning an error rejects the call, and
+    /// [`SMCCC_RET_NOT_SUPPORTED`] is returned to the guest.
+    fn sanitize(&self, func_id: u32, args: &mut [u64; 6]) -> AxResult;
+}
+
+/// A rule of [`SecureCallProxy`], allowing a range of SMCCC function ids.
+#[derive(Debug, Clone)]
+struct SecureCallRule {
+    /// The allowed function ids.
+    func_ids: RangeInclusive<u32>,
+    /// The number of arguments forwarded, the others are zeroed.
+    passed_args: usize,
+}
+
+/// A proxy forwarding whitelisted [`AxVCpuExitReason::FirmwareCall`](crate::AxVCpuExitReason::FirmwareCall)s made
+/// through `SMC` to the real secure monitor with [`AxVCpuHal::smc_call`], e.g., so that guests can talk to OP-TEE.
+/// Used by [`AxVCpu::forward_secure_call`](crate::AxVCpu::forward_secure_call).
+///
+/// Nothing is allowed by default. Before a call is forwarded, arguments beyond the ones allowed by its rule are
+/// zeroed, arguments of SMC32 calls are truncated to 32 bits, and the [`SecureCallSanitizer`] (if any) is run.
+#[derive(Clone, Default)]
+pub struct SecureCallProxy {
+    /// The whitelist.
+    rules: Vec<SecureCallRule>,
+    /// The sanitizer run on the arguments of allowed calls.
+    sanitizer: Option<Arc<dyn SecureCallSanitizer>>,
+}
+
+impl SecureCallProxy {
+    /// Create a new [`SecureCallProxy`] which allows nothing.
+    pub const fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            sanitizer: None,
+        }
+    }
+
+    /// Allow the SMCCC function ids in `func_ids`, forwarding their first `passed_args` arguments (at most 6).
+    pub fn allow(mut self, func_ids: RangeInclusive<u32>, passed_args: usize) -> Self {
+        self.rules.push(SecureCallRule {
+            func_ids,
+            passed_args: passed_args.min(6),
+        });
+        self
+    }
+
+    /// Set the sanitizer run on the arguments of allowed calls.
+    pub fn with_sanitizer(mut self, sanitizer: Arc<dyn SecureCallSanitizer>) -> Self {
+        self.sanitizer = Some(sanitizer);
+        self
+    }
+
+    /// Whether the function id is allowed.
+    pub fn is_allowed(&self, func_id: u64) -> bool {
+        self.rule(func_id).is_some()
+    }
+
+    /// Find the rule allowing the function id.
+    fn rule(&self, func_id: u64) -> Option<&SecureCallRule> {
+        let func_id = u32::try_from(func_id).ok()?;
+        self.rules
+            .iter()
+            .find(|rule| rule.func_ids.contains(&func_id))
+    }
+
+    /// Forward a firmware call to the secure monitor, returning the results to be written back to `x0`-`x3`.
+    ///
+    /// Returns `Unsupported` if the call is not made through `SMC`. Calls which are not allowed, or rejected by the
+    /// sanitizer, are not forwarded, and [`SMCCC_RET_NOT_SUPPORTED`] is returned as the result instead.
+    pub fn forward<H: AxVCpuHal>(
+        &self,
+        conduit: FirmwareConduit,
+        func_id: u64,
+        args: &[u64; 6],
+    ) -> AxResult<[u64; 4]> {
+        if conduit != FirmwareConduit::Smc {
+            return ax_err!(Unsupported, "only SMC calls can be forwarded");
+        }
+        let not_supported = [SMCCC_RET_NOT_SUPPORTED, 0, 0, 0];
+        let Some(rule) = self.rule(func_id) else {
+            return Ok(not_supported);
+        };
+        let func_id = func_id as u32;
+        let mut sanitized = [0; 6];
+        for (dst, &src) in sanitized.iter_mut().zip(&args[..rule.passed_args]) {
+            *dst = if func_id & SMCCC_SMC64 == 0 {
+                src as u32 as u64
+            } else {
+                src
+            };
+        }
+        let rejected = self
+            .sanitizer
+            .as_ref()
+            .is_some_and(|sanitizer| sanitizer.sanitize(func_id, &mut sanitized).is_err());
+        if rejected {
+            return Ok(not_supported);
+        }
+        H::smc_call(func_id, &sanitized)
+    }
+}
@@ -0,0 +1,681 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use axerrno::AxResult;
+
+use crate::{AxVCpuError, Compressor, SysRegFile, VirtHwFeatures, VirtHwInfo};
+
+/// The magic number at the start of an encoded [`AxVCpuSnapshot`].
+const SNAPSHOT_MAGIC: [u8; 4] = *b"AXVS";
+
+/// The magic number at the start of a compressed encoded [`AxVCpuSnapshot`].
+const COMPRESSED_SNAPSHOT_MAGIC: [u8; 4] = *b"AXVZ";
+
+/// The size in bytes of the encoded header of an [`AxVCpuSnapshot`], including the magic number and the checksum.
+pub(crate) const SNAPSHOT_HEADER_SIZE: usize = 28;
+
+/// The size in bytes of the framing of an encoded section, i.e., its length and checksum.
+pub(crate) const SNAPSHOT_SECTION_OVERHEAD: usize = 8;
+
+/// The version of the encoding of [`AxVCpuSnapshot`], bumped on every incompatible change.
+pub const SNAPSHOT_FORMAT_VERSION: u16 = 4;
+
+/// The architecture a snapshot is taken on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SnapshotArch {
+    /// x86_64.
+    X86_64 = 1,
+    /// Aarch64.
+    Aarch64 = 2,
+    /// RISC-V 64.
+    Riscv64 = 3,
+    /// Any other architecture.
+    Other = 0xff,
+}
+
+impl SnapshotArch {
+    /// The current architecture.
+    pub const fn current() -> Self {
+        if cfg!(target_arch = "x86_64") {
+            Self::X86_64
+        } else if cfg!(target_arch = "aarch64") {
+            Self::Aarch64
+        } else if cfg!(target_arch = "riscv64") {
+            Self::Riscv64
+        } else {
+            Self::Other
+        }
+    }
+
+    /// Decode an architecture id.
+    const fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::X86_64),
+            2 => Some(Self::Aarch64),
+            3 => Some(Self::Riscv64),
+            0xff => Some(Self::Other),
+            _ => None,
+        }
+    }
+}
+
+/// A part of an encoded [`AxVCpuSnapshot`] protected by its own checksum, reported by
+/// [`AxVCpuError::SnapshotCorrupted`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotSection {
+    /// The header.
+    Header,
+    /// The architecture-specific state.
+    ArchState,
+    /// The extended register state.
+    ExtState,
+    /// The system registers shadowed by the VMM.
+    SysRegs,
+    /// The time state.
+    Time,
+}
+
+impl SnapshotSection {
+    /// Get the name of this section, in `snake_case`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Header => "header",
+            Self::ArchState => "arch_state",
+            Self::ExtState => "ext_state",
+            Self::SysRegs => "sysregs",
+            Self::Time => "time",
+        }
+    }
+}
+
+/// The lookup table of [`crc32`].
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Compute the CRC-32 (IEEE 802.3) checksum of `data`, protecting the header and sections of encoded snapshots.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// The version of this crate, as `(major, minor, patch)`.
+fn crate_version() -> (u16, u16, u16) {
+    let parse = |s: &str| s.parse().unwrap_or(0);
+    (
+        parse(env!("CARGO_PKG_VERSION_MAJOR")),
+        parse(env!("CARGO_PKG_VERSION_MINOR")),
+        parse(env!("CARGO_PKG_VERSION_PATCH")),
+    )
+}
+
+/// The description of a host a snapshot is taken on or restored to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostInfo {
+    /// The architecture of the host.
+    pub arch: SnapshotArch,
+    /// The hardware virtualization features of the host.
+    pub features: VirtHwFeatures,
+}
+
+impl HostInfo {
+    /// Describe the current host with the given hardware report, generally obtained from
+    /// [`AxPerCpu::hardware_info`](crate::AxPerCpu::hardware_info).
+    pub fn current(hw_info: &VirtHwInfo) -> Self {
+        Self {
+            arch: SnapshotArch::current(),
+            features: hw_info.features,
+        }
+    }
+}
+
+/// The header of an [`AxVCpuSnapshot`], describing where it's taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    /// The version of the snapshot encoding.
+    pub format_version: u16,
+    /// The version of this crate which took the snapshot, as `(major, minor, patch)`.
+    pub crate_version: (u16, u16, u16),
+    /// The architecture of the host the snapshot is taken on.
+    pub arch: SnapshotArch,
+    /// The hardware virtualization features of the host the snapshot is taken on.
+    pub features: VirtHwFeatures,
+}
+
+impl SnapshotHeader {
+    /// Create the header of a snapshot taken on `host` by this crate.
+    pub fn new(host: &HostInfo) -> Self {
+        Self {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            crate_version: crate_version(),
+            arch: host.arch,
+            features: host.features,
+        }
+    }
+
+    /// Encode the header, preceded by the magic number and followed by its checksum.
+    pub(crate) fn to_bytes(self) -> [u8; SNAPSHOT_HEADER_SIZE] {
+        let mut bytes = [0; SNAPSHOT_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&SNAPSHOT_MAGIC);
+        bytes[4..6].copy_from_slice(&self.format_version.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.crate_version.0.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.crate_version.1.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.crate_version.2.to_le_bytes());
+        bytes[12] = self.arch as u8;
+        bytes[16..24].copy_from_slice(&self.features.bits().to_le_bytes());
+        let checksum = crc32(&bytes[..24]);
+        bytes[24..28].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+}
+
+/// The reason why a snapshot can't be restored, carried by [`AxVCpuError::SnapshotIncompatible`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SnapshotIncompatibility {
+    /// The data is not a snapshot.
+    BadMagic,
+    /// The data ends prematurely or has trailing bytes.
+    Malformed,
+    /// The snapshot encoding is not supported by this crate.
+    FormatVersion {
+        /// The version of the snapshot.
+        found: u16,
+        /// The version supported by this crate.
+        expected: u16,
+    },
+    /// The snapshot is taken by an incompatible version of this crate.
+    CrateVersion {
+        /// The version of the crate which took the snapshot.
+        found: (u16, u16, u16),
+        /// The version of this crate.
+        expected: (u16, u16, u16),
+    },
+    /// The snapshot is taken on another architecture.
+    Arch {
+        /// The architecture of the snapshot.
+        found: SnapshotArch,
+        /// The architecture of the host.
+        expected: SnapshotArch,
+    },
+    /// The host lacks hardware features available where the snapshot is taken.
+    MissingFeatures(VirtHwFeatures),
+    /// The snapshot is compressed with another algorithm than the given one, or compressed while no algorithm is
+    /// given.
+    Compression {
+        /// The id of the algorithm the snapshot is compressed with.
+        found: u8,
+        /// The id of the given algorithm, if any.
+        expected: Option<u8>,
+    },
+}
+
+impl fmt::Display for SnapshotIncompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a vcpu snapshot"),
+            Self::Malformed => write!(f, "malformed vcpu snapshot"),
+            Self::FormatVersion { found, expected } => write!(
+                f,
+                "snapshot format version {} is not supported, expected {}",
+                found, expected
+            ),
+            Self::CrateVersion { found, expected } => write!(
+                f,
+                "snapshot taken by axvcpu {}.{}.{}, incompatible with {}.{}.{}",
+                found.0, found.1, found.2, expected.0, expected.1, expected.2
+            ),
+            Self::Arch { found, expected } => write!(
+                f,
+                "snapshot taken on {:?}, cannot be restored on {:?}",
+                found, expected
+            ),
+            Self::MissingFeatures(missing) => {
+                write!(f, "host lacks hardware features {:?}", missing)
+            }
+            Self::Compression { found, expected } => match expected {
+                Some(expected) => write!(
+                    f,
+                    "snapshot compressed with algorithm {}, expected {}",
+                    found, expected
+                ),
+                None => write!(f, "snapshot compressed with algorithm {}", found),
+            },
+        }
+    }
+}
+
+/// The time state of a vcpu, the clock of record of [`AxVCpuSnapshot`]s, obtained by
+/// [`AxVCpu::time_state`](crate::AxVCpu::time_state).
+///
+/// Restoring it (see [`AxVCpu::restore_time_state`](crate::AxVCpu::restore_time_state)) adjusts the time offset of
+/// the vcpu so that the guest clock resumes where it stopped, and re-arms the timer against the restored clock, so
+/// that the guest neither sees time go backwards nor gets stale timer interrupts fired immediately.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VCpuTimeState {
+    /// The guest clock, i.e., the guest virtual counter in nanoseconds (see [`AxVCpu::time_offset`](crate::AxVCpu::time_offset)).
+    pub guest_time_ns: u64,
+    /// The deadline of the guest timer on the guest clock, if armed, see
+    /// [`AxArchVCpu::timer_deadline`](crate::AxArchVCpu::timer_deadline).
+    pub timer_deadline_ns: Option<u64>,
+}
+
+impl VCpuTimeState {
+    /// The size in bytes of the encoded state.
+    pub(crate) const ENCODED_SIZE: usize = 24;
+
+    /// Encode the state into `buf`, whose length is [`VCpuTimeState::ENCODED_SIZE`].
+    pub(crate) fn encode_into(&self, buf: &mut [u8]) {
+        buf[0..8].copy_from_slice(&self.guest_time_ns.to_le_bytes());
+        buf[8..16].copy_from_slice(&(self.timer_deadline_ns.is_some() as u64).to_le_bytes());
+        buf[16..24].copy_from_slice(&self.timer_deadline_ns.unwrap_or(0).to_le_bytes());
+    }
+
+    /// Decode a state encoded by [`VCpuTimeState::encode_into`]. Returns `None` if malformed.
+    pub(crate) fn decode(data: &[u8]) -> Option<Self> {
+        let mut reader = Reader(data);
+        let guest_time_ns = reader.u64()?;
+        let armed = reader.u64()?;
+        let deadline = reader.u64()?;
+        if !reader.0.is_empty() || armed > 1 {
+            return None;
+        }
+        Some(Self {
+            guest_time_ns,
+            timer_deadline_ns: (armed == 1).then_some(deadline),
+        })
+    }
+}
+
+/// A snapshot of the state of a vcpu, taken by [`AxVCpu::snapshot`](crate::AxVCpu::snapshot) and restored by
+/// [`AxVCpu::restore`](crate::AxVCpu::restore), e.g., for migration or fork.
+///
+/// The snapshot can be encoded into bytes with [`AxVCpuSnapshot::encode`]. The header records where the
+/// snapshot is taken, so that restoring it on an incompatible host or crate version fails cleanly with
+/// [`AxVCpuError::SnapshotIncompatible`] instead of corrupting the guest state. The header and each section of the
+/// encoding carry a CRC-32 checksum, so that partially transferred or damaged snapshots fail to decode with
+/// [`AxVCpuError::SnapshotCorrupted`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AxVCpuSnapshot {
+    /// The header.
+    pub header: SnapshotHeader,
+    /// The architecture-specific state, see [`AxArchVCpu::save_state`](crate::AxArchVCpu::save_state).
+    pub arch_state: Vec<u8>,
+    /// The extended register state, see [`AxArchVCpu::save_ext_state`](crate::AxArchVCpu::save_ext_state).
+    pub ext_state: Vec<u8>,
+    /// The system registers shadowed by the VMM, see [`AxVCpu::sysregs`](crate::AxVCpu::sysregs).
+    pub sysregs: SysRegFile,
+    /// The time state.
+    pub time: VCpuTimeState,
+}
+
+impl AxVCpuSnapshot {
+    /// Check whether the snapshot can be restored on `host` by this crate.
+    ///
+    /// Snapshots are compatible across patch versions, and across minor versions from 1.0 on. All hardware
+    /// features available where the snapshot is taken must be available on `host`.
+    pub fn is_compatible(&self, host: &HostInfo) -> Result<(), AxVCpuError> {
+        let header = &self.header;
+        let incompatible = |reason| Err(AxVCpuError::SnapshotIncompatible(reason));
+        if header.format_version != SNAPSHOT_FORMAT_VERSION {
+            return incompatible(SnapshotIncompatibility::FormatVersion {
+                found: header.format_version,
+                expected: SNAPSHOT_FORMAT_VERSION,
+            });
+        }
+        let (found, expected) = (header.crate_version, crate_version());
+        if found.0 != expected.0 || (expected.0 == 0 && found.1 != expected.1) {
+            return incompatible(SnapshotIncompatibility::CrateVersion { found, expected });
+        }
+        if header.arch != host.arch {
+            return incompatible(SnapshotIncompatibility::Arch {
+                found: header.arch,
+                expected: host.arch,
+            });
+        }
+        let missing = header.features.difference(host.features);
+        if !missing.is_empty() {
+            return incompatible(SnapshotIncompatibility::MissingFeatures(missing));
+        }
+        Ok(())
+    }
+
+    /// Encode the snapshot, appending the bytes to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.header.to_bytes());
+        let mut sysregs = Vec::new();
+        self.sysregs.encode(&mut sysregs);
+        let mut time = [0; VCpuTimeState::ENCODED_SIZE];
+        self.time.encode_into(&mut time);
+        for section in [&self.arch_state[..], &self.ext_state, &sysregs, &time] {
+            out.extend_from_slice(&(section.len() as u32).to_le_bytes());
+            out.extend_from_slice(section);
+            out.extend_from_slice(&crc32(section).to_le_bytes());
+        }
+    }
+
+    /// Encode the snapshot like [`AxVCpuSnapshot::encode`], compressed with `compressor` if any, appending the bytes
+    /// to `out`. The id of the compressor is recorded, see [`AxVCpuSnapshot::decode_with`].
+    pub fn encode_with(&self, compressor: Option<&dyn Compressor>, out: &mut Vec<u8>) {
+        let Some(compressor) = compressor else {
+            return self.encode(out);
+        };
+        let mut raw = Vec::new();
+        self.encode(&mut raw);
+        out.extend_from_slice(&COMPRESSED_SNAPSHOT_MAGIC);
+        out.extend_from_slice(&[compressor.id(), 0, 0, 0]);
+        out.extend_from_slice(&(raw.len() as u32).to_le_bytes());
+        compressor.compress(&raw, out);
+    }
+
+    /// Decode a snapshot encoded by [`AxVCpuSnapshot::encode_with`], decompressing it with `compressor` if it's
+    /// compressed.
+    ///
+    /// Fails with [`SnapshotIncompatibility::Compression`] if the snapshot is compressed with another algorithm,
+    /// or no compressor is given.
+    pub fn decode_with(
+        data: &[u8],
+        compressor: Option<&dyn Compressor>,
+    ) -> Result<Self, AxVCpuError> {
+        if data.get(..4) != Some(&COMPRESSED_SNAPSHOT_MAGIC[..]) {
+            return Self::decode(data);
+        }
+        let malformed = AxVCpuError::SnapshotIncompatible(SnapshotIncompatibility::Malformed);
+        let mut reader = Reader(&data[4..]);
+        let found = reader.take(4).ok_or(malformed)?[0];
+        let raw_len = reader.u32().ok_or(malformed)? as usize;
+        let compressor = match compressor {
+            Some(compressor) if compressor.id() == found => compressor,
+            _ => {
+                return Err(AxVCpuError::SnapshotIncompatible(
+                    SnapshotIncompatibility::Compression {
+                        found,
+                        expected: compressor.map(|compressor| compressor.id()),
+                    },
+                ));
+            }
+        };
+        // `raw_len` is untrusted: only preallocate what `data` may plausibly expand to.
+        let mut raw =
+            Vec::with_capacity(raw_len.min(reader.0.len().saturating_mul(u8::MAX as usize)));
+        compressor
+            .decompress(reader.0, raw_len, &mut raw)
+            .map_err(|_| malformed)?;
+        if raw.len() != raw_len {
+            return Err(malformed);
+        }
+        Self::decode(&raw)
+    }
+
+    /// Decode a snapshot encoded by [`AxVCpuSnapshot::encode`].
+    ///
+    /// Only the encoding and the checksums are checked, use [`AxVCpuSnapshot::is_compatible`] to check the
+    /// content. A checksum mismatch fails with [`AxVCpuError::SnapshotCorrupted`].
+    pub fn decode(data: &[u8]) -> Result<Self, AxVCpuError> {
+        let malformed = AxVCpuError::SnapshotIncompatible(SnapshotIncompatibility::Malformed);
+        let mut reader = Reader(data);
+        if reader.take(4).ok_or(malformed)? != SNAPSHOT_MAGIC {
+            return Err(AxVCpuError::SnapshotIncompatible(
+                SnapshotIncompatibility::BadMagic,
+            ));
+        }
+        let format_version = reader.u16().ok_or(malformed)?;
+        if format_version != SNAPSHOT_FORMAT_VERSION {
+            return Err(AxVCpuError::SnapshotIncompatible(
+                SnapshotIncompatibility::FormatVersion {
+                    found: format_version,
+                    expected: SNAPSHOT_FORMAT_VERSION,
+                },
+            ));
+        }
+        let crate_version = (
+            reader.u16().ok_or(malformed)?,
+            reader.u16().ok_or(malformed)?,
+            reader.u16().ok_or(malformed)?,
+        );
+        let arch = reader
+            .take(4)
+            .and_then(|arch| SnapshotArch::from_id(arch[0]))
+            .ok_or(malformed)?;
+        let features = VirtHwFeatures::from_bits_retain(reader.u64().ok_or(malformed)?);
+        if reader.u32().ok_or(malformed)? != crc32(&data[..24]) {
+            return Err(AxVCpuError::SnapshotCorrupted {
+                section: SnapshotSection::Header,
+            });
+        }
+        let arch_state = reader.section(SnapshotSection::ArchState)?.to_vec();
+        let ext_state = reader.section(SnapshotSection::ExtState)?.to_vec();
+        let sysregs =
+            SysRegFile::decode(reader.section(SnapshotSection::SysRegs)?).ok_or(malformed)?;
+        let time =
+            VCpuTimeState::decode(reader.section(SnapshotSection::Time)?).ok_or(malformed)?;
+        if !reader.0.is_empty() {
+            return Err(malformed);
+        }
+        Ok(Self {
+            header: SnapshotHeader {
+                format_version,
+                crate_version,
+                arch,
+                features,
+            },
+            arch_state,
+            ext_state,
+            sysregs,
+            time,
+        })
+    }
+}
+
+/// A cursor writing encoded snapshot data into a caller-provided buffer, see
+/// [`AxVCpu::save_state_into`](crate::AxVCpu::save_state_into).
+pub(crate) struct Writer<'a> {
+    /// The buffer.
+    buf: &'a mut [u8],
+    /// The number of bytes written.
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// Create a writer at the start of `buf`.
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Get the number of bytes written.
+    pub(crate) fn written(&self) -> usize {
+        self.pos
+    }
+
+    /// Reserve the next `len` bytes, to be filled by the caller. The buffer must be large enough.
+    pub(crate) fn reserve(&mut self, len: usize) -> &mut [u8] {
+        let start = self.pos;
+        self.pos += len;
+        &mut self.buf[start..self.pos]
+    }
+
+    /// Write `bytes`. The buffer must be large enough.
+    pub(crate) fn put(&mut self, bytes: &[u8]) {
+        self.reserve(bytes.len()).copy_from_slice(bytes);
+    }
+
+    /// Write a section of `len` bytes filled by `fill`, with its length prefix and checksum.
+    pub(crate) fn section(
+        &mut self,
+        len: usize,
+        fill: impl FnOnce(&mut [u8]) -> AxResult,
+    ) -> AxResult {
+        self.put(&(len as u32).to_le_bytes());
+        let data = self.reserve(len);
+        fill(data)?;
+        let checksum = crc32(data);
+        self.put(&checksum.to_le_bytes());
+        Ok(())
+    }
+}
+
+/// A cursor over encoded snapshot data.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Take `len` bytes.
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    /// Take a little-endian `u16`.
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    /// Take a little-endian `u32`.
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    /// Take a little-endian `u64`.
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    /// Take a length-prefixed section followed by its checksum, verifying the checksum.
+    fn section(&mut self, section: SnapshotSection) -> Result<&'a [u8], AxVCpuError> {
+        let malformed = AxVCpuError::SnapshotIncompatible(SnapshotIncompatibility::Malformed);
+        let len = self.u32().ok_or(malformed)? as usize;
+        let data = self.take(len).ok_or(malformed)?;
+        if self.u32().ok_or(malformed)? != crc32(data) {
+            return Err(AxVCpuError::SnapshotCorrupted { section });
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::RleCompressor;
+
+    fn snapshot() -> AxVCpuSnapshot {
+        AxVCpuSnapshot {
+            header: SnapshotHeader::new(&HostInfo {
+                arch: SnapshotArch::current(),
+                features: VirtHwFeatures::empty(),
+            }),
+            arch_state: [vec![0; 200], vec![1, 2, 3]].concat(),
+            ext_state: vec![7; 16],
+            sysregs: SysRegFile::default(),
+            time: VCpuTimeState {
+                guest_time_ns: 1000,
+                timer_deadline_ns: Some(1500),
+            },
+        }
+    }
+
+    const MALFORMED: AxVCpuError =
+        AxVCpuError::SnapshotIncompatible(SnapshotIncompatibility::Malformed);
+
+    #[test]
+    fn crc32_matches_the_ieee_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut data = Vec::new();
+        snapshot().encode(&mut data);
+        assert_eq!(AxVCpuSnapshot::decode(&data), Ok(snapshot()));
+    }
+
+    #[test]
+    fn truncated_or_extended_snapshots_are_malformed() {
+        let mut data = Vec::new();
+        snapshot().encode(&mut data);
+        for len in SNAPSHOT_MAGIC.len()..data.len() {
+            assert_eq!(AxVCpuSnapshot::decode(&data[..len]), Err(MALFORMED));
+        }
+        data.push(0);
+        assert_eq!(AxVCpuSnapshot::decode(&data), Err(MALFORMED));
+    }
+
+    #[test]
+    fn corrupted_sections_are_reported() {
+        let mut data = Vec::new();
+        snapshot().encode(&mut data);
+        let arch_state = SNAPSHOT_HEADER_SIZE + 4;
+        let ext_state = arch_state + snapshot().arch_state.len() + SNAPSHOT_SECTION_OVERHEAD;
+        let time = data.len() - 4 - VCpuTimeState::ENCODED_SIZE;
+        for (offset, section) in [
+            (20, SnapshotSection::Header),
+            (arch_state + 100, SnapshotSection::ArchState),
+            (ext_state, SnapshotSection::ExtState),
+            (time, SnapshotSection::Time),
+        ] {
+            let mut corrupted = data.clone();
+            corrupted[offset] ^= 0x10;
+            assert_eq!(
+                AxVCpuSnapshot::decode(&corrupted),
+                Err(AxVCpuError::SnapshotCorrupted { section })
+            );
+        }
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let mut data = Vec::new();
+        snapshot().encode_with(Some(&RleCompressor), &mut data);
+        assert_eq!(
+            AxVCpuSnapshot::decode_with(&data, Some(&RleCompressor)),
+            Ok(snapshot())
+        );
+        assert_eq!(
+            AxVCpuSnapshot::decode_with(&data, None),
+            Err(AxVCpuError::SnapshotIncompatible(
+                SnapshotIncompatibility::Compression {
+                    found: RleCompressor::ID,
+                    expected: None,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn compressed_length_is_checked() {
+        let mut data = Vec::new();
+        snapshot().encode_with(Some(&RleCompressor), &mut data);
+        let raw_len = u32::from_le_bytes(data[8..12].try_into().unwrap());
+        // Lengths not matching the decompressed data are rejected, huge ones without being preallocated.
+        for len in [u32::MAX, raw_len - 1, raw_len + 1] {
+            data[8..12].copy_from_slice(&len.to_le_bytes());
+            assert_eq!(
+                AxVCpuSnapshot::decode_with(&data, Some(&RleCompressor)),
+                Err(MALFORMED)
+            );
+        }
+    }
+}
@@ -0,0 +1,498 @@
+#[cfg(feature = "no-alloc-fastpath")]
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use crate::{AxArchVCpu, AxVCpu, AxVCpuHal, ExecCounters, ExitKind};
+
+/// The receiver of the final counters of a vcpu, see [`FinalStatsReport::Reporter`].
+pub trait StatsReporter: Send + Sync {
+    /// Called with the final counters of a vcpu when it's dropped.
+    fn report_final_stats(&self, stats: &AxVCpuStats);
+}
+
+/// How the final counters of a vcpu are reported when it's dropped, set by
+/// [`AxVCpu::set_final_stats_report`](crate::AxVCpu::set_final_stats_report), so that short-lived VMs still
+/// produce telemetry.
+#[derive(Clone, Default)]
+pub enum FinalStatsReport {
+    /// Don't report.
+    #[default]
+    Off,
+    /// Emit a summary through the logging facade, under the `axvcpu::exit` target. Does nothing if the `log`
+    /// feature is disabled.
+    Log,
+    /// Pass the counters to a reporter.
+    Reporter(Arc<dyn StatsReporter>),
+}
+
+/// A stage of handling an exit, see [`ExitTiming`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandlerStage {
+    /// Decoding the exit and dispatching it to its handler.
+    Dispatch,
+    /// Emulating the accessed device.
+    Device,
+    /// Injecting interrupts into the guest before re-entry.
+    ///
+    /// Timed by [`AxVCpu::run`](crate::AxVCpu::run) itself for the interrupts queued by
+    /// [`AxVCpu::inject_interrupt`](crate::AxVCpu::inject_interrupt).
+    Injection,
+}
+
+/// The host time spent on handling the exits of a kind, in nanoseconds of [`AxVCpuHal::current_time_nanos`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExitTiming {
+    /// The total time from the exits to the following re-entries.
+    pub total_ns: u64,
+    /// The time spent in [`HandlerStage::Dispatch`].
+    pub dispatch_ns: u64,
+    /// The time spent in [`HandlerStage::Device`].
+    pub device_ns: u64,
+    /// The time spent in [`HandlerStage::Injection`].
+    pub injection_ns: u64,
+}
+
+impl ExitTiming {
+    /// Get the time spent in the given stage.
+    pub const fn stage(&self, stage: HandlerStage) -> u64 {
+        match stage {
+            HandlerStage::Dispatch => self.dispatch_ns,
+            HandlerStage::Device => self.device_ns,
+            HandlerStage::Injection => self.injection_ns,
+        }
+    }
+
+    /// Add time to the given stage.
+    fn add_stage(&mut self, stage: HandlerStage, ns: u64) {
+        let counter = match stage {
+            HandlerStage::Dispatch => &mut self.dispatch_ns,
+            HandlerStage::Device => &mut self.device_ns,
+            HandlerStage::Injection => &mut self.injection_ns,
+        };
+        *counter += ns;
+    }
+}
+
+/// The injection counters of an interrupt vector of a vcpu, see [`AxVCpuStats::vector`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VectorStats {
+    /// The number of times the vector was injected into the guest.
+    pub injected: u64,
+    /// The number of duplicates of the vector merged into a pending one by
+    /// [`AxVCpu::inject_interrupt_from_irq`](crate::AxVCpu::inject_interrupt_from_irq).
+    pub coalesced: u64,
+    /// The number of times the vector was dropped, e.g., because the pending queue was full, or by
+    /// [`IrqFallbackPolicy::Drop`](crate::IrqFallbackPolicy::Drop) or
+    /// [`CpuDownIrqPolicy::Drop`](crate::CpuDownIrqPolicy::Drop).
+    pub dropped: u64,
+    /// The number of times the level-triggered line of the vector stayed asserted beyond the lost-interrupt
+    /// threshold, see [`AxVCpu::set_lost_irq_threshold`](crate::AxVCpu::set_lost_irq_threshold).
+    pub lost: u64,
+}
+
+/// The per-vector counters of a vcpu being updated, merged into [`AxVCpuStats::vectors`] when the counters are read.
+///
+/// With the `no-alloc-fastpath` feature, the counters of the vectors below
+/// [`IRQ_BITMAP_VECTORS`](crate::IRQ_BITMAP_VECTORS) live in a table allocated with the vcpu, so that counting a
+/// vector for the first time on the injection path doesn't allocate a node of the map. Other vectors, and all
+/// vectors without the feature, are counted in the map directly.
+pub(crate) struct VectorTable {
+    /// The counters, indexed by vector.
+    #[cfg(feature = "no-alloc-fastpath")]
+    counters: Box<[VectorStats]>,
+}
+
+impl VectorTable {
+    /// Create a table of zeroed counters.
+    pub(crate) fn new() -> Self {
+        Self {
+            #[cfg(feature = "no-alloc-fastpath")]
+            counters: vec![VectorStats::default(); crate::IRQ_BITMAP_VECTORS].into_boxed_slice(),
+        }
+    }
+
+    /// Get the counters of `vector` for updating, from the table if it has a slot for the vector, from the map of
+    /// `stats` otherwise.
+    pub(crate) fn get_mut<'a>(
+        &'a mut self,
+        stats: &'a mut AxVCpuStats,
+        vector: usize,
+    ) -> &'a mut VectorStats {
+        #[cfg(feature = "no-alloc-fastpath")]
+        if let Some(counters) = self.counters.get_mut(vector) {
+            return counters;
+        }
+        stats.vector_mut(vector)
+    }
+
+    /// Merge the counters of the table into the map of `stats`.
+    pub(crate) fn merge_into(&self, stats: &mut AxVCpuStats) {
+        #[cfg(feature = "no-alloc-fastpath")]
+        for (vector, counters) in self.counters.iter().enumerate() {
+            if *counters != VectorStats::default() {
+                *stats.vector_mut(vector) = *counters;
+            }
+        }
+        #[cfg(not(feature = "no-alloc-fastpath"))]
+        let _ = stats;
+    }
+
+    /// Zero all counters of the table.
+    pub(crate) fn reset(&mut self) {
+        #[cfg(feature = "no-alloc-fastpath")]
+        self.counters.fill(VectorStats::default());
+    }
+}
+
+/// The counters of a vcpu, obtained by [`AxVCpu::stats`](crate::AxVCpu::stats).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AxVCpuStats {
+    /// The id of the vcpu.
+    pub vcpu_id: usize,
+    /// The number of times the vcpu entered the guest.
+    pub runs: u64,
+    /// The number of exits, indexed by [`ExitKind::id`].
+    pub exits: [u64; ExitKind::COUNT],
+    /// The number of interrupts injected into the guest.
+    pub injected_interrupts: u64,
+    /// The host time spent in guest mode, in nanoseconds.
+    pub guest_ns: u64,
+    /// The host time spent on handling exits, indexed by [`ExitKind::id`].
+    pub exit_timing: [ExitTiming; ExitKind::COUNT],
+    /// The time the vcpu was kept out of the guest by its CPU quota, in nanoseconds.
+    pub throttled_ns: u64,
+    /// The number of binds under [`FpuPolicy::Lazy`](crate::FpuPolicy::Lazy) after which the guest FP/SIMD state
+    /// had to be restored.
+    pub fpu_lazy_restores: u64,
+    /// The number of binds under [`FpuPolicy::Lazy`](crate::FpuPolicy::Lazy) during which the guest never used the
+    /// FP/SIMD unit, i.e., the laziness paid off.
+    pub fpu_lazy_skips: u64,
+    /// The total time the injected interrupts waited in the queue of the vcpu, from
+    /// [`AxVCpu::inject_interrupt`](crate::AxVCpu::inject_interrupt) to the injection into the guest, in
+    /// nanoseconds.
+    pub injection_latency_ns: u64,
+    /// The longest time an injected interrupt waited in the queue of the vcpu, in nanoseconds.
+    pub max_injection_latency_ns: u64,
+    /// The number of retired guest instructions, counted while execution profiling is enabled (see
+    /// [`AxVCpu::enable_exec_profiling`](crate::AxVCpu::enable_exec_profiling)).
+    pub guest_instructions: u64,
+    /// The number of retired guest branches, counted while execution profiling is enabled.
+    pub guest_branches: u64,
+    /// The injection counters of the vectors, only present for the vectors counted at least once.
+    pub vectors: BTreeMap<usize, VectorStats>,
+}
+
+impl AxVCpuStats {
+    /// Create empty counters for the given vcpu.
+    pub(crate) const fn new(vcpu_id: usize) -> Self {
+        Self {
+            vcpu_id,
+            runs: 0,
+            exits: [0; ExitKind::COUNT],
+            injected_interrupts: 0,
+            guest_ns: 0,
+            exit_timing: [ExitTiming {
+                total_ns: 0,
+                dispatch_ns: 0,
+                device_ns: 0,
+                injection_ns: 0,
+            }; ExitKind::COUNT],
+            throttled_ns: 0,
+            fpu_lazy_restores: 0,
+            fpu_lazy_skips: 0,
+            injection_latency_ns: 0,
+            max_injection_latency_ns: 0,
+            guest_instructions: 0,
+            guest_branches: 0,
+            vectors: BTreeMap::new(),
+        }
+    }
+
+    /// Get the injection counters of the given vector.
+    pub fn vector(&self, vector: usize) -> VectorStats {
+        self.vectors.get(&vector).copied().unwrap_or_default()
+    }
+
+    /// Get the injection counters of the given vector for updating.
+    pub(crate) fn vector_mut(&mut self, vector: usize) -> &mut VectorStats {
+        self.vectors.entry(vector).or_default()
+    }
+
+    /// Add the guest execution counters of a run.
+    pub(crate) fn record_exec(&mut self, counters: ExecCounters) {
+        self.guest_instructions += counters.instructions;
+        self.guest_branches += counters.branches;
+    }
+
+    /// Count an exit of the given kind, after `guest_ns` nanoseconds in guest mode.
+    pub(crate) fn record_exit(&mut self, kind: ExitKind, guest_ns: u64) {
+        self.runs += 1;
+        self.guest_ns += guest_ns;
+        self.exits[kind.id() as usize] += 1;
+    }
+
+    /// Count an interrupt injected after waiting `latency_ns` nanoseconds in the queue. The counters of the vector
+    /// are updated separately, see [`VectorTable`].
+    pub(crate) fn record_injection(&mut self, latency_ns: u64) {
+        self.injected_interrupts += 1;
+        self.injection_latency_ns += latency_ns;
+        self.max_injection_latency_ns = self.max_injection_latency_ns.max(latency_ns);
+    }
+
+    /// Attribute `ns` nanoseconds of the given stage to exits of the given kind.
+    pub(crate) fn record_stage(&mut self, kind: ExitKind, stage: HandlerStage, ns: u64) {
+        self.exit_timing[kind.id() as usize].add_stage(stage, ns);
+    }
+
+    /// Attribute the time from an exit to the following re-entry to exits of the given kind.
+    pub(crate) fn record_handling(&mut self, kind: ExitKind, ns: u64) {
+        self.exit_timing[kind.id() as usize].total_ns += ns;
+    }
+
+    /// Get the host time spent on handling exits of the given kind.
+    pub fn timing(&self, kind: ExitKind) -> &ExitTiming {
+        &self.exit_timing[kind.id() as usize]
+    }
+
+    /// Get the number of exits of the given kind.
+    pub fn exits(&self, kind: ExitKind) -> u64 {
+        self.exits[kind.id() as usize]
+    }
+
+    /// Get the number of exits of all kinds.
+    pub fn total_exits(&self) -> u64 {
+        self.exits.iter().sum()
+    }
+
+    /// Emit a summary of the counters through the logging facade.
+    pub(crate) fn log_summary(&self) {
+        vcpu_log!(Exit, Info, vcpu = self.vcpu_id, runs = self.runs, exits = self.total_exits(),
+            injected_interrupts = self.injected_interrupts, throttled_ns = self.throttled_ns; "final vcpu stats");
+        for &kind in ExitKind::ALL {
+            #[cfg_attr(not(feature = "log"), allow(unused_variables))]
+            let (count, timing) = (self.exits(kind), self.timing(kind));
+            if count != 0 {
+                vcpu_log!(Exit, Info, vcpu = self.vcpu_id, kind = kind.as_str(), exits = count,
+                    handling_ns = timing.total_ns; "final vcpu exit stats");
+            }
+        }
+    }
+
+    /// Call `f` with the name, the exit kind (for per-kind counters), and the value of every counter.
+    ///
+    /// Counter names are stable, so that the counters can be forwarded to any metrics backend.
+    pub fn for_each_counter(&self, mut f: impl FnMut(&'static str, Option<ExitKind>, u64)) {
+        f("runs", None, self.runs);
+        f("injected_interrupts", None, self.injected_interrupts);
+        f("guest_ns", None, self.guest_ns);
+        f("throttled_ns", None, self.throttled_ns);
+        f("fpu_lazy_restores", None, self.fpu_lazy_restores);
+        f("fpu_lazy_skips", None, self.fpu_lazy_skips);
+        f("injection_latency_ns", None, self.injection_latency_ns);
+        f("guest_instructions", None, self.guest_instructions);
+        f("guest_branches", None, self.guest_branches);
+        for &kind in ExitKind::ALL {
+            let timing = self.timing(kind);
+            f("exits", Some(kind), self.exits(kind));
+            f("exit_handling_ns", Some(kind), timing.total_ns);
+            f("exit_dispatch_ns", Some(kind), timing.dispatch_ns);
+            f("exit_device_ns", Some(kind), timing.device_ns);
+            f("exit_injection_ns", Some(kind), timing.injection_ns);
+        }
+    }
+
+    /// Render the counters in the Prometheus text exposition format, including the `# HELP` and `# TYPE` lines.
+    ///
+    /// When exporting the counters of multiple vcpus, call [`AxVCpuStats::export_header`] once and
+    /// [`AxVCpuStats::export_samples`] for each vcpu instead, as the header must not be repeated.
+    #[cfg(feature = "metrics")]
+    pub fn export(&self, out: &mut dyn core::fmt::Write) -> core::fmt::Result {
+        Self::export_header(out)?;
+        self.export_samples(out)
+    }
+
+    /// Render the `# HELP` and `# TYPE` lines of the counters in the Prometheus text exposition format.
+    #[cfg(feature = "metrics")]
+    pub fn export_header(out: &mut dyn core::fmt::Write) -> core::fmt::Result {
+        for (name, help) in [
+            ("runs", "Number of guest entries."),
+            ("injected_interrupts", "Number of interrupts injected."),
+            ("guest_ns", "Time spent in guest mode."),
+            (
+                "throttled_ns",
+                "Time kept out of the guest by the CPU quota.",
+            ),
+            (
+                "fpu_lazy_restores",
+                "Number of binds with a lazy FP/SIMD restore.",
+            ),
+            ("fpu_lazy_skips", "Number of binds without FP/SIMD use."),
+            (
+                "injection_latency_ns",
+                "Time injected interrupts waited in the queue.",
+            ),
+            (
+                "guest_instructions",
+                "Number of retired guest instructions.",
+            ),
+            ("guest_branches", "Number of retired guest branches."),
+            ("exits", "Number of vm-exits by kind."),
+            (
+                "exit_handling_ns",
+                "Host time from vm-exit to re-entry by kind.",
+            ),
+            (
+                "exit_dispatch_ns",
+                "Host time dispatching vm-exits by kind.",
+            ),
+            ("exit_device_ns", "Host time emulating devices by kind."),
+            (
+                "exit_injection_ns",
+                "Host time injecting interrupts by kind.",
+            ),
+        ] {
+            writeln!(out, "# HELP axvcpu_{name}_total {help}")?;
+            writeln!(out, "# TYPE axvcpu_{name}_total counter")?;
+        }
+        Ok(())
+    }
+
+    /// Render the samples of the counters in the Prometheus text exposition format, labeled with the vcpu id.
+    #[cfg(feature = "metrics")]
+    pub fn export_samples(&self, out: &mut dyn core::fmt::Write) -> core::fmt::Result {
+        let mut result = Ok(());
+        self.for_each_counter(|name, kind, value| {
+            if result.is_err() {
+                return;
+            }
+            result = match kind {
+                Some(kind) => writeln!(
+                    out,
+                    "axvcpu_{name}_total{{vcpu=\"{}\",kind=\"{kind}\"}} {value}",
+                    self.vcpu_id
+                ),
+                None => writeln!(
+                    out,
+                    "axvcpu_{name}_total{{vcpu=\"{}\"}} {value}",
+                    self.vcpu_id
+                ),
+            };
+        });
+        result
+    }
+}
+
+/// The counters of all vcpus of a group combined, obtained by
+/// [`AxVCpuGroup::aggregate_stats`](crate::AxVCpuGroup::aggregate_stats), so that the CPU cost of a VM can be
+/// attributed to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupStats {
+    /// The number of vcpus in the group.
+    pub vcpus: usize,
+    /// The number of times the vcpus entered the guest.
+    pub runs: u64,
+    /// The number of exits, indexed by [`ExitKind::id`].
+    pub exits: [u64; ExitKind::COUNT],
+    /// The number of interrupts injected into the guest.
+    pub injected_interrupts: u64,
+    /// The host time the vcpus spent in guest mode, in nanoseconds.
+    pub guest_ns: u64,
+    /// The host time spent on handling exits, in nanoseconds.
+    pub handling_ns: u64,
+    /// The time the vcpus were runnable but not running, in nanoseconds, see
+    /// [`AxVCpu::steal_time`](crate::AxVCpu::steal_time).
+    pub steal_ns: u64,
+    /// The time the vcpus were kept out of the guest by their CPU quotas, in nanoseconds.
+    pub throttled_ns: u64,
+    /// The number of exits per second in the last complete load window of each vcpu (see
+    /// [`LoadHint::exits_per_sec`](crate::LoadHint::exits_per_sec)), summed up.
+    pub exits_per_sec: u64,
+    /// The average number of exits per second since the previous call to
+    /// [`AxVCpuGroup::aggregate_stats`](crate::AxVCpuGroup::aggregate_stats), or 0 on the first call.
+    pub exit_rate: u64,
+    /// The value of [`GroupStats::exit_rate`] at the previous call, or 0.
+    pub previous_exit_rate: u64,
+}
+
+impl GroupStats {
+    /// Create empty counters for a group of `vcpus` vcpus.
+    pub(crate) const fn new(vcpus: usize) -> Self {
+        Self {
+            vcpus,
+            runs: 0,
+            exits: [0; ExitKind::COUNT],
+            injected_interrupts: 0,
+            guest_ns: 0,
+            handling_ns: 0,
+            steal_ns: 0,
+            throttled_ns: 0,
+            exits_per_sec: 0,
+            exit_rate: 0,
+            previous_exit_rate: 0,
+        }
+    }
+
+    /// Add the counters of a vcpu.
+    pub(crate) fn add(&mut self, stats: &AxVCpuStats) {
+        self.runs += stats.runs;
+        self.exits
+            .iter_mut()
+            .zip(stats.exits)
+            .for_each(|(total, exits)| *total += exits);
+        self.injected_interrupts += stats.injected_interrupts;
+        self.guest_ns += stats.guest_ns;
+        self.handling_ns += stats
+            .exit_timing
+            .iter()
+            .map(|timing| timing.total_ns)
+            .sum::<u64>();
+        self.throttled_ns += stats.throttled_ns;
+    }
+
+    /// Get the number of exits of the given kind.
+    pub fn exits(&self, kind: ExitKind) -> u64 {
+        self.exits[kind.id() as usize]
+    }
+
+    /// Get the number of exits of all kinds.
+    pub fn total_exits(&self) -> u64 {
+        self.exits.iter().sum()
+    }
+
+    /// Get the host time consumed by the vcpus, in guest mode and handling exits, in nanoseconds.
+    pub fn cpu_ns(&self) -> u64 {
+        self.guest_ns + self.handling_ns
+    }
+
+    /// Get how the exit rate changed since the previous call, in exits per second: a rising rate often reveals a
+    /// misbehaving guest or a device emulation regression before it shows up as CPU usage.
+    pub fn exit_rate_change(&self) -> i64 {
+        self.exit_rate as i64 - self.previous_exit_rate as i64
+    }
+}
+
+/// A scoped timer attributing the host time until it's dropped to a [`HandlerStage`] of the last exit of a vcpu,
+/// created by [`AxVCpu::stage_timer`](crate::AxVCpu::stage_timer).
+///
+/// Exit handlers and event listeners wrap each stage in a timer, e.g.:
+///
+/// ```ignore
+/// let _timer = vcpu.stage_timer(HandlerStage::Device);
+/// device.handle_mmio_write(addr, width, data)?;
+/// ```
+pub struct StageTimer<'a, A: AxArchVCpu> {
+    /// The vcpu whose last exit the time is attributed to.
+    pub(crate) vcpu: &'a AxVCpu<A>,
+    /// The stage being timed.
+    pub(crate) stage: HandlerStage,
+    /// When the stage started.
+    pub(crate) start_ns: u64,
+}
+
+impl<A: AxArchVCpu> Drop for StageTimer<'_, A> {
+    fn drop(&mut self) {
+        let elapsed = A::Hal::current_time_nanos().saturating_sub(self.start_ns);
+        self.vcpu.record_stage_time(self.stage, elapsed);
+    }
+}
@@ -0,0 +1,130 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// A shadow of the system registers emulated by the VMM (`MSR`s in x86, `CSR`s in RISC-V, and `System registers`
+/// in Aarch64), obtained by [`AxVCpu::sysregs`](crate::AxVCpu::sysregs).
+///
+/// Only registers explicitly defined (by [`SysRegFile::define`] or [`SysRegFile::write`]) are shadowed. See
+/// [`AxVCpuExitReason::SysRegRead`](crate::AxVCpuExitReason::SysRegRead) for the encoding of the addresses.
+///
+/// Each register carries a dirty flag, set when it's written and cleared by [`SysRegFile::clear_dirty`], so that
+/// the VMM (or a debugger) only propagates the registers changed since the last synchronization. The file is part
+/// of [`AxVCpuSnapshot`](crate::AxVCpuSnapshot).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SysRegFile {
+    /// The shadowed registers, as `(value, dirty)`, keyed by their addresses.
+    regs: BTreeMap<usize, (u64, bool)>,
+}
+
+impl SysRegFile {
+    /// Create an empty [`SysRegFile`].
+    pub const fn new() -> Self {
+        Self {
+            regs: BTreeMap::new(),
+        }
+    }
+
+    /// Shadow the register at `addr` with its reset value, marking it clean.
+    pub fn define(&mut self, addr: usize, reset_value: u64) {
+        self.regs.insert(addr, (reset_value, false));
+    }
+
+    /// Stop shadowing the register at `addr`. Returns its value if it was shadowed.
+    pub fn remove(&mut self, addr: usize) -> Option<u64> {
+        self.regs.remove(&addr).map(|(value, _)| value)
+    }
+
+    /// Whether the register at `addr` is shadowed.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.regs.contains_key(&addr)
+    }
+
+    /// Read the register at `addr`, or `None` if it's not shadowed.
+    pub fn read(&self, addr: usize) -> Option<u64> {
+        self.regs.get(&addr).map(|&(value, _)| value)
+    }
+
+    /// Write the register at `addr`, shadowing it if it's not yet, and mark it dirty.
+    pub fn write(&mut self, addr: usize, value: u64) {
+        self.regs.insert(addr, (value, true));
+    }
+
+    /// Whether the register at `addr` is written since the last [`SysRegFile::clear_dirty`].
+    pub fn is_dirty(&self, addr: usize) -> bool {
+        self.regs.get(&addr).is_some_and(|&(_, dirty)| dirty)
+    }
+
+    /// Iterate over the dirty registers, as `(addr, value)`, in ascending order of addresses.
+    pub fn dirty(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.regs
+            .iter()
+            .filter(|(_, (_, dirty))| *dirty)
+            .map(|(&addr, &(value, _))| (addr, value))
+    }
+
+    /// Mark all registers clean.
+    pub fn clear_dirty(&mut self) {
+        for (_, dirty) in self.regs.values_mut() {
+            *dirty = false;
+        }
+    }
+
+    /// Mark all registers dirty, e.g., after the file is restored from a snapshot.
+    pub fn mark_all_dirty(&mut self) {
+        for (_, dirty) in self.regs.values_mut() {
+            *dirty = true;
+        }
+    }
+
+    /// Iterate over all shadowed registers, as `(addr, value)`, in ascending order of addresses.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.regs.iter().map(|(&addr, &(value, _))| (addr, value))
+    }
+
+    /// Get the number of shadowed registers.
+    pub fn len(&self) -> usize {
+        self.regs.len()
+    }
+
+    /// Whether no register is shadowed.
+    pub fn is_empty(&self) -> bool {
+        self.regs.is_empty()
+    }
+
+    /// Encode the registers as little-endian `(addr: u64, value: u64)` pairs, appending the bytes to `out`. Dirty
+    /// flags are not encoded.
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        for (addr, value) in self.iter() {
+            out.extend_from_slice(&(addr as u64).to_le_bytes());
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    /// Get the size in bytes of the encoding of the registers.
+    pub(crate) fn encoded_len(&self) -> usize {
+        self.regs.len() * 16
+    }
+
+    /// Encode the registers like [`SysRegFile::encode`] into `buf`, whose length is [`SysRegFile::encoded_len`].
+    pub(crate) fn encode_into(&self, buf: &mut [u8]) {
+        for ((addr, value), pair) in self.iter().zip(buf.chunks_exact_mut(16)) {
+            pair[..8].copy_from_slice(&(addr as u64).to_le_bytes());
+            pair[8..].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    /// Decode registers encoded by [`SysRegFile::encode`], all marked dirty. Returns `None` if malformed.
+    pub(crate) fn decode(data: &[u8]) -> Option<Self> {
+        let pairs = data.chunks_exact(16);
+        if !pairs.remainder().is_empty() {
+            return None;
+        }
+        let mut file = Self::new();
+        for pair in pairs {
+            let addr = u64::from_le_bytes(pair[..8].try_into().ok()?);
+            let value = u64::from_le_bytes(pair[8..].try_into().ok()?);
+            file.write(usize::try_from(addr).ok()?, value);
+        }
+        Some(file)
+    }
+}
@@ -0,0 +1,308 @@
+//! Mocks shared by the unit tests.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use axaddrspace::{GuestPhysAddr, HostPhysAddr, HostVirtAddr};
+use axerrno::{AxResult, ax_err};
+
+use crate::{AxArchVCpu, AxVCpu, AxVCpuError, AxVCpuExitReason, AxVCpuHal, RunToken};
+
+/// The host clock of [`MockHal`], in nanoseconds.
+static NOW: AtomicU64 = AtomicU64::new(0);
+
+/// The number of errors reported to [`AxVCpuHal::on_fatal_vcpu_error`] of [`MockHal`].
+static FATAL_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of calls to [`AxVCpuHal::yield_now`] of [`MockHal`].
+static YIELDS: AtomicUsize = AtomicUsize::new(0);
+
+/// The physical CPU the vcpu is pinned to by [`AxVCpuHal::pin_vcpu`] of [`MockHal`], `usize::MAX` if none.
+static PINNED_CPU: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The current physical CPU of [`MockHal`].
+static CURRENT_CPU: AtomicUsize = AtomicUsize::new(0);
+
+/// Serializes the tests operating on vcpus, as the current vcpu and the clock of [`MockHal`] are global.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Take the lock serializing the tests operating on vcpus, and reset the clock, the fatal error count, the yield
+/// count, the pinning and the current CPU of [`MockHal`].
+pub(crate) fn serial() -> MutexGuard<'static, ()> {
+    let guard = SERIAL.lock().unwrap_or_else(|err| err.into_inner());
+    NOW.store(0, Ordering::Relaxed);
+    FATAL_ERRORS.store(0, Ordering::Relaxed);
+    YIELDS.store(0, Ordering::Relaxed);
+    PINNED_CPU.store(usize::MAX, Ordering::Relaxed);
+    CURRENT_CPU.store(0, Ordering::Relaxed);
+    guard
+}
+
+/// Get the number of errors reported to [`AxVCpuHal::on_fatal_vcpu_error`] of [`MockHal`].
+pub(crate) fn fatal_errors() -> usize {
+    FATAL_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Get the number of calls to [`AxVCpuHal::yield_now`] of [`MockHal`].
+pub(crate) fn yields() -> usize {
+    YIELDS.load(Ordering::Relaxed)
+}
+
+/// Get the physical CPU the vcpu is pinned to by [`AxVCpuHal::pin_vcpu`] of [`MockHal`].
+pub(crate) fn pinned_cpu() -> Option<usize> {
+    Some(PINNED_CPU.load(Ordering::Relaxed)).filter(|&cpu_id| cpu_id != usize::MAX)
+}
+
+/// Move [`MockHal`] to the physical CPU `cpu_id`.
+pub(crate) fn set_current_cpu(cpu_id: usize) {
+    CURRENT_CPU.store(cpu_id, Ordering::Relaxed);
+}
+
+/// Advance the clock of [`MockHal`] by `ns` nanoseconds.
+pub(crate) fn advance_time(ns: u64) {
+    NOW.fetch_add(ns, Ordering::Relaxed);
+}
+
+/// A HAL with a manual clock, advanced by 100 ns on every yield, running on physical CPU 0 unless moved, which supports pinning
+/// vcpus but not isolating host interrupts.
+pub(crate) struct MockHal;
+
+impl AxVCpuHal for MockHal {
+    fn alloc_frame() -> Option<HostPhysAddr> {
+        None
+    }
+
+    fn dealloc_frame(_paddr: HostPhysAddr) {}
+
+    fn phys_to_virt(paddr: HostPhysAddr) -> HostVirtAddr {
+        HostVirtAddr::from(paddr.as_usize())
+    }
+
+    fn virt_to_phys(vaddr: HostVirtAddr) -> HostPhysAddr {
+        HostPhysAddr::from(vaddr.as_usize())
+    }
+
+    fn irq_fetch() -> usize {
+        0
+    }
+
+    fn current_cpu_id() -> usize {
+        CURRENT_CPU.load(Ordering::Relaxed)
+    }
+
+    fn irq_hanlder() {}
+
+    fn current_time_nanos() -> u64 {
+        NOW.load(Ordering::Relaxed)
+    }
+
+    fn yield_now() {
+        YIELDS.fetch_add(1, Ordering::Relaxed);
+        advance_time(100);
+    }
+
+    fn pin_vcpu(_vcpu_id: usize, cpu_id: Option<usize>) -> AxResult {
+        PINNED_CPU.store(cpu_id.unwrap_or(usize::MAX), Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn on_fatal_vcpu_error(_vm_id: usize, _vcpu_id: usize, _err: &AxVCpuError) {
+        FATAL_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The create configuration of [`MockArchVCpu`], selecting contract violations for the conformance suite.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct MockConfig {
+    /// Report a different extended state size once bound.
+    pub(crate) unstable_ext_state: bool,
+    /// Report the register state as protected without the `CONFIDENTIAL` capability.
+    pub(crate) protected: bool,
+}
+
+/// An architecture-specific vcpu returning scripted exits, [`AxVCpuExitReason::Halt`] once the script is over.
+pub(crate) struct MockArchVCpu {
+    /// The create configuration.
+    config: MockConfig,
+    /// Whether the vcpu is bound.
+    pub(crate) bound: bool,
+    /// The entry address.
+    pub(crate) entry: GuestPhysAddr,
+    /// The general-purpose registers.
+    pub(crate) gprs: [usize; 32],
+    /// The exits returned by the next runs.
+    pub(crate) exits: VecDeque<AxVCpuExitReason>,
+    /// The number of interrupts injected.
+    pub(crate) injected: usize,
+    /// The last interrupt injected.
+    pub(crate) last_injected: Option<usize>,
+    /// The guest time of each run, in nanoseconds.
+    pub(crate) run_ns: u64,
+}
+
+impl MockArchVCpu {
+    /// The size of the state saved by [`AxArchVCpu::save_state`].
+    const STATE_SIZE: usize = 33 * 8;
+}
+
+impl AxArchVCpu for MockArchVCpu {
+    type CreateConfig = MockConfig;
+    type SetupConfig = ();
+    type Hal = MockHal;
+
+    fn new(config: MockConfig) -> AxResult<Self> {
+        Ok(Self {
+            config,
+            bound: false,
+            entry: GuestPhysAddr::from(0),
+            gprs: [0; 32],
+            exits: VecDeque::new(),
+            injected: 0,
+            last_injected: None,
+            run_ns: 0,
+        })
+    }
+
+    fn set_entry(&mut self, entry: GuestPhysAddr) -> AxResult {
+        self.entry = entry;
+        Ok(())
+    }
+
+    fn set_boot_args(&mut self, entry: GuestPhysAddr, arch_cpu_id: u64, arg: u64) -> AxResult {
+        self.entry = entry;
+        self.gprs[0] = arch_cpu_id as usize;
+        self.gprs[1] = arg as usize;
+        Ok(())
+    }
+
+    fn set_ept_root(&mut self, _ept_root: HostPhysAddr) -> AxResult {
+        Ok(())
+    }
+
+    fn setup(&mut self, _config: ()) -> AxResult {
+        Ok(())
+    }
+
+    fn run(&mut self) -> AxResult<AxVCpuExitReason> {
+        advance_time(self.run_ns);
+        Ok(self.exits.pop_front().unwrap_or(AxVCpuExitReason::Halt))
+    }
+
+    fn bind(&mut self) -> AxResult {
+        self.bound = true;
+        Ok(())
+    }
+
+    fn unbind(&mut self) -> AxResult {
+        self.bound = false;
+        Ok(())
+    }
+
+    fn set_gpr(&mut self, reg: usize, val: usize) {
+        self.gprs[reg] = val;
+    }
+
+    fn inject_interrupt(&mut self, vector: usize) -> AxResult {
+        self.injected += 1;
+        self.last_injected = Some(vector);
+        Ok(())
+    }
+
+    fn is_protected(&self) -> bool {
+        self.config.protected
+    }
+
+    fn ext_state_size(&self) -> usize {
+        if self.config.unstable_ext_state && self.bound {
+            8
+        } else {
+            0
+        }
+    }
+
+    fn save_state(&mut self, out: &mut Vec<u8>) -> AxResult {
+        out.extend_from_slice(&self.entry.as_usize().to_le_bytes());
+        for gpr in self.gprs {
+            out.extend_from_slice(&gpr.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    fn restore_state(&mut self, data: &[u8]) -> AxResult {
+        if data.len() != Self::STATE_SIZE {
+            return ax_err!(InvalidData, "state size mismatch");
+        }
+        let mut words = data
+            .chunks_exact(8)
+            .map(|word| usize::from_le_bytes(word.try_into().unwrap()));
+        self.entry = GuestPhysAddr::from(words.next().unwrap());
+        for (gpr, word) in self.gprs.iter_mut().zip(words) {
+            *gpr = word;
+        }
+        Ok(())
+    }
+}
+
+/// Create, set up and bind a vcpu with the given configuration.
+pub(crate) fn bound_vcpu(config: MockConfig) -> (AxVCpu<MockArchVCpu>, RunToken) {
+    let vcpu = AxVCpu::new(0, 0, None, config).unwrap();
+    vcpu.setup(GuestPhysAddr::from(0x8000), HostPhysAddr::from(0), ())
+        .unwrap();
+    let token = vcpu.bind().unwrap();
+    (vcpu, token)
+}
+
+/// Script the next exits of a vcpu.
+pub(crate) fn script_exits(
+    vcpu: &AxVCpu<MockArchVCpu>,
+    exits: impl IntoIterator<Item = AxVCpuExitReason>,
+) {
+    vcpu.with_arch(|arch_vcpu| {
+        arch_vcpu.exits.extend(exits);
+        Ok(())
+    })
+    .unwrap();
+}
+
+/// Counts the allocations of the current thread, to check the `no-alloc-fastpath` guarantee.
+#[cfg(feature = "no-alloc-fastpath")]
+pub(crate) mod alloc_counter {
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::cell::Cell;
+    use std::alloc::System;
+
+    std::thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// The system allocator, counting allocations per thread.
+    struct CountingAlloc;
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    /// Run `f`, returning its result and the number of allocations it made on the current thread.
+    pub(crate) fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+        let before = ALLOCATIONS.with(Cell::get);
+        let result = f();
+        (result, ALLOCATIONS.with(Cell::get) - before)
+    }
+}
@@ -0,0 +1,319 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The size in bytes of an encoded [`TraceRecord`].
+pub const TRACE_RECORD_SIZE: usize = 40;
+
+/// The event of a [`TraceRecord`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum TraceEvent {
+    /// The vcpu enters the guest. The payload is empty.
+    Entry = 0,
+    /// The vcpu exits the guest. The payload is `[kind, arg]`, where `kind` is the [`ExitKind::id`](crate::ExitKind::id)
+    /// and `arg` the first word of [`VCpuRunPage::encode_payload`](crate::VCpuRunPage::encode_payload).
+    Exit = 1,
+    /// An interrupt is injected into the guest. The payload is `[vector, latency_ns]`.
+    Injection = 2,
+    /// The vcpu transitions between states. The payload is `[from, to]`, the numeric values of the
+    /// [`VCpuState`](crate::VCpuState)s.
+    StateChange = 3,
+    /// A level-triggered interrupt line stayed asserted beyond the lost-interrupt threshold, see
+    /// [`AxVCpu::set_lost_irq_threshold`](crate::AxVCpu::set_lost_irq_threshold). The payload is
+    /// `[vector, asserted_ns]`.
+    LostInterrupt = 4,
+}
+
+impl TraceEvent {
+    /// Get the event with the given numeric id.
+    pub const fn from_id(id: u16) -> Option<Self> {
+        match id {
+            0 => Some(Self::Entry),
+            1 => Some(Self::Exit),
+            2 => Some(Self::Injection),
+            3 => Some(Self::StateChange),
+            4 => Some(Self::LostInterrupt),
+            _ => None,
+        }
+    }
+
+    /// Get the stable name of this event, in `snake_case`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Entry => "entry",
+            Self::Exit => "exit",
+            Self::Injection => "injection",
+            Self::StateChange => "state_change",
+            Self::LostInterrupt => "lost_interrupt",
+        }
+    }
+}
+
+/// A fixed-size record of a binary vcpu trace, see [`TraceSink`].
+///
+/// Records are encoded in [`TRACE_RECORD_SIZE`] bytes, little-endian, in the order of the fields, so that long
+/// captures can be dumped as-is and decoded offline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct TraceRecord {
+    /// The host time of the event, in nanoseconds of [`AxVCpuHal::current_time_nanos`](crate::AxVCpuHal::current_time_nanos).
+    pub timestamp: u64,
+    /// The raw cycle counter at the event, see [`AxVCpuHal::read_cycle_counter`](crate::AxVCpuHal::read_cycle_counter).
+    pub cycles: u64,
+    /// The id of the vcpu.
+    pub vcpu_id: u32,
+    /// The numeric id of the [`TraceEvent`].
+    pub event: u16,
+    /// Reserved, zero.
+    pub reserved: u16,
+    /// The payload of the event, see [`TraceEvent`].
+    pub payload: [u64; 2],
+}
+
+const _: () = assert!(core::mem::size_of::<TraceRecord>() == TRACE_RECORD_SIZE);
+
+impl TraceRecord {
+    /// Create a record.
+    pub const fn new(
+        timestamp: u64,
+        cycles: u64,
+        vcpu_id: usize,
+        event: TraceEvent,
+        payload: [u64; 2],
+    ) -> Self {
+        Self {
+            timestamp,
+            cycles,
+            vcpu_id: vcpu_id as u32,
+            event: event as u16,
+            reserved: 0,
+            payload,
+        }
+    }
+
+    /// Get the event of the record, if it's known.
+    pub const fn event(&self) -> Option<TraceEvent> {
+        TraceEvent::from_id(self.event)
+    }
+
+    /// Encode the record.
+    pub fn to_bytes(&self) -> [u8; TRACE_RECORD_SIZE] {
+        let mut bytes = [0; TRACE_RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.cycles.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.vcpu_id.to_le_bytes());
+        bytes[20..22].copy_from_slice(&self.event.to_le_bytes());
+        bytes[22..24].copy_from_slice(&self.reserved.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.payload[0].to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.payload[1].to_le_bytes());
+        bytes
+    }
+
+    /// Decode a record encoded by [`TraceRecord::to_bytes`].
+    pub fn from_bytes(bytes: &[u8; TRACE_RECORD_SIZE]) -> Self {
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        Self {
+            timestamp: u64_at(0),
+            cycles: u64_at(8),
+            vcpu_id: u32::from_le_bytes(bytes[16..20].try_into().unwrap()),
+            event: u16::from_le_bytes(bytes[20..22].try_into().unwrap()),
+            reserved: u16::from_le_bytes(bytes[22..24].try_into().unwrap()),
+            payload: [u64_at(24), u64_at(32)],
+        }
+    }
+}
+
+/// The receiver of the trace records of a vcpu, set with [`AxVCpu::set_trace_sink`](crate::AxVCpu::set_trace_sink).
+///
+/// Called on the hot path of [`AxVCpu::run`](crate::AxVCpu::run), so implementations should only copy the record,
+/// like [`TraceRing`] does.
+pub trait TraceSink: Send + Sync {
+    /// Record an event.
+    fn record(&self, record: &TraceRecord);
+}
+
+/// A [`TraceSink`] writing records into a caller-supplied buffer used as a ring, overwriting the oldest records
+/// when it's full, i.e., a flight recorder.
+///
+/// Slots are claimed atomically, so a ring may be shared by the vcpus of a VM. The records should be read (with
+/// [`TraceRing::for_each`]) only while no vcpu writes to the ring, otherwise records being overwritten may be
+/// read torn.
+pub struct TraceRing {
+    /// The buffer.
+    buf: *mut TraceRecord,
+    /// The number of records the buffer holds.
+    capacity: usize,
+    /// The number of records written so far.
+    written: AtomicU64,
+}
+
+// SAFETY: each slot is written by the single writer which claimed it.
+unsafe impl Send for TraceRing {}
+unsafe impl Sync for TraceRing {}
+
+impl TraceRing {
+    /// Create a ring writing into `buf`. A ring with an empty buffer drops all records.
+    pub fn new(buf: &'static mut [TraceRecord]) -> Self {
+        Self {
+            capacity: buf.len(),
+            buf: buf.as_mut_ptr(),
+            written: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the number of records the ring holds.
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get the number of records written so far, including the overwritten ones.
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Acquire)
+    }
+
+    /// Call `f` with the records in the ring, from the oldest to the newest.
+    pub fn for_each(&self, mut f: impl FnMut(&TraceRecord)) {
+        if self.capacity == 0 {
+            return;
+        }
+        let written = self.written();
+        let start = written.saturating_sub(self.capacity as u64);
+        for seq in start..written {
+            // SAFETY: the slot is within the buffer.
+            let record = unsafe {
+                self.buf
+                    .add((seq % self.capacity as u64) as usize)
+                    .read_volatile()
+            };
+            f(&record);
+        }
+    }
+}
+
+impl TraceSink for TraceRing {
+    fn record(&self, record: &TraceRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        let seq = self.written.fetch_add(1, Ordering::AcqRel);
+        // SAFETY: the slot is within the buffer, and claimed by this writer.
+        unsafe {
+            self.buf
+                .add((seq % self.capacity as u64) as usize)
+                .write_volatile(*record)
+        };
+    }
+}
+
+/// Decode a binary trace dumped as consecutive encoded [`TraceRecord`]s, ignoring a trailing partial record.
+#[cfg(feature = "std")]
+pub fn decode_trace(bytes: &[u8]) -> std::vec::Vec<TraceRecord> {
+    bytes
+        .chunks_exact(TRACE_RECORD_SIZE)
+        .map(|chunk| TraceRecord::from_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+/// Write decoded records in the Chrome trace event JSON format, which Perfetto and `chrome://tracing` import:
+/// one instant event per record, with the vcpu id as the thread id, and timestamps in microseconds.
+#[cfg(feature = "std")]
+pub fn write_chrome_trace(
+    records: &[TraceRecord],
+    w: &mut dyn std::io::Write,
+) -> std::io::Result<()> {
+    writeln!(w, "[")?;
+    for (i, record) in records.iter().enumerate() {
+        let name = record.event().map_or("unknown", TraceEvent::as_str);
+        write!(
+            w,
+            "{{\"name\":\"{}\",\"ph\":\"i\",\"s\":\"t\",\"pid\":0,\"tid\":{},\"ts\":{}.{:03},\"args\":{{\"cycles\":{},\"a0\":{},\"a1\":{}}}}}",
+            name,
+            record.vcpu_id,
+            record.timestamp / 1000,
+            record.timestamp % 1000,
+            record.cycles,
+            record.payload[0],
+            record.payload[1]
+        )?;
+        writeln!(w, "{}", if i + 1 < records.len() { "," } else { "" })?;
+    }
+    writeln!(w, "]")
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn record(timestamp: u64, event: TraceEvent) -> TraceRecord {
+        TraceRecord::new(timestamp, timestamp * 3, 2, event, [timestamp, u64::MAX])
+    }
+
+    fn trace_ring(capacity: usize) -> TraceRing {
+        TraceRing::new(Box::leak(
+            alloc::vec![TraceRecord::default(); capacity].into_boxed_slice(),
+        ))
+    }
+
+    #[test]
+    fn records_round_trip_little_endian() {
+        let record = record(0x0102_0304, TraceEvent::LostInterrupt);
+        let bytes = record.to_bytes();
+        assert_eq!(bytes[0..4], [0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(bytes[16..22], [2, 0, 0, 0, 4, 0]);
+        assert_eq!(TraceRecord::from_bytes(&bytes), record);
+        assert_eq!(record.event(), Some(TraceEvent::LostInterrupt));
+    }
+
+    #[test]
+    fn event_ids_are_stable() {
+        for id in 0..5 {
+            assert_eq!(TraceEvent::from_id(id).unwrap() as u16, id);
+        }
+        assert_eq!(TraceEvent::from_id(5), None);
+        let unknown = TraceRecord {
+            event: 5,
+            ..TraceRecord::default()
+        };
+        assert_eq!(unknown.event(), None);
+    }
+
+    #[test]
+    fn ring_keeps_the_newest_records() {
+        let ring = trace_ring(3);
+        for timestamp in 0..5 {
+            ring.record(&record(timestamp, TraceEvent::Entry));
+        }
+        assert_eq!(ring.written(), 5);
+        let mut timestamps = Vec::new();
+        ring.for_each(|record| timestamps.push(record.timestamp));
+        assert_eq!(timestamps, [2, 3, 4]);
+
+        let empty = trace_ring(0);
+        empty.record(&record(0, TraceEvent::Entry));
+        assert_eq!(empty.written(), 0);
+        empty.for_each(|_| panic!("an empty ring holds no record"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn dumps_decode_to_chrome_traces() {
+        let records = [
+            record(1_500, TraceEvent::Exit),
+            record(2_000, TraceEvent::Injection),
+        ];
+        let mut dump: Vec<u8> = records.iter().flat_map(TraceRecord::to_bytes).collect();
+        dump.push(0);
+        let decoded = decode_trace(&dump);
+        assert_eq!(decoded, records);
+
+        let mut json = Vec::new();
+        write_chrome_trace(&decoded, &mut json).unwrap();
+        let json = std::string::String::from_utf8(json).unwrap();
+        assert!(json.starts_with("[\n{\"name\":\"exit\","));
+        assert!(json.contains("\"tid\":2,\"ts\":1.500,"));
+        assert!(json.contains("\"name\":\"injection\""));
+        assert!(json.ends_with("}\n]\n"));
+    }
+}
@@ -0,0 +1,255 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axerrno::{AxResult, ax_err};
+
+use crate::{AxVCpuExitReason, ExitKind, VCpuRunPage};
+
+/// An exit forwarded to a remote handler by [`AxVCpu::forward_exit`](crate::AxVCpu::forward_exit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitMessage {
+    /// The id of the vcpu.
+    pub vcpu_id: usize,
+    /// The sequence number of the exit, echoed by the completion.
+    pub sequence: u64,
+    /// The kind of the exit.
+    pub kind: ExitKind,
+    /// The payload of the exit, encoded as in the run page (see [`VCpuRunPage::encode_payload`]).
+    pub payload: [u64; 8],
+}
+
+impl ExitMessage {
+    /// Create the message of an exit.
+    pub fn new(vcpu_id: usize, sequence: u64, exit: &AxVCpuExitReason) -> Self {
+        Self {
+            vcpu_id,
+            sequence,
+            kind: exit.kind(),
+            payload: VCpuRunPage::encode_payload(exit),
+        }
+    }
+}
+
+impl Default for ExitMessage {
+    fn default() -> Self {
+        Self {
+            vcpu_id: 0,
+            sequence: 0,
+            kind: ExitKind::Nothing,
+            payload: [0; 8],
+        }
+    }
+}
+
+/// The completion of an [`ExitMessage`], sent back by the remote handler.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExitCompletion {
+    /// The sequence number of the completed exit.
+    pub sequence: u64,
+    /// The value read by the guest, for exits reading a value into the guest (see [`VCpuRunPage`]), ignored for
+    /// others.
+    pub value: u64,
+}
+
+/// A channel forwarding exits to a remote handler, e.g., device emulation running on another core or in another
+/// protection domain, while the vcpu itself stays on the host side. See
+/// [`AxVCpu::forward_exit`](crate::AxVCpu::forward_exit).
+///
+/// [`RingExitTransport`] is the default in-memory implementation.
+pub trait ExitTransport {
+    /// Push an exit to the remote handler. Returns `ResourceBusy` if the channel is full.
+    fn push_exit(&self, msg: ExitMessage) -> AxResult;
+    /// Poll the next completion sent back by the remote handler, if any.
+    fn poll_completion(&self) -> Option<ExitCompletion>;
+}
+
+/// A single-producer single-consumer ring of `N` slots.
+struct SpscRing<T, const N: usize> {
+    /// The slots.
+    slots: [UnsafeCell<T>; N],
+    /// The index of the next slot to read modulo `2 * N`, only written by the consumer.
+    head: AtomicUsize,
+    /// The index of the next slot to write modulo `2 * N`, only written by the producer.
+    tail: AtomicUsize,
+}
+
+impl<T: Copy + Default, const N: usize> SpscRing<T, N> {
+    /// Create an empty ring. `N` must not be 0.
+    fn new() -> Self {
+        const { assert!(N > 0, "a ring needs at least one slot") };
+        Self {
+            slots: core::array::from_fn(|_| UnsafeCell::new(T::default())),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get the index following `index`. Indices run modulo `2 * N` rather than wrapping at `usize::MAX`, so that
+    /// `index % N` stays contiguous for any `N`, and a full ring is told apart from an empty one.
+    const fn next(index: usize) -> usize {
+        (index + 1) % (2 * N)
+    }
+
+    /// Push an item, returning `false` if the ring is full.
+    fn push(&self, item: T) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if (tail + 2 * N - self.head.load(Ordering::Acquire)) % (2 * N) >= N {
+            return false;
+        }
+        // SAFETY: the slot is not full, so the consumer doesn't read it until `tail` is published below.
+        unsafe { *self.slots[tail % N].get() = item };
+        self.tail.store(Self::next(tail), Ordering::Release);
+        true
+    }
+
+    /// Pop an item, if any.
+    fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // SAFETY: the slot is published by the producer, and it's not overwritten until `head` is published below.
+        let item = unsafe { *self.slots[head % N].get() };
+        self.head.store(Self::next(head), Ordering::Release);
+        Some(item)
+    }
+}
+
+/// The default [`ExitTransport`]: a pair of lock-free single-producer single-consumer rings of `N` entries in
+/// shared memory, one for exits and one for completions. `N` must not be 0.
+///
+/// Each ring has exactly one producer and one consumer, so a transport must be dedicated to one vcpu, with one
+/// remote handler calling [`RingExitTransport::pop_exit`] and [`RingExitTransport::push_completion`].
+pub struct RingExitTransport<const N: usize> {
+    /// The exits, pushed by the vcpu.
+    exits: SpscRing<ExitMessage, N>,
+    /// The completions, pushed by the remote handler.
+    completions: SpscRing<ExitCompletion, N>,
+}
+
+// SAFETY: every slot is accessed by at most one side at a time, as synchronized by the head and tail indices.
+unsafe impl<const N: usize> Sync for RingExitTransport<N> {}
+
+impl<const N: usize> Default for RingExitTransport<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> RingExitTransport<N> {
+    /// Create an empty transport.
+    pub fn new() -> Self {
+        Self {
+            exits: SpscRing::new(),
+            completions: SpscRing::new(),
+        }
+    }
+
+    /// Take the next exit, called by the remote handler.
+    pub fn pop_exit(&self) -> Option<ExitMessage> {
+        self.exits.pop()
+    }
+
+    /// Send back the completion of an exit, called by the remote handler, which should then notify the vcpu with
+    /// [`AxVCpuHal::notify_vcpu`](crate::AxVCpuHal::notify_vcpu). Returns `ResourceBusy` if the ring is full.
+    pub fn push_completion(&self, completion: ExitCompletion) -> AxResult {
+        if self.completions.push(completion) {
+            Ok(())
+        } else {
+            ax_err!(ResourceBusy, "completion ring is full")
+        }
+    }
+}
+
+impl<const N: usize> ExitTransport for RingExitTransport<N> {
+    fn push_exit(&self, msg: ExitMessage) -> AxResult {
+        if self.exits.push(msg) {
+            Ok(())
+        } else {
+            ax_err!(ResourceBusy, "exit ring is full")
+        }
+    }
+
+    fn poll_completion(&self) -> Option<ExitCompletion> {
+        self.completions.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axerrno::AxError;
+
+    use super::*;
+
+    fn message(sequence: u64) -> ExitMessage {
+        ExitMessage::new(0, sequence, &AxVCpuExitReason::Halt)
+    }
+
+    #[test]
+    fn rings_are_fifo_and_bounded() {
+        let transport = RingExitTransport::<4>::new();
+        assert_eq!(transport.pop_exit(), None);
+        for sequence in 0..4 {
+            transport.push_exit(message(sequence)).unwrap();
+        }
+        assert_eq!(transport.push_exit(message(4)), Err(AxError::ResourceBusy));
+        // Interleave pops and pushes so that the indices wrap around the slots.
+        for sequence in 4..10 {
+            assert_eq!(transport.pop_exit(), Some(message(sequence - 4)));
+            transport.push_exit(message(sequence)).unwrap();
+        }
+        for sequence in 6..10 {
+            assert_eq!(transport.pop_exit().unwrap().sequence, sequence);
+        }
+        assert_eq!(transport.pop_exit(), None);
+
+        let completion = ExitCompletion {
+            sequence: 3,
+            value: 0x42,
+        };
+        assert_eq!(transport.poll_completion(), None);
+        transport.push_completion(completion).unwrap();
+        assert_eq!(transport.poll_completion(), Some(completion));
+    }
+
+    #[test]
+    fn ring_indices_wrap() {
+        let ring = SpscRing::<u64, 3>::new();
+        for round in 0..4 {
+            for item in 0..3 {
+                assert!(ring.push(round * 3 + item));
+            }
+            assert!(!ring.push(0));
+            for item in 0..3 {
+                assert_eq!(ring.pop(), Some(round * 3 + item));
+            }
+            assert_eq!(ring.pop(), None);
+        }
+    }
+
+    #[test]
+    fn exits_cross_threads_in_order() {
+        const COUNT: u64 = 10_000;
+        let transport = RingExitTransport::<8>::new();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for sequence in 0..COUNT {
+                    while transport.push_exit(message(sequence)).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut expected = 0;
+            while expected < COUNT {
+                match transport.pop_exit() {
+                    Some(msg) => {
+                        assert_eq!(msg, message(expected));
+                        expected += 1;
+                    }
+                    None => std::thread::yield_now(),
+                }
+            }
+        });
+        assert_eq!(transport.pop_exit(), None);
+    }
+}
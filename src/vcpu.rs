@@ -1,20 +1,89 @@
-use core::cell::{RefCell, UnsafeCell};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::{Any, TypeId};
+use core::cell::{Cell, RefCell, UnsafeCell};
+use core::ops::{Range, RangeInclusive};
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
 
-use axaddrspace::{GuestPhysAddr, HostPhysAddr};
-use axerrno::{AxResult, ax_err};
+use axaddrspace::{GuestPhysAddr, GuestVirtAddr, HostPhysAddr, HostVirtAddr, MappingFlags};
+use axerrno::{AxError, AxResult, ax_err, ax_err_type};
 
-use super::{AxArchVCpu, AxVCpuExitReason};
+use super::{
+    AxArchVCpu, AxVCpuExitReason, AxVCpuHal, ExitAction, GuestRegionClassifier, HaltPollConfig,
+    HaltPollStats, InterceptConfig, RegionKind, SysRegTrapMode, VCpuCapabilities,
+};
+use crate::halt_poll::HaltPoll;
+use crate::history::ExitHistory;
+use crate::integrity::CodeIntegrity;
+use crate::introspect::IntrospectionWatch;
+use crate::irq_bitmap::{AtomicFaultSlot, AtomicIrqBitmap};
+use crate::load::LoadTracker;
+use crate::pvclock::write_steal_time;
+use crate::quota::CpuQuota;
+use crate::replay::ReplayHook;
+use crate::run_page::{CompletionTarget, completion_target, publish_exit, take_completion};
+use crate::snapshot::{SNAPSHOT_HEADER_SIZE, SNAPSHOT_SECTION_OVERHEAD, Writer};
+use crate::stats::VectorTable;
+use crate::{
+    AxVCpuBuilder, AxVCpuError, AxVCpuResult, AxVCpuSnapshot, AxVCpuStats, BreakpointManager,
+    CpuClass, DeviceJournal, ExecProfilingConfig, ExitBreakpointHandler, ExitCompletion,
+    ExitDispatcher, ExitDisposition, ExitFilter, ExitKind, ExitMessage, ExitPolicy, ExitStamp,
+    ExitTransport, ExtStateBuffer, FinalStatsReport, FirmwareConduit, FpuPolicy, GuestEndian,
+    GuestMemoryAccess, GuestMode, GuestSymbolResolver, HandlerStage, HostInfo, HwWatchpoint,
+    IRQ_BITMAP_VECTORS, IntrospectionVerdict, LoadHint, MemoryAttributePolicy, PvConsole,
+    SandboxConfig, SandboxViolation, SecureCallProxy, ShutdownReason, SnapshotHeader, StageTimer,
+    SymbolizedPc, SysRegFile, TraceEvent, TraceRecord, TraceSink, UnhandledMmioPolicy,
+    VCpuCreateContext, VCpuRunPage, VCpuTimeState, VCpuTopology, VectorStats,
+};
 
 /// The constant part of `AxVCpu`.
-struct AxVCpuInnerConst {
+pub(crate) struct AxVCpuInnerConst {
     /// The id of the vcpu.
-    id: usize,
+    pub(crate) id: usize,
+    /// The id of the VM this vcpu belongs to.
+    pub(crate) vm_id: usize,
     /// The id of the physical CPU who has the priority to run this vcpu.
-    favor_phys_cpu: usize,
+    pub(crate) favor_phys_cpu: usize,
     /// The set of physical CPUs who can run this vcpu.
     /// If `None`, the vcpu can run on any physical CPU.
     /// Refer to [CPU_SET](https://man7.org/linux/man-pages/man3/CPU_SET.3.html) in Linux.
-    phys_cpu_set: Option<usize>,
+    pub(crate) phys_cpu_set: Option<usize>,
+    /// The class of physical CPUs this vcpu should preferably run on.
+    /// If `None`, the vcpu has no preference.
+    pub(crate) prefer_class: Option<CpuClass>,
+    /// The negotiated number of guest (nested) page table levels, checked at setup.
+    pub(crate) guest_page_table_levels: Option<usize>,
+    /// The architectural id of this vcpu (APIC ID, MPIDR affinity, hartid).
+    pub(crate) arch_cpu_id: u64,
+    /// The position of this vcpu in the CPU topology of the VM.
+    pub(crate) topology: VCpuTopology,
+    /// The NUMA node the memory of this vcpu should preferably be allocated from.
+    pub(crate) numa_node: Option<usize>,
+    /// The execution mode the guest starts in.
+    pub(crate) guest_mode: GuestMode,
+    /// The byte order of the data accesses of the guest.
+    pub(crate) guest_endian: GuestEndian,
+    /// The size of the guest physical address space in bits, if configured.
+    pub(crate) guest_phys_bits: Option<u8>,
+    /// The default attributes of the stage-2 mappings.
+    pub(crate) memory_attribute_policy: MemoryAttributePolicy,
+}
+
+impl AxVCpuInnerConst {
+    /// Get the context passed to [`AxArchVCpu::new_with_context`].
+    pub(crate) const fn create_context(&self) -> VCpuCreateContext {
+        VCpuCreateContext {
+            vcpu_id: self.id,
+            arch_cpu_id: self.arch_cpu_id,
+            topology: self.topology,
+            numa_node: self.numa_node,
+            guest_mode: self.guest_mode,
+        }
+    }
 }
 
 /// The state of a virtual CPU.
@@ -30,14 +99,235 @@ pub enum VCpuState {
     Ready = 3,
     /// The vcpu is bound to a physical CPU and running.
     Running = 4,
-    /// The vcpu is blocked.
+    /// The vcpu is bound to a physical CPU and blocked, waiting for an interrupt.
+    ///
+    /// Entered from [`VCpuState::Running`] when the guest halts with no pending interrupt, and left to
+    /// [`VCpuState::Ready`] when an interrupt is injected (see [`AxVCpu::wake`]).
     Blocked = 5,
+    /// The vcpu is bound to a physical CPU but powered off by the guest, e.g., by a PSCI `CPU_OFF` call.
+    ///
+    /// Entered from [`VCpuState::Ready`] on [`AxVCpuExitReason::CpuDown`] (see [`AxVCpu::park`]), and left to
+    /// [`VCpuState::Ready`] when the guest brings the vcpu up again (see [`AxVCpu::unpark`]).
+    Parked = 6,
+}
+
+impl VCpuState {
+    /// All states, in the order of their discriminants.
+    pub const ALL: [VCpuState; 7] = [
+        VCpuState::Invalid,
+        VCpuState::Created,
+        VCpuState::Free,
+        VCpuState::Ready,
+        VCpuState::Running,
+        VCpuState::Blocked,
+        VCpuState::Parked,
+    ];
+
+    /// Get the graph of allowed state transitions, as a list of `(from, to)` edges.
+    ///
+    /// This is the single source of truth of the state machine of [`AxVCpu`]. Transitions to
+    /// [`VCpuState::Invalid`] are always allowed (on errors) and not listed.
+    pub const fn transition_table() -> &'static [(VCpuState, VCpuState)] {
+        &[
+            // `setup`
+            (VCpuState::Created, VCpuState::Free),
+            // `bind`
+            (VCpuState::Free, VCpuState::Ready),
+            // `unbind`
+            (VCpuState::Ready, VCpuState::Free),
+            // `run` entering the guest
+            (VCpuState::Ready, VCpuState::Running),
+            // `run` returning from the guest
+            (VCpuState::Running, VCpuState::Ready),
+            // `run` returning from the guest on a halt with no pending interrupt
+            (VCpuState::Running, VCpuState::Blocked),
+            // `wake`, generally on interrupt injection
+            (VCpuState::Blocked, VCpuState::Ready),
+            // `park`, generally on `CpuDown`
+            (VCpuState::Ready, VCpuState::Parked),
+            // `unpark`, generally on `CpuUp`
+            (VCpuState::Parked, VCpuState::Ready),
+        ]
+    }
+
+    /// Whether the transition from `from` to `to` is allowed, see [`VCpuState::transition_table`].
+    pub fn is_valid_transition(from: VCpuState, to: VCpuState) -> bool {
+        to == VCpuState::Invalid || Self::transition_table().contains(&(from, to))
+    }
+
+    /// Render the graph of allowed state transitions in the DOT language of Graphviz.
+    pub fn write_transition_dot(w: &mut dyn core::fmt::Write) -> core::fmt::Result {
+        writeln!(w, "digraph VCpuState {{")?;
+        for state in Self::ALL {
+            writeln!(w, "    {:?};", state)?;
+        }
+        for (from, to) in Self::transition_table() {
+            writeln!(w, "    {:?} -> {:?};", from, to)?;
+        }
+        writeln!(w, "}}")
+    }
+}
+
+/// The order in which the pending interrupts of a vcpu are injected, see [`AxVCpu::set_injection_order`].
+///
+/// Interrupts injected with [`AxVCpu::inject_interrupt`] are queued, and injected into the architecture-specific
+/// vcpu at the next VM entry. Interrupt controllers differ in their expectations about the delivery order, so it's
+/// selectable per vcpu.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InjectionOrder {
+    /// Strictly in the order the interrupts are queued. Vectors injected from interrupt context (see
+    /// [`AxVCpu::inject_interrupt_from_irq`]) are queued at the next safe point, in ascending order.
+    #[default]
+    Fifo,
+    /// By descending [`AxArchVCpu::interrupt_priority`], in the order they're queued among equal priorities.
+    /// Only meaningful for vcpus reporting [`VCpuCapabilities::INTERRUPT_PRIORITIES`], otherwise it's the same as
+    /// [`InjectionOrder::Fifo`].
+    Priority,
+    /// The order preferred by the architecture-specific vcpu, see [`AxArchVCpu::injection_order`].
+    ArchDefined,
+}
+
+/// The injection deadline of high-priority vectors of a vcpu, see [`AxVCpu::set_injection_deadline`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InjectionDeadline {
+    /// The first high-priority vector.
+    first_vector: usize,
+    /// The last high-priority vector.
+    last_vector: usize,
+    /// How long a high-priority vector may wait in the queue, in nanoseconds.
+    threshold_ns: u64,
+}
+
+impl InjectionDeadline {
+    /// Create a deadline of `threshold_ns` nanoseconds for the vectors in `vectors`.
+    pub fn new(vectors: RangeInclusive<usize>, threshold_ns: u64) -> Self {
+        Self {
+            first_vector: *vectors.start(),
+            last_vector: *vectors.end(),
+            threshold_ns,
+        }
+    }
+
+    /// How long a high-priority vector may wait in the queue, in nanoseconds.
+    pub const fn threshold_ns(&self) -> u64 {
+        self.threshold_ns
+    }
+
+    /// Whether the deadline applies to the given vector.
+    pub const fn applies_to(&self, vector: usize) -> bool {
+        self.first_vector <= vector && vector <= self.last_vector
+    }
+}
+
+/// A token proving that a vcpu is bound to a physical CPU, handed out by [`AxVCpu::bind`] and required by
+/// [`AxVCpu::run`] and [`AxVCpu::unbind`].
+///
+/// The token can't be cloned, so only the host context which bound the vcpu can run it. A token becomes stale
+/// once the vcpu is unbound.
+#[derive(Debug)]
+pub struct RunToken {
+    /// The id of the vcpu this token belongs to.
+    vcpu_id: usize,
+    /// The bind generation of the vcpu when this token was handed out.
+    generation: u64,
+}
+
+impl RunToken {
+    /// Get the id of the vcpu this token belongs to.
+    pub const fn vcpu_id(&self) -> usize {
+        self.vcpu_id
+    }
+}
+
+/// Clears the running flag of a vcpu when dropped.
+struct RunningGuard<'a>(&'a AtomicBool);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
 }
 
 /// The mutable part of [`AxVCpu`].
 pub struct AxVCpuInnerMut {
-    /// The state of the vcpu.
-    state: VCpuState,
+    /// The number of times the vcpu has been bound, used to detect stale [`RunToken`]s.
+    bind_generation: u64,
+    /// The exits reported from outside the vcpu, which are returned by [`AxVCpu::run`] before entering the guest.
+    pending_exits: VecDeque<AxVCpuExitReason>,
+    /// The offset (in nanoseconds) between the host clock and the guest clock.
+    time_offset_ns: u64,
+    /// Whether `time_offset_ns` has changed since it was last applied to the architecture-specific vcpu.
+    time_offset_dirty: bool,
+    /// The host time when the guest clock was paused, if it's paused.
+    time_paused_at_ns: Option<u64>,
+    /// The adaptive halt-polling state.
+    halt_poll: HaltPoll,
+    /// Whether the guest should access the architectural timer directly while the vcpu is bound.
+    timer_passthrough: bool,
+    /// The classifier used to fill [`AxVCpuExitReason::NestedPageFault::region_kind`].
+    region_classifier: Option<Arc<dyn GuestRegionClassifier>>,
+    /// What to do with MMIO accesses to addresses without a device, see [`AxVCpu::set_unhandled_mmio_policy`].
+    unhandled_mmio_policy: UnhandledMmioPolicy,
+    /// How [`AxVCpu::run_loop`] treats each kind of exit, see [`AxVCpu::set_exit_policy`].
+    exit_policy: ExitPolicy,
+    /// The interrupt controller glue used by the fast-path handlers.
+    #[cfg(any(feature = "x86-apic-fast", feature = "arm-gic-fast"))]
+    irqchip_glue: Option<Arc<dyn crate::IrqChipGlue>>,
+    /// Whether the vcpu is in real-time mode.
+    realtime: bool,
+    /// Whether non-essential host interrupts are masked on the favored physical CPU for the real-time mode.
+    host_irqs_isolated: bool,
+    /// The generation of decoded instruction caches, see [`AxVCpu::decode_generation`].
+    decode_generation: u64,
+    /// The steal time structure registered by the guest, see [`AxVCpu::register_steal_time_page`].
+    steal_time_page: Option<HostVirtAddr>,
+    /// The accumulated steal time in nanoseconds.
+    steal_time_ns: u64,
+    /// Whether the steal time structure should be updated before the next VM entry.
+    steal_time_dirty: bool,
+    /// Whether [`AxVCpuExitReason::ExternalInterrupt`] exits are handled inside [`AxVCpu::run`].
+    auto_handle_host_irqs: bool,
+    /// Whether [`AxVCpu::bind`] skips checking the current physical CPU against `phys_cpu_set`.
+    affinity_override: bool,
+    /// The policy of switching the FP/SIMD state, see [`AxVCpu::set_fpu_policy`].
+    fpu_policy: FpuPolicy,
+    /// Whether the FP/SIMD state of the guest has been restored since the last bind, under [`FpuPolicy::Lazy`].
+    fpu_loaded: bool,
+    /// The filter of exits to break on and its handler, see [`AxVCpu::set_exit_filter`].
+    exit_filter: Option<(ExitFilter, Arc<dyn ExitBreakpointHandler>)>,
+    /// The resolver of guest symbols, see [`AxVCpu::set_symbol_resolver`].
+    symbol_resolver: Option<Arc<dyn GuestSymbolResolver>>,
+    /// The waker registered by [`AxVCpu::poll_runnable`] while the vcpu is blocked.
+    waker: Option<Waker>,
+    /// The power state requested by the guest when the vcpu is parked, see [`AxVCpu::park`].
+    parked_state: Option<u64>,
+    /// How the final counters are reported when the vcpu is dropped.
+    final_stats_report: FinalStatsReport,
+    /// The run page registered by the VMM, see [`AxVCpu::register_run_page`].
+    run_page: Option<HostVirtAddr>,
+    /// Where the completion of the last published exit is stored.
+    run_page_completion: Option<CompletionTarget>,
+    /// The sequence number of the last exit forwarded by [`AxVCpu::forward_exit`].
+    forward_sequence: u64,
+    /// The guest physical address ranges watched for introspection, see [`AxVCpu::watch_guest_memory`].
+    introspection: IntrospectionWatch,
+    /// The access held by the last [`AxVCpuExitReason::Introspection`] exit, until it's completed.
+    pending_introspection: Option<(GuestPhysAddr, MappingFlags)>,
+    /// The built-in para-virtualized console handler, see [`AxVCpu::set_pv_console`].
+    pv_console: Option<Arc<PvConsole>>,
+    /// The immutable code regions and the pending violation, see [`AxVCpu::protect_code_region`].
+    integrity: CodeIntegrity,
+    /// The device access allowlist of the sandbox mode, if enabled, see [`AxVCpu::enable_sandbox`].
+    sandbox: Option<SandboxConfig>,
+    /// The access which terminated the sandboxed guest, if any.
+    sandbox_violation: Option<SandboxViolation>,
+    /// The journal of device accesses, if enabled, see [`AxVCpu::enable_journal`].
+    journal: Option<DeviceJournal>,
+    /// The asserted level-triggered interrupt lines, as `(asserted_at, reported)` keyed by their vectors, see
+    /// [`AxVCpu::assert_irq_line`].
+    irq_lines: BTreeMap<usize, (u64, bool)>,
+    /// For how long a level-triggered line may stay asserted before the interrupt is considered lost.
+    lost_irq_threshold_ns: Option<u64>,
 }
 
 /// A virtual CPU with architecture-independent interface.
@@ -55,6 +345,73 @@ pub struct AxVCpu<A: AxArchVCpu> {
     inner_const: AxVCpuInnerConst,
     /// The mutable part of the vcpu.
     inner_mut: RefCell<AxVCpuInnerMut>,
+    /// The state of the vcpu, as a [`VCpuState`] discriminant.
+    ///
+    /// Kept out of `inner_mut`, as are `bound_cpu` and `blocked_since`, so that it can be read from other host
+    /// contexts (e.g., by [`AxVCpuGroup::collect_states`](crate::AxVCpuGroup::collect_states)) while `inner_mut`
+    /// is borrowed by the running vcpu. It's still only written with `inner_mut` borrowed.
+    state: AtomicU8,
+    /// The physical CPU the vcpu is bound to, or `usize::MAX` if it's unbound.
+    bound_cpu: AtomicUsize,
+    /// The host time the vcpu entered [`VCpuState::Blocked`] at, or `u64::MAX` if it's not blocked.
+    blocked_since: AtomicU64,
+    /// The interrupt vectors waiting to be injected into the guest the next time the vcpu runs.
+    ///
+    /// Kept out of `inner_mut` so that it can be drained while the state transition of [`AxVCpu::run`] is in
+    /// progress, without moving (and reallocating) the queue.
+    ///
+    /// Each vector is queued with the host time it's queued at, for measuring the injection latency.
+    pending_irqs: RefCell<VecDeque<(usize, u64)>>,
+    /// The interrupt vectors injected from interrupt context by [`AxVCpu::inject_interrupt_from_irq`], merged into
+    /// `pending_irqs` at the next safe point.
+    irq_bitmap: AtomicIrqBitmap,
+    /// The IOMMU fault reported from interrupt context, see
+    /// [`AxVCpuGroup::report_iommu_fault`](crate::AxVCpuGroup::report_iommu_fault), moved to the queued exits at
+    /// the next safe point as the vectors of `irq_bitmap`.
+    iommu_fault: AtomicFaultSlot,
+    /// The work queued by [`AxVCpu::defer`], run right before the next VM entry.
+    deferred: RefCell<VecDeque<DeferredWork<A>>>,
+    /// The injection deadline of high-priority vectors, see [`AxVCpu::set_injection_deadline`].
+    injection_deadline: Cell<Option<InjectionDeadline>>,
+    /// The order in which the pending interrupts are injected.
+    injection_order: Cell<InjectionOrder>,
+    /// Whether [`AxVCpu::run`] is in progress, checked before anything else so that racing calls are rejected.
+    running: AtomicBool,
+    /// Whether the vcpu is requested to stop, see [`AxVCpu::request_stop`].
+    stop_requested: AtomicBool,
+    /// Whether the vcpu is kept outside guest mode, see [`AxVCpuGroup::quiesce`](crate::AxVCpuGroup::quiesce).
+    quiesced: AtomicBool,
+    /// The receiver of the binary trace records of the vcpu, see [`AxVCpu::set_trace_sink`].
+    trace_sink: RefCell<Option<Arc<dyn TraceSink>>>,
+    /// The last exits of the vcpu, see [`AxVCpu::exit_history`].
+    exit_history: RefCell<ExitHistory>,
+    /// The state shared with the record/replay session of the vcpu, if any, see [`ReplaySession`](crate::ReplaySession).
+    replay: RefCell<Option<ReplayHook>>,
+    /// Whether guest execution profiling is enabled, see [`AxVCpu::enable_exec_profiling`].
+    exec_profiling: Cell<bool>,
+    /// The execution mode of the guest, see [`AxVCpu::guest_mode`].
+    guest_mode: Cell<GuestMode>,
+    /// Whether [`AxVCpu::read_arch_vcpu`] is allowed while an operation on this vcpu is in progress.
+    reentrant_reads: Cell<bool>,
+    /// The counters of the vcpu, kept out of `inner_mut` so that they can be updated while the state transition of
+    /// [`AxVCpu::run`] is in progress.
+    stats: RefCell<AxVCpuStats>,
+    /// The per-vector counters of the vcpu, merged into `stats` when it's read.
+    vector_table: RefCell<VectorTable>,
+    /// The kind and the host time of the last exit returned by the architecture-specific vcpu, for attributing the
+    /// handling time to it.
+    last_exit: Cell<Option<(ExitKind, u64)>>,
+    /// The tracker of the load of the vcpu, see [`AxVCpu::load_hint`].
+    load: RefCell<LoadTracker>,
+    /// The CPU bandwidth cap of the vcpu, see [`AxVCpu::set_cpu_quota`].
+    quota: RefCell<CpuQuota>,
+    /// The typed scratch storage of [`AxVCpu::scratch`], each entry is a boxed `RefCell<T>` keyed by the type id
+    /// of `T`. Entries are never removed before the vcpu is dropped.
+    scratch: RefCell<BTreeMap<TypeId, Box<dyn Any + Send>>>,
+    /// The system registers shadowed by the VMM, see [`AxVCpu::sysregs`].
+    sysregs: RefCell<SysRegFile>,
+    /// The breakpoints and watchpoints set by the debugger.
+    breakpoints: RefCell<BreakpointManager>,
     /// The architecture-specific state of the vcpu.
     ///
     /// `UnsafeCell` is used to allow interior mutability. Note that `RefCell` or `Mutex` is not suitable here
@@ -64,37 +421,139 @@ pub struct AxVCpu<A: AxArchVCpu> {
 
 impl<A: AxArchVCpu> AxVCpu<A> {
     /// Create a new [`AxVCpu`].
+    ///
+    /// Use [`AxVCpuBuilder`] to configure more attributes of the vcpu.
     pub fn new(
         id: usize,
         favor_phys_cpu: usize,
         phys_cpu_set: Option<usize>,
         arch_config: A::CreateConfig,
     ) -> AxResult<Self> {
-        Ok(Self {
-            inner_const: AxVCpuInnerConst {
-                id,
-                favor_phys_cpu,
-                phys_cpu_set,
-            },
+        AxVCpuBuilder::new(id, arch_config)
+            .favor_phys_cpu(favor_phys_cpu)
+            .phys_cpu_set(phys_cpu_set)
+            .build()
+    }
+
+    /// Create a new [`AxVCpu`] with the given constant attributes, used by [`AxVCpuBuilder::build`].
+    pub(crate) fn new_with(
+        inner_const: AxVCpuInnerConst,
+        arch_config: A::CreateConfig,
+    ) -> AxResult<Self> {
+        let arch_vcpu = A::new_with_context(arch_config, &inner_const.create_context())?;
+        Ok(Self::from_arch(inner_const, arch_vcpu))
+    }
+
+    /// Create a new [`AxVCpu`] with the given constant attributes around an architecture-specific vcpu created with
+    /// [`AxVCpuInnerConst::create_context`].
+    pub(crate) fn from_arch(inner_const: AxVCpuInnerConst, arch_vcpu: A) -> Self {
+        let guest_mode = inner_const.guest_mode;
+        Self {
+            stats: RefCell::new(AxVCpuStats::new(inner_const.id)),
+            vector_table: RefCell::new(VectorTable::new()),
+            inner_const,
             inner_mut: RefCell::new(AxVCpuInnerMut {
-                state: VCpuState::Created,
+                bind_generation: 0,
+                pending_exits: VecDeque::with_capacity(PENDING_EXITS_CAPACITY),
+                time_offset_ns: 0,
+                time_offset_dirty: false,
+                time_paused_at_ns: None,
+                halt_poll: HaltPoll::default(),
+                timer_passthrough: false,
+                region_classifier: None,
+                unhandled_mmio_policy: UnhandledMmioPolicy::Surface,
+                exit_policy: ExitPolicy::new(),
+                #[cfg(any(feature = "x86-apic-fast", feature = "arm-gic-fast"))]
+                irqchip_glue: None,
+                realtime: false,
+                host_irqs_isolated: false,
+                decode_generation: 0,
+                steal_time_page: None,
+                steal_time_ns: 0,
+                steal_time_dirty: false,
+                auto_handle_host_irqs: false,
+                affinity_override: false,
+                fpu_policy: FpuPolicy::Eager,
+                fpu_loaded: false,
+                exit_filter: None,
+                symbol_resolver: None,
+                waker: None,
+                parked_state: None,
+                final_stats_report: FinalStatsReport::Off,
+                run_page: None,
+                run_page_completion: None,
+                forward_sequence: 0,
+                introspection: IntrospectionWatch::default(),
+                pending_introspection: None,
+                pv_console: None,
+                integrity: CodeIntegrity::default(),
+                sandbox: None,
+                sandbox_violation: None,
+                journal: None,
+                irq_lines: BTreeMap::new(),
+                lost_irq_threshold_ns: None,
             }),
-            arch_vcpu: UnsafeCell::new(A::new(arch_config)?),
-        })
+            pending_irqs: RefCell::new(VecDeque::with_capacity(PENDING_IRQS_CAPACITY)),
+            state: AtomicU8::new(VCpuState::Created as u8),
+            bound_cpu: AtomicUsize::new(usize::MAX),
+            blocked_since: AtomicU64::new(u64::MAX),
+            irq_bitmap: AtomicIrqBitmap::new(),
+            iommu_fault: AtomicFaultSlot::new(),
+            injection_deadline: Cell::new(None),
+            injection_order: Cell::new(InjectionOrder::Fifo),
+            deferred: RefCell::new(VecDeque::new()),
+            running: AtomicBool::new(false),
+            stop_requested: AtomicBool::new(false),
+            quiesced: AtomicBool::new(false),
+            reentrant_reads: Cell::new(false),
+            exec_profiling: Cell::new(false),
+            guest_mode: Cell::new(guest_mode),
+            exit_history: RefCell::new(ExitHistory::new()),
+            replay: RefCell::new(None),
+            trace_sink: RefCell::new(None),
+            last_exit: Cell::new(None),
+            load: RefCell::new(LoadTracker::default()),
+            quota: RefCell::new(CpuQuota::default()),
+            scratch: RefCell::new(BTreeMap::new()),
+            sysregs: RefCell::new(SysRegFile::new()),
+            breakpoints: RefCell::new(BreakpointManager::new()),
+            arch_vcpu: UnsafeCell::new(arch_vcpu),
+        }
     }
 
     /// Setup the vcpu.
+    ///
+    /// If the number of guest page table levels is negotiated (see [`AxVCpuBuilder::guest_page_table_levels`]),
+    /// it's checked against the one the architecture-specific vcpu is set up with
+    /// ([`AxVCpuError::PageTableLevelsMismatch`]).
     pub fn setup(
         &self,
         entry: GuestPhysAddr,
         ept_root: HostPhysAddr,
         arch_config: A::SetupConfig,
-    ) -> AxResult {
-        self.manipulate_arch_vcpu(VCpuState::Created, VCpuState::Free, |arch_vcpu| {
+    ) -> AxVCpuResult {
+        let expected_levels = self.inner_const.guest_page_table_levels;
+        let guest_mode = self.guest_mode();
+        let guest_endian = self.guest_endian();
+        let guest_phys_bits = self.guest_phys_bits();
+        let memory_attribute_policy = *self.memory_attribute_policy();
+        let location = Location::caller();
+        self.manipulate_arch_vcpu_at(location, VCpuState::Created, VCpuState::Free, |arch_vcpu| {
+            arch_vcpu.set_guest_mode(guest_mode)?;
+            arch_vcpu.set_guest_endian(guest_endian)?;
+            if let Some(bits) = guest_phys_bits {
+                arch_vcpu.set_guest_phys_bits(bits)?;
+            }
+            arch_vcpu.set_memory_attribute_policy(&memory_attribute_policy)?;
             arch_vcpu.set_entry(entry)?;
             arch_vcpu.set_ept_root(ept_root)?;
             arch_vcpu.setup(arch_config)?;
-            Ok(())
+            match (expected_levels, arch_vcpu.guest_page_table_levels()) {
+                (Some(expected), Some(actual)) if expected != actual => {
+                    Err(AxVCpuError::PageTableLevelsMismatch { expected, actual })
+                }
+                _ => Ok(()),
+            }
         })
     }
 
@@ -103,6 +562,39 @@ impl<A: AxArchVCpu> AxVCpu<A> {
         self.inner_const.id
     }
 
+    /// Get the id of the VM the vcpu belongs to.
+    pub const fn vm_id(&self) -> usize {
+        self.inner_const.vm_id
+    }
+
+    /// Get the architectural id of the vcpu, i.e., the APIC ID in x86, the MPIDR affinity in Aarch64, and the
+    /// hartid in RISC-V.
+    pub const fn arch_cpu_id(&self) -> u64 {
+        self.inner_const.arch_cpu_id
+    }
+
+    /// Get the position of the vcpu in the CPU topology of the VM.
+    pub const fn topology(&self) -> VCpuTopology {
+        self.inner_const.topology
+    }
+
+    /// Get the size of the guest physical address space in bits, if configured with
+    /// [`AxVCpuBuilder::guest_phys_bits`].
+    pub const fn guest_phys_bits(&self) -> Option<u8> {
+        self.inner_const.guest_phys_bits
+    }
+
+    /// Get the default attributes of the stage-2 mappings, configured with
+    /// [`AxVCpuBuilder::memory_attribute_policy`].
+    pub const fn memory_attribute_policy(&self) -> &MemoryAttributePolicy {
+        &self.inner_const.memory_attribute_policy
+    }
+
+    /// Get the NUMA node the memory of the vcpu is preferably allocated from, if known.
+    pub const fn numa_node(&self) -> Option<usize> {
+        self.inner_const.numa_node
+    }
+
     /// Get the id of the physical CPU who has the priority to run this vcpu.
     /// Currently unused.
     pub const fn favor_phys_cpu(&self) -> usize {
@@ -115,14 +607,22 @@ impl<A: AxArchVCpu> AxVCpu<A> {
         self.inner_const.phys_cpu_set
     }
 
+    /// Get the class of physical CPUs this vcpu should preferably run on.
+    /// If `None`, this vcpu has no preference.
+    pub const fn prefer_class(&self) -> Option<CpuClass> {
+        self.inner_const.prefer_class
+    }
+
     /// Get whether the vcpu is the BSP. We always assume the first vcpu (vcpu with id #0) is the BSP.
     pub const fn is_bsp(&self) -> bool {
         self.inner_const.id == 0
     }
 
     /// Get the state of the vcpu.
+    ///
+    /// The state may be read from any host context, even while the vcpu runs on another physical CPU.
     pub fn state(&self) -> VCpuState {
-        self.inner_mut.borrow().state
+        VCpuState::ALL[self.state.load(Ordering::Acquire) as usize]
     }
 
     /// Set the state of the vcpu.
@@ -130,161 +630,3417 @@ impl<A: AxArchVCpu> AxVCpu<A> {
     /// This method is unsafe because it may break the state transition model.
     /// Use it with caution.
     pub unsafe fn set_state(&self, state: VCpuState) {
-        self.inner_mut.borrow_mut().state = state;
+        let _inner_mut = self.inner_mut.borrow_mut();
+        self.store_state(state);
+    }
+
+    /// Store the state of the vcpu, which must be done with `inner_mut` borrowed.
+    fn store_state(&self, state: VCpuState) {
+        self.state.store(state as u8, Ordering::Release);
     }
 
     /// Execute a block with the state of the vcpu transitioned from `from` to `to`. If the current state is not `from`, return an error.
     ///
     /// The state will be set to [`VCpuState::Invalid`] if an error occurs (including the case that the current state is not `from`).
+    /// Errors of the block are reported to [`AxVCpuHal::on_fatal_vcpu_error`], unless they are transient
+    /// (`WouldBlock`). A state mismatch is a mistake of the caller rather than a failure of the vcpu, and is only
+    /// returned.
     ///
     /// The state will be set to `to` if the block is executed successfully.
-    pub fn with_state_transition<F, T>(&self, from: VCpuState, to: VCpuState, f: F) -> AxResult<T>
+    ///
+    /// The block runs without any borrow of the vcpu held, so it may call other methods of the vcpu, e.g.,
+    /// [`AxVCpu::inject_interrupt`] or [`AxVCpu::queue_exit`].
+    pub fn with_state_transition<F, T>(
+        &self,
+        from: VCpuState,
+        to: VCpuState,
+        f: F,
+    ) -> AxVCpuResult<T>
     where
         F: FnOnce() -> AxResult<T>,
     {
-        let mut inner_mut = self.inner_mut.borrow_mut();
-        if inner_mut.state != from {
-            inner_mut.state = VCpuState::Invalid;
-            ax_err!(
-                BadState,
-                format!("VCpu state is not {:?}, but {:?}", from, inner_mut.state)
-            )
+        self.transition_with(from, to, || Ok(f()?))
+    }
+
+    /// Execute a block with the state of the vcpu transitioned from `from` to `to`, see
+    /// [`AxVCpu::with_state_transition`], keeping the typed errors of the block.
+    fn transition_with<F, T>(&self, from: VCpuState, to: VCpuState, f: F) -> AxVCpuResult<T>
+    where
+        F: FnOnce() -> AxVCpuResult<T>,
+    {
+        Self::assert_valid_transition(from, to);
+        let inner_mut = self.inner_mut.borrow_mut();
+        let state = self.state();
+        if state != from {
+            self.store_state(VCpuState::Invalid);
+            drop(inner_mut);
+            vcpu_log!(State, Warn, vcpu = self.id(), expected:? = from, actual:? = state; "unexpected vcpu state");
+            bad_state(from, state)
         } else {
+            // The state is atomic, so `inner_mut` needn't stay borrowed while the block runs.
+            drop(inner_mut);
             let result = f();
-            inner_mut.state = if result.is_err() {
+            let state = if result.is_err() {
                 VCpuState::Invalid
             } else {
                 to
             };
+            {
+                let _inner_mut = self.inner_mut.borrow_mut();
+                self.store_state(state);
+            }
+            self.trace(TraceEvent::StateChange, [from as u64, state as u64]);
+            vcpu_log!(State, Trace, vcpu = self.id(), from:? = from, to:? = state; "vcpu state transition");
+            let fatal = result
+                .as_ref()
+                .err()
+                .filter(|&&err| AxError::from(err) != AxError::WouldBlock);
+            if let Some(err) = fatal {
+                self.report_fatal(err);
+            }
             result
         }
     }
 
+    /// Report an unrecoverable error of the vcpu to [`AxVCpuHal::on_fatal_vcpu_error`].
+    fn report_fatal(&self, err: &AxVCpuError) {
+        vcpu_log!(State, Error, vcpu = self.id(), vm = self.vm_id(), err:? = err; "fatal vcpu error");
+        A::Hal::on_fatal_vcpu_error(self.vm_id(), self.id(), err);
+    }
+
     /// Execute a block with the current vcpu set to `&self`.
-    pub fn with_current_cpu_set<F, T>(&self, f: F) -> T
+    ///
+    /// Nested vcpu operations are not allowed: if a current vcpu is already set on this physical CPU,
+    /// [`AxVCpuError::NestedOperation`] is returned without executing the block, carrying the vcpu already current
+    /// and the caller of the operation owning it. Handlers running inside the exit path may read the vcpu with
+    /// [`AxVCpu::read_arch_vcpu`] instead, if allowed by [`AxVCpu::set_reentrant_reads`].
+    #[track_caller]
+    pub fn with_current_cpu_set<F, T>(&self, f: F) -> AxVCpuResult<T>
     where
-        F: FnOnce() -> T,
+        F: FnOnce() -> AxResult<T>,
     {
-        if get_current_vcpu::<A>().is_some() {
-            panic!("Nested vcpu operation is not allowed!");
-        } else {
-            unsafe {
-                set_current_vcpu(self);
-            }
-            let result = f();
-            unsafe {
-                clear_current_vcpu::<A>();
-            }
-            result
+        self.with_current_cpu_set_at(Location::caller(), f)
+    }
+
+    /// Execute a block with the current vcpu set to `&self`, on behalf of the operation called at `location`.
+    fn with_current_cpu_set_at<F, T, E>(
+        &self,
+        location: &'static Location<'static>,
+        f: F,
+    ) -> AxVCpuResult<T>
+    where
+        F: FnOnce() -> Result<T, E>,
+        AxVCpuError: From<E>,
+    {
+        check_not_nested::<A>()?;
+        unsafe {
+            set_current_vcpu(self);
+            *CURRENT_VCPU_OWNER.current_ref_mut_raw() = Some(location);
+        }
+        let result = f();
+        unsafe {
+            *CURRENT_VCPU_OWNER.current_ref_mut_raw() = None;
+            clear_current_vcpu::<A>();
+        }
+        Ok(result?)
+    }
+
+    /// Allow (`true`) or forbid (`false`, the default) [`AxVCpu::read_arch_vcpu`] while an operation on this vcpu
+    /// is in progress, e.g., for exit handlers reading registers inside [`AxVCpu::run`].
+    pub fn set_reentrant_reads(&self, allow: bool) {
+        self.reentrant_reads.set(allow);
+    }
+
+    /// Read the architecture-specific vcpu with the current vcpu set to `&self`.
+    ///
+    /// If an operation on this vcpu is already in progress on this physical CPU (e.g., the caller is a handler
+    /// running inside the exit path), the read is allowed only if enabled by [`AxVCpu::set_reentrant_reads`], and
+    /// [`AxVCpuError::NestedOperation`] is returned otherwise, as for any other nested operation.
+    #[track_caller]
+    pub fn read_arch_vcpu<F, T>(&self, f: F) -> AxVCpuResult<T>
+    where
+        F: FnOnce(&A) -> T,
+    {
+        let reentrant = get_current_vcpu::<A>().is_some_and(|current| core::ptr::eq(current, self));
+        if reentrant && self.reentrant_reads.get() {
+            return Ok(f(self.arch_vcpu_mut()));
         }
+        self.with_current_cpu_set_at(Location::caller(), || {
+            Ok::<_, AxVCpuError>(f(self.arch_vcpu_mut()))
+        })
     }
 
     /// Execute an operation on the architecture-specific vcpu, with the state transitioned from `from` to `to` and the current vcpu set to `&self`.
     ///
     /// This method is a combination of [`AxVCpu::with_state_transition`] and [`AxVCpu::with_current_cpu_set`].
-    pub fn manipulate_arch_vcpu<F, T>(&self, from: VCpuState, to: VCpuState, f: F) -> AxResult<T>
+    #[track_caller]
+    pub fn manipulate_arch_vcpu<F, T>(
+        &self,
+        from: VCpuState,
+        to: VCpuState,
+        f: F,
+    ) -> AxVCpuResult<T>
     where
         F: FnOnce(&mut A) -> AxResult<T>,
     {
-        self.with_state_transition(from, to, || {
-            self.with_current_cpu_set(|| f(self.get_arch_vcpu()))
+        self.manipulate_arch_vcpu_at(Location::caller(), from, to, f)
+    }
+
+    /// Execute an operation on the architecture-specific vcpu on behalf of the operation called at `location`, see
+    /// [`AxVCpu::manipulate_arch_vcpu`], keeping the typed errors of the operation.
+    fn manipulate_arch_vcpu_at<F, T, E>(
+        &self,
+        location: &'static Location<'static>,
+        from: VCpuState,
+        to: VCpuState,
+        f: F,
+    ) -> AxVCpuResult<T>
+    where
+        F: FnOnce(&mut A) -> Result<T, E>,
+        AxVCpuError: From<E>,
+    {
+        self.transition_with(from, to, || {
+            self.with_current_cpu_set_at(location, || f(self.arch_vcpu_mut()))
         })
     }
 
+    /// Assert (in debug builds) that the transition from `from` to `to` is allowed by [`VCpuState::transition_table`].
+    pub fn assert_valid_transition(from: VCpuState, to: VCpuState) {
+        debug_assert!(
+            VCpuState::is_valid_transition(from, to),
+            "Invalid vcpu state transition from {:?} to {:?}",
+            from,
+            to
+        );
+    }
+
     /// Transition the state of the vcpu. If the current state is not `from`, return an error.
-    pub fn transition_state(&self, from: VCpuState, to: VCpuState) -> AxResult {
-        self.with_state_transition(from, to, || Ok(()))
+    pub fn transition_state(&self, from: VCpuState, to: VCpuState) -> AxVCpuResult {
+        self.transition_with(from, to, || Ok(()))
     }
 
     /// Get the architecture-specific vcpu.
+    ///
+    /// In debug builds, this panics if the guest is running in [`AxVCpu::run`] on another physical CPU, since the
+    /// returned reference would alias the one used by the running vcpu.
+    #[deprecated(
+        note = "the returned reference is unchecked, use `AxVCpu::with_arch` or `AxVCpu::read_arch_vcpu`"
+    )]
     #[allow(clippy::mut_from_ref)]
+    #[track_caller]
     pub fn get_arch_vcpu(&self) -> &mut A {
+        self.arch_vcpu_mut()
+    }
+
+    /// Get the architecture-specific vcpu, panicking in debug builds if the guest is running on another physical
+    /// CPU.
+    #[allow(clippy::mut_from_ref)]
+    #[track_caller]
+    pub(crate) fn arch_vcpu_mut(&self) -> &mut A {
+        self.debug_check_not_running_elsewhere("get_arch_vcpu");
         unsafe { &mut *self.arch_vcpu.get() }
     }
 
+    /// Execute `f` on the architecture-specific vcpu, with the current vcpu set to `&self`.
+    ///
+    /// Unlike [`AxVCpu::get_arch_vcpu`], the mutable reference is only handed out if no one else may hold one:
+    /// `BadState` is returned if the vcpu is invalid, [`AxVCpuError::ForeignCpu`] if it's bound to another
+    /// physical CPU, [`AxVCpuError::NestedOperation`] if another operation is in progress on this physical CPU,
+    /// and [`AxVCpuError::AlreadyRunning`] if a call to [`AxVCpu::run`] is in progress.
+    #[track_caller]
+    pub fn with_arch<F, T>(&self, f: F) -> AxVCpuResult<T>
+    where
+        F: FnOnce(&mut A) -> AxResult<T>,
+    {
+        let location = Location::caller();
+        let (state, bound_cpu) = (self.state(), self.bound_cpu());
+        if state == VCpuState::Invalid {
+            return Err(ax_err_type!(BadState, "vcpu is invalid").into());
+        }
+        let cpu_id = A::Hal::current_cpu_id();
+        if let Some(bound_cpu) = bound_cpu.filter(|&bound_cpu| bound_cpu != cpu_id) {
+            return Err(AxVCpuError::ForeignCpu { cpu_id, bound_cpu });
+        }
+        if state == VCpuState::Running || self.is_running() {
+            return Err(AxVCpuError::AlreadyRunning);
+        }
+        self.with_current_cpu_set_at(location, || f(self.arch_vcpu_mut()))
+    }
+
+    /// Check (in debug builds) that the guest is not running on a physical CPU other than the current one, where
+    /// `op` would race it.
+    #[track_caller]
+    fn debug_check_not_running_elsewhere(&self, op: &str) {
+        if !cfg!(debug_assertions) || !self.is_running() {
+            return;
+        }
+        let cpu_id = A::Hal::current_cpu_id();
+        let bound_cpu = self.bound_cpu();
+        debug_assert!(
+            bound_cpu.is_none_or(|bound_cpu| bound_cpu == cpu_id),
+            "{} called on CPU {} while vcpu {} of VM {} runs on CPU {:?}",
+            op,
+            cpu_id,
+            self.id(),
+            self.vm_id(),
+            bound_cpu
+        );
+    }
+
     /// Run the vcpu.
-    pub fn run(&self) -> AxResult<AxVCpuExitReason> {
+    ///
+    /// Interrupts queued by [`AxVCpu::inject_interrupt`] are injected before entering the guest.
+    ///
+    /// If there are exits queued by [`AxVCpu::queue_exit`], the first of them is returned without entering the guest.
+    ///
+    /// If the guest halts with no pending interrupt, the vcpu enters [`VCpuState::Blocked`] and the
+    /// [`AxVCpuExitReason::Halt`] is returned. Running a blocked vcpu returns `WouldBlock` without invalidating it,
+    /// the caller should wait with [`AxVCpu::block_until_interrupt`] first.
+    ///
+    /// If enabled by [`AxVCpu::set_auto_handle_host_irqs`], [`AxVCpuExitReason::ExternalInterrupt`] exits are
+    /// handled by the host and the guest is re-entered without returning.
+    ///
+    /// `token` must be the one handed out by the last [`AxVCpu::bind`]. If another call to this method is in
+    /// progress, [`AxVCpuError::AlreadyRunning`] is returned without touching the vcpu. The vcpu must be bound to
+    /// the current physical CPU: in all builds, running it unbound (e.g., if [`AxVCpu::bind`] was never called,
+    /// [`AxVCpuError::NotBound`]) or on another physical CPU than the one it's bound to
+    /// ([`AxVCpuError::ForeignCpu`]) returns the error without entering the guest. Like the other errors keeping
+    /// the guest out, e.g., [`AxVCpuError::Throttled`] or [`AxVCpuError::Quiesced`], these are mistakes of the
+    /// caller and are only returned: only failures leaving the vcpu [`VCpuState::Invalid`] are reported to
+    /// [`AxVCpuHal::on_fatal_vcpu_error`].
+    pub fn run(&self, token: &RunToken) -> AxVCpuResult<AxVCpuExitReason> {
+        if self
+            .running
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(AxVCpuError::AlreadyRunning);
+        }
+        let _guard = RunningGuard(&self.running);
+        let cpu_id = A::Hal::current_cpu_id();
+        match self.bound_cpu() {
+            Some(bound_cpu) if bound_cpu != cpu_id => {
+                return Err(AxVCpuError::ForeignCpu { cpu_id, bound_cpu });
+            }
+            Some(_) => {}
+            None => return Err(AxVCpuError::NotBound),
+        }
+        self.check_run_token(token)?;
+        let replaying = self.is_replaying();
+        if !replaying {
+            self.complete_run_page()?;
+        }
+
+        let mut exit = self.enter_guest()?;
+        loop {
+            self.check_sandbox(&exit)?;
+            if self.try_unhandled_mmio(&mut exit)? {
+                exit = self.enter_guest()?;
+                continue;
+            }
+            if !replaying {
+                self.check_exit_filter(&exit);
+            }
+            #[cfg(any(feature = "x86-apic-fast", feature = "arm-gic-fast"))]
+            if self.try_fast_path(&exit)? {
+                exit = self.enter_guest()?;
+                continue;
+            }
+            if matches!(exit, AxVCpuExitReason::FirstFpuUse) {
+                self.handle_first_fpu_use()?;
+                exit = self.enter_guest()?;
+                continue;
+            }
+            if self.try_pv_console(&exit) {
+                exit = self.enter_guest()?;
+                continue;
+            }
+            if matches!(exit, AxVCpuExitReason::ExternalInterrupt { .. })
+                && self.auto_handle_host_irqs()
+            {
+                #[cfg_attr(not(feature = "log"), allow(unused_variables))]
+                let irq = A::Hal::irq_fetch();
+                vcpu_log!(Exit, Trace, vcpu = self.id(), irq = irq; "host interrupt handled");
+                A::Hal::irq_hanlder();
+                exit = self.enter_guest()?;
+                continue;
+            }
+            if let AxVCpuExitReason::IntegrityViolation { addr, access } = exit {
+                vcpu_log!(Exit, Warn, vcpu = self.id(), addr:? = addr, access:? = access; "code integrity violation");
+                self.inner_mut.borrow_mut().integrity.violation = Some((addr, access));
+            }
+            if !replaying {
+                self.publish_run_page(&exit);
+            }
+            return Ok(exit);
+        }
+    }
+
+    /// Register the run page of the vcpu, shared with a monitor handling exits out of process, see
+    /// [`VCpuRunPage`]. `None` unregisters the page.
+    ///
+    /// # Safety
+    ///
+    /// `page` must point to a valid, writable [`VCpuRunPage`], and stay valid until it's unregistered or this vcpu
+    /// is dropped.
+    pub unsafe fn register_run_page(&self, page: Option<HostVirtAddr>) -> AxResult {
+        let align_mask = core::mem::align_of::<VCpuRunPage>() - 1;
+        if page.is_some_and(|page| page.as_usize() & align_mask != 0) {
+            return ax_err!(InvalidInput, "run page is not aligned");
+        }
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        inner_mut.run_page = page;
+        inner_mut.run_page_completion = None;
+        Ok(())
+    }
+
+    /// Forward an exit returned by [`AxVCpu::run`] to a remote handler through `transport`, and wait up to
+    /// `timeout_ns` nanoseconds for its completion (with [`AxVCpuHal::wait_for_notification`]).
+    ///
+    /// For exits reading a value into the guest, the value of the completion is stored into the target register,
+    /// as for the run page (see [`VCpuRunPage`]). Stale completions of earlier exits are discarded. Returns
+    /// `WouldBlock` if the completion doesn't arrive in time.
+    pub fn forward_exit(
+        &self,
+        transport: &dyn ExitTransport,
+        exit: &AxVCpuExitReason,
+        timeout_ns: u64,
+    ) -> AxResult<ExitCompletion> {
+        let sequence = {
+            let mut inner_mut = self.inner_mut.borrow_mut();
+            inner_mut.forward_sequence += 1;
+            inner_mut.forward_sequence
+        };
+        transport.push_exit(ExitMessage::new(self.id(), sequence, exit))?;
+        let start = A::Hal::current_time_nanos();
+        loop {
+            while let Some(completion) = transport.poll_completion() {
+                if completion.sequence != sequence {
+                    continue;
+                }
+                if let Some(target) = completion_target(exit, self.guest_endian()) {
+                    self.set_gpr(target.reg, target.value(completion.value))?;
+                }
+                return Ok(completion);
+            }
+            if A::Hal::current_time_nanos().saturating_sub(start) >= timeout_ns {
+                return ax_err!(WouldBlock, "forwarded exit is not completed in time");
+            }
+            A::Hal::wait_for_notification(self.id());
+        }
+    }
+
+    /// Publish an exit returned by [`AxVCpu::run`] in the run page, if any.
+    fn publish_run_page(&self, exit: &AxVCpuExitReason) {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        if let Some(journal) = &mut inner_mut.journal {
+            journal.begin_exit(exit);
+        }
+        if let Some(page) = inner_mut.run_page {
+            // SAFETY: `page` is guaranteed to be valid by the caller of `register_run_page`.
+            unsafe { publish_exit(page, exit) };
+            inner_mut.run_page_completion = completion_target(exit, self.guest_endian());
+        }
+    }
+
+    /// Store the completion of the last published exit written by the monitor to the run page, if any.
+    fn complete_run_page(&self) -> AxResult {
+        let (page, target) = {
+            let mut inner_mut = self.inner_mut.borrow_mut();
+            (inner_mut.run_page, inner_mut.run_page_completion.take())
+        };
+        let (Some(page), Some(target)) = (page, target) else {
+            return Ok(());
+        };
+        // SAFETY: `page` is guaranteed to be valid by the caller of `register_run_page`.
+        match unsafe { take_completion(page) } {
+            Some(value) => self.set_gpr(target.reg, target.value(value)),
+            None => Ok(()),
+        }
+    }
+
+    /// Enter the guest once, see [`AxVCpu::run`].
+    fn enter_guest(&self) -> AxVCpuResult<AxVCpuExitReason> {
+        self.merge_from_irq();
+        if self.stop_requested.load(Ordering::Acquire) {
+            return Err(AxVCpuError::Stopped);
+        }
+        if self.quiesced.load(Ordering::Acquire) {
+            return Err(AxVCpuError::Quiesced);
+        }
+        if self.inner_mut.borrow().integrity.violation.is_some() {
+            return Err(AxVCpuError::IntegrityViolation);
+        }
+        if let Some(violation) = self.inner_mut.borrow().sandbox_violation {
+            return Err(AxVCpuError::SandboxViolation(violation));
+        }
+        match self.state() {
+            VCpuState::Blocked => return Err(ax_err_type!(WouldBlock, "vcpu is blocked").into()),
+            VCpuState::Parked => return Err(ax_err_type!(WouldBlock, "vcpu is parked").into()),
+            _ => {}
+        }
+        let replaying = self.is_replaying();
+        let metered = !replaying && !self.is_realtime();
+        let pending_exit = match replaying {
+            true => None,
+            false => self.take_pending_exit()?,
+        };
+        if let Some(exit) = pending_exit {
+            if let Some(hook) = self.replay.borrow_mut().as_mut() {
+                hook.queued = true;
+            }
+            return Ok(exit);
+        }
+        if metered {
+            match self.quota.borrow_mut().check(A::Hal::current_time_nanos()) {
+                Ok(throttled_ns) => self.stats.borrow_mut().throttled_ns += throttled_ns,
+                Err(resume_in_ns) => return Err(AxVCpuError::Throttled { resume_in_ns }),
+            }
+        }
         self.transition_state(VCpuState::Ready, VCpuState::Running)?;
-        self.manipulate_arch_vcpu(VCpuState::Running, VCpuState::Ready, |arch_vcpu| {
-            arch_vcpu.run()
+        if !replaying {
+            self.check_lost_interrupts();
+        }
+        let time_offset = {
+            let mut inner_mut = self.inner_mut.borrow_mut();
+            if let (Some(page), true) = (
+                inner_mut.steal_time_page,
+                inner_mut.steal_time_dirty && !replaying,
+            ) {
+                inner_mut.steal_time_dirty = false;
+                // SAFETY: `page` is guaranteed to be valid by the caller of `register_steal_time_page`.
+                unsafe { write_steal_time(page, inner_mut.steal_time_ns, false) };
+            }
+            core::mem::take(&mut inner_mut.time_offset_dirty).then_some(inner_mut.time_offset_ns)
+        };
+        let exit = self.manipulate_arch_vcpu(VCpuState::Running, VCpuState::Ready, |arch_vcpu| {
+            if let Some(offset) = time_offset {
+                arch_vcpu.set_virtual_counter_offset(offset)?;
+            }
+            if replaying {
+                let interrupts = self
+                    .replay
+                    .borrow_mut()
+                    .as_mut()
+                    .map(|hook| core::mem::take(&mut hook.interrupts))
+                    .unwrap_or_default();
+                for vector in interrupts {
+                    arch_vcpu.inject_interrupt(vector)?;
+                }
+                let exit = arch_vcpu.run()?;
+                arch_vcpu.sync_hw_irq_state(&mut |_| {})?;
+                return Ok(exit);
+            }
+            let injection_start = A::Hal::current_time_nanos();
+            let by_priority = self.resolve_injection_order(arch_vcpu) == InjectionOrder::Priority;
+            while let Some((vector, queued_at)) = self.pop_pending_irq(arch_vcpu, by_priority) {
+                vcpu_log!(Injection, Trace, vcpu = self.id(), vector = vector; "interrupt injected");
+                arch_vcpu.inject_interrupt(vector)?;
+                if let Some(hook) = self.replay.borrow_mut().as_mut() {
+                    hook.interrupts.push(vector);
+                }
+                let latency = injection_start.saturating_sub(queued_at);
+                self.stats.borrow_mut().record_injection(latency);
+                self.count_vector(vector, |counters| counters.injected += 1);
+                self.trace(TraceEvent::Injection, [vector as u64, latency]);
+            }
+            loop {
+                // The queue must not be borrowed while the work runs, as it may defer more work.
+                let work = self.deferred.borrow_mut().pop_front();
+                let Some(work) = work else {
+                    break;
+                };
+                work(arch_vcpu)?;
+            }
+            let entry = A::Hal::current_time_nanos();
+            if let Some((kind, exit_time)) = self.last_exit.take() {
+                let mut stats = self.stats.borrow_mut();
+                stats.record_stage(kind, HandlerStage::Injection, entry.saturating_sub(injection_start));
+                stats.record_handling(kind, entry.saturating_sub(exit_time));
+            }
+            self.trace(TraceEvent::Entry, [0, 0]);
+            let exit = arch_vcpu.run()?;
+            let exit_cycles = A::Hal::read_cycle_counter();
+            arch_vcpu.sync_hw_irq_state(&mut |vector| self.requeue_interrupt(vector))?;
+            let exit_time = A::Hal::current_time_nanos();
+            self.exit_history.borrow_mut().record(ExitStamp {
+                kind: exit.kind(),
+                time_ns: exit_time,
+                cycles: exit_cycles,
+            });
+            let guest_ns = exit_time.saturating_sub(entry);
+            if metered {
+                self.quota.borrow_mut().charge(guest_ns);
+            }
+            self.stats.borrow_mut().record_exit(exit.kind(), guest_ns);
+            if self.exec_profiling.get() {
+                let counters = arch_vcpu.read_exec_counters();
+                self.stats.borrow_mut().record_exec(counters);
+            }
+            self.trace_at(
+                exit_time,
+                exit_cycles,
+                TraceEvent::Exit,
+                [
+                    exit.kind().id() as u64,
+                    VCpuRunPage::encode_payload(&exit)[0],
+                ],
+            );
+            self.last_exit.set(Some((exit.kind(), exit_time)));
+            self.load.borrow_mut().record_exit(exit_time);
+            vcpu_log!(Exit, Debug, vcpu = self.id(), reason:? = exit; "vm-exit");
+            Ok(exit)
         })
+        .map(|mut exit| {
+            match &mut exit {
+                AxVCpuExitReason::Halt
+                    if !replaying
+                        && self.pending_interrupts() == 0
+                        && self.inner_mut.borrow().pending_exits.is_empty() =>
+                {
+                    Self::assert_valid_transition(VCpuState::Running, VCpuState::Blocked);
+                    let now = A::Hal::current_time_nanos();
+                    {
+                        let _inner_mut = self.inner_mut.borrow_mut();
+                        self.blocked_since.store(now, Ordering::Release);
+                        self.store_state(VCpuState::Blocked);
+                    }
+                    self.trace(
+                        TraceEvent::StateChange,
+                        [VCpuState::Running as u64, VCpuState::Blocked as u64],
+                    );
+                    self.load.borrow_mut().record_block(now);
+                    vcpu_log!(State, Trace, vcpu = self.id(), from:? = VCpuState::Running, to:? = VCpuState::Blocked; "vcpu state transition");
+                }
+                AxVCpuExitReason::MmioWrite { width, data, .. } => {
+                    *data = self.guest_endian().swap_mmio(*data, *width);
+                }
+                AxVCpuExitReason::NestedPageFault {
+                    addr,
+                    access_flags,
+                    region_kind,
+                } => {
+                    let mut inner_mut = self.inner_mut.borrow_mut();
+                    if access_flags.contains(MappingFlags::WRITE)
+                        && inner_mut.integrity.is_protected(*addr)
+                    {
+                        return AxVCpuExitReason::IntegrityViolation {
+                            addr: *addr,
+                            access: *access_flags,
+                        };
+                    }
+                    if inner_mut.introspection.matches(*addr, *access_flags) {
+                        inner_mut.pending_introspection = Some((*addr, *access_flags));
+                        return AxVCpuExitReason::Introspection {
+                            addr: *addr,
+                            access: *access_flags,
+                        };
+                    }
+                    if let (Some(classifier), RegionKind::Unknown) =
+                        (&inner_mut.region_classifier, *region_kind)
+                    {
+                        *region_kind = classifier.classify(*addr, *access_flags);
+                    }
+                }
+                _ => {}
+            }
+            exit
+        })?;
+        Ok(exit)
     }
 
-    /// Bind the vcpu to the current physical CPU.
-    pub fn bind(&self) -> AxResult {
-        self.manipulate_arch_vcpu(VCpuState::Free, VCpuState::Ready, |arch_vcpu| {
-            arch_vcpu.bind()
-        })
+    /// Set the receiver of the binary trace records of the vcpu (entries, exits, injections, and state
+    /// transitions), e.g., a [`TraceRing`](crate::TraceRing). `None` disables tracing.
+    pub fn set_trace_sink(&self, sink: Option<Arc<dyn TraceSink>>) {
+        *self.trace_sink.borrow_mut() = sink;
     }
 
-    /// Unbind the vcpu from the current physical CPU.
-    pub fn unbind(&self) -> AxResult {
-        self.manipulate_arch_vcpu(VCpuState::Ready, VCpuState::Free, |arch_vcpu| {
-            arch_vcpu.unbind()
-        })
+    /// Emit a trace record to the trace sink, if any.
+    fn trace(&self, event: TraceEvent, payload: [u64; 2]) {
+        if self.trace_sink.borrow().is_some() {
+            self.trace_at(
+                A::Hal::current_time_nanos(),
+                A::Hal::read_cycle_counter(),
+                event,
+                payload,
+            );
+        }
     }
 
-    /// Sets the entry address of the vcpu.
-    pub fn set_entry(&self, entry: GuestPhysAddr) -> AxResult {
-        self.get_arch_vcpu().set_entry(entry)
+    /// Emit a trace record of an event which happened at the given host time and cycle counter.
+    fn trace_at(&self, time_ns: u64, cycles: u64, event: TraceEvent, payload: [u64; 2]) {
+        if let Some(sink) = &*self.trace_sink.borrow() {
+            sink.record(&TraceRecord::new(
+                time_ns,
+                cycles,
+                self.id(),
+                event,
+                payload,
+            ));
+        }
     }
 
-    /// Sets the value of a general-purpose register according to the given index.
-    pub fn set_gpr(&self, reg: usize, val: usize) {
-        self.get_arch_vcpu().set_gpr(reg, val);
+    /// Get the last [`EXIT_HISTORY_LEN`](crate::EXIT_HISTORY_LEN) exits of the vcpu, from the oldest to the
+    /// newest, each timestamped with the host clock and the raw cycle counter, e.g., for analyzing exit storms.
+    pub fn exit_history(&self) -> Vec<ExitStamp> {
+        self.exit_history.borrow().to_vec()
     }
-}
 
-#[percpu::def_percpu]
-static mut CURRENT_VCPU: Option<*mut u8> = None;
+    /// Get the state shared with the record/replay session of the vcpu, `None` if there is no session.
+    pub(crate) fn replay_hook(&self) -> &RefCell<Option<ReplayHook>> {
+        &self.replay
+    }
 
-/// Get the current vcpu on the current physical CPU.
-///
-/// It's guaranteed that each time before a method of [`AxArchVCpu`] is called, the current vcpu is set to the corresponding [`AxVCpu`].
-/// So methods of [`AxArchVCpu`] can always get the [`AxVCpu`] containing itself by calling this method.
-pub fn get_current_vcpu<'a, A: AxArchVCpu>() -> Option<&'a AxVCpu<A>> {
-    unsafe {
-        CURRENT_VCPU
-            .current_ref_raw()
+    /// Whether a record/replay session is replaying recorded exits, see [`ReplayHook`].
+    fn is_replaying(&self) -> bool {
+        self.replay
+            .borrow()
             .as_ref()
-            .copied()
-            .and_then(|p| (p as *const AxVCpu<A>).as_ref())
+            .is_some_and(|hook| hook.replaying)
     }
-}
 
-/// Get a mutable reference to the current vcpu on the current physical CPU.
-///
-/// See [`get_current_vcpu`] for more details.
-pub fn get_current_vcpu_mut<'a, A: AxArchVCpu>() -> Option<&'a mut AxVCpu<A>> {
-    unsafe {
-        CURRENT_VCPU
-            .current_ref_mut_raw()
-            .as_mut()
-            .copied()
-            .and_then(|p| (p as *mut AxVCpu<A>).as_mut())
+    /// Invoke the breakpoint handler if `exit` matches the exit filter.
+    fn check_exit_filter(&self, exit: &AxVCpuExitReason) {
+        let handler = match &self.inner_mut.borrow().exit_filter {
+            Some((filter, handler)) if filter.matches(exit) => handler.clone(),
+            _ => return,
+        };
+        handler.on_exit_break(self.id(), exit);
     }
-}
 
-/// Set the current vcpu on the current physical CPU.
-///
-/// # Safety
-/// This method is marked as unsafe because it may result in unexpected behavior if not used properly.
-/// Do not call this method unless you know what you are doing.
-pub unsafe fn set_current_vcpu<A: AxArchVCpu>(vcpu: &AxVCpu<A>) {
-    unsafe {
-        CURRENT_VCPU
-            .current_ref_mut_raw()
-            .replace(vcpu as *const _ as *mut u8);
+    /// Enable (`true`, starting an empty journal) or disable (`false`, discarding it) the journal of the device
+    /// accesses performed while handling the exits returned by [`AxVCpu::run`], see [`DeviceJournal`].
+    pub fn enable_journal(&self, enable: bool) {
+        self.inner_mut.borrow_mut().journal = enable.then(DeviceJournal::new);
     }
-}
 
-/// Clear the current vcpu on the current physical CPU.
-///
-/// # Safety
-/// This method is marked as unsafe because it may result in unexpected behavior if not used properly.
-/// Do not call this method unless you know what you are doing.    
-pub unsafe fn clear_current_vcpu<A: AxArchVCpu>() {
-    unsafe {
-        CURRENT_VCPU.current_ref_mut_raw().take();
+    /// Record, in the journal of the vcpu, an access to `device` performed while handling the last exit returned
+    /// by [`AxVCpu::run`], resulting in the state mutation `mutation` (see [`JournalEntry`](crate::JournalEntry)).
+    ///
+    /// Device models may call this method unconditionally, it does nothing if the journal is disabled.
+    pub fn journal_device_access(&self, device: u32, mutation: u64) -> AxResult {
+        match &mut self.inner_mut.borrow_mut().journal {
+            Some(journal) => journal.record(device, mutation),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the digest of the journal (see [`DeviceJournal::digest`]), or `None` if the journal is disabled.
+    pub fn journal_digest(&self) -> Option<u64> {
+        self.inner_mut
+            .borrow()
+            .journal
+            .as_ref()
+            .map(DeviceJournal::digest)
+    }
+
+    /// Take the journal recorded so far, leaving an empty one which continues the exit sequence, or `None` if the
+    /// journal is disabled.
+    pub fn take_journal(&self) -> Option<DeviceJournal> {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        Some(inner_mut.journal.as_mut()?.take_entries())
+    }
+
+    /// Set the built-in handler of the para-virtualized console hypercall, see [`PvConsole`]. `None` removes it.
+    ///
+    /// Calls to the console are handled inside [`AxVCpu::run`] and never returned to the VMM.
+    pub fn set_pv_console(&self, console: Option<PvConsole>) {
+        self.inner_mut.borrow_mut().pv_console = console.map(Arc::new);
+    }
+
+    /// Handle the exit with the para-virtualized console handler, returns whether the exit is handled.
+    fn try_pv_console(&self, exit: &AxVCpuExitReason) -> bool {
+        if !matches!(exit, AxVCpuExitReason::Hypercall { .. }) {
+            return false;
+        }
+        let Some(console) = self.inner_mut.borrow().pv_console.clone() else {
+            return false;
+        };
+        console.handle::<A::Hal>(exit, !self.is_replaying())
+    }
+
+    /// Apply the [`UnhandledMmioPolicy`] to an MMIO exit accessing an address without a device, turning it into
+    /// an [`AxVCpuExitReason::UnhandledMmio`] exit or handling it. Returns whether the exit is handled.
+    ///
+    /// Reads as zero are written with [`AxVCpu::set_gpr`], so they fail for protected guests.
+    fn try_unhandled_mmio(&self, exit: &mut AxVCpuExitReason) -> AxVCpuResult<bool> {
+        let (addr, width, read_reg) = match *exit {
+            AxVCpuExitReason::MmioRead {
+                addr, width, reg, ..
+            } => (addr, width, Some(reg)),
+            AxVCpuExitReason::MmioWrite { addr, width, .. } => (addr, width, None),
+            _ => return Ok(false),
+        };
+        let is_write = read_reg.is_none();
+        let policy = {
+            let inner_mut = self.inner_mut.borrow();
+            let Some(classifier) = &inner_mut.region_classifier else {
+                return Ok(false);
+            };
+            let access = if is_write {
+                MappingFlags::WRITE
+            } else {
+                MappingFlags::READ
+            };
+            if classifier.classify(addr, access) != RegionKind::Unmapped {
+                return Ok(false);
+            }
+            inner_mut.unhandled_mmio_policy
+        };
+        vcpu_log!(Exit, Debug, vcpu = self.id(), addr:? = addr, is_write = is_write, policy:? = policy; "unhandled MMIO access");
+        match policy {
+            UnhandledMmioPolicy::Surface => {
+                *exit = AxVCpuExitReason::UnhandledMmio {
+                    addr,
+                    width,
+                    is_write,
+                };
+                Ok(false)
+            }
+            UnhandledMmioPolicy::RazWi => {
+                if let Some(reg) = read_reg {
+                    self.with_current_cpu_set(|| self.set_gpr(reg, 0))?;
+                }
+                Ok(true)
+            }
+            UnhandledMmioPolicy::InjectAbort => {
+                self.with_current_cpu_set(|| {
+                    self.arch_vcpu_mut().inject_data_abort(addr, is_write)
+                })?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Handle a [`AxVCpuExitReason::FirstFpuUse`] exit: restore the FP/SIMD state of the guest and stop trapping.
+    fn handle_first_fpu_use(&self) -> AxResult {
+        let arch_vcpu = self.arch_vcpu_mut();
+        arch_vcpu.restore_fpu_state()?;
+        arch_vcpu.set_fpu_trap(false)?;
+        self.inner_mut.borrow_mut().fpu_loaded = true;
+        self.stats.borrow_mut().fpu_lazy_restores += 1;
+        Ok(())
+    }
+
+    /// Handle the exit with the fast-path handlers, returns whether the exit is handled.
+    ///
+    /// Acknowledged vectors are written with [`AxVCpu::set_gpr`], so they fail for protected guests.
+    #[cfg(any(feature = "x86-apic-fast", feature = "arm-gic-fast"))]
+    fn try_fast_path(&self, exit: &AxVCpuExitReason) -> AxVCpuResult<bool> {
+        let Some(glue) = self.inner_mut.borrow().irqchip_glue.clone() else {
+            return Ok(false);
+        };
+        let Some(op) = crate::fastpath::classify(exit) else {
+            return Ok(false);
+        };
+        match op {
+            crate::fastpath::FastPathOp::Eoi(value) => glue.eoi(self.id(), value)?,
+            crate::fastpath::FastPathOp::SendIpi(value) => glue.send_ipi(self.id(), value)?,
+            crate::fastpath::FastPathOp::Acknowledge { reg } => {
+                let value = glue.acknowledge(self.id())?;
+                self.with_current_cpu_set(|| self.set_gpr(reg, value as usize))?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Run the vcpu repeatedly, handling each exit with `handler`, until the handler returns an action other than
+    /// [`ExitAction::Continue`].
+    ///
+    /// Each exit is first checked against the [`ExitPolicy`] of the vcpu (see [`AxVCpu::set_exit_policy`]): exits
+    /// to forward to the VMM are returned in [`ExitAction::Forward`], and denied
+    /// exits are reported to [`AxVCpuHal::on_fatal_vcpu_error`] and return [`ExitAction::Shutdown`], without
+    /// calling the handler.
+    ///
+    /// The returned action is left to the caller to carry out, see [`ExitAction`] for the contract. Errors
+    /// returned by [`AxVCpu::run`] or the handler are propagated immediately.
+    pub fn run_loop<F>(&self, token: &RunToken, mut handler: F) -> AxVCpuResult<ExitAction>
+    where
+        F: FnMut(&Self, AxVCpuExitReason) -> AxResult<ExitAction>,
+    {
+        loop {
+            let exit = self.run(token)?;
+            let kind = exit.kind();
+            match self.exit_policy().disposition(kind) {
+                ExitDisposition::HandleInKernel => {}
+                ExitDisposition::ForwardToVmm => return Ok(ExitAction::Forward { exit }),
+                ExitDisposition::Deny => {
+                    vcpu_log!(Exit, Warn, vcpu = self.id(), kind = kind.as_str(); "exit denied by the exit policy");
+                    self.report_fatal(&AxVCpuError::ExitDenied { kind });
+                    return Ok(ExitAction::Shutdown {
+                        reason: ShutdownReason::ExitDenied { kind },
+                    });
+                }
+            }
+            match handler(self, exit)? {
+                ExitAction::Continue => continue,
+                action => return Ok(action),
+            }
+        }
+    }
+
+    /// Run the vcpu repeatedly, handling each exit with the middleware chain of `dispatcher`, until a middleware
+    /// returns an action other than [`ExitAction::Continue`]. See [`AxVCpu::run_loop`].
+    pub fn run_dispatch(
+        &self,
+        token: &RunToken,
+        dispatcher: &mut ExitDispatcher<A>,
+    ) -> AxVCpuResult<ExitAction> {
+        self.run_loop(token, |vcpu, exit| dispatcher.dispatch(vcpu, &exit))
+    }
+
+    /// Bind the vcpu to the current physical CPU.
+    ///
+    /// If timer passthrough is enabled (see [`AxVCpu::set_timer_passthrough`]), the timer of the current physical
+    /// CPU is granted to the guest.
+    ///
+    /// The host is notified with [`AxVCpuHal::on_vcpu_bind`] once the vcpu is bound.
+    ///
+    /// If the current physical CPU is not in the [`AxVCpu::phys_cpu_set`] of the vcpu,
+    /// [`AxVCpuError::AffinityViolation`] is returned, unless the check is overridden by
+    /// [`AxVCpu::set_affinity_override`].
+    ///
+    /// Returns the [`RunToken`] required to run and unbind the vcpu.
+    pub fn bind(&self) -> AxVCpuResult<RunToken> {
+        let cpu_id = A::Hal::current_cpu_id();
+        if let Some(allowed) = self.phys_cpu_set() {
+            let in_set = cpu_id < usize::BITS as usize && allowed & (1 << cpu_id) != 0;
+            if !in_set && !self.inner_mut.borrow().affinity_override {
+                return Err(AxVCpuError::AffinityViolation { cpu_id, allowed });
+            }
+        }
+        let timer_passthrough = self.timer_passthrough();
+        let lazy_fpu = self.fpu_policy() == FpuPolicy::Lazy;
+        self.manipulate_arch_vcpu(VCpuState::Free, VCpuState::Ready, |arch_vcpu| {
+            arch_vcpu.bind()?;
+            if arch_vcpu.capabilities().has_security_state() {
+                arch_vcpu.restore_security_state()?;
+            }
+            if timer_passthrough {
+                arch_vcpu.set_timer_passthrough(true)?;
+            }
+            if lazy_fpu {
+                arch_vcpu.set_fpu_trap(true)?;
+            }
+            Ok(())
+        })?;
+        self.inner_mut.borrow_mut().fpu_loaded = false;
+        A::Hal::on_vcpu_bind(cpu_id, self.vm_id(), self.id());
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        self.bound_cpu.store(cpu_id, Ordering::Release);
+        inner_mut.bind_generation += 1;
+        Ok(RunToken {
+            vcpu_id: self.id(),
+            generation: inner_mut.bind_generation,
+        })
+    }
+
+    /// Check that `token` is the one handed out by the last [`AxVCpu::bind`].
+    fn check_run_token(&self, token: &RunToken) -> AxResult {
+        if token.vcpu_id != self.id() || token.generation != self.inner_mut.borrow().bind_generation
+        {
+            ax_err!(BadState, "stale or foreign run token")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Unbind the vcpu from the current physical CPU.
+    ///
+    /// If timer passthrough is enabled, the timer of the current physical CPU is reclaimed from the guest, so that
+    /// the vcpu can be migrated to another physical CPU.
+    ///
+    /// The host is notified with [`AxVCpuHal::on_vcpu_unbind`] once the vcpu is unbound.
+    ///
+    /// `token` must be the one handed out by the last [`AxVCpu::bind`], and becomes stale afterwards.
+    pub fn unbind(&self, token: RunToken) -> AxVCpuResult {
+        self.check_run_token(&token)?;
+        let timer_passthrough = self.timer_passthrough();
+        let (lazy_fpu, fpu_loaded) = {
+            let inner_mut = self.inner_mut.borrow();
+            (
+                inner_mut.fpu_policy == FpuPolicy::Lazy,
+                inner_mut.fpu_loaded,
+            )
+        };
+        if lazy_fpu && !fpu_loaded {
+            self.stats.borrow_mut().fpu_lazy_skips += 1;
+        }
+        self.manipulate_arch_vcpu(VCpuState::Ready, VCpuState::Free, |arch_vcpu| {
+            arch_vcpu.sync_hw_irq_state(&mut |vector| self.requeue_interrupt(vector))?;
+            if timer_passthrough {
+                arch_vcpu.set_timer_passthrough(false)?;
+            }
+            if fpu_loaded {
+                arch_vcpu.save_fpu_state()?;
+            }
+            if arch_vcpu.capabilities().has_security_state() {
+                arch_vcpu.save_security_state()?;
+            }
+            arch_vcpu.unbind()
+        })?;
+        {
+            let mut inner_mut = self.inner_mut.borrow_mut();
+            if let Some(page) = inner_mut.steal_time_page {
+                // SAFETY: `page` is guaranteed to be valid by the caller of `register_steal_time_page`.
+                unsafe { write_steal_time(page, inner_mut.steal_time_ns, true) };
+                inner_mut.steal_time_dirty = true;
+            }
+        }
+        let cpu_id = {
+            let _inner_mut = self.inner_mut.borrow_mut();
+            Some(self.bound_cpu.swap(usize::MAX, Ordering::AcqRel))
+                .filter(|&cpu_id| cpu_id != usize::MAX)
+                .unwrap_or_else(A::Hal::current_cpu_id)
+        };
+        self.load
+            .borrow_mut()
+            .record_unbind(cpu_id, A::Hal::current_time_nanos());
+        A::Hal::on_vcpu_unbind(cpu_id, self.vm_id(), self.id());
+        Ok(())
+    }
+
+    /// Set whether [`AxVCpu::bind`] skips checking the current physical CPU against [`AxVCpu::phys_cpu_set`], for
+    /// schedulers that deliberately run the vcpu elsewhere (e.g., when all allowed CPUs are offline).
+    pub fn set_affinity_override(&self, enable: bool) {
+        self.inner_mut.borrow_mut().affinity_override = enable;
+    }
+
+    /// Forward a [`AxVCpuExitReason::CpuFreqRequest`] of the vcpu to [`AxVCpuHal::apply_cpu_freq_request`] with
+    /// the physical CPU the vcpu is bound to. Returns whether the request is applied; requests of unbound vcpus
+    /// are ignored.
+    pub fn apply_cpu_freq_request(&self, level: u32) -> bool {
+        match self.bound_cpu() {
+            Some(cpu_id) => A::Hal::apply_cpu_freq_request(cpu_id, self.id(), level),
+            None => false,
+        }
+    }
+
+    /// Perform the operation of a [`AxVCpuExitReason::CacheMaintenance`] exit on the host memory backing the guest
+    /// range, with [`AxVCpuHal::cache_maintain`]. `translate` maps a guest physical address to the host physical
+    /// address backing it (e.g., by walking the nested page table of the VM), and is called once per 4K page:
+    /// the pages contiguous in host memory are maintained at once, and unmapped pages are skipped, as there is no
+    /// memory to maintain.
+    ///
+    /// Returns `InvalidInput` if `exit` is not a [`AxVCpuExitReason::CacheMaintenance`] exit, or the error of the
+    /// HAL.
+    pub fn handle_cache_maintenance(
+        &self,
+        exit: &AxVCpuExitReason,
+        mut translate: impl FnMut(GuestPhysAddr) -> Option<HostPhysAddr>,
+    ) -> AxResult {
+        const PAGE_SIZE: usize = 0x1000;
+        let AxVCpuExitReason::CacheMaintenance { addr, size, op } = *exit else {
+            return ax_err!(InvalidInput, "not a cache maintenance exit");
+        };
+        let mut pending: Option<Range<HostPhysAddr>> = None;
+        let mut offset = 0;
+        while offset < size {
+            let gpa = addr + offset;
+            let len = (PAGE_SIZE - gpa.align_offset_4k()).min(size - offset);
+            match (translate(gpa), pending.as_mut()) {
+                (Some(hpa), Some(range)) if range.end == hpa => range.end = hpa + len,
+                (hpa, _) => {
+                    if let Some(range) = pending.take() {
+                        A::Hal::cache_maintain(range, op)?;
+                    }
+                    pending = hpa.map(|hpa| hpa..hpa + len);
+                }
+            }
+            offset += len;
+        }
+        match pending {
+            Some(range) => A::Hal::cache_maintain(range, op),
+            None => Ok(()),
+        }
+    }
+
+    /// Cap the guest time of the vcpu to `runtime_ns` nanoseconds in every period of `period_ns` nanoseconds, so
+    /// that a noisy guest can be limited without an external scheduler. `period_ns == 0` removes the cap.
+    ///
+    /// The cap is enforced at VM entry: once the budget of the current period is used up, [`AxVCpu::run`] returns
+    /// [`AxVCpuError::Throttled`] until the next period. The throttled time is accounted in
+    /// [`AxVCpuStats::throttled_ns`]. Real-time vcpus (see [`AxVCpu::set_realtime`]) are not capped, and their
+    /// guest time is not charged.
+    pub fn set_cpu_quota(&self, period_ns: u64, runtime_ns: u64) {
+        self.quota
+            .borrow_mut()
+            .set(period_ns, runtime_ns, A::Hal::current_time_nanos());
+    }
+
+    /// Get the CPU bandwidth cap of the vcpu as `(period_ns, runtime_ns)`, or `None` if it's not capped.
+    pub fn cpu_quota(&self) -> Option<(u64, u64)> {
+        self.quota.borrow().get()
+    }
+
+    /// Save the extended (variable-length vector) register state of the vcpu, e.g., for migration or fork, into a
+    /// buffer sized by [`AxArchVCpu::ext_state_size`] and allocated through the HAL on the NUMA node of the vcpu.
+    ///
+    /// Returns `Ok(None)` if the vcpu has no extended state, and `NoMemory` if the buffer can't be allocated.
+    pub fn save_ext_state(&self) -> AxResult<Option<ExtStateBuffer<A::Hal>>> {
+        let arch_vcpu = self.arch_vcpu_mut();
+        let size = arch_vcpu.ext_state_size();
+        if size == 0 {
+            return Ok(None);
+        }
+        let Some(mut buf) = ExtStateBuffer::new(size, self.numa_node()) else {
+            return ax_err!(NoMemory, "failed to allocate the extended state buffer");
+        };
+        arch_vcpu.save_ext_state(buf.as_mut_slice())?;
+        Ok(Some(buf))
+    }
+
+    /// Take a snapshot of the state of the vcpu, e.g., for migration or fork. The vcpu must not be running.
+    ///
+    /// `host` describes the current host, and is recorded in the header of the snapshot for
+    /// [`AxVCpuSnapshot::is_compatible`].
+    pub fn snapshot(&self, host: &HostInfo) -> AxResult<AxVCpuSnapshot> {
+        if self.state() == VCpuState::Running {
+            return ax_err!(BadState, "cannot snapshot a running vcpu");
+        }
+        let mut arch_state = Vec::new();
+        self.arch_vcpu_mut().save_state(&mut arch_state)?;
+        let ext_state = self
+            .save_ext_state()?
+            .map_or_else(Vec::new, |buf| buf.as_slice().to_vec());
+        Ok(AxVCpuSnapshot {
+            header: SnapshotHeader::new(host),
+            arch_state,
+            ext_state,
+            sysregs: self.sysregs.borrow().clone(),
+            time: self.time_state()?,
+        })
+    }
+
+    /// Get the size in bytes of the snapshot of the vcpu encoded by [`AxVCpu::save_state_into`], i.e., the minimum
+    /// size of its buffer. The size doesn't change as long as the VMM doesn't define new system registers (see
+    /// [`AxVCpu::sysregs`]).
+    pub fn required_snapshot_size(&self) -> AxResult<usize> {
+        let sections = self.snapshot_sections()?;
+        Ok(SNAPSHOT_HEADER_SIZE
+            + sections
+                .iter()
+                .map(|len| SNAPSHOT_SECTION_OVERHEAD + len)
+                .sum::<usize>())
+    }
+
+    /// Get the sizes in bytes of the sections of the encoded snapshot of the vcpu, i.e., the architecture-specific
+    /// state, the extended state, the system registers, and the time state.
+    fn snapshot_sections(&self) -> AxResult<[usize; 4]> {
+        let arch_vcpu = self.arch_vcpu_mut();
+        Ok([
+            arch_vcpu.saved_state_size()?,
+            arch_vcpu.ext_state_size(),
+            self.sysregs.borrow().encoded_len(),
+            VCpuTimeState::ENCODED_SIZE,
+        ])
+    }
+
+    /// Take a snapshot of the state of the vcpu like [`AxVCpu::snapshot`], but encode it directly into `buf` (e.g.,
+    /// a network or shared-memory buffer) as [`AxVCpuSnapshot::encode`] would, without intermediate allocations as
+    /// long as the architecture-specific vcpu implements [`AxArchVCpu::save_state_into`]. The vcpu must not be
+    /// running.
+    ///
+    /// Returns the number of bytes written, or `StorageFull` if `buf` is smaller than
+    /// [`AxVCpu::required_snapshot_size`]. The result can be decoded with [`AxVCpuSnapshot::decode`].
+    pub fn save_state_into(&self, host: &HostInfo, buf: &mut [u8]) -> AxResult<usize> {
+        if self.state() == VCpuState::Running {
+            return ax_err!(BadState, "cannot snapshot a running vcpu");
+        }
+        let sections = self.snapshot_sections()?;
+        let [arch_len, ext_len, sysregs_len, time_len] = sections;
+        let required = SNAPSHOT_HEADER_SIZE
+            + sections
+                .iter()
+                .map(|len| SNAPSHOT_SECTION_OVERHEAD + len)
+                .sum::<usize>();
+        if buf.len() < required {
+            return ax_err!(
+                StorageFull,
+                format!(
+                    "snapshot needs {} bytes, buffer holds {}",
+                    required,
+                    buf.len()
+                )
+            );
+        }
+        let time = self.time_state()?;
+        let arch_vcpu = self.arch_vcpu_mut();
+        let sysregs = self.sysregs.borrow();
+        let mut writer = Writer::new(buf);
+        writer.put(&SnapshotHeader::new(host).to_bytes());
+        writer.section(arch_len, |data| arch_vcpu.save_state_into(data))?;
+        writer.section(ext_len, |data| {
+            if data.is_empty() {
+                Ok(())
+            } else {
+                arch_vcpu.save_ext_state(data)
+            }
+        })?;
+        writer.section(sysregs_len, |data| {
+            sysregs.encode_into(data);
+            Ok(())
+        })?;
+        writer.section(time_len, |data| {
+            time.encode_into(data);
+            Ok(())
+        })?;
+        Ok(writer.written())
+    }
+
+    /// Restore a snapshot taken by [`AxVCpu::snapshot`]. The vcpu must not be running.
+    ///
+    /// The snapshot is checked against `host` (describing the current host) first,
+    /// [`AxVCpuError::SnapshotIncompatible`] is returned without touching the vcpu if it's incompatible. The time
+    /// state is restored last, see [`AxVCpu::restore_time_state`].
+    pub fn restore(&self, snapshot: &AxVCpuSnapshot, host: &HostInfo) -> AxVCpuResult {
+        if self.state() == VCpuState::Running {
+            return Err(ax_err_type!(BadState, "cannot restore a running vcpu").into());
+        }
+        snapshot.is_compatible(host)?;
+        let arch_vcpu = self.arch_vcpu_mut();
+        if arch_vcpu.ext_state_size() != snapshot.ext_state.len() {
+            return Err(ax_err_type!(
+                InvalidData,
+                "extended state size of the snapshot differs from the vcpu"
+            )
+            .into());
+        }
+        arch_vcpu.restore_state(&snapshot.arch_state)?;
+        if !snapshot.ext_state.is_empty() {
+            let Some(mut buf) = ExtStateBuffer::new(snapshot.ext_state.len(), self.numa_node())
+            else {
+                return Err(
+                    ax_err_type!(NoMemory, "failed to allocate the extended state buffer").into(),
+                );
+            };
+            buf.as_mut_slice().copy_from_slice(&snapshot.ext_state);
+            self.restore_ext_state(&buf)?;
+        }
+        let mut sysregs = self.sysregs.borrow_mut();
+        *sysregs = snapshot.sysregs.clone();
+        sysregs.mark_all_dirty();
+        drop(sysregs);
+        Ok(self.restore_time_state(&snapshot.time)?)
+    }
+
+    /// Restore an encoded snapshot, e.g., received from the migration source, produced by
+    /// [`AxVCpuSnapshot::encode`] or [`AxVCpu::save_state_into`]. The vcpu must not be running.
+    ///
+    /// The checksums of the snapshot are verified before anything is restored, so that partially transferred
+    /// state fails fast with [`AxVCpuError::SnapshotCorrupted`] instead of producing a subtly broken guest. The
+    /// snapshot is then restored like [`AxVCpu::restore`].
+    pub fn restore_encoded(&self, data: &[u8], host: &HostInfo) -> AxVCpuResult {
+        let snapshot = AxVCpuSnapshot::decode(data)?;
+        self.restore(&snapshot, host)
+    }
+
+    /// Restore the extended register state of the vcpu from a buffer saved by [`AxVCpu::save_ext_state`], possibly
+    /// on another vcpu or host.
+    ///
+    /// Returns `InvalidInput` if the size of the saved state differs from the one of this vcpu, e.g., if the SVE
+    /// vector lengths of the hosts differ, rather than truncating the guest state.
+    pub fn restore_ext_state(&self, buf: &ExtStateBuffer<A::Hal>) -> AxResult {
+        let arch_vcpu = self.arch_vcpu_mut();
+        let size = arch_vcpu.ext_state_size();
+        if buf.len() != size {
+            return ax_err!(
+                InvalidInput,
+                format!(
+                    "extended state size mismatch: {} bytes saved, {} bytes expected",
+                    buf.len(),
+                    size
+                )
+            );
+        }
+        arch_vcpu.restore_ext_state(buf.as_slice())
+    }
+
+    /// Break on exits matching `filter`: [`AxVCpu::run`] calls `handler` with each matching exit before handling it
+    /// in any other way (including fast-path handling), keeping the vcpu paused until the handler returns.
+    ///
+    /// `filter` can be a plain [`crate::ExitKindSet`], or an [`ExitFilter`] with address or port predicates.
+    pub fn set_exit_filter(
+        &self,
+        filter: impl Into<ExitFilter>,
+        handler: Arc<dyn ExitBreakpointHandler>,
+    ) {
+        self.inner_mut.borrow_mut().exit_filter = Some((filter.into(), handler));
+    }
+
+    /// Remove the exit filter set by [`AxVCpu::set_exit_filter`].
+    pub fn clear_exit_filter(&self) {
+        self.inner_mut.borrow_mut().exit_filter = None;
+    }
+
+    /// Set the resolver used to annotate guest PCs with symbol names in reports about this vcpu, see
+    /// [`AxVCpu::symbolize`].
+    pub fn set_symbol_resolver(&self, resolver: Option<Arc<dyn GuestSymbolResolver>>) {
+        self.inner_mut.borrow_mut().symbol_resolver = resolver;
+    }
+
+    /// Annotate a guest PC of this vcpu with its symbol for reports, e.g.,
+    /// `error!("vcpu {} crashed at {}", vcpu.id(), vcpu.symbolize(pc))`, which prints `0x1234 <func+0x10>` if the
+    /// resolver set by [`AxVCpu::set_symbol_resolver`] knows the symbol.
+    pub fn symbolize(&self, pc: GuestVirtAddr) -> SymbolizedPc {
+        SymbolizedPc {
+            pc,
+            resolver: self.inner_mut.borrow().symbol_resolver.clone(),
+        }
+    }
+
+    /// Set the policy of switching the FP/SIMD state of the vcpu, taking effect from the next [`AxVCpu::bind`].
+    ///
+    /// [`FpuPolicy::Lazy`] requires the architecture-specific vcpu to implement [`AxArchVCpu::set_fpu_trap`],
+    /// [`AxArchVCpu::restore_fpu_state`] and [`AxArchVCpu::save_fpu_state`]. How often the laziness pays off is
+    /// shown by [`AxVCpuStats::fpu_lazy_skips`] and [`AxVCpuStats::fpu_lazy_restores`].
+    pub fn set_fpu_policy(&self, policy: FpuPolicy) {
+        self.inner_mut.borrow_mut().fpu_policy = policy;
+    }
+
+    /// Get the policy of switching the FP/SIMD state of the vcpu.
+    pub fn fpu_policy(&self) -> FpuPolicy {
+        self.inner_mut.borrow().fpu_policy
+    }
+
+    /// Get the physical CPU the vcpu is bound to, as returned by [`AxVCpuHal::current_cpu_id`] in
+    /// [`AxVCpu::bind`], or `None` if the vcpu is not bound.
+    pub fn bound_cpu(&self) -> Option<usize> {
+        Some(self.bound_cpu.load(Ordering::Acquire)).filter(|&cpu_id| cpu_id != usize::MAX)
+    }
+
+    /// Sets the entry address of the vcpu.
+    ///
+    /// Returns `PermissionDenied` if the register state of the guest is protected. Debug builds panic if the guest
+    /// is running on another physical CPU.
+    #[track_caller]
+    pub fn set_entry(&self, entry: GuestPhysAddr) -> AxResult {
+        self.debug_check_not_running_elsewhere("set_entry");
+        self.check_register_access()?;
+        self.arch_vcpu_mut().set_entry(entry)
+    }
+
+    /// Sets the entry address of the vcpu, along with the execution mode the guest runs in from there on, e.g.,
+    /// when a secondary CPU of a mixed-width guest is brought up.
+    ///
+    /// Returns `PermissionDenied` if the register state of the guest is protected, and `Unsupported` if the
+    /// architecture-specific vcpu doesn't support `mode`.
+    #[track_caller]
+    pub fn set_entry_with_mode(&self, entry: GuestPhysAddr, mode: GuestMode) -> AxResult {
+        self.debug_check_not_running_elsewhere("set_entry_with_mode");
+        self.check_register_access()?;
+        let arch_vcpu = self.arch_vcpu_mut();
+        arch_vcpu.set_guest_mode(mode)?;
+        self.guest_mode.set(mode);
+        arch_vcpu.set_entry(entry)
+    }
+
+    /// Get the execution mode of the guest, see [`GuestMode`].
+    pub fn guest_mode(&self) -> GuestMode {
+        self.guest_mode.get()
+    }
+
+    /// Get the byte order of the data accesses of the guest, see [`GuestEndian`].
+    pub const fn guest_endian(&self) -> GuestEndian {
+        self.inner_const.guest_endian
+    }
+
+    /// Complete an [`AxVCpuExitReason::MmioRead`] exit with `value`, read from the device in bus (little-endian)
+    /// order: the value is converted to the byte order of the guest, truncated to the width of the access, and
+    /// stored into the target register.
+    ///
+    /// Returns `InvalidInput` if `exit` is not an MMIO read.
+    pub fn complete_mmio_read(&self, exit: &AxVCpuExitReason, value: u64) -> AxResult {
+        let Some(target) = completion_target(exit, self.guest_endian())
+            .filter(|_| matches!(exit, AxVCpuExitReason::MmioRead { .. }))
+        else {
+            return ax_err!(InvalidInput, "not an MMIO read exit");
+        };
+        self.set_gpr(target.reg, target.value(value))
+    }
+
+    /// Sets the value of a general-purpose register according to the given index.
+    ///
+    /// The value is truncated to the width of the registers of the guest (see [`GuestMode::gpr_width`]), so that
+    /// 32-bit guests never observe stale upper bits.
+    ///
+    /// Returns `PermissionDenied` if the register state of the guest is protected. Debug builds panic if the guest
+    /// is running on another physical CPU.
+    #[track_caller]
+    pub fn set_gpr(&self, reg: usize, val: usize) -> AxResult {
+        self.debug_check_not_running_elsewhere("set_gpr");
+        self.check_register_access()?;
+        let mask = self.guest_mode().gpr_mask();
+        self.arch_vcpu_mut().set_gpr(reg, val & mask);
+        Ok(())
+    }
+
+    /// Whether the register state of the guest is protected and not accessible to the host.
+    pub fn is_protected(&self) -> bool {
+        self.arch_vcpu_mut().is_protected()
+    }
+
+    /// Check that the register state of the guest is accessible to the host.
+    fn check_register_access(&self) -> AxResult {
+        if self.is_protected() {
+            ax_err!(
+                PermissionDenied,
+                "register state of a protected guest is not accessible"
+            )
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Queue an interrupt to be injected into the vcpu.
+    ///
+    /// The interrupt is delivered to the architecture-specific vcpu the next time [`AxVCpu::run`] is called. All
+    /// pending interrupts are delivered before that entry, in the order selected by [`AxVCpu::set_injection_order`]
+    /// (by default, the order they're queued in).
+    ///
+    /// With the `no-alloc-fastpath` feature enabled, the queue never grows beyond its initial capacity, and
+    /// `ResourceBusy` is returned if it's full. Only the counters of vectors below [`IRQ_BITMAP_VECTORS`] are
+    /// preallocated then, counting a larger vector for the first time allocates.
+    ///
+    /// This method must not be called while the guest is running on another physical CPU (debug builds panic),
+    /// use [`AxVCpu::inject_interrupt_from_irq`] there instead.
+    #[track_caller]
+    pub fn inject_interrupt(&self, vector: usize) -> AxResult {
+        self.debug_check_not_running_elsewhere("inject_interrupt");
+        {
+            let mut pending_irqs = self.pending_irqs.borrow_mut();
+            if cfg!(feature = "no-alloc-fastpath") && pending_irqs.len() == pending_irqs.capacity()
+            {
+                self.record_dropped_irq(vector);
+                return ax_err!(ResourceBusy, "pending interrupt queue is full");
+            }
+            pending_irqs.push_back((vector, A::Hal::current_time_nanos()));
+        }
+        vcpu_log!(Injection, Trace, vcpu = self.id(), vector = vector; "interrupt queued");
+        if !self.wake() {
+            self.check_injection_deadline();
+        }
+        Ok(())
+    }
+
+    /// Mark an interrupt pending from host interrupt context, e.g., the handler of a passthrough device interrupt.
+    ///
+    /// Unlike [`AxVCpu::inject_interrupt`], this method takes no lock and touches no `RefCell`, so it may be called
+    /// from any host context, even while another context operates on the vcpu. The vector is recorded in a
+    /// fixed-size atomic bitmap and merged into the queue of [`AxVCpu::inject_interrupt`] at the next safe point,
+    /// i.e., the next [`AxVCpu::run`] or poll of [`AxVCpu::block_until_interrupt`]. The vcpu is kicked out of the
+    /// guest with [`AxVCpuHal::kick_vcpu`] if it's running, and notified with [`AxVCpuHal::notify_vcpu`]
+    /// otherwise, both of which must be safe to call from interrupt context then.
+    ///
+    /// Returns `InvalidInput` if `vector` is not less than [`IRQ_BITMAP_VECTORS`]. A vector already pending in the
+    /// bitmap is injected only once, the duplicates are counted in [`VectorStats::coalesced`](crate::VectorStats::coalesced).
+    pub fn inject_interrupt_from_irq(&self, vector: usize) -> AxResult {
+        if vector >= IRQ_BITMAP_VECTORS {
+            return ax_err!(InvalidInput, "interrupt vector out of the pending bitmap");
+        }
+        self.irq_bitmap.set(vector);
+        if self.is_running() {
+            A::Hal::kick_vcpu(self.vm_id(), self.id());
+        } else {
+            A::Hal::notify_vcpu(self.id());
+        }
+        Ok(())
+    }
+
+    /// Queue an [`AxVCpuExitReason::IommuFault`] from host interrupt context, e.g., the IOMMU fault handler.
+    ///
+    /// Like [`AxVCpu::inject_interrupt_from_irq`], this method takes no lock and touches no `RefCell`: the fault is
+    /// stored in a single-entry atomic slot and moved to the exits queued by [`AxVCpu::queue_exit`] at the next
+    /// safe point, and the vcpu is kicked or notified. Returns `ResourceBusy` if a fault reported earlier is not
+    /// picked up yet, in which case the new one is discarded.
+    pub(crate) fn queue_iommu_fault_from_irq(
+        &self,
+        device: u32,
+        addr: GuestPhysAddr,
+        flags: MappingFlags,
+    ) -> AxResult {
+        if !self.iommu_fault.put(device, addr, flags) {
+            return ax_err!(ResourceBusy, "an IOMMU fault is already pending");
+        }
+        if self.is_running() {
+            A::Hal::kick_vcpu(self.vm_id(), self.id());
+        } else {
+            A::Hal::notify_vcpu(self.id());
+        }
+        Ok(())
+    }
+
+    /// Count a dropped interrupt in [`VectorStats::dropped`](crate::VectorStats::dropped).
+    pub(crate) fn record_dropped_irq(&self, vector: usize) {
+        self.count_vector(vector, |counters| counters.dropped += 1);
+    }
+
+    /// Update the counters of `vector` with `f`.
+    fn count_vector(&self, vector: usize, f: impl FnOnce(&mut VectorStats)) {
+        f(self
+            .vector_table
+            .borrow_mut()
+            .get_mut(&mut self.stats.borrow_mut(), vector));
+    }
+
+    /// Assert the level-triggered interrupt line of `vector`, e.g., the line of an emulated disk controller,
+    /// injecting the interrupt if the line was not asserted yet.
+    ///
+    /// The line is watched until [`AxVCpu::deassert_irq_line`]: if it stays asserted beyond the threshold set by
+    /// [`AxVCpu::set_lost_irq_threshold`], the guest has likely lost the interrupt, which is reported by
+    /// [`AxVCpu::check_lost_interrupts`].
+    pub fn assert_irq_line(&self, vector: usize) -> AxResult {
+        let newly_asserted = {
+            let mut inner_mut = self.inner_mut.borrow_mut();
+            let now = A::Hal::current_time_nanos();
+            let mut newly_asserted = false;
+            inner_mut.irq_lines.entry(vector).or_insert_with(|| {
+                newly_asserted = true;
+                (now, false)
+            });
+            newly_asserted
+        };
+        if newly_asserted {
+            self.inject_interrupt(vector)?;
+        }
+        Ok(())
+    }
+
+    /// Deassert the level-triggered interrupt line of `vector` asserted by [`AxVCpu::assert_irq_line`], e.g., once
+    /// the guest has serviced the device.
+    pub fn deassert_irq_line(&self, vector: usize) {
+        self.inner_mut.borrow_mut().irq_lines.remove(&vector);
+    }
+
+    /// Set for how long a level-triggered line may stay asserted before the interrupt is considered lost, in
+    /// nanoseconds, or disable the detection with `None` (the default).
+    pub fn set_lost_irq_threshold(&self, threshold_ns: Option<u64>) {
+        self.inner_mut.borrow_mut().lost_irq_threshold_ns = threshold_ns;
+    }
+
+    /// Report the level-triggered lines asserted (see [`AxVCpu::assert_irq_line`]) for longer than the threshold
+    /// set by [`AxVCpu::set_lost_irq_threshold`], each once per assertion: the loss is counted in
+    /// [`VectorStats::lost`](crate::VectorStats::lost), logged, and emitted as a [`TraceEvent::LostInterrupt`] trace record. Returns the
+    /// number of lines newly reported.
+    ///
+    /// Called by [`AxVCpu::run`] before each VM entry, and may be called by watchdogs for blocked vcpus.
+    pub fn check_lost_interrupts(&self) -> usize {
+        /// The number of lines collected at once, so that they're reported without allocating, and without
+        /// `inner_mut` borrowed while the trace sink runs.
+        const BATCH: usize = 8;
+        let now = A::Hal::current_time_nanos();
+        let mut reported_lines = 0;
+        loop {
+            let mut batch = [(0, 0); BATCH];
+            let mut len = 0;
+            {
+                let mut inner_mut = self.inner_mut.borrow_mut();
+                let Some(threshold_ns) = inner_mut.lost_irq_threshold_ns else {
+                    return reported_lines;
+                };
+                for (&vector, (asserted_at, reported)) in inner_mut.irq_lines.iter_mut() {
+                    let asserted_ns = now.saturating_sub(*asserted_at);
+                    if *reported || asserted_ns <= threshold_ns {
+                        continue;
+                    }
+                    *reported = true;
+                    batch[len] = (vector, asserted_ns);
+                    len += 1;
+                    if len == BATCH {
+                        break;
+                    }
+                }
+            }
+            for &(vector, asserted_ns) in &batch[..len] {
+                vcpu_log!(Injection, Warn, vcpu = self.id(), vector = vector, asserted_ns = asserted_ns; "level-triggered interrupt likely lost");
+                self.count_vector(vector, |counters| counters.lost += 1);
+                self.trace(TraceEvent::LostInterrupt, [vector as u64, asserted_ns]);
+            }
+            reported_lines += len;
+            if len < BATCH {
+                return reported_lines;
+            }
+        }
+    }
+
+    /// Merge the vectors injected by [`AxVCpu::inject_interrupt_from_irq`] into the pending interrupt queue, and
+    /// the IOMMU fault reported by [`AxVCpu::queue_iommu_fault_from_irq`] into the queued exits, waking the vcpu
+    /// up if it's blocked.
+    fn merge_from_irq(&self) {
+        if let Some(exit) = self.iommu_fault.take() {
+            self.queue_exit(exit);
+        }
+        let mut merged = false;
+        self.irq_bitmap.drain(|vector, coalesced| {
+            if coalesced > 0 {
+                self.count_vector(vector, |counters| counters.coalesced += coalesced);
+            }
+            let mut pending_irqs = self.pending_irqs.borrow_mut();
+            if cfg!(feature = "no-alloc-fastpath") && pending_irqs.len() == pending_irqs.capacity()
+            {
+                // Keep the vector pending until the queue has room.
+                self.irq_bitmap.restore(vector);
+                return;
+            }
+            pending_irqs.push_back((vector, A::Hal::current_time_nanos()));
+            merged = true;
+        });
+        if merged {
+            self.wake();
+        }
+    }
+
+    /// Queue work on the architecture-specific vcpu, run right before the next VM entry: after the pending
+    /// interrupts are injected, right before [`AxArchVCpu::run`].
+    ///
+    /// This gives device models a safe point to touch the state of the vcpu without racing a running guest. Work
+    /// runs in the order it's queued, and may queue more work, which runs before the same entry. An error returned
+    /// by the work is propagated by [`AxVCpu::run`], like errors of [`AxArchVCpu::run`].
+    ///
+    /// The work must be `Send`, as the vcpu holding it may be moved to another host context before it runs.
+    pub fn defer(&self, work: impl FnOnce(&mut A) -> AxResult + Send + 'static) {
+        self.deferred.borrow_mut().push_back(Box::new(work));
+    }
+
+    /// Set the order in which the pending interrupts are injected, see [`InjectionOrder`]. Defaults to
+    /// [`InjectionOrder::Fifo`].
+    pub fn set_injection_order(&self, order: InjectionOrder) {
+        self.injection_order.set(order);
+    }
+
+    /// Get the active injection order, i.e., the one set by [`AxVCpu::set_injection_order`], with
+    /// [`InjectionOrder::ArchDefined`] resolved to the order preferred by the architecture-specific vcpu.
+    pub fn injection_order(&self) -> InjectionOrder {
+        self.resolve_injection_order(self.arch_vcpu_mut())
+    }
+
+    /// Resolve [`InjectionOrder::ArchDefined`], and [`InjectionOrder::Priority`] on vcpus without interrupt
+    /// priorities.
+    fn resolve_injection_order(&self, arch_vcpu: &A) -> InjectionOrder {
+        let order = match self.injection_order.get() {
+            InjectionOrder::ArchDefined => arch_vcpu.injection_order(),
+            order => order,
+        };
+        match order {
+            InjectionOrder::Priority
+                if arch_vcpu
+                    .capabilities()
+                    .contains(VCpuCapabilities::INTERRUPT_PRIORITIES) =>
+            {
+                InjectionOrder::Priority
+            }
+            _ => InjectionOrder::Fifo,
+        }
+    }
+
+    /// Take the next pending interrupt to inject, the oldest one with the highest priority if `by_priority`.
+    fn pop_pending_irq(&self, arch_vcpu: &A, by_priority: bool) -> Option<(usize, u64)> {
+        let mut pending_irqs = self.pending_irqs.borrow_mut();
+        if !by_priority {
+            return pending_irqs.pop_front();
+        }
+        let index = pending_irqs
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, (vector, _))| arch_vcpu.interrupt_priority(*vector))
+            .map(|(index, _)| index)?;
+        pending_irqs.remove(index)
+    }
+
+    /// Set the injection deadline of high-priority vectors, for latency-sensitive guest interrupts (e.g., audio,
+    /// industrial control), or remove it if `deadline` is `None`.
+    ///
+    /// While the vcpu is running, [`AxVCpu::check_injection_deadline`] kicks it out of the guest (see
+    /// [`AxVCpuHal::kick_vcpu`]) if a vector of the deadline has waited longer than its threshold, so that it's
+    /// injected at the following re-entry.
+    pub fn set_injection_deadline(&self, deadline: Option<InjectionDeadline>) {
+        self.injection_deadline.set(deadline);
+    }
+
+    /// Kick the vcpu out of the guest if a high-priority vector has waited longer than the threshold set by
+    /// [`AxVCpu::set_injection_deadline`]. Returns whether the vcpu is kicked.
+    ///
+    /// It's checked whenever an interrupt is queued, and should also be called periodically (e.g., from the timer
+    /// interrupt handler of the host) so that no deadline is missed.
+    pub fn check_injection_deadline(&self) -> bool {
+        let Some(deadline) = self.injection_deadline.get() else {
+            return false;
+        };
+        if !self.is_running() {
+            return false;
+        }
+        let now = A::Hal::current_time_nanos();
+        let overdue = self
+            .pending_irqs
+            .borrow()
+            .iter()
+            .any(|&(vector, queued_at)| {
+                deadline.applies_to(vector)
+                    && now.saturating_sub(queued_at) >= deadline.threshold_ns
+            });
+        if overdue {
+            vcpu_log!(Injection, Debug, vcpu = self.id(); "injection deadline missed, kicking vcpu");
+            A::Hal::kick_vcpu(self.vm_id(), self.id());
+        }
+        overdue
+    }
+
+    /// Get the longest time an injected interrupt waited in the queue of the vcpu since the counters were reset,
+    /// in nanoseconds.
+    pub fn max_injection_latency(&self) -> u64 {
+        self.stats.borrow().max_injection_latency_ns
+    }
+
+    /// Queue an interrupt handed back by [`AxArchVCpu::sync_hw_irq_state`], without waking the vcpu.
+    ///
+    /// With the `no-alloc-fastpath` feature enabled and the queue full, the vector is kept pending in the bitmap
+    /// of [`AxVCpu::inject_interrupt_from_irq`] instead, or dropped (and counted) if it doesn't fit there.
+    fn requeue_interrupt(&self, vector: usize) {
+        vcpu_log!(Injection, Trace, vcpu = self.id(), vector = vector; "interrupt requeued from hardware");
+        let mut pending_irqs = self.pending_irqs.borrow_mut();
+        if cfg!(feature = "no-alloc-fastpath") && pending_irqs.len() == pending_irqs.capacity() {
+            drop(pending_irqs);
+            if vector < IRQ_BITMAP_VECTORS {
+                self.irq_bitmap.restore(vector);
+            } else {
+                self.record_dropped_irq(vector);
+            }
+            return;
+        }
+        pending_irqs.push_back((vector, A::Hal::current_time_nanos()));
+    }
+
+    /// Attach a hardware guest-interrupt file allocated by [`AxPerCpu::alloc_guest_irq_file`](crate::AxPerCpu::alloc_guest_irq_file)
+    /// on the current physical CPU to the vcpu, or detach it if `file` is `None`.
+    ///
+    /// The vcpu must be bound to the current physical CPU. The pending state of the previous file is synchronized
+    /// back to the common injection queue first (see [`AxArchVCpu::sync_hw_irq_state`]), so that no interrupt is
+    /// lost. The file must be detached before the vcpu is unbound and the file is freed. Like
+    /// [`AxVCpu::configure_intercepts`], a failure here does not invalidate the vcpu.
+    pub fn set_guest_irq_file(&self, file: Option<usize>) -> AxVCpuResult {
+        if self.state() != VCpuState::Ready {
+            return Err(ax_err_type!(
+                BadState,
+                format!(
+                    "Cannot set the guest-interrupt file of a vcpu in state {:?}",
+                    self.state()
+                )
+            )
+            .into());
+        }
+        self.with_current_cpu_set(|| {
+            let arch_vcpu = self.arch_vcpu_mut();
+            arch_vcpu.sync_hw_irq_state(&mut |vector| self.requeue_interrupt(vector))?;
+            arch_vcpu.set_guest_irq_file(file)
+        })
+    }
+
+    /// Wake the vcpu up if it's blocked, transitioning it to [`VCpuState::Ready`] and notifying it via
+    /// [`AxVCpuHal::notify_vcpu`] and the waker registered by [`AxVCpu::poll_runnable`], if any.
+    ///
+    /// Returns whether the vcpu was blocked.
+    pub fn wake(&self) -> bool {
+        let waker = {
+            let mut inner_mut = self.inner_mut.borrow_mut();
+            if self.state() != VCpuState::Blocked {
+                return false;
+            }
+            self.store_state(VCpuState::Ready);
+            self.blocked_since.store(u64::MAX, Ordering::Release);
+            inner_mut.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        self.load
+            .borrow_mut()
+            .record_wake(A::Hal::current_time_nanos());
+        vcpu_log!(State, Trace, vcpu = self.id(), from:? = VCpuState::Blocked, to:? = VCpuState::Ready; "vcpu state transition");
+        self.trace(
+            TraceEvent::StateChange,
+            [VCpuState::Blocked as u64, VCpuState::Ready as u64],
+        );
+        A::Hal::notify_vcpu(self.id());
+        true
+    }
+
+    /// Park the vcpu, i.e., transition it from [`VCpuState::Ready`] to [`VCpuState::Parked`], generally on
+    /// [`AxVCpuExitReason::CpuDown`] (see [`AxVCpuGroup::handle_cpu_down`](crate::AxVCpuGroup::handle_cpu_down)).
+    ///
+    /// `state` is the power state requested by the guest (e.g., the `_state` of the exit), kept until the vcpu is
+    /// unparked. Running a parked vcpu returns `WouldBlock`.
+    pub fn park(&self, state: u64) -> AxVCpuResult {
+        self.transition_state(VCpuState::Ready, VCpuState::Parked)?;
+        self.inner_mut.borrow_mut().parked_state = Some(state);
+        Ok(())
+    }
+
+    /// Unpark the vcpu, resuming it at `entry` with `arg` as the argument of the entry (see
+    /// [`AxVCpuExitReason::CpuUp`]), generally on behalf of the `CpuUp` of another vcpu (see
+    /// [`AxVCpuGroup::handle_cpu_up`](crate::AxVCpuGroup::handle_cpu_up)).
+    ///
+    /// The boot registers of the guest are set by [`AxArchVCpu::set_boot_args`], with the architecture-specific id
+    /// of the vcpu (see [`AxVCpu::arch_cpu_id`]).
+    pub fn unpark(&self, entry: GuestPhysAddr, arg: u64) -> AxVCpuResult {
+        let arch_cpu_id = self.arch_cpu_id();
+        self.manipulate_arch_vcpu(VCpuState::Parked, VCpuState::Ready, |arch_vcpu| {
+            arch_vcpu.set_boot_args(entry, arch_cpu_id, arg)
+        })?;
+        self.inner_mut.borrow_mut().parked_state = None;
+        Ok(())
+    }
+
+    /// Get the power state requested by the guest when the vcpu was parked, or `None` if it's not parked.
+    pub fn parked_state(&self) -> Option<u64> {
+        self.inner_mut.borrow().parked_state
+    }
+
+    /// Get for how long the vcpu has been blocked, or `None` if it's not blocked.
+    pub(crate) fn blocked_for_ns(&self) -> Option<u64> {
+        self.blocked_for_ns_at(A::Hal::current_time_nanos())
+    }
+
+    /// Get for how long the vcpu has been blocked at the host time `now`, or `None` if it's not blocked.
+    ///
+    /// Like [`AxVCpu::state`], this may be called from any host context.
+    pub(crate) fn blocked_for_ns_at(&self, now: u64) -> Option<u64> {
+        if self.state() != VCpuState::Blocked {
+            return None;
+        }
+        Some(self.blocked_since.load(Ordering::Acquire))
+            .filter(|&since| since != u64::MAX)
+            .map(|since| now.saturating_sub(since))
+    }
+
+    /// Take all interrupts waiting to be injected into the vcpu.
+    pub(crate) fn take_pending_irqs(&self) -> Vec<usize> {
+        self.pending_irqs
+            .borrow_mut()
+            .drain(..)
+            .map(|(vector, _)| vector)
+            .collect()
+    }
+
+    /// Poll whether the vcpu is runnable, i.e., not in [`VCpuState::Blocked`], for cooperative schedulers parking
+    /// vcpu tasks with the standard [`Waker`] mechanics.
+    ///
+    /// If the vcpu is blocked, the waker of `cx` is registered (replacing the previous one) and woken by
+    /// [`AxVCpu::wake`], e.g., when an interrupt is injected.
+    pub fn poll_runnable(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        if self.state() != VCpuState::Blocked {
+            return Poll::Ready(());
+        }
+        match &mut inner_mut.waker {
+            Some(waker) => waker.clone_from(cx.waker()),
+            waker => *waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+
+    /// Wait asynchronously until the vcpu is runnable, see [`AxVCpu::poll_runnable`].
+    pub fn runnable(&self) -> impl Future<Output = ()> + '_ {
+        core::future::poll_fn(|cx| self.poll_runnable(cx))
+    }
+
+    /// Wait until the vcpu is woken up from [`VCpuState::Blocked`], generally called on [`AxVCpuExitReason::Halt`].
+    /// Returns immediately if the vcpu is not blocked.
+    ///
+    /// The vcpu polls for interrupts for a dynamically tuned window before actually blocking via
+    /// [`AxVCpuHal::wait_for_notification`], following the adaptive halt-polling algorithm of KVM. The window
+    /// grows when an interrupt arrives shortly after blocking, and shrinks when the vcpu blocks for long.
+    pub fn block_until_interrupt(&self) {
+        if self.state() != VCpuState::Blocked {
+            return;
+        }
+        let start = A::Hal::current_time_nanos();
+        let window = self.inner_mut.borrow().halt_poll.stats.window_ns;
+
+        let mut now = start;
+        while now.saturating_sub(start) < window {
+            self.merge_from_irq();
+            if self.state() != VCpuState::Blocked {
+                let mut inner_mut = self.inner_mut.borrow_mut();
+                inner_mut
+                    .halt_poll
+                    .record_poll_success(now.saturating_sub(start));
+                return;
+            }
+            core::hint::spin_loop();
+            now = A::Hal::current_time_nanos();
+        }
+        let poll_ns = now.saturating_sub(start);
+
+        loop {
+            self.merge_from_irq();
+            if self.state() != VCpuState::Blocked {
+                break;
+            }
+            A::Hal::wait_for_notification(self.id());
+        }
+
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        let block_ns = A::Hal::current_time_nanos().saturating_sub(start);
+        inner_mut.halt_poll.record_block(poll_ns, block_ns);
+    }
+
+    /// Set the tunables of the adaptive halt-polling used by [`AxVCpu::block_until_interrupt`].
+    pub fn set_halt_poll_config(&self, config: HaltPollConfig) {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        inner_mut.halt_poll.stats.window_ns =
+            inner_mut.halt_poll.stats.window_ns.min(config.max_ns);
+        inner_mut.halt_poll.config = config;
+    }
+
+    /// Get the statistics of the adaptive halt-polling.
+    pub fn halt_poll_stats(&self) -> HaltPollStats {
+        self.inner_mut.borrow().halt_poll.stats
+    }
+
+    /// Queue an exit reported from outside the vcpu, it will be returned by the next call to [`AxVCpu::run`].
+    ///
+    /// A blocked vcpu is woken up (see [`AxVCpu::wake`]), so that the exit is not held back until the next
+    /// interrupt.
+    pub fn queue_exit(&self, exit: AxVCpuExitReason) {
+        self.inner_mut.borrow_mut().pending_exits.push_back(exit);
+        self.wake();
+    }
+
+    /// Take the first queued exit, checking that the vcpu is ready to run.
+    fn take_pending_exit(&self) -> AxVCpuResult<Option<AxVCpuExitReason>> {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        if inner_mut.pending_exits.is_empty() {
+            return Ok(None);
+        }
+        if self.state() != VCpuState::Ready {
+            return bad_state(VCpuState::Ready, self.state());
+        }
+        Ok(inner_mut.pending_exits.pop_front())
+    }
+
+    /// Configure which guest operations cause vm-exits, see [`InterceptConfig`].
+    ///
+    /// The vcpu must be set up and not running. Unlike [`AxVCpu::manipulate_arch_vcpu`], a failure here does not
+    /// invalidate the vcpu, as the architecture-specific vcpu may not support some of the configurations.
+    pub fn configure_intercepts(&self, config: InterceptConfig) -> AxVCpuResult {
+        match self.state() {
+            VCpuState::Free | VCpuState::Ready => {
+                self.with_current_cpu_set(|| self.arch_vcpu_mut().set_intercepts(&config))
+            }
+            state => Err(ax_err_type!(
+                BadState,
+                format!("Cannot configure intercepts of a vcpu in state {:?}", state)
+            )
+            .into()),
+        }
+    }
+
+    /// Program hardware counters around guest execution (retired instructions and/or branches), surfaced in
+    /// [`AxVCpuStats::guest_instructions`] and [`AxVCpuStats::guest_branches`]. A config with nothing enabled stops
+    /// profiling.
+    ///
+    /// Like [`AxVCpu::configure_intercepts`], the vcpu must be set up and not running, and a failure here (e.g.,
+    /// `Unsupported` without hardware assists) does not invalidate the vcpu.
+    pub fn enable_exec_profiling(&self, cfg: ExecProfilingConfig) -> AxVCpuResult {
+        let state = self.state();
+        if !matches!(state, VCpuState::Free | VCpuState::Ready) {
+            return bad_state(VCpuState::Ready, state);
+        }
+        self.with_current_cpu_set(|| self.arch_vcpu_mut().enable_exec_profiling(&cfg))?;
+        self.exec_profiling.set(!cfg.is_disabled());
+        Ok(())
+    }
+
+    /// Configure which accesses to the system registers in `range` cause vm-exits, see [`SysRegTrapMode`].
+    ///
+    /// Later calls take precedence over earlier ones for overlapping ranges. Like [`AxVCpu::configure_intercepts`],
+    /// the vcpu must be set up and not running, and a failure here does not invalidate the vcpu.
+    pub fn trap_sysreg_range(&self, range: Range<usize>, mode: SysRegTrapMode) -> AxVCpuResult {
+        if range.is_empty() {
+            return Err(ax_err_type!(InvalidInput, "empty system register range").into());
+        }
+        match self.state() {
+            VCpuState::Free | VCpuState::Ready => self
+                .with_current_cpu_set(|| self.arch_vcpu_mut().configure_sysreg_traps(range, mode)),
+            state => Err(ax_err_type!(
+                BadState,
+                format!(
+                    "Cannot configure system register traps of a vcpu in state {:?}",
+                    state
+                )
+            )
+            .into()),
+        }
+    }
+
+    /// Watch the accesses in `access` (read, write, and/or execute) to the guest physical address range `range`:
+    /// such accesses exit with [`AxVCpuExitReason::Introspection`] instead of
+    /// [`AxVCpuExitReason::NestedPageFault`], and are held until completed with
+    /// [`AxVCpu::complete_introspection`].
+    ///
+    /// The VMM must also revoke the watched permissions of the range in the stage-2 page table, so that the
+    /// accesses fault in the first place.
+    pub fn watch_guest_memory(
+        &self,
+        range: Range<GuestPhysAddr>,
+        access: MappingFlags,
+    ) -> AxResult {
+        let access = access & (MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE);
+        if range.is_empty() || access.is_empty() {
+            return ax_err!(InvalidInput, "empty introspection range or access");
+        }
+        self.inner_mut
+            .borrow_mut()
+            .introspection
+            .watch(range, access);
+        Ok(())
+    }
+
+    /// Stop watching a range watched by [`AxVCpu::watch_guest_memory`], returns whether it was watched.
+    pub fn unwatch_guest_memory(&self, range: &Range<GuestPhysAddr>) -> bool {
+        self.inner_mut.borrow_mut().introspection.unwatch(range)
+    }
+
+    /// Complete the access held by the last [`AxVCpuExitReason::Introspection`] exit: permit, deny, or emulate it,
+    /// see [`IntrospectionVerdict`].
+    ///
+    /// Returns `BadState` if no introspection exit is pending, or the vcpu is not ready.
+    pub fn complete_introspection(&self, verdict: IntrospectionVerdict) -> AxVCpuResult {
+        let state = self.state();
+        if state != VCpuState::Ready {
+            return bad_state(VCpuState::Ready, state);
+        }
+        let Some((addr, access)) = self.inner_mut.borrow_mut().pending_introspection.take() else {
+            return Err(ax_err_type!(BadState, "no introspection exit pending").into());
+        };
+        vcpu_log!(Exit, Debug, vcpu = self.id(), addr:? = addr, verdict:? = verdict; "introspection completed");
+        self.with_current_cpu_set(|| {
+            self.arch_vcpu_mut()
+                .complete_introspection(addr, access, verdict)
+        })
+    }
+
+    /// Make the guest executable region `range` immutable, for hardened appliance VMs: attempts of the guest to
+    /// make it writable surface as [`AxVCpuExitReason::IntegrityViolation`] exits, after which the vcpu refuses to
+    /// run (with [`AxVCpuError::IntegrityViolation`]) until the violation is cleared with
+    /// [`AxVCpu::clear_integrity_violation`]. Regions can't be made mutable again.
+    ///
+    /// Writes are caught as stage-2 faults, so the VMM must map the region read-only (and executable) in the
+    /// stage-2 page table. Permission changes trapped by the VMM must be checked with
+    /// [`AxVCpu::check_permission_change`].
+    pub fn protect_code_region(&self, range: Range<GuestPhysAddr>) -> AxResult {
+        if range.is_empty() {
+            return ax_err!(InvalidInput, "empty code region");
+        }
+        self.inner_mut.borrow_mut().integrity.protect(range);
+        Ok(())
+    }
+
+    /// Whether `addr` is in a region made immutable by [`AxVCpu::protect_code_region`].
+    pub fn is_code_protected(&self, addr: GuestPhysAddr) -> bool {
+        self.inner_mut.borrow().integrity.is_protected(addr)
+    }
+
+    /// Check a permission change of `range` to `flags` requested by the guest and trapped by the VMM, e.g., a
+    /// stage-2 permission hypercall.
+    ///
+    /// If the change would make an immutable code region writable, an [`AxVCpuExitReason::IntegrityViolation`]
+    /// exit is queued (see [`AxVCpu::queue_exit`]) and [`AxVCpuError::IntegrityViolation`] is returned, the VMM
+    /// must not perform the change then.
+    pub fn check_permission_change(
+        &self,
+        range: Range<GuestPhysAddr>,
+        flags: MappingFlags,
+    ) -> AxVCpuResult {
+        if !flags.contains(MappingFlags::WRITE) {
+            return Ok(());
+        }
+        let Some(addr) = self.inner_mut.borrow().integrity.first_overlap(&range) else {
+            return Ok(());
+        };
+        self.queue_exit(AxVCpuExitReason::IntegrityViolation {
+            addr,
+            access: flags,
+        });
+        Err(AxVCpuError::IntegrityViolation)
+    }
+
+    /// Clear the violation blocking the vcpu after an [`AxVCpuExitReason::IntegrityViolation`] exit, returning
+    /// its address and access, if any. The attempt of the guest has not been performed, so the VMM should
+    /// generally skip the faulting instruction or inject a fault before running the vcpu again.
+    pub fn clear_integrity_violation(&self) -> Option<(GuestPhysAddr, MappingFlags)> {
+        self.inner_mut.borrow_mut().integrity.violation.take()
+    }
+
+    /// Put the vcpu in sandbox mode, for untrusted compute-only guests: any MMIO, port I/O or system register exit
+    /// not allowed by `config` terminates the guest.
+    ///
+    /// The offending access is not performed. [`AxVCpu::run`] returns `PermissionDenied`
+    /// ([`AxVCpuError::SandboxViolation`]) with a structured report of the access, which is also reported to
+    /// [`AxVCpuHal::on_fatal_vcpu_error`] and kept in [`AxVCpu::sandbox_violation`], and the vcpu refuses to run
+    /// again. The sandbox mode can't be left, but the allowlist can be replaced by calling this method again.
+    pub fn enable_sandbox(&self, config: SandboxConfig) {
+        self.inner_mut.borrow_mut().sandbox = Some(config);
+    }
+
+    /// Whether the vcpu is in sandbox mode, see [`AxVCpu::enable_sandbox`].
+    pub fn is_sandboxed(&self) -> bool {
+        self.inner_mut.borrow().sandbox.is_some()
+    }
+
+    /// Get the access which terminated the sandboxed guest, if any.
+    pub fn sandbox_violation(&self) -> Option<SandboxViolation> {
+        self.inner_mut.borrow().sandbox_violation
+    }
+
+    /// Check an exit against the allowlist of the sandbox mode, terminating the guest on violations.
+    fn check_sandbox(&self, exit: &AxVCpuExitReason) -> AxVCpuResult {
+        let violation = {
+            let mut inner_mut = self.inner_mut.borrow_mut();
+            let Some(sandbox) = &inner_mut.sandbox else {
+                return Ok(());
+            };
+            let Some(violation) = sandbox.check(exit, A::Hal::current_time_nanos()) else {
+                return Ok(());
+            };
+            inner_mut.sandbox_violation = Some(violation);
+            violation
+        };
+        vcpu_log!(Exit, Error, vcpu = self.id(), violation:? = violation; "sandboxed guest terminated");
+        let err = AxVCpuError::SandboxViolation(violation);
+        self.report_fatal(&err);
+        Err(err)
+    }
+
+    /// Set the offset (in nanoseconds) between the host clock and the guest clock.
+    ///
+    /// The offset is applied to the architecture-specific vcpu the next time the vcpu runs.
+    pub fn set_time_offset(&self, offset_ns: u64) {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        inner_mut.time_offset_ns = offset_ns;
+        inner_mut.time_offset_dirty = true;
+    }
+
+    /// Get the offset (in nanoseconds) between the host clock and the guest clock.
+    pub fn time_offset(&self) -> u64 {
+        self.inner_mut.borrow().time_offset_ns
+    }
+
+    /// Get the time state of the vcpu, i.e., the current guest clock and the deadline of the guest timer, saved in
+    /// snapshots. The guest clock of a paused vcpu (see [`AxVCpu::pause_time`]) is the one at the pause. The vcpu
+    /// must not be running.
+    pub fn time_state(&self) -> AxResult<VCpuTimeState> {
+        if self.state() == VCpuState::Running {
+            return ax_err!(BadState, "cannot save the time state of a running vcpu");
+        }
+        let timer_deadline_ns = self.arch_vcpu_mut().timer_deadline()?;
+        Ok(VCpuTimeState {
+            guest_time_ns: self.guest_time_ns(),
+            timer_deadline_ns,
+        })
+    }
+
+    /// Get the current guest clock, in nanoseconds. The guest clock of a paused vcpu (see [`AxVCpu::pause_time`])
+    /// is the one at the pause.
+    pub fn guest_time_ns(&self) -> u64 {
+        let inner_mut = self.inner_mut.borrow();
+        let host_now = inner_mut
+            .time_paused_at_ns
+            .unwrap_or_else(A::Hal::current_time_nanos);
+        host_now.wrapping_sub(inner_mut.time_offset_ns)
+    }
+
+    /// Adjust the time offset so that the guest clock reads `guest_time_ns` at the host time `host_now`. The guest
+    /// clock of a paused vcpu stays there until it's resumed.
+    pub(crate) fn set_guest_time_at(&self, guest_time_ns: u64, host_now: u64) {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        if inner_mut.time_paused_at_ns.is_some() {
+            inner_mut.time_paused_at_ns = Some(host_now);
+        }
+        inner_mut.time_offset_ns = host_now.wrapping_sub(guest_time_ns);
+        inner_mut.time_offset_dirty = true;
+    }
+
+    /// Restore a time state saved by [`AxVCpu::time_state`], possibly on another host. The vcpu must not be
+    /// running.
+    ///
+    /// The time offset is adjusted so that the guest clock resumes at the saved one (and stays there while the
+    /// vcpu is paused), and the guest timer is re-armed with [`AxArchVCpu::set_timer_deadline`], so that it fires
+    /// when the guest clock reaches the deadline rather than immediately.
+    pub fn restore_time_state(&self, time: &VCpuTimeState) -> AxResult {
+        if self.state() == VCpuState::Running {
+            return ax_err!(BadState, "cannot restore the time state of a running vcpu");
+        }
+        self.arch_vcpu_mut()
+            .set_timer_deadline(time.timer_deadline_ns)?;
+        self.set_guest_time_at(time.guest_time_ns, A::Hal::current_time_nanos());
+        Ok(())
+    }
+
+    /// Pause the guest clock, called when the vcpu is paused (or migrated out).
+    ///
+    /// The time elapsed until [`AxVCpu::resume_time`] is measured by [`AxVCpuHal::current_time_nanos`] and added
+    /// to the time offset, so that the guest clock does not jump across the pause.
+    pub fn pause_time(&self) {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        if inner_mut.time_paused_at_ns.is_none() {
+            inner_mut.time_paused_at_ns = Some(A::Hal::current_time_nanos());
+        }
+    }
+
+    /// Resume the guest clock paused by [`AxVCpu::pause_time`].
+    pub fn resume_time(&self) {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        if let Some(paused_at) = inner_mut.time_paused_at_ns.take() {
+            let paused_ns = A::Hal::current_time_nanos().saturating_sub(paused_at);
+            if paused_ns > 0 {
+                inner_mut.time_offset_ns = inner_mut.time_offset_ns.wrapping_add(paused_ns);
+                inner_mut.time_offset_dirty = true;
+            }
+        }
+    }
+
+    /// Enable or disable direct guest access to the architectural timer, generally for vcpus pinned to a physical
+    /// CPU, reducing the overhead of timer interrupt virtualization.
+    ///
+    /// The timer is granted when the vcpu is bound and reclaimed when it's unbound. If the vcpu is currently bound
+    /// and not running, the change takes effect immediately.
+    pub fn set_timer_passthrough(&self, enable: bool) -> AxVCpuResult {
+        let state = self.state();
+        if state == VCpuState::Ready || state == VCpuState::Blocked {
+            self.with_current_cpu_set(|| self.arch_vcpu_mut().set_timer_passthrough(enable))?;
+        }
+        self.inner_mut.borrow_mut().timer_passthrough = enable;
+        Ok(())
+    }
+
+    /// Whether direct guest access to the architectural timer is enabled.
+    pub fn timer_passthrough(&self) -> bool {
+        self.inner_mut.borrow().timer_passthrough
+    }
+
+    /// Enable or disable the real-time mode for latency-critical guests.
+    ///
+    /// Enabling the real-time mode pins the vcpu to its favored physical CPU via [`AxVCpuHal::pin_vcpu`] and, if
+    /// `isolate_host_irqs` is `true`, masks non-essential host interrupts on that CPU via
+    /// [`AxVCpuHal::isolate_host_irqs`]. A real-time vcpu is also exempt from the CPU quota set by
+    /// [`AxVCpu::set_cpu_quota`]. Disabling the real-time mode reverts all of these.
+    ///
+    /// If masking the host interrupts fails when enabling the real-time mode, the vcpu is unpinned again and left
+    /// out of the real-time mode.
+    pub fn set_realtime(&self, enable: bool, isolate_host_irqs: bool) -> AxResult {
+        let cpu_id = self.favor_phys_cpu();
+        let (realtime, isolated) = {
+            let inner_mut = self.inner_mut.borrow();
+            (inner_mut.realtime, inner_mut.host_irqs_isolated)
+        };
+
+        let isolate = enable && isolate_host_irqs;
+        if enable && !realtime {
+            A::Hal::pin_vcpu(self.id(), Some(cpu_id))?;
+        }
+        let isolation = match isolate != isolated {
+            true => A::Hal::isolate_host_irqs(cpu_id, isolate),
+            false => Ok(()),
+        };
+        if let Err(err) = isolation {
+            if enable && !realtime {
+                // Best effort, the pinning error is less relevant than the isolation one.
+                let _ = A::Hal::pin_vcpu(self.id(), None);
+            }
+            return Err(err);
+        }
+        if !enable && realtime {
+            A::Hal::pin_vcpu(self.id(), None)?;
+        }
+
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        inner_mut.realtime = enable;
+        inner_mut.host_irqs_isolated = isolate;
+        Ok(())
+    }
+
+    /// Whether the vcpu is in real-time mode, see [`AxVCpu::set_realtime`].
+    pub fn is_realtime(&self) -> bool {
+        self.inner_mut.borrow().realtime
+    }
+
+    /// Set the classifier used to fill the `region_kind` of [`AxVCpuExitReason::NestedPageFault`] exits, so that
+    /// the VMM can tell copy-on-write faults from device accesses cheaply.
+    pub fn set_region_classifier(&self, classifier: Option<Arc<dyn GuestRegionClassifier>>) {
+        self.inner_mut.borrow_mut().region_classifier = classifier;
+    }
+
+    /// Set what [`AxVCpu::run`] does with MMIO accesses to addresses the region classifier (see
+    /// [`AxVCpu::set_region_classifier`]) classifies as [`RegionKind::Unmapped`], i.e., where no device is
+    /// implemented. Without a region classifier, all MMIO accesses are returned as is. Defaults to
+    /// [`UnhandledMmioPolicy::Surface`].
+    pub fn set_unhandled_mmio_policy(&self, policy: UnhandledMmioPolicy) {
+        self.inner_mut.borrow_mut().unhandled_mmio_policy = policy;
+    }
+
+    /// Get the policy set by [`AxVCpu::set_unhandled_mmio_policy`].
+    pub fn unhandled_mmio_policy(&self) -> UnhandledMmioPolicy {
+        self.inner_mut.borrow().unhandled_mmio_policy
+    }
+
+    /// Set how [`AxVCpu::run_loop`] treats each kind of exit, e.g., to deny hypercalls or port I/O for untrusted
+    /// guests. Takes effect from the next exit. Defaults to handling all exits in kernel.
+    pub fn set_exit_policy(&self, policy: ExitPolicy) {
+        self.inner_mut.borrow_mut().exit_policy = policy;
+    }
+
+    /// Get the policy set by [`AxVCpu::set_exit_policy`].
+    pub fn exit_policy(&self) -> ExitPolicy {
+        self.inner_mut.borrow().exit_policy
+    }
+
+    /// Get the generation of decoded instruction caches of this vcpu, to be passed to [`crate::DecodeCache`].
+    pub fn decode_generation(&self) -> u64 {
+        self.inner_mut.borrow().decode_generation
+    }
+
+    /// Invalidate all decoded instruction caches of this vcpu. Must be called after the stage-2 mappings or
+    /// permissions of the guest are changed, see [`AxVCpuGroup::invalidate_decode_caches`](crate::AxVCpuGroup::invalidate_decode_caches).
+    pub fn invalidate_decode_cache(&self) {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        inner_mut.decode_generation = inner_mut.decode_generation.wrapping_add(1);
+    }
+
+    /// Set whether [`AxVCpuExitReason::ExternalInterrupt`] exits are handled inside [`AxVCpu::run`].
+    ///
+    /// If enabled, [`AxVCpu::run`] fetches the interrupt with [`AxVCpuHal::irq_fetch`], dispatches it to the host
+    /// with [`AxVCpuHal::irq_hanlder`], and re-enters the guest, so that such exits never reach the VMM.
+    /// Disabled by default.
+    pub fn set_auto_handle_host_irqs(&self, enable: bool) {
+        self.inner_mut.borrow_mut().auto_handle_host_irqs = enable;
+    }
+
+    /// Whether [`AxVCpuExitReason::ExternalInterrupt`] exits are handled inside [`AxVCpu::run`], see
+    /// [`AxVCpu::set_auto_handle_host_irqs`].
+    pub fn auto_handle_host_irqs(&self) -> bool {
+        self.inner_mut.borrow().auto_handle_host_irqs
+    }
+
+    /// Register the para-virtualized steal time structure of the vcpu, generally on behalf of a hypercall (or an MSR
+    /// write) of the guest. `None` unregisters the structure.
+    ///
+    /// The structure is updated with the steal time accounted by [`AxVCpu::account_steal_time`] before each VM
+    /// entry, and marked preempted when the vcpu is unbound.
+    ///
+    /// # Safety
+    ///
+    /// `page` must point to a valid, writable [`PvStealTime`](crate::PvStealTime) mapped to the guest, and stay
+    /// valid until it's unregistered or this vcpu is dropped.
+    pub unsafe fn register_steal_time_page(&self, page: Option<HostVirtAddr>) -> AxResult {
+        let align_mask = core::mem::align_of::<crate::PvStealTime>() - 1;
+        if page.is_some_and(|page| page.as_usize() & align_mask != 0) {
+            return ax_err!(InvalidInput, "steal time structure is not aligned");
+        }
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        inner_mut.steal_time_page = page;
+        inner_mut.steal_time_dirty = page.is_some();
+        Ok(())
+    }
+
+    /// Account `ns` nanoseconds the vcpu was runnable but not running, e.g., waiting in the run queue of the host
+    /// scheduler. Generally called by the scheduler right before the vcpu is run.
+    pub fn account_steal_time(&self, ns: u64) {
+        let mut inner_mut = self.inner_mut.borrow_mut();
+        inner_mut.steal_time_ns = inner_mut.steal_time_ns.wrapping_add(ns);
+        inner_mut.steal_time_dirty = true;
+    }
+
+    /// Get the accumulated steal time of the vcpu in nanoseconds.
+    pub fn steal_time(&self) -> u64 {
+        self.inner_mut.borrow().steal_time_ns
+    }
+
+    /// Install the interrupt controller glue used by the fast-path handlers, see [`crate::IrqChipGlue`].
+    /// If `None`, all interrupt controller accesses are returned to the VMM.
+    #[cfg(any(feature = "x86-apic-fast", feature = "arm-gic-fast"))]
+    pub fn set_irqchip_glue(&self, glue: Option<Arc<dyn crate::IrqChipGlue>>) {
+        self.inner_mut.borrow_mut().irqchip_glue = glue;
+    }
+
+    /// Get the optional capabilities of the vcpu.
+    pub fn capabilities(&self) -> VCpuCapabilities {
+        self.arch_vcpu_mut().capabilities()
+    }
+
+    /// Get the number of interrupts waiting to be injected into the vcpu.
+    pub fn pending_interrupts(&self) -> usize {
+        self.pending_irqs.borrow().len() + self.irq_bitmap.count()
+    }
+
+    /// Request the vcpu to stop, e.g., before the VM is destroyed: the vcpu is kicked out of the guest with
+    /// [`AxVCpuHal::kick_vcpu`] if it's running, and [`AxVCpu::run`] returns [`AxVCpuError::Stopped`] instead
+    /// of entering the guest until [`AxVCpu::clear_stop_request`] is called.
+    ///
+    /// This method may be called from any host context.
+    pub fn request_stop(&self) {
+        self.stop_requested.store(true, Ordering::Release);
+        if self.is_running() {
+            A::Hal::kick_vcpu(self.vm_id(), self.id());
+        }
+    }
+
+    /// Keep the vcpu outside guest mode (kicking it out if it's running), or release it, see
+    /// [`AxVCpuGroup::quiesce`](crate::AxVCpuGroup::quiesce).
+    pub(crate) fn set_quiesced(&self, quiesced: bool) {
+        self.quiesced.store(quiesced, Ordering::Release);
+        if !quiesced {
+            A::Hal::notify_vcpu(self.id());
+        } else if self.is_running() {
+            A::Hal::kick_vcpu(self.vm_id(), self.id());
+        }
+    }
+
+    /// Clear the request made by [`AxVCpu::request_stop`].
+    pub fn clear_stop_request(&self) {
+        self.stop_requested.store(false, Ordering::Release);
+    }
+
+    /// Whether a call to [`AxVCpu::run`] is in progress.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Acquire)
+    }
+
+    /// Get the kind of the last exit returned by the architecture-specific vcpu, if it's not handled yet.
+    pub(crate) fn last_exit_kind(&self) -> Option<ExitKind> {
+        self.last_exit.get().map(|(kind, _)| kind)
+    }
+
+    /// Get a snapshot of the counters of the vcpu.
+    pub fn stats(&self) -> AxVCpuStats {
+        let mut stats = self.stats.borrow().clone();
+        self.vector_table.borrow().merge_into(&mut stats);
+        stats
+    }
+
+    /// Set how the final counters of the vcpu are reported when it's dropped. Defaults to
+    /// [`FinalStatsReport::Off`].
+    pub fn set_final_stats_report(&self, report: FinalStatsReport) {
+        self.inner_mut.borrow_mut().final_stats_report = report;
+    }
+
+    /// Start timing a stage of handling the last exit of the vcpu, the time is attributed to the kind of the exit
+    /// when the returned timer is dropped.
+    pub fn stage_timer(&self, stage: HandlerStage) -> StageTimer<'_, A> {
+        StageTimer {
+            vcpu: self,
+            stage,
+            start_ns: A::Hal::current_time_nanos(),
+        }
+    }
+
+    /// Attribute the host time of a stage to the last exit of the vcpu, if any.
+    pub(crate) fn record_stage_time(&self, stage: HandlerStage, ns: u64) {
+        if let Some((kind, _)) = self.last_exit.get() {
+            self.stats.borrow_mut().record_stage(kind, stage, ns);
+        }
+    }
+
+    /// Get the load-balancing hints of the vcpu: how busy it is recently, and how warm the caches of each physical
+    /// CPU it ran on are likely to be. Schedulers can build work stealing and NUMA-aware placement on top of them.
+    pub fn load_hint(&self) -> LoadHint {
+        self.load.borrow_mut().hint(A::Hal::current_time_nanos())
+    }
+
+    /// Reset the counters of the vcpu.
+    pub fn reset_stats(&self) {
+        *self.stats.borrow_mut() = AxVCpuStats::new(self.id());
+        self.vector_table.borrow_mut().reset();
+    }
+
+    /// Forward a [`AxVCpuExitReason::FirmwareCall`] made through `SMC` to the secure monitor with `proxy` (see
+    /// [`SecureCallProxy`]), and write the results back to the first 4 GPRs of the guest.
+    ///
+    /// Returns `Ok(false)` without doing anything if the exit is of another kind or made through another conduit.
+    pub fn forward_secure_call(
+        &self,
+        proxy: &SecureCallProxy,
+        exit: &AxVCpuExitReason,
+    ) -> AxResult<bool> {
+        let AxVCpuExitReason::FirmwareCall {
+            conduit: FirmwareConduit::Smc,
+            func_id,
+            args,
+        } = *exit
+        else {
+            return Ok(false);
+        };
+        self.check_register_access()?;
+        let results = proxy.forward::<A::Hal>(FirmwareConduit::Smc, func_id, &args)?;
+        for (reg, value) in results.into_iter().enumerate() {
+            self.set_gpr(reg, value as usize)?;
+        }
+        Ok(true)
+    }
+
+    /// Get the system registers of the vcpu shadowed by the VMM, see [`SysRegFile`].
+    ///
+    /// The file is empty until the VMM defines the registers it emulates. It's saved in snapshots, and marked all
+    /// dirty when a snapshot is restored.
+    pub fn sysregs(&self) -> &RefCell<SysRegFile> {
+        &self.sysregs
+    }
+
+    /// Get the breakpoints and watchpoints of the vcpu, see [`BreakpointManager`].
+    pub fn breakpoint_manager(&self) -> &RefCell<BreakpointManager> {
+        &self.breakpoints
+    }
+
+    /// Set a software breakpoint at `addr` with the breakpoint instruction of the architecture (see
+    /// [`AxArchVCpu::sw_breakpoint_insn`]), patching guest memory through `mem`.
+    ///
+    /// Returns `Unsupported` if the architecture-specific vcpu doesn't support software breakpoints.
+    pub fn add_sw_breakpoint(&self, mem: &dyn GuestMemoryAccess, addr: GuestPhysAddr) -> AxResult {
+        let Some(insn) = A::sw_breakpoint_insn() else {
+            return ax_err!(Unsupported, "software breakpoints are not supported");
+        };
+        self.breakpoints
+            .borrow_mut()
+            .add_sw_breakpoint(mem, addr, insn)
+    }
+
+    /// Remove the software breakpoint at `addr`, restoring the original instruction through `mem`.
+    pub fn remove_sw_breakpoint(
+        &self,
+        mem: &dyn GuestMemoryAccess,
+        addr: GuestPhysAddr,
+    ) -> AxResult {
+        self.breakpoints
+            .borrow_mut()
+            .remove_sw_breakpoint(mem, addr)
+    }
+
+    /// Set a hardware watchpoint in a free debug register slot of the architecture-specific vcpu, returning the
+    /// slot.
+    ///
+    /// Returns `AlreadyExists` if the watchpoint is already set, and `ResourceBusy` if all slots are occupied. Like
+    /// [`AxVCpu::configure_intercepts`], the vcpu must be set up and not running.
+    pub fn add_watchpoint(&self, watchpoint: HwWatchpoint) -> AxVCpuResult<usize> {
+        let state = self.state();
+        if !matches!(state, VCpuState::Free | VCpuState::Ready) {
+            return bad_state(VCpuState::Ready, state);
+        }
+        let mut breakpoints = self.breakpoints.borrow_mut();
+        if breakpoints.watchpoint_slot(&watchpoint).is_some() {
+            return Err(ax_err_type!(AlreadyExists, "watchpoint already set").into());
+        }
+        let Some(slot) =
+            breakpoints.free_watchpoint_slot(self.arch_vcpu_mut().hw_watchpoint_slots())
+        else {
+            return Err(ax_err_type!(ResourceBusy, "no free watchpoint slot").into());
+        };
+        self.with_current_cpu_set(|| {
+            self.arch_vcpu_mut()
+                .set_hw_watchpoint(slot, Some(watchpoint))
+        })?;
+        breakpoints.set_watchpoint(slot, Some(watchpoint));
+        Ok(slot)
+    }
+
+    /// Remove a hardware watchpoint set by [`AxVCpu::add_watchpoint`]. Returns `NotFound` if it's not set.
+    pub fn remove_watchpoint(&self, watchpoint: &HwWatchpoint) -> AxVCpuResult {
+        let state = self.state();
+        if !matches!(state, VCpuState::Free | VCpuState::Ready) {
+            return bad_state(VCpuState::Ready, state);
+        }
+        let mut breakpoints = self.breakpoints.borrow_mut();
+        let Some(slot) = breakpoints.watchpoint_slot(watchpoint) else {
+            return Err(ax_err_type!(NotFound, "watchpoint not set").into());
+        };
+        self.with_current_cpu_set(|| self.arch_vcpu_mut().set_hw_watchpoint(slot, None))?;
+        breakpoints.set_watchpoint(slot, None);
+        Ok(())
+    }
+
+    /// Emulate a [`AxVCpuExitReason::SysRegRead`] or [`AxVCpuExitReason::SysRegWrite`] exit with the registers
+    /// shadowed in [`AxVCpu::sysregs`]: reads store the shadowed value into the target GPR, writes update the shadow
+    /// and mark it dirty.
+    ///
+    /// Returns `Ok(false)` without doing anything if the exit is of another kind or the register is not shadowed,
+    /// so that the VMM can fall back to its own handling.
+    pub fn emulate_sysreg_access(&self, exit: &AxVCpuExitReason) -> AxResult<bool> {
+        match *exit {
+            AxVCpuExitReason::SysRegRead { addr, reg } => {
+                let Some(value) = self.sysregs.borrow().read(addr) else {
+                    return Ok(false);
+                };
+                self.set_gpr(reg, value as usize)?;
+                Ok(true)
+            }
+            AxVCpuExitReason::SysRegWrite { addr, value } => {
+                let mut sysregs = self.sysregs.borrow_mut();
+                if !sysregs.contains(addr) {
+                    return Ok(false);
+                }
+                sysregs.write(addr, value);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Get the per-vcpu scratch storage of type `T`, created with `T::default()` on first access.
+    ///
+    /// Architecture-specific vcpus and device glue can use it to stash auxiliary per-vcpu state (e.g., a cache of
+    /// the last decoded instruction, a list of pending EOIs) without changing the layout of [`AxArchVCpu`]. Each
+    /// type has exactly one instance per vcpu, so wrap the state in a private newtype to avoid collisions.
+    ///
+    /// `T` must be `Send`, so that the vcpu can still be moved between host contexts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T::default()` accesses the scratch storage of the same vcpu.
+    pub fn scratch<T: Default + Send + 'static>(&self) -> &RefCell<T> {
+        let mut scratch = self.scratch.borrow_mut();
+        let entry = scratch
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(RefCell::new(T::default())));
+        let cell: *const RefCell<T> = entry
+            .downcast_ref::<RefCell<T>>()
+            .expect("scratch entry type mismatch");
+        // SAFETY: the entry is boxed, so its address doesn't change when the map is modified, and it's never
+        // removed before `self` is dropped.
+        unsafe { &*cell }
+    }
+}
+
+impl<A: AxArchVCpu> Drop for AxVCpu<A> {
+    /// Report the final counters as set by [`AxVCpu::set_final_stats_report`].
+    fn drop(&mut self) {
+        let stats = self.stats.get_mut();
+        self.vector_table.get_mut().merge_into(stats);
+        match &self.inner_mut.get_mut().final_stats_report {
+            FinalStatsReport::Off => {}
+            FinalStatsReport::Log => stats.log_summary(),
+            FinalStatsReport::Reporter(reporter) => reporter.report_final_stats(stats),
+        }
+    }
+}
+
+/// Work queued by [`AxVCpu::defer`].
+type DeferredWork<A> = Box<dyn FnOnce(&mut A) -> AxResult + Send>;
+
+/// The initial capacity of the pending interrupt queue of a vcpu.
+const PENDING_IRQS_CAPACITY: usize = 64;
+/// The initial capacity of the pending exit queue of a vcpu.
+const PENDING_EXITS_CAPACITY: usize = 4;
+
+/// Build the error returned when the state of a vcpu is not the expected one.
+#[cfg(not(feature = "no-alloc-fastpath"))]
+fn bad_state<T>(expected: VCpuState, actual: VCpuState) -> AxVCpuResult<T> {
+    Err(ax_err_type!(
+        BadState,
+        format!("VCpu state is not {:?}, but {:?}", expected, actual)
+    )
+    .into())
+}
+
+/// Build the error returned when the state of a vcpu is not the expected one, without allocating.
+#[cfg(feature = "no-alloc-fastpath")]
+fn bad_state<T>(expected: VCpuState, actual: VCpuState) -> AxVCpuResult<T> {
+    let _ = (expected, actual);
+    Err(ax_err_type!(BadState, "VCpu state is not the expected one").into())
+}
+
+#[percpu::def_percpu]
+static mut CURRENT_VCPU: Option<*mut u8> = None;
+
+/// The caller of a vcpu operation.
+type OperationCaller = Option<&'static Location<'static>>;
+
+/// The caller of the operation owning the current vcpu, for diagnosing nested operations.
+#[percpu::def_percpu]
+static mut CURRENT_VCPU_OWNER: OperationCaller = None;
+
+/// Check that no current vcpu is set on the current physical CPU, i.e., a new vcpu operation is not nested.
+fn check_not_nested<A: AxArchVCpu>() -> AxVCpuResult {
+    let Some(current) = get_current_vcpu::<A>() else {
+        return Ok(());
+    };
+    // SAFETY: the owner is only written together with the current vcpu, on the current physical CPU.
+    let owner = unsafe { *CURRENT_VCPU_OWNER.current_ref_raw() };
+    vcpu_log!(State, Error, vcpu = current.id(), owner:? = owner; "nested vcpu operation");
+    Err(AxVCpuError::NestedOperation {
+        vm_id: current.vm_id(),
+        vcpu_id: current.id(),
+        owner,
+    })
+}
+
+/// Get the current vcpu on the current physical CPU.
+///
+/// It's guaranteed that each time before a method of [`AxArchVCpu`] is called, the current vcpu is set to the corresponding [`AxVCpu`].
+/// So methods of [`AxArchVCpu`] can always get the [`AxVCpu`] containing itself by calling this method.
+pub fn get_current_vcpu<'a, A: AxArchVCpu>() -> Option<&'a AxVCpu<A>> {
+    unsafe {
+        CURRENT_VCPU
+            .current_ref_raw()
+            .as_ref()
+            .copied()
+            .and_then(|p| (p as *const AxVCpu<A>).as_ref())
+    }
+}
+
+/// Get a mutable reference to the current vcpu on the current physical CPU.
+///
+/// See [`get_current_vcpu`] for more details.
+pub fn get_current_vcpu_mut<'a, A: AxArchVCpu>() -> Option<&'a mut AxVCpu<A>> {
+    unsafe {
+        CURRENT_VCPU
+            .current_ref_mut_raw()
+            .as_mut()
+            .copied()
+            .and_then(|p| (p as *mut AxVCpu<A>).as_mut())
+    }
+}
+
+/// Set the current vcpu on the current physical CPU.
+///
+/// # Safety
+/// This method is marked as unsafe because it may result in unexpected behavior if not used properly.
+/// Do not call this method unless you know what you are doing.
+pub unsafe fn set_current_vcpu<A: AxArchVCpu>(vcpu: &AxVCpu<A>) {
+    unsafe {
+        CURRENT_VCPU
+            .current_ref_mut_raw()
+            .replace(vcpu as *const _ as *mut u8);
+    }
+}
+
+/// Clear the current vcpu on the current physical CPU.
+///
+/// # Safety
+/// This method is marked as unsafe because it may result in unexpected behavior if not used properly.
+/// Do not call this method unless you know what you are doing.    
+pub unsafe fn clear_current_vcpu<A: AxArchVCpu>() {
+    unsafe {
+        CURRENT_VCPU.current_ref_mut_raw().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        MockArchVCpu, MockConfig, advance_time, bound_vcpu, fatal_errors, pinned_cpu, script_exits,
+        serial, set_current_cpu, yields,
+    };
+    use crate::{
+        AccessWidth, ReplaySession, SnapshotArch, SnapshotIncompatibility, SnapshotSection,
+        VirtHwFeatures,
+    };
+
+    #[test]
+    fn vcpus_are_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<AxVCpu<MockArchVCpu>>();
+    }
+
+    #[test]
+    fn transition_table_renders_to_dot() {
+        let mut dot = alloc::string::String::new();
+        VCpuState::write_transition_dot(&mut dot).unwrap();
+        assert!(dot.starts_with("digraph VCpuState {\n"));
+        assert!(dot.ends_with("}\n"));
+        for state in VCpuState::ALL {
+            assert!(dot.contains(&format!("    {:?};\n", state)));
+        }
+        for (from, to) in VCpuState::transition_table() {
+            assert!(dot.contains(&format!("    {:?} -> {:?};\n", from, to)));
+            assert!(VCpuState::is_valid_transition(*from, *to));
+        }
+        assert_eq!(
+            dot.lines().count(),
+            2 + VCpuState::ALL.len() + VCpuState::transition_table().len()
+        );
+        assert!(!VCpuState::is_valid_transition(
+            VCpuState::Created,
+            VCpuState::Running
+        ));
+    }
+
+    #[test]
+    fn halt_blocks_until_interrupt() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        assert_eq!(vcpu.state(), VCpuState::Blocked);
+        assert!(vcpu.run(&token).is_err());
+        assert_eq!(vcpu.state(), VCpuState::Blocked);
+
+        vcpu.inject_interrupt(32).unwrap();
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        let injected = vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.last_injected);
+        assert_eq!(injected.unwrap(), Some(32));
+        assert_eq!(vcpu.state(), VCpuState::Blocked);
+
+        assert!(vcpu.wake());
+        assert!(!vcpu.wake());
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+    }
+
+    #[test]
+    fn halt_with_pending_interrupt_stays_ready() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        // An interrupt arriving while the guest runs.
+        vcpu.defer(|_| {
+            get_current_vcpu::<MockArchVCpu>()
+                .unwrap()
+                .inject_interrupt_from_irq(33)
+        });
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        assert_eq!(vcpu.state(), VCpuState::Blocked);
+    }
+
+    #[test]
+    fn queued_exit_wakes_blocked_vcpu() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        assert_eq!(vcpu.state(), VCpuState::Blocked);
+        vcpu.queue_exit(AxVCpuExitReason::SystemDown);
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+        vcpu.block_until_interrupt();
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::SystemDown)));
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+    }
+
+    #[test]
+    fn bind_outside_affinity_is_typed() {
+        let _serial = serial();
+        let vcpu = AxVCpu::<MockArchVCpu>::new(0, 1, Some(0b110), MockConfig::default()).unwrap();
+        vcpu.setup(GuestPhysAddr::from(0x8000), HostPhysAddr::from(0), ())
+            .unwrap();
+        assert_eq!(
+            vcpu.bind().unwrap_err(),
+            AxVCpuError::AffinityViolation {
+                cpu_id: 0,
+                allowed: 0b110,
+            }
+        );
+        assert_eq!(vcpu.state(), VCpuState::Free);
+        vcpu.set_affinity_override(true);
+        let token = vcpu.bind().unwrap();
+        vcpu.unbind(token).unwrap();
+    }
+
+    #[test]
+    fn replay_reinjects_interrupts_without_side_effects() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        let host = HostInfo {
+            arch: SnapshotArch::current(),
+            features: VirtHwFeatures::empty(),
+        };
+        let mut session = ReplaySession::new(&vcpu, host, 8).unwrap();
+        vcpu.inject_interrupt(32).unwrap();
+        assert!(matches!(session.run(&token), Ok(AxVCpuExitReason::Halt)));
+        assert_eq!(vcpu.state(), VCpuState::Blocked);
+        vcpu.inject_interrupt(33).unwrap();
+        assert!(matches!(session.run(&token), Ok(AxVCpuExitReason::Halt)));
+        vcpu.queue_exit(AxVCpuExitReason::Nothing);
+        assert!(matches!(session.run(&token), Ok(AxVCpuExitReason::Nothing)));
+        assert_eq!(session.log()[0].interrupts, [32]);
+        assert_eq!(session.log()[1].interrupts, [33]);
+        assert!(session.log()[2].queued);
+        let exits = vcpu.stats().total_exits();
+
+        // The recorded halts neither block the vcpu nor count as exits again.
+        assert_eq!(session.step_back(2, &token, |_| Ok(())).unwrap(), 1);
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+        let injected = vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.last_injected);
+        assert_eq!(injected.unwrap(), Some(32));
+        assert_eq!(session.step_forward(2, &token).unwrap(), 3);
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+        let injected = vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.last_injected);
+        assert_eq!(injected.unwrap(), Some(33));
+        assert_eq!(vcpu.stats().total_exits(), exits);
+    }
+
+    #[test]
+    fn snapshot_errors_are_typed() {
+        let _serial = serial();
+        let (vcpu, _token) = bound_vcpu(MockConfig::default());
+        let host = HostInfo {
+            arch: SnapshotArch::current(),
+            features: VirtHwFeatures::empty(),
+        };
+        let mut data = Vec::new();
+        vcpu.snapshot(&host).unwrap().encode(&mut data);
+        vcpu.restore_encoded(&data, &host).unwrap();
+
+        // The first byte of the architecture-specific state, after the header and the section length.
+        data[SNAPSHOT_HEADER_SIZE + 4] ^= 1;
+        assert_eq!(
+            vcpu.restore_encoded(&data, &host).unwrap_err(),
+            AxVCpuError::SnapshotCorrupted {
+                section: SnapshotSection::ArchState,
+            }
+        );
+
+        let snapshot = vcpu.snapshot(&host).unwrap();
+        let other_host = HostInfo {
+            arch: SnapshotArch::Other,
+            ..host
+        };
+        assert_eq!(
+            vcpu.restore(&snapshot, &other_host).unwrap_err(),
+            AxVCpuError::SnapshotIncompatible(SnapshotIncompatibility::Arch {
+                found: SnapshotArch::current(),
+                expected: SnapshotArch::Other,
+            })
+        );
+    }
+
+    #[test]
+    fn only_unrecoverable_errors_are_reported() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        vcpu.defer(|_| ax_err!(WouldBlock, "device model busy"));
+        assert_eq!(
+            vcpu.run(&token).unwrap_err(),
+            AxVCpuError::Other(AxError::WouldBlock)
+        );
+        assert_eq!(fatal_errors(), 0);
+        assert!(
+            vcpu.transition_state(VCpuState::Ready, VCpuState::Running)
+                .is_err()
+        );
+        assert_eq!(fatal_errors(), 0);
+
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        vcpu.defer(|_| ax_err!(BadAddress, "device model broken"));
+        assert_eq!(
+            vcpu.run(&token).unwrap_err(),
+            AxVCpuError::Other(AxError::BadAddress)
+        );
+        assert_eq!(fatal_errors(), 1);
+        assert_eq!(vcpu.state(), VCpuState::Invalid);
+    }
+
+    #[test]
+    fn run_loop_forwards_exits_once() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        vcpu.set_exit_policy(
+            ExitPolicy::new().with(ExitKind::SystemReset, ExitDisposition::ForwardToVmm),
+        );
+        script_exits(
+            &vcpu,
+            [AxVCpuExitReason::Nothing, AxVCpuExitReason::SystemReset],
+        );
+        let mut handled = 0;
+        let action = vcpu
+            .run_loop(&token, |_, _| {
+                handled += 1;
+                Ok(ExitAction::Continue)
+            })
+            .unwrap();
+        assert!(matches!(
+            action,
+            ExitAction::Forward {
+                exit: AxVCpuExitReason::SystemReset
+            }
+        ));
+        assert_eq!(handled, 1);
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+    }
+
+    #[test]
+    fn deferred_work_may_use_the_vcpu() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        vcpu.defer(|_| {
+            let vcpu = get_current_vcpu::<MockArchVCpu>().unwrap();
+            vcpu.inject_interrupt(33)?;
+            vcpu.queue_exit(AxVCpuExitReason::SystemReset);
+            assert!(!vcpu.wake());
+            Ok(())
+        });
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+        assert!(matches!(
+            vcpu.run(&token),
+            Ok(AxVCpuExitReason::SystemReset)
+        ));
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        let injected = vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.last_injected);
+        assert_eq!(injected.unwrap(), Some(33));
+        assert_eq!(fatal_errors(), 0);
+    }
+
+    #[test]
+    fn nested_operations_are_typed() {
+        let _serial = serial();
+        let (vcpu, _token) = bound_vcpu(MockConfig::default());
+        let mut nested = None;
+        vcpu.with_current_cpu_set(|| {
+            nested = Some(vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.injected));
+            Ok(())
+        })
+        .unwrap();
+        assert!(matches!(
+            nested,
+            Some(Err(AxVCpuError::NestedOperation {
+                vm_id: 0,
+                vcpu_id: 0,
+                owner: Some(_),
+            }))
+        ));
+        vcpu.set_reentrant_reads(true);
+        vcpu.with_current_cpu_set(|| {
+            nested = Some(vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.injected));
+            Ok(())
+        })
+        .unwrap();
+        assert!(matches!(nested, Some(Ok(0))));
+    }
+
+    #[test]
+    fn run_errors_are_typed() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        // Another host context is running the vcpu.
+        vcpu.running.store(true, Ordering::Release);
+        assert_eq!(
+            vcpu.with_arch(|_| Ok(())).unwrap_err(),
+            AxVCpuError::AlreadyRunning
+        );
+        assert_eq!(vcpu.run(&token).unwrap_err(), AxVCpuError::AlreadyRunning);
+        vcpu.running.store(false, Ordering::Release);
+
+        vcpu.request_stop();
+        assert_eq!(vcpu.run(&token).unwrap_err(), AxVCpuError::Stopped);
+        vcpu.clear_stop_request();
+
+        vcpu.with_arch(|arch_vcpu| {
+            arch_vcpu.run_ns = 200;
+            Ok(())
+        })
+        .unwrap();
+        vcpu.set_cpu_quota(1000, 100);
+        script_exits(&vcpu, [AxVCpuExitReason::Nothing]);
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Nothing)));
+        assert_eq!(
+            vcpu.run(&token).unwrap_err(),
+            AxVCpuError::Throttled { resume_in_ns: 800 }
+        );
+    }
+
+    #[test]
+    fn realtime_vcpus_bypass_quota() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        vcpu.with_arch(|arch_vcpu| {
+            arch_vcpu.run_ns = 200;
+            Ok(())
+        })
+        .unwrap();
+        vcpu.set_cpu_quota(1000, 100);
+        vcpu.set_realtime(true, false).unwrap();
+        assert_eq!(pinned_cpu(), Some(vcpu.favor_phys_cpu()));
+        script_exits(&vcpu, (0..3).map(|_| AxVCpuExitReason::Nothing));
+        for _ in 0..3 {
+            assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Nothing)));
+        }
+
+        // The guest time of the real-time mode isn't charged once it's left.
+        vcpu.set_realtime(false, false).unwrap();
+        assert_eq!(pinned_cpu(), None);
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+    }
+
+    #[test]
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn sync_time_follows_bsp_clock() {
+        let _serial = serial();
+        let bsp = AxVCpu::<MockArchVCpu>::new(0, 0, None, MockConfig::default()).unwrap();
+        let ap = AxVCpu::<MockArchVCpu>::new(1, 0, None, MockConfig::default()).unwrap();
+        advance_time(1000);
+        bsp.set_time_offset(400);
+        ap.pause_time();
+        advance_time(500);
+        let group = crate::AxVCpuGroup::new(vec![Arc::new(bsp), Arc::new(ap)]);
+        let (bsp, ap) = (group.vcpu(0).unwrap(), group.vcpu(1).unwrap());
+        assert_eq!(group.sync_time(), 1500);
+        assert_eq!(bsp.guest_time_ns(), 1100);
+        assert_eq!(ap.guest_time_ns(), 1100);
+
+        // The paused vcpu resumes from the synchronized clock.
+        advance_time(300);
+        assert_eq!(ap.guest_time_ns(), 1100);
+        ap.resume_time();
+        advance_time(100);
+        assert_eq!(bsp.guest_time_ns(), 1500);
+        assert_eq!(ap.guest_time_ns(), 1200);
+    }
+
+    #[test]
+    fn time_offsets_without_virtual_counter_support() {
+        let _serial = serial();
+        // `MockArchVCpu` keeps the default `set_virtual_counter_offset`.
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        vcpu.set_time_offset(400);
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        vcpu.wake();
+        vcpu.pause_time();
+        advance_time(100);
+        vcpu.resume_time();
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        assert_ne!(vcpu.state(), VCpuState::Invalid);
+        assert_eq!(fatal_errors(), 0);
+    }
+
+    #[test]
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn quiesce_yields_until_timeout() {
+        let _serial = serial();
+        let (vcpu, _token) = bound_vcpu(MockConfig::default());
+        // The vcpu never leaves the guest.
+        vcpu.running.store(true, Ordering::Release);
+        let group = crate::AxVCpuGroup::new(vec![Arc::new(vcpu)]);
+        let stuck = group.quiesce(1000).unwrap_err();
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].vcpu_id, 0);
+        assert_eq!(yields(), 10);
+    }
+
+    #[test]
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn group_operations_while_running() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        let group = Arc::new(crate::AxVCpuGroup::new(vec![Arc::new(vcpu)]));
+        let vcpu = group.vcpu(0).unwrap();
+        std::thread_local! {
+            static GROUP: RefCell<Option<Arc<crate::AxVCpuGroup<MockArchVCpu>>>> = const { RefCell::new(None) };
+        }
+        GROUP.set(Some(group.clone()));
+        // Runs with the vcpu in the guest, as another physical CPU would see it.
+        vcpu.defer(|_| {
+            let group = GROUP.take().unwrap();
+            let status = group.collect_states().remove(0);
+            assert_eq!(status.state, VCpuState::Running);
+            assert!(status.in_guest);
+            assert_eq!(status.bound_cpu, Some(0));
+            assert_eq!(status.blocked_for_ns, None);
+            let vcpu = group.vcpu(0).unwrap();
+            assert_eq!(
+                vcpu.with_arch(|_| Ok(())).unwrap_err(),
+                AxVCpuError::AlreadyRunning
+            );
+            group.inject_interrupt(0, 40)?;
+            let addr = GuestPhysAddr::from(0x1000);
+            group.report_iommu_fault(7, addr, MappingFlags::WRITE)?;
+            assert_eq!(
+                group.report_iommu_fault(7, addr, MappingFlags::WRITE),
+                Err(AxError::ResourceBusy)
+            );
+            Ok(())
+        });
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+        assert!(matches!(
+            vcpu.run(&token),
+            Ok(AxVCpuExitReason::IommuFault { device: 7, .. })
+        ));
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        let injected = vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.last_injected);
+        assert_eq!(injected.unwrap(), Some(40));
+    }
+
+    #[test]
+    fn run_unbound_or_on_foreign_cpu_is_not_reported() {
+        let _serial = serial();
+        let vcpu = AxVCpu::<MockArchVCpu>::new(0, 0, None, MockConfig::default()).unwrap();
+        vcpu.setup(GuestPhysAddr::from(0x8000), HostPhysAddr::from(0), ())
+            .unwrap();
+        // A forged token, as none is handed out before the vcpu is bound.
+        let token = RunToken {
+            vcpu_id: 0,
+            generation: 0,
+        };
+        assert_eq!(vcpu.run(&token).unwrap_err(), AxVCpuError::NotBound);
+        assert_eq!(vcpu.state(), VCpuState::Free);
+
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        set_current_cpu(1);
+        assert_eq!(
+            vcpu.run(&token).unwrap_err(),
+            AxVCpuError::ForeignCpu {
+                cpu_id: 1,
+                bound_cpu: 0
+            }
+        );
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+        assert_eq!(fatal_errors(), 0);
+    }
+
+    #[test]
+    fn writable_code_region_is_refused() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        let region = GuestPhysAddr::from(0x1000)..GuestPhysAddr::from(0x2000);
+        vcpu.protect_code_region(region).unwrap();
+        let change = GuestPhysAddr::from(0x1800)..GuestPhysAddr::from(0x3000);
+        vcpu.check_permission_change(change.clone(), MappingFlags::READ)
+            .unwrap();
+        assert_eq!(
+            vcpu.check_permission_change(change, MappingFlags::READ | MappingFlags::WRITE)
+                .unwrap_err(),
+            AxVCpuError::IntegrityViolation
+        );
+        assert!(matches!(
+            vcpu.run(&token),
+            Ok(AxVCpuExitReason::IntegrityViolation { .. })
+        ));
+        assert_eq!(
+            vcpu.run(&token).unwrap_err(),
+            AxVCpuError::IntegrityViolation
+        );
+        assert_eq!(
+            vcpu.clear_integrity_violation(),
+            Some((
+                GuestPhysAddr::from(0x1800),
+                MappingFlags::READ | MappingFlags::WRITE
+            ))
+        );
+        assert!(vcpu.run(&token).is_ok());
+        assert_eq!(fatal_errors(), 0);
+    }
+
+    #[test]
+    fn failed_unbind_does_not_mark_preempted() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        let mut steal_time = Box::new(crate::PvStealTime::default());
+        let page = HostVirtAddr::from(&mut *steal_time as *mut crate::PvStealTime as usize);
+        // SAFETY: `steal_time` outlives the vcpu.
+        unsafe { vcpu.register_steal_time_page(Some(page)).unwrap() };
+        assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+        assert_eq!(vcpu.state(), VCpuState::Blocked);
+        vcpu.unbind(token).unwrap_err();
+        // SAFETY: the vcpu is not running, so the structure is not written concurrently.
+        let preempted = unsafe { core::ptr::read_volatile(&steal_time.preempted) };
+        assert_eq!(preempted, 0);
+        drop(vcpu);
+    }
+
+    #[test]
+    fn unbind_marks_preempted() {
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        let mut steal_time = Box::new(crate::PvStealTime::default());
+        let page = HostVirtAddr::from(&mut *steal_time as *mut crate::PvStealTime as usize);
+        // SAFETY: `steal_time` outlives the vcpu.
+        unsafe { vcpu.register_steal_time_page(Some(page)).unwrap() };
+        vcpu.unbind(token).unwrap();
+        // SAFETY: the vcpu is not running, so the structure is not written concurrently.
+        let preempted = unsafe { core::ptr::read_volatile(&steal_time.preempted) };
+        assert_eq!(preempted, 1);
+        drop(vcpu);
+    }
+
+    #[test]
+    fn unpark_sets_boot_args() {
+        let _serial = serial();
+        let (vcpu, _token) = bound_vcpu(MockConfig::default());
+        vcpu.park(0).unwrap();
+        vcpu.unpark(GuestPhysAddr::from(0x9000), 42).unwrap();
+        assert_eq!(vcpu.state(), VCpuState::Ready);
+        let (entry, gprs) = vcpu
+            .read_arch_vcpu(|arch_vcpu| (arch_vcpu.entry, arch_vcpu.gprs))
+            .unwrap();
+        assert_eq!(entry, GuestPhysAddr::from(0x9000));
+        assert_eq!(gprs[..2], [vcpu.arch_cpu_id() as usize, 42]);
+    }
+
+    /// Classifies all addresses as [`RegionKind::Unmapped`].
+    struct NothingMapped;
+
+    impl GuestRegionClassifier for NothingMapped {
+        fn classify(&self, _addr: GuestPhysAddr, _access_flags: MappingFlags) -> RegionKind {
+            RegionKind::Unmapped
+        }
+    }
+
+    #[test]
+    fn unhandled_mmio_reads_go_through_set_gpr() {
+        let _serial = serial();
+        for protected in [false, true] {
+            let (vcpu, token) = bound_vcpu(MockConfig {
+                protected,
+                ..Default::default()
+            });
+            vcpu.set_region_classifier(Some(Arc::new(NothingMapped)));
+            vcpu.set_unhandled_mmio_policy(UnhandledMmioPolicy::RazWi);
+            vcpu.with_arch(|arch_vcpu| {
+                arch_vcpu.gprs[3] = 7;
+                Ok(())
+            })
+            .unwrap();
+            script_exits(
+                &vcpu,
+                [AxVCpuExitReason::MmioRead {
+                    addr: GuestPhysAddr::from(0x1000),
+                    width: AccessWidth::Dword,
+                    reg: 3,
+                    reg_width: AccessWidth::Qword,
+                }],
+            );
+            let result = vcpu.run(&token);
+            let gpr = vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.gprs[3]).unwrap();
+            if protected {
+                assert_eq!(
+                    result.unwrap_err(),
+                    AxVCpuError::Other(AxError::PermissionDenied)
+                );
+                assert_eq!(gpr, 7);
+            } else {
+                assert!(matches!(result, Ok(AxVCpuExitReason::Halt)));
+                assert_eq!(gpr, 0);
+            }
+        }
+    }
+
+    /// Acknowledges vector 27 for all vcpus.
+    #[cfg(feature = "arm-gic-fast")]
+    struct AckGlue;
+
+    #[cfg(feature = "arm-gic-fast")]
+    impl crate::IrqChipGlue for AckGlue {
+        fn eoi(&self, _vcpu_id: usize, _value: u64) -> AxResult {
+            Ok(())
+        }
+
+        fn send_ipi(&self, _vcpu_id: usize, _value: u64) -> AxResult {
+            Ok(())
+        }
+
+        fn acknowledge(&self, _vcpu_id: usize) -> AxResult<u64> {
+            Ok(27)
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "arm-gic-fast")]
+    fn fast_path_acknowledge_goes_through_set_gpr() {
+        let _serial = serial();
+        for protected in [false, true] {
+            let (vcpu, token) = bound_vcpu(MockConfig {
+                protected,
+                ..Default::default()
+            });
+            vcpu.set_irqchip_glue(Some(Arc::new(AckGlue)));
+            // A read of `ICC_IAR1_EL1`.
+            script_exits(
+                &vcpu,
+                [AxVCpuExitReason::SysRegRead {
+                    addr: 0x30_3018,
+                    reg: 5,
+                }],
+            );
+            let result = vcpu.run(&token);
+            let gpr = vcpu.read_arch_vcpu(|arch_vcpu| arch_vcpu.gprs[5]).unwrap();
+            if protected {
+                assert_eq!(
+                    result.unwrap_err(),
+                    AxVCpuError::Other(AxError::PermissionDenied)
+                );
+                assert_eq!(gpr, 0);
+            } else {
+                assert!(matches!(result, Ok(AxVCpuExitReason::Halt)));
+                assert_eq!(gpr, 27);
+            }
+        }
+    }
+
+    #[test]
+    fn failed_host_irq_isolation_unpins() {
+        let _serial = serial();
+        let (vcpu, _token) = bound_vcpu(MockConfig::default());
+        assert_eq!(
+            vcpu.set_realtime(true, true).unwrap_err(),
+            AxError::Unsupported
+        );
+        assert_eq!(pinned_cpu(), None);
+        assert!(!vcpu.is_realtime());
+    }
+
+    #[test]
+    fn lost_interrupts_reported_once_per_assertion() {
+        let _serial = serial();
+        let (vcpu, _token) = bound_vcpu(MockConfig::default());
+        vcpu.set_lost_irq_threshold(Some(100));
+        for vector in 0..20 {
+            vcpu.assert_irq_line(vector).unwrap();
+        }
+        advance_time(101);
+        assert_eq!(vcpu.check_lost_interrupts(), 20);
+        assert_eq!(vcpu.check_lost_interrupts(), 0);
+        assert_eq!(vcpu.stats().vector(19).lost, 1);
+    }
+
+    #[cfg(feature = "no-alloc-fastpath")]
+    #[test]
+    fn requeue_into_full_queue_does_not_allocate() {
+        use crate::test_utils::alloc_counter::count_allocations;
+
+        let _serial = serial();
+        let (vcpu, _token) = bound_vcpu(MockConfig::default());
+        while vcpu.inject_interrupt(32).is_ok() {}
+        let queued = vcpu.pending_interrupts();
+        let ((), allocations) = count_allocations(|| vcpu.requeue_interrupt(40));
+        assert_eq!(allocations, 0);
+        assert_eq!(vcpu.pending_interrupts(), queued + 1);
+        vcpu.requeue_interrupt(IRQ_BITMAP_VECTORS);
+        assert_eq!(vcpu.pending_interrupts(), queued + 1);
+        assert_eq!(vcpu.stats().vector(IRQ_BITMAP_VECTORS).dropped, 1);
+    }
+
+    #[cfg(feature = "no-alloc-fastpath")]
+    #[test]
+    fn fast_path_does_not_allocate() {
+        use crate::test_utils::alloc_counter::count_allocations;
+
+        let _serial = serial();
+        let (vcpu, token) = bound_vcpu(MockConfig::default());
+        vcpu.set_lost_irq_threshold(Some(100));
+        vcpu.assert_irq_line(40).unwrap();
+        script_exits(&vcpu, [AxVCpuExitReason::Nothing]);
+        advance_time(1_000);
+
+        let ((), allocations) = count_allocations(|| {
+            vcpu.inject_interrupt(32).unwrap();
+            assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Nothing)));
+            assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+            assert_eq!(vcpu.state(), VCpuState::Blocked);
+            assert!(vcpu.run(&token).is_err());
+            vcpu.inject_interrupt(33).unwrap();
+            assert_eq!(vcpu.state(), VCpuState::Ready);
+            vcpu.inject_interrupt_from_irq(34).unwrap();
+            assert!(matches!(vcpu.run(&token), Ok(AxVCpuExitReason::Halt)));
+            assert!(vcpu.wake());
+            vcpu.park(0).unwrap();
+            vcpu.unpark(GuestPhysAddr::from(0x9000), 0).unwrap();
+            assert_eq!(vcpu.state(), VCpuState::Ready);
+        });
+        assert_eq!(allocations, 0);
+
+        let stats = vcpu.stats();
+        assert_eq!(stats.vector(40).lost, 1);
+        for vector in [32, 33, 34, 40] {
+            assert_eq!(stats.vector(vector).injected, 1);
+        }
     }
 }